@@ -2,32 +2,41 @@ use std::env;
 
 use dotenvy::dotenv;
 use jsonwebtoken::{encode, Header, EncodingKey};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use jsonwebtoken::{decode, DecodingKey, Validation};
+use actix_web::{dev, error::ErrorUnauthorized, Error, FromRequest, HttpRequest};
+use futures::future::{err, ok, Ready};
 
-#[derive(serde::Serialize, serde::Deserialize)]
+/// Access tokens only live for a few minutes, clients get new ones from `/refresh`.
+pub const ACCESS_TOKEN_TTL_MINUTES: i64 = 15;
+/// A session ends this long after sign in, however often it is refreshed.
+pub const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
 pub struct Payload {
     pub user_id: String,
     pub exp: usize,
+    /// Token id, checked against the revocation list
+    pub jti: String,
+    /// The session the token was handed out for
+    pub sid: String,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Clone)]
-pub struct InternalPayload {
-    pub id: String,
-    pub exp: usize,
+/// When an access token created now expires.
+pub fn access_token_expiry() -> DateTime<Utc> {
+    Utc::now()
+        .checked_add_signed(chrono::Duration::minutes(ACCESS_TOKEN_TTL_MINUTES))
+        .expect("valid timestamp")
 }
 
-pub fn create_jwt(user_id: String) -> Result<String, jsonwebtoken::errors::Error> {
+pub fn create_jwt(user_id: &str, session_id: &str, jti: &str, expires_at: DateTime<Utc>) -> Result<String, jsonwebtoken::errors::Error> {
     dotenv().ok();
 
-    let expiration = Utc::now()
-        .checked_add_signed(chrono::Duration::minutes(60))
-        .expect("valid timestamp")
-        .timestamp() as usize;
-
     let claims = Payload {
-        user_id: user_id,
-        exp: expiration,
+        user_id: user_id.to_string(),
+        exp: expires_at.timestamp() as usize,
+        jti: jti.to_string(),
+        sid: session_id.to_string(),
     };
     let jwt_secret = env::var("JWT_SECRET")
         .unwrap_or_else(|_| panic!("JWT_SECRET must be set"));
@@ -35,6 +44,11 @@ pub fn create_jwt(user_id: String) -> Result<String, jsonwebtoken::errors::Error
     encode(&Header::default(), &claims, &EncodingKey::from_secret(jwt_secret.as_ref()))
 }
 
+/// An opaque random token for refresh tokens and mailed links, only its hash is stored.
+pub fn create_opaque_token() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
 pub fn verify_jwt(token: &str) -> Result<Payload, jsonwebtoken::errors::Error> {
     let jwt_secret = env::var("JWT_SECRET")
         .unwrap_or_else(|_| panic!("JWT_SECRET must be set"));
@@ -43,20 +57,16 @@ pub fn verify_jwt(token: &str) -> Result<Payload, jsonwebtoken::errors::Error> {
         .map(|data| data.claims)
 }
 
-pub fn create_jwt_for_communication(id: String) -> Result<String, jsonwebtoken::errors::Error> {
-    dotenv().ok();
-
-    let expiration = Utc::now()
-        .checked_add_signed(chrono::Duration::minutes(60))
-        .expect("valid timestamp")
-        .timestamp() as usize;
+/// The verified token of the user making the request, put into the request extensions by
+/// `AuthMiddleware`. Only available on routes behind the middleware.
+impl FromRequest for Payload {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
 
-    let claims = InternalPayload {
-        id: id,
-        exp: expiration,
-    };
-    let jwt_secret = env::var("JWT_SECRET")
-        .unwrap_or_else(|_| panic!("JWT_SECRET must be set"));
-
-    encode(&Header::default(), &claims, &EncodingKey::from_secret(jwt_secret.as_ref()))
-}
\ No newline at end of file
+    fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+        match req.extensions().get::<Payload>() {
+            Some(payload) => ok(payload.clone()),
+            None => err(ErrorUnauthorized("Invalid token")),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use base64::engine::Engine;
+use serde::{Deserialize, Serialize};
+use solana_sdk::transaction::VersionedTransaction;
+
+use crate::routes::solana::QuoteResponse;
+
+const DEFAULT_JUPITER_API_URL: &str = "https://quote-api.jup.ag/v6";
+
+#[derive(Debug)]
+pub enum JupiterError {
+    Request(reqwest::Error),
+    Status(reqwest::StatusCode),
+    InvalidResponse(String),
+}
+
+impl std::fmt::Display for JupiterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JupiterError::Request(e) => write!(f, "Error sending request to aggregator: {}", e),
+            JupiterError::Status(status) => write!(f, "Aggregator responded with status: {}", status),
+            JupiterError::InvalidResponse(msg) => write!(f, "Invalid aggregator response: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for JupiterError {}
+
+#[derive(Serialize)]
+struct SwapTransactionRequest<'a> {
+    #[serde(rename = "quoteResponse")]
+    quote_response: &'a QuoteResponse,
+    #[serde(rename = "userPublicKey")]
+    user_public_key: &'a str,
+    #[serde(rename = "wrapAndUnwrapSol")]
+    wrap_and_unwrap_sol: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SwapTransactionResponse {
+    #[serde(rename = "swapTransaction")]
+    pub swap_transaction: String,
+    #[serde(rename = "lastValidBlockHeight")]
+    pub last_valid_block_height: u64,
+}
+
+/// Unsigned swap transaction built by the aggregator.
+pub struct SwapTransaction {
+    pub transaction: VersionedTransaction,
+    pub last_valid_block_height: u64,
+}
+
+/// Thin client for the Jupiter swap API. The base URL can be pointed at a local
+/// mock aggregator through `JUPITER_API_URL`.
+pub struct JupiterClient {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl JupiterClient {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let base_url = dotenvy::var("JUPITER_API_URL").unwrap_or_else(|_| DEFAULT_JUPITER_API_URL.to_string());
+        Self::new(base_url)
+    }
+
+    pub async fn quote(&self, input_mint: &str, output_mint: &str, amount: u64, slippage_bps: u64) -> Result<QuoteResponse, JupiterError> {
+        let target_url = format!("{}/quote?inputMint={}&outputMint={}&amount={}&slippageBps={}",
+            self.base_url, input_mint, output_mint, amount, slippage_bps);
+
+        let response = self.client.get(target_url)
+            .send()
+            .await
+            .map_err(JupiterError::Request)?;
+        if !response.status().is_success() {
+            return Err(JupiterError::Status(response.status()));
+        }
+        let body = response.text().await.map_err(JupiterError::Request)?;
+        serde_json::from_str::<QuoteResponse>(&body).map_err(|e| JupiterError::InvalidResponse(e.to_string()))
+    }
+
+    /// Ask the aggregator to build the swap transaction for `quote`, paid and signed by `user_public_key`.
+    pub async fn swap_transaction(&self, quote: &QuoteResponse, user_public_key: &str) -> Result<SwapTransaction, JupiterError> {
+        let response = self.client.post(format!("{}/swap", self.base_url))
+            .json(&SwapTransactionRequest {
+                quote_response: quote,
+                user_public_key,
+                wrap_and_unwrap_sol: true,
+            })
+            .send()
+            .await
+            .map_err(JupiterError::Request)?;
+        if !response.status().is_success() {
+            return Err(JupiterError::Status(response.status()));
+        }
+        let body = response.json::<SwapTransactionResponse>()
+            .await
+            .map_err(|e| JupiterError::InvalidResponse(e.to_string()))?;
+
+        let tx_bytes = base64::engine::general_purpose::STANDARD
+            .decode(&body.swap_transaction)
+            .map_err(|e| JupiterError::InvalidResponse(format!("swapTransaction is not base64: {}", e)))?;
+        let transaction = bincode::deserialize::<VersionedTransaction>(&tx_bytes)
+            .map_err(|e| JupiterError::InvalidResponse(format!("swapTransaction is not a transaction: {}", e)))?;
+        Ok(SwapTransaction {
+            transaction,
+            last_valid_block_height: body.last_valid_block_height,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use base64::engine::Engine;
+    use solana_sdk::{
+        hash::Hash,
+        instruction::{AccountMeta, Instruction},
+        message::{v0, VersionedMessage},
+        pubkey::Pubkey,
+        signature::Signature,
+        transaction::VersionedTransaction,
+    };
+
+    use super::{JupiterClient, SwapTransactionResponse};
+    use crate::routes::solana::QuoteResponse;
+
+    const QUOTE: &str = r#"{
+        "inputMint": "So11111111111111111111111111111111111111112",
+        "inAmount": "1000000",
+        "outputMint": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+        "outAmount": "150000",
+        "otherAmountThreshold": "149250",
+        "swapMode": "ExactIn",
+        "slippageBps": 50,
+        "platformFee": null,
+        "priceImpactPct": "0",
+        "routePlan": [],
+        "contextSlot": 1,
+        "timeTaken": 0.01
+    }"#;
+
+    fn swap_message(payer: Pubkey) -> VersionedMessage {
+        let program_id = Pubkey::new_unique();
+        let ix = Instruction::new_with_bytes(program_id, &[1, 2, 3], vec![AccountMeta::new(payer, true)]);
+        VersionedMessage::V0(v0::Message::try_compile(&payer, &[ix], &[], Hash::new_unique()).unwrap())
+    }
+
+    async fn mock_quote() -> HttpResponse {
+        HttpResponse::Ok().content_type("application/json").body(QUOTE)
+    }
+
+    async fn mock_swap(body: web::Json<serde_json::Value>) -> HttpResponse {
+        let payer: Pubkey = body["userPublicKey"].as_str().unwrap().parse().unwrap();
+        let tx = VersionedTransaction { signatures: vec![Signature::default()], message: swap_message(payer) };
+        HttpResponse::Ok().json(SwapTransactionResponse {
+            swap_transaction: base64::engine::general_purpose::STANDARD.encode(bincode::serialize(&tx).unwrap()),
+            last_valid_block_height: 100,
+        })
+    }
+
+    fn start_mock_aggregator() -> String {
+        let server = HttpServer::new(|| {
+            App::new()
+                .route("/quote", web::get().to(mock_quote))
+                .route("/swap", web::post().to(mock_swap))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{}", addr)
+    }
+
+    #[actix_web::test]
+    async fn test_swap_against_mock_aggregator() {
+        let client = JupiterClient::new(start_mock_aggregator());
+        let user = Pubkey::new_unique();
+
+        let quote = client.quote("So11111111111111111111111111111111111111112", "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v", 1_000_000, 50)
+            .await
+            .unwrap();
+        assert_eq!(quote.out_amount, "150000");
+
+        let swap = client.swap_transaction(&quote, &user.to_string()).await.unwrap();
+        assert_eq!(swap.last_valid_block_height, 100);
+        assert!(matches!(swap.transaction.message, VersionedMessage::V0(_)));
+        assert_eq!(swap.transaction.message.static_account_keys()[0], user);
+    }
+
+    #[actix_web::test]
+    async fn test_swap_rejects_garbage_transaction() {
+        let server = HttpServer::new(|| {
+            App::new().route("/swap", web::post().to(|| async {
+                HttpResponse::Ok().json(serde_json::json!({"swapTransaction": "bm90IGEgdHg=", "lastValidBlockHeight": 1}))
+            }))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        let client = JupiterClient::new(format!("http://{}", addr));
+        let quote: QuoteResponse = serde_json::from_str(QUOTE).unwrap();
+        assert!(client.swap_transaction(&quote, &Pubkey::new_unique().to_string()).await.is_err());
+    }
+}
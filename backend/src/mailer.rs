@@ -0,0 +1,162 @@
+//! Outgoing mail. `MAILER=smtp` sends through `SMTP_HOST` (`SMTP_PORT`, `SMTP_USERNAME`,
+//! `SMTP_PASSWORD`), anything else writes the mails to `MAIL_DIR` for local development.
+//! The sender address is `MAIL_FROM`.
+
+use std::{path::PathBuf, sync::{Arc, Mutex}};
+
+use futures::future::BoxFuture;
+use lettre::{
+    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport,
+    Message, Tokio1Executor,
+};
+
+const DEFAULT_MAIL_FROM: &str = "wallet@localhost";
+const DEFAULT_MAIL_DIR: &str = "mail";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum MailerError {
+    Config(String),
+    Send(String),
+}
+
+impl std::fmt::Display for MailerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MailerError::Config(msg) => write!(f, "Invalid mailer configuration: {}", msg),
+            MailerError::Send(msg) => write!(f, "Failed to send mail: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for MailerError {}
+
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: Email) -> BoxFuture<'_, Result<(), MailerError>>;
+}
+
+/// The mailer configured in the environment.
+pub fn from_env() -> Result<Arc<dyn Mailer>, MailerError> {
+    let from = dotenvy::var("MAIL_FROM").unwrap_or_else(|_| DEFAULT_MAIL_FROM.to_string());
+    match dotenvy::var("MAILER").as_deref() {
+        Ok("smtp") => Ok(Arc::new(SmtpMailer::from_env(&from)?)),
+        _ => {
+            let dir = dotenvy::var("MAIL_DIR").unwrap_or_else(|_| DEFAULT_MAIL_DIR.to_string());
+            Ok(Arc::new(FileMailer::new(dir, &from)))
+        }
+    }
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: String,
+}
+
+impl SmtpMailer {
+    pub fn from_env(from: &str) -> Result<Self, MailerError> {
+        let host = dotenvy::var("SMTP_HOST").map_err(|_| MailerError::Config("SMTP_HOST is not set".to_string()))?;
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::relay(&host).map_err(|e| MailerError::Config(e.to_string()))?;
+        if let Ok(port) = dotenvy::var("SMTP_PORT") {
+            builder = builder.port(port.parse().map_err(|_| MailerError::Config(format!("invalid SMTP_PORT {}", port)))?);
+        }
+        if let (Ok(username), Ok(password)) = (dotenvy::var("SMTP_USERNAME"), dotenvy::var("SMTP_PASSWORD")) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        Ok(Self { transport: builder.build(), from: from.to_string() })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: Email) -> BoxFuture<'_, Result<(), MailerError>> {
+        Box::pin(async move {
+            let message = Message::builder()
+                .from(self.from.parse().map_err(|_| MailerError::Config(format!("invalid MAIL_FROM {}", self.from)))?)
+                .to(email.to.parse().map_err(|_| MailerError::Send(format!("invalid recipient {}", email.to)))?)
+                .subject(email.subject)
+                .header(ContentType::TEXT_PLAIN)
+                .body(email.body)
+                .map_err(|e| MailerError::Send(e.to_string()))?;
+            self.transport.send(message).await.map_err(|e| MailerError::Send(e.to_string()))?;
+            Ok(())
+        })
+    }
+}
+
+/// Writes every mail to its own file instead of sending it.
+pub struct FileMailer {
+    dir: PathBuf,
+    from: String,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>, from: &str) -> Self {
+        Self { dir: dir.into(), from: from.to_string() }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: Email) -> BoxFuture<'_, Result<(), MailerError>> {
+        Box::pin(async move {
+            let path = self.dir.join(format!("{}-{}.txt", chrono::Utc::now().format("%Y%m%dT%H%M%S"), uuid::Uuid::new_v4()));
+            let contents = format!("From: {}\nTo: {}\nSubject: {}\n\n{}\n", self.from, email.to, email.subject, email.body);
+            tokio::fs::create_dir_all(&self.dir).await.map_err(|e| MailerError::Send(e.to_string()))?;
+            tokio::fs::write(&path, contents).await.map_err(|e| MailerError::Send(e.to_string()))
+        })
+    }
+}
+
+/// Keeps the mails in memory, for tests.
+#[derive(Default)]
+pub struct MemoryMailer {
+    sent: Mutex<Vec<Email>>,
+}
+
+impl MemoryMailer {
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().map(|sent| sent.clone()).unwrap_or_default()
+    }
+}
+
+impl Mailer for MemoryMailer {
+    fn send(&self, email: Email) -> BoxFuture<'_, Result<(), MailerError>> {
+        Box::pin(async move {
+            self.sent.lock().map_err(|_| MailerError::Send("mailbox poisoned".to_string()))?.push(email);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Email, FileMailer, Mailer, MemoryMailer};
+
+    fn email() -> Email {
+        Email { to: "alice@example.com".to_string(), subject: "Hello".to_string(), body: "Welcome".to_string() }
+    }
+
+    #[actix_web::test]
+    async fn test_memory_mailer() {
+        let mailer = MemoryMailer::default();
+        mailer.send(email()).await.unwrap();
+        assert_eq!(mailer.sent(), vec![email()]);
+    }
+
+    #[actix_web::test]
+    async fn test_file_mailer() {
+        let dir = std::env::temp_dir().join(format!("mailer-{}", uuid::Uuid::new_v4()));
+        FileMailer::new(&dir, "wallet@localhost").send(email()).await.unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let contents = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert!(contents.contains("To: alice@example.com"));
+        assert!(contents.contains("Welcome"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
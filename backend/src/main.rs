@@ -5,35 +5,105 @@ use actix_web::{web::Data, App, HttpServer};
 mod routes;
 use routes::*;
 use store::Store;
+use topology::{identity::ServiceIdentity, Topology};
 mod auth;
+mod jupiter;
+mod mailer;
 mod middleware;
+mod mpc;
+mod totp;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let s = match Store::new().await {
-        Ok(store) => store.backend,
+        Ok(store) => store,
         Err(e) => {
             eprintln!("Failed to initialize the store: {}", e);
             std::process::exit(1);
         }
     };
+    let topology = match Topology::load() {
+        Ok(topology) => topology,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let bind_address = match topology.backend().and_then(|party| party.bind_address()) {
+        Ok(address) => address,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // Signs the backend's requests to the coordinator
+    let identity = match topology.backend().map_err(Into::into).and_then(|party| ServiceIdentity::load(&topology, &party.id)) {
+        Ok(identity) => Data::new(identity),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let mailer = match mailer::from_env() {
+        Ok(mailer) => Data::from(mailer),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let topology = Data::new(topology);
     let arced_s = Arc::new(Mutex::new(s));
+    spawn_link_refunder(arced_s.clone(), topology.clone(), identity.clone());
+    spawn_transaction_tracker(arced_s.clone(), topology.clone(), identity.clone());
     HttpServer::new(move || {
         App::new()
             .service(sign_up)  
             .service(sign_in)
+            .service(refresh)
+            .service(verify_email)
+            .service(forgot_password)
+            .service(reset_password)
+            .service(get_link)
+            .service(claim_link)
             .service(
                 actix_web::web::scope("/api")
                     .wrap(middleware::AuthMiddleware)
                     .service(get_user)
                     .service(quote)
                     .service(swap)
+                    .service(transfer_token)
+                    .service(get_nonce)
+                    .service(create_nonce)
+                    .service(simulate)
                     .service(sol_balance)
                     .service(token_balance)
+                    .service(list_transactions)
+                    .service(get_transaction)
+                    .service(request_export)
+                    .service(list_exports)
+                    .service(cancel_export)
+                    .service(release_export)
+                    .service(logout)
+                    .service(list_sessions)
+                    .service(revoke_session)
+                    .service(enroll_totp)
+                    .service(confirm_totp)
+                    .service(disable_totp)
+                    .service(resend_verification)
+                    .service(change_password)
+                    .service(create_link)
+                    .service(list_links)
+                    .service(create_campaign)
+                    .service(list_campaigns)
+                    .service(export_campaign)
+                    .service(get_campaign)
             )
             .app_data(Data::new(arced_s.clone()))
+            .app_data(topology.clone())
+            .app_data(identity.clone())
+            .app_data(mailer.clone())
     })
-    .bind("127.0.0.1:3000")?
+    .bind(bind_address)?
     .run()
     .await
 }
\ No newline at end of file
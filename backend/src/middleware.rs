@@ -1,10 +1,12 @@
 use actix_web::{
     body::BoxBody,
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpResponse,
+    web::Data,
+    Error, HttpMessage, HttpResponse,
 };
 use futures::future::{ok, Ready, LocalBoxFuture};
-use std::rc::Rc;
+use std::{rc::Rc, sync::{Arc, Mutex}};
+use store::Store;
 
 use crate::auth::verify_jwt;
 
@@ -78,6 +80,14 @@ where
                                 let srv_resp = req.into_response(response.map_into_boxed_body());
                                 return Ok(srv_resp);
                             }
+                            if is_revoked(&req, &_payload.jti, &_payload.sid).await {
+                                let response = HttpResponse::Unauthorized()
+                                    .json(serde_json::json!({"error": "Token has been revoked"}));
+                                let srv_resp = req.into_response(response.map_into_boxed_body());
+                                return Ok(srv_resp);
+                            }
+                            // Handlers take the acting user from here, never from the request body
+                            req.extensions_mut().insert(_payload);
                             service.call(req).await
                         }
                         Err(_) => {
@@ -106,3 +116,22 @@ where
         })
     }
 }
+
+/// Whether the token or its session was revoked by a logout, a session revoke or a password
+/// change. If that can't be checked the token is treated as revoked.
+async fn is_revoked(req: &ServiceRequest, jti: &str, session_id: &str) -> bool {
+    let Some(store) = req.app_data::<Data<Arc<Mutex<Store>>>>() else {
+        return true;
+    };
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return true,
+    };
+    match locked_store.is_token_revoked(jti, session_id).await {
+        Ok(revoked) => revoked,
+        Err(e) => {
+            eprintln!("Failed to check token revocation: {}", e);
+            true
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use base64::engine::Engine;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use solana_sdk::message::VersionedMessage;
+use topology::identity::ServiceIdentity;
+
+/// How the coordinator prices a transaction it builds, its own definition so both ends agree on the JSON.
+pub use ::mpc::fee::FeeStrategy;
+
+/// Calls to the coordinator made for one user. Each call gets a fresh service token, the
+/// coordinator accepts every token only once.
+pub struct ServiceToken<'a> {
+    identity: &'a ServiceIdentity,
+    coordinator_id: &'a str,
+    user_id: &'a str,
+}
+
+impl<'a> ServiceToken<'a> {
+    pub fn new(identity: &'a ServiceIdentity, coordinator_id: &'a str, user_id: &'a str) -> Self {
+        Self { identity, coordinator_id, user_id }
+    }
+}
+
+/// POST `input` to one of the coordinator's endpoints.
+async fn call<I: Serialize, O: DeserializeOwned>(coordinator_url: &str, path: &str, token: &ServiceToken<'_>, input: &I) -> Result<O, String> {
+    let token = token.identity
+        .token(token.coordinator_id, token.user_id)
+        .map_err(|e| format!("Error creating service token: {}", e))?;
+    let response = reqwest::Client::new()
+        .post(format!("{}/{}", coordinator_url, path))
+        .json(input)
+        .bearer_auth(&token)
+        .send()
+        .await
+        .map_err(|e| format!("Error sending request to {}: {:?}", path, e))?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("Failed to send data to {}: {:?} {}", path, status, body));
+    }
+    response.json::<O>()
+        .await
+        .map_err(|_| format!("Failed to read response body from {}", path))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SignMessageInput {
+    pub user_id: String,
+    pub message: String,
+    pub last_valid_block_height: u64,
+}
+
+/// How one account's balance would move, in lamports or in base units of `mint`.
+#[derive(Serialize, Deserialize)]
+pub struct BalanceChange {
+    pub account: String,
+    pub mint: Option<String>,
+    pub pre: u64,
+    pub post: u64,
+}
+
+/// The coordinator's dry run of a transaction before it is signed.
+#[derive(Serialize, Deserialize)]
+pub struct Simulation {
+    /// Why the transaction would fail, `None` if it would succeed
+    pub err: Option<serde_json::Value>,
+    pub logs: Vec<String>,
+    pub units_consumed: Option<u64>,
+    pub balance_changes: Vec<BalanceChange>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BroadcastResponse {
+    pub signature: String,
+    /// `None` if a durable nonce transaction didn't land while the coordinator waited, it still can
+    pub confirmation_status: Option<String>,
+    /// Signature and priority fees paid by the wallet
+    pub fee_lamports: u64,
+    pub simulation: Simulation,
+    /// The wallet's next durable nonce, if the transaction used one and landed
+    pub nonce: Option<String>,
+    /// The durable nonce the transaction was signed over, it can land until that nonce advances
+    pub durable_nonce: Option<String>,
+}
+
+/// Have the coordinator run the MuSig2 rounds with the share servers for an arbitrary
+/// message paid by the user's aggregated key. The coordinator submits the signed transaction
+/// and waits until it is confirmed or `last_valid_block_height` has passed.
+pub async fn sign_message(coordinator_url: &str, token: &ServiceToken<'_>, user_id: &str, message: &VersionedMessage, last_valid_block_height: u64) -> Result<BroadcastResponse, String> {
+    let message_bytes = bincode::serialize(message)
+        .map_err(|e| format!("Failed to serialize message: {:?}", e))?;
+    let input = SignMessageInput {
+        user_id: user_id.to_string(),
+        message: base64::engine::general_purpose::STANDARD.encode(message_bytes),
+        last_valid_block_height,
+    };
+    call(coordinator_url, "sign-message", token, &input).await
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SimulateInput {
+    pub user_id: String,
+    pub message: String,
+}
+
+/// Dry run a message paid by the user's wallet without signing it. A transaction that would
+/// fail comes back with its `err` set rather than as an error.
+pub async fn simulate(coordinator_url: &str, token: &ServiceToken<'_>, user_id: &str, message: &VersionedMessage) -> Result<Simulation, String> {
+    let message_bytes = bincode::serialize(message)
+        .map_err(|e| format!("Failed to serialize message: {:?}", e))?;
+    let input = SimulateInput {
+        user_id: user_id.to_string(),
+        message: base64::engine::general_purpose::STANDARD.encode(message_bytes),
+    };
+    call(coordinator_url, "simulate", token, &input).await
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GenerateInput {
+    pub user_id: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GenerateOutput {
+    pub pubkey: String,
+}
+
+/// Run key generation for a wallet that isn't tied to a user account, like a claim link's.
+/// `wallet_id` takes the place of the user id on the share servers.
+pub async fn generate_wallet(coordinator_url: &str, token: &ServiceToken<'_>, wallet_id: &str) -> Result<String, String> {
+    let input = GenerateInput { user_id: wallet_id.to_string() };
+    let output: GenerateOutput = call(coordinator_url, "generate", token, &input).await?;
+    Ok(output.pubkey)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TransferInput {
+    pub user_id: String,
+    pub amount: f64,
+    pub to: String,
+    pub memo: Option<String>,
+    pub lamports: Option<u64>,
+}
+
+pub async fn transfer_lamports(coordinator_url: &str, token: &ServiceToken<'_>, user_id: &str, to: &str, lamports: u64) -> Result<BroadcastResponse, String> {
+    let input = TransferInput { user_id: user_id.to_string(), amount: 0.0, to: to.to_string(), memo: None, lamports: Some(lamports) };
+    call(coordinator_url, "transfer", token, &input).await
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TransferTokenInput {
+    pub user_id: String,
+    pub mint: String,
+    pub to: String,
+    pub amount: u64,
+    pub memo: Option<String>,
+    pub fee: FeeStrategy,
+    /// Sign over the wallet's durable nonce instead of a recent blockhash
+    pub durable_nonce: bool,
+}
+
+/// Send `amount` base units of `mint` to `to`'s associated token account, creating it if needed.
+pub async fn transfer_token(coordinator_url: &str, token: &ServiceToken<'_>, input: &TransferTokenInput) -> Result<BroadcastResponse, String> {
+    call(coordinator_url, "transfer-token", token, input).await
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BatchTransferInput {
+    pub user_id: String,
+    pub mint: Option<String>,
+    pub transfers: Vec<BatchTransferItem>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BatchTransferItem {
+    pub to: String,
+    pub lamports: u64,
+    /// Base units of the batch's mint
+    pub amount: u64,
+}
+
+/// Pay every recipient in one transaction, the coordinator refuses batches that don't fit.
+pub async fn transfer_batch(coordinator_url: &str, token: &ServiceToken<'_>, user_id: &str, mint: Option<&str>, transfers: Vec<BatchTransferItem>) -> Result<BroadcastResponse, String> {
+    let input = BatchTransferInput { user_id: user_id.to_string(), mint: mint.map(str::to_string), transfers };
+    call(coordinator_url, "transfer-batch", token, &input).await
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SweepInput {
+    pub user_id: String,
+    pub to: String,
+    pub mint: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SweepResponse {
+    /// `None` if there was nothing to sweep
+    pub signature: Option<String>,
+    pub confirmation_status: Option<String>,
+    pub amount: u64,
+    pub fee_lamports: u64,
+}
+
+/// Empty the wallet's SOL, or its balance of `mint`, into `to`. Token sweeps leave the SOL behind.
+pub async fn sweep(coordinator_url: &str, token: &ServiceToken<'_>, user_id: &str, to: &str, mint: Option<&str>) -> Result<SweepResponse, String> {
+    let input = SweepInput { user_id: user_id.to_string(), to: to.to_string(), mint: mint.map(str::to_string) };
+    call(coordinator_url, "sweep", token, &input).await
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SignatureStatusInput {
+    pub user_id: String,
+    pub signature: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SignatureStatus {
+    /// `processed`, `confirmed` or `finalized`, `None` while the cluster has not seen the transaction
+    pub confirmation_status: Option<String>,
+    /// Why the transaction failed on chain
+    pub error: Option<String>,
+}
+
+/// Look up a transaction the user's wallet signed.
+pub async fn signature_status(coordinator_url: &str, token: &ServiceToken<'_>, user_id: &str, signature: &str) -> Result<SignatureStatus, String> {
+    let input = SignatureStatusInput { user_id: user_id.to_string(), signature: signature.to_string() };
+    call(coordinator_url, "signature-status", token, &input).await
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NonceInput {
+    pub user_id: String,
+    pub fee: FeeStrategy,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NonceResponse {
+    pub address: String,
+    /// `None` while the account doesn't exist
+    pub nonce: Option<String>,
+    /// Transaction that created the account, if this request did
+    pub signature: Option<String>,
+    pub confirmation_status: Option<String>,
+    /// Fees paid for creating the account
+    pub fee_lamports: u64,
+}
+
+/// Where the user's durable nonce account is and its current nonce, read from the chain.
+pub async fn get_nonce(coordinator_url: &str, token: &ServiceToken<'_>, user_id: &str) -> Result<NonceResponse, String> {
+    let input = NonceInput { user_id: user_id.to_string(), fee: FeeStrategy::None };
+    call(coordinator_url, "nonce", token, &input).await
+}
+
+/// Create the user's durable nonce account with the wallet as payer and authority. An existing
+/// account is returned as is.
+pub async fn create_nonce(coordinator_url: &str, token: &ServiceToken<'_>, user_id: &str, fee: FeeStrategy) -> Result<NonceResponse, String> {
+    let input = NonceInput { user_id: user_id.to_string(), fee };
+    call(coordinator_url, "nonce/create", token, &input).await
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportInput {
+    pub export_id: String,
+    pub user_id: String,
+    pub recipient: String,
+}
+
+/// The shares of a wallet, each sealed to the recipient key by its share server.
+#[derive(Serialize, Deserialize)]
+pub struct ExportShares {
+    pub shares: Vec<serde_json::Value>,
+    pub failures: Vec<String>,
+    /// False if too few share servers released their share, `shares` is empty then
+    pub complete: bool,
+}
+
+/// Have the coordinator collect the user's key shares for an export request.
+pub async fn export_wallet(coordinator_url: &str, token: &ServiceToken<'_>, export_id: &str, user_id: &str, recipient: &str) -> Result<ExportShares, String> {
+    let input = ExportInput {
+        export_id: export_id.to_string(),
+        user_id: user_id.to_string(),
+        recipient: recipient.to_string(),
+    };
+    call(coordinator_url, "export", token, &input).await
+}
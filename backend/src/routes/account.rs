@@ -0,0 +1,200 @@
+use std::sync::{Arc, Mutex};
+
+use actix_web::{web, HttpResponse, Result};
+use serde::Deserialize;
+use store::{email_token::{EmailTokenError, EmailTokenPurpose}, user::validate_password, Store};
+use topology::Topology;
+
+use crate::{auth::{create_opaque_token, Payload}, mailer::{Email, Mailer}};
+
+/// Verification links work for two days, reset links for an hour.
+const VERIFY_EMAIL_TTL_HOURS: i64 = 48;
+const RESET_PASSWORD_TTL_HOURS: i64 = 1;
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+fn email_token_error_response(e: EmailTokenError) -> HttpResponse {
+    match e {
+        EmailTokenError::InvalidToken => HttpResponse::BadRequest().body(e.to_string()),
+        EmailTokenError::DatabaseError(_) => {
+            eprintln!("Email token error: {}", e);
+            HttpResponse::InternalServerError().body("Failed to check token")
+        }
+    }
+}
+
+/// Where the links in mails point to, `PUBLIC_URL` or the backend's URL in the topology.
+pub fn public_url(topology: &Topology) -> String {
+    dotenvy::var("PUBLIC_URL")
+        .ok()
+        .or_else(|| topology.backend().ok().map(|party| party.url.clone()))
+        .unwrap_or_default()
+}
+
+/// Create a mailed token for `user_id` and the mail carrying it. The mail is sent by the caller,
+/// after the store lock is released.
+async fn token_email(
+    store: &Store,
+    topology: &Topology,
+    user_id: &str,
+    to: &str,
+    purpose: EmailTokenPurpose,
+) -> Result<Email, EmailTokenError> {
+    let token = create_opaque_token();
+    let (ttl, subject, path) = match purpose {
+        EmailTokenPurpose::VerifyEmail => (VERIFY_EMAIL_TTL_HOURS, "Confirm your email address", "verify-email"),
+        EmailTokenPurpose::ResetPassword => (RESET_PASSWORD_TTL_HOURS, "Reset your password", "reset-password"),
+    };
+    store.create_email_token(user_id, purpose, &token, chrono::Duration::hours(ttl)).await?;
+
+    let link = format!("{}/{}?token={}", public_url(topology), path, token);
+    let body = match purpose {
+        EmailTokenPurpose::VerifyEmail => format!("Open this link to confirm your email address:\n\n{}\n", link),
+        EmailTokenPurpose::ResetPassword => format!(
+            "Open this link within an hour to choose a new password:\n\n{}\n\nIf you didn't ask for this, ignore this mail.\n",
+            link
+        ),
+    };
+    Ok(Email { to: to.to_string(), subject: subject.to_string(), body })
+}
+
+pub async fn verification_email(store: &Store, topology: &Topology, user_id: &str, to: &str) -> Result<Email, EmailTokenError> {
+    token_email(store, topology, user_id, to, EmailTokenPurpose::VerifyEmail).await
+}
+
+/// A failed mail doesn't fail the request, the user can ask for another one.
+pub async fn send_email(mailer: &dyn Mailer, email: Email) {
+    if let Err(e) = mailer.send(email).await {
+        eprintln!("{}", e);
+    }
+}
+
+#[actix_web::post("/verify-email")]
+pub async fn verify_email(req: web::Json<TokenRequest>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    let user_id = match locked_store.consume_email_token(EmailTokenPurpose::VerifyEmail, &req.token).await {
+        Ok(user_id) => user_id,
+        Err(e) => return Ok(email_token_error_response(e)),
+    };
+    match locked_store.mark_email_verified(&user_id).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(email_token_error_response(e)),
+    }
+}
+
+/// Mail a fresh verification link to the signed in user.
+#[actix_web::post("/resend-verification")]
+pub async fn resend_verification(
+    auth: Payload,
+    store: web::Data<Arc<Mutex<Store>>>,
+    topology: web::Data<Topology>,
+    mailer: web::Data<dyn Mailer>,
+) -> Result<HttpResponse> {
+    let email = {
+        let locked_store = match store.lock() {
+            Ok(locked) => locked,
+            Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+        };
+        let user = match locked_store.get_user_by_id(auth.user_id.clone()).await {
+            Ok(user) => user,
+            Err(err) => return Ok(HttpResponse::BadRequest().body(err.to_string())),
+        };
+        match verification_email(&locked_store, &topology, &user.id, &user.email).await {
+            Ok(email) => email,
+            Err(e) => return Ok(email_token_error_response(e)),
+        }
+    };
+    send_email(mailer.get_ref(), email).await;
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Mail a reset link. Answers the same whether or not the address belongs to a user.
+#[actix_web::post("/forgot-password")]
+pub async fn forgot_password(
+    req: web::Json<ForgotPasswordRequest>,
+    store: web::Data<Arc<Mutex<Store>>>,
+    topology: web::Data<Topology>,
+    mailer: web::Data<dyn Mailer>,
+) -> Result<HttpResponse> {
+    let email = {
+        let locked_store = match store.lock() {
+            Ok(locked) => locked,
+            Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+        };
+        let user_id = match locked_store.find_user_id_by_email(&req.email).await {
+            Ok(Some(user_id)) => user_id,
+            Ok(None) => return Ok(HttpResponse::Accepted().finish()),
+            Err(err) => return Ok(HttpResponse::InternalServerError().body(err.to_string())),
+        };
+        match token_email(&locked_store, &topology, &user_id, &req.email, EmailTokenPurpose::ResetPassword).await {
+            Ok(email) => email,
+            Err(e) => return Ok(email_token_error_response(e)),
+        }
+    };
+    send_email(mailer.get_ref(), email).await;
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Set a new password with a mailed reset token. All sessions of the user end.
+#[actix_web::post("/reset-password")]
+pub async fn reset_password(req: web::Json<ResetPasswordRequest>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    // Checked before the token is used up
+    if let Err(err) = validate_password(&req.password) {
+        return Ok(HttpResponse::BadRequest().body(err.to_string()));
+    }
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    let user_id = match locked_store.consume_email_token(EmailTokenPurpose::ResetPassword, &req.token).await {
+        Ok(user_id) => user_id,
+        Err(e) => return Ok(email_token_error_response(e)),
+    };
+    // Getting the mail proves the address too
+    if let Err(e) = locked_store.mark_email_verified(&user_id).await {
+        return Ok(email_token_error_response(e));
+    }
+    match locked_store.change_password(&user_id, &req.password).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(err) => Ok(HttpResponse::BadRequest().body(err.to_string())),
+    }
+}
+
+/// Change the password of the signed in user. All sessions end, this one included.
+#[actix_web::post("/password")]
+pub async fn change_password(auth: Payload, req: web::Json<ChangePasswordRequest>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    if let Err(err) = locked_store.verify_password(auth.user_id.clone(), req.current_password.clone()).await {
+        return Ok(HttpResponse::Unauthorized().body(err.to_string()));
+    }
+    match locked_store.change_password(&auth.user_id, &req.new_password).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(err) => Ok(HttpResponse::BadRequest().body(err.to_string())),
+    }
+}
@@ -0,0 +1,386 @@
+use std::{collections::BTreeMap, str::FromStr, sync::{Arc, Mutex}};
+
+use actix_web::{web, HttpResponse, Result};
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use store::{
+    campaign::{CampaignError, LinkCampaign, NewLinkCampaign},
+    link::{ClaimLink, NewClaimLink},
+    transaction::TransactionKind,
+    Store,
+};
+use topology::{identity::ServiceIdentity, Party, Topology};
+
+use crate::{auth::{create_opaque_token, Payload}, mpc::{self, BatchTransferItem, ServiceToken}};
+
+use super::{
+    link::{
+        invalid_amount_response, invalid_ttl_response, link_error_response, link_ttl, link_url, new_link_wallet, MAX_LINK_AMOUNT,
+        SOL_LINK_RESERVE_LAMPORTS, TOKEN_LINK_RESERVE_LAMPORTS,
+    },
+    transaction::tracked,
+    two_factor::require_step_up,
+};
+
+const MAX_CAMPAIGN_LINKS: u32 = 500;
+/// Links funded per transaction, token links need an account and two transfers each.
+const SOL_LINKS_PER_BATCH: usize = 16;
+const TOKEN_LINKS_PER_BATCH: usize = 4;
+/// Key generations of link wallets running at once
+const WALLET_GENERATION_CONCURRENCY: usize = 8;
+
+#[derive(Deserialize)]
+pub struct CreateCampaignRequest {
+    pub name: String,
+    pub link_count: u32,
+    /// Per link, lamports or base units of `mint`
+    pub amount: u64,
+    /// SPL token mint, SOL if missing
+    pub mint: Option<String>,
+    pub expires_in_seconds: Option<i64>,
+    /// Current TOTP code, required if the user enabled two-factor authentication
+    pub totp_code: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CampaignResponse {
+    pub id: String,
+    pub name: String,
+    pub mint: Option<String>,
+    pub amount: u64,
+    pub link_count: u32,
+    pub created_at: String,
+    pub expires_at: String,
+    /// Number of links per status
+    pub statuses: BTreeMap<String, u32>,
+}
+
+impl CampaignResponse {
+    fn new(campaign: LinkCampaign, links: &[ClaimLink]) -> Self {
+        let now = Utc::now();
+        let mut statuses = BTreeMap::new();
+        for link in links {
+            *statuses.entry(link.status(now).to_string()).or_insert(0) += 1;
+        }
+        Self {
+            id: campaign.id,
+            name: campaign.name,
+            mint: campaign.mint,
+            amount: campaign.amount,
+            link_count: campaign.link_count,
+            created_at: campaign.created_at.to_rfc3339(),
+            expires_at: campaign.expires_at.to_rfc3339(),
+            statuses,
+        }
+    }
+}
+
+fn campaign_error_response(e: CampaignError) -> HttpResponse {
+    match e {
+        CampaignError::NotFound => HttpResponse::NotFound().body(e.to_string()),
+        CampaignError::DatabaseError(_) => {
+            eprintln!("Campaign error: {}", e);
+            HttpResponse::InternalServerError().body("Failed to update campaign")
+        }
+    }
+}
+
+/// Quote a CSV field if it needs it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv_row(fields: &[&str]) -> String {
+    let mut row = fields.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(",");
+    row.push_str("\r\n");
+    row
+}
+
+fn csv_response(csv: String, filename: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/csv")
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", filename)))
+        .body(csv)
+}
+
+/// Fund the links from the creator's wallet, as many per transaction as fit. A failed batch
+/// leaves its links unfunded with the error, the other batches go ahead. A link whose outcome
+/// can't be stored is returned as it was, its secret still has to reach the creator.
+async fn fund_campaign(store: &Mutex<Store>, coordinator_url: &str, token: &ServiceToken<'_>, creator_id: &str, mint: Option<&str>, links: &[ClaimLink]) -> Vec<ClaimLink> {
+    let batch_size = if mint.is_some() { TOKEN_LINKS_PER_BATCH } else { SOL_LINKS_PER_BATCH };
+    let mut funded = Vec::with_capacity(links.len());
+    for batch in links.chunks(batch_size) {
+        let transfers = batch
+            .iter()
+            .map(|link| match mint {
+                Some(_) => BatchTransferItem { to: link.public_key.clone(), lamports: TOKEN_LINK_RESERVE_LAMPORTS, amount: link.amount },
+                None => BatchTransferItem { to: link.public_key.clone(), lamports: link.amount + SOL_LINK_RESERVE_LAMPORTS, amount: 0 },
+            })
+            .collect();
+        let broadcast = tracked(store, creator_id, TransactionKind::CampaignFunding, mpc::transfer_batch(coordinator_url, token, creator_id, mint, transfers)).await;
+        let result = match &broadcast {
+            Ok(broadcast) => Ok(broadcast.signature.as_str()),
+            Err(error_message) => {
+                eprintln!("Failed to fund campaign batch: {}", error_message);
+                Err(error_message.as_str())
+            }
+        };
+
+        let Ok(locked_store) = store.lock() else {
+            eprintln!("Failed to lock store, funding of {} campaign links not recorded", batch.len());
+            funded.extend_from_slice(batch);
+            continue;
+        };
+        for link in batch {
+            match locked_store.finish_link_funding(&link.id, result).await {
+                Ok(link) => funded.push(link),
+                Err(e) => {
+                    eprintln!("Failed to record funding of link {}: {}", link.id, e);
+                    funded.push(link.clone());
+                }
+            }
+        }
+    }
+    funded
+}
+
+/// Create the wallets and links of a campaign and fund them. Fails only while nothing was
+/// created yet, from the first link on the secrets are always returned.
+async fn launch_campaign(
+    store: Arc<Mutex<Store>>,
+    identity: web::Data<ServiceIdentity>,
+    coordinator: Party,
+    creator_id: String,
+    req: CreateCampaignRequest,
+    expires_at: DateTime<Utc>,
+) -> Result<(String, Vec<ClaimLink>, BTreeMap<String, String>), HttpResponse> {
+    // All wallets first, so a failing key generation doesn't leave half a campaign behind
+    let wallets: Vec<(String, String)> = match stream::iter(0..req.link_count)
+        .map(|_| new_link_wallet(&coordinator, &identity))
+        .buffered(WALLET_GENERATION_CONCURRENCY)
+        .try_collect()
+        .await
+    {
+        Ok(wallets) => wallets,
+        Err(error_message) => return Err(HttpResponse::BadGateway().body(error_message)),
+    };
+
+    let campaign_id = uuid::Uuid::new_v4().to_string();
+    let mut secrets = BTreeMap::new();
+    let links = {
+        let locked_store = match store.lock() {
+            Ok(locked) => locked,
+            Err(_) => return Err(HttpResponse::InternalServerError().body("Failed to lock store")),
+        };
+        let new_campaign = NewLinkCampaign {
+            id: &campaign_id,
+            creator_id: &creator_id,
+            name: &req.name,
+            mint: req.mint.as_deref(),
+            amount: req.amount,
+            link_count: req.link_count,
+            expires_at,
+        };
+        if let Err(e) = locked_store.create_link_campaign(new_campaign).await {
+            return Err(campaign_error_response(e));
+        }
+        let mut links = Vec::with_capacity(wallets.len());
+        for (link_id, public_key) in &wallets {
+            let secret = create_opaque_token();
+            let new_link = NewClaimLink {
+                id: link_id,
+                creator_id: &creator_id,
+                campaign_id: Some(&campaign_id),
+                public_key,
+                mint: req.mint.as_deref(),
+                amount: req.amount,
+                secret: &secret,
+                expires_at,
+            };
+            // A link that isn't stored is never funded, the campaign just ends up smaller
+            match locked_store.create_claim_link(new_link).await {
+                Ok(link) => {
+                    links.push(link);
+                    secrets.insert(link_id.clone(), secret);
+                }
+                Err(e) => eprintln!("Failed to create link {} of campaign {}: {}", link_id, campaign_id, e),
+            }
+        }
+        links
+    };
+    if links.is_empty() {
+        return Err(HttpResponse::InternalServerError().body("Failed to create links"));
+    }
+
+    let token = ServiceToken::new(&identity, &coordinator.id, &creator_id);
+    let links = fund_campaign(&store, &coordinator.url, &token, &creator_id, req.mint.as_deref(), &links).await;
+    Ok((campaign_id, links, secrets))
+}
+
+/// Mint and fund a batch of claim links. The response is the CSV with every link's URL, the
+/// secrets aren't stored so this is the only time they can be handed out.
+#[actix_web::post("/campaigns")]
+pub async fn create_campaign(
+    auth: Payload,
+    req: web::Json<CreateCampaignRequest>,
+    store: web::Data<Arc<Mutex<Store>>>,
+    topology: web::Data<Topology>,
+    identity: web::Data<ServiceIdentity>,
+) -> Result<HttpResponse> {
+    if req.link_count == 0 || req.link_count > MAX_CAMPAIGN_LINKS {
+        return Ok(HttpResponse::BadRequest().body(format!("link_count must be between 1 and {}", MAX_CAMPAIGN_LINKS)));
+    }
+    if req.amount == 0 || req.amount > MAX_LINK_AMOUNT {
+        return Ok(invalid_amount_response());
+    }
+    if req.mint.as_deref().is_some_and(|mint| Pubkey::from_str(mint).is_err()) {
+        return Ok(HttpResponse::BadRequest().body("Invalid mint public key"));
+    }
+    let Some(ttl) = link_ttl(req.expires_in_seconds) else {
+        return Ok(invalid_ttl_response());
+    };
+    let coordinator = match topology.coordinator() {
+        Ok(party) => party.clone(),
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+
+    {
+        let locked_store = match store.lock() {
+            Ok(locked) => locked,
+            Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+        };
+        if let Err(response) = require_step_up(&locked_store, &auth.user_id, req.totp_code.as_deref()).await {
+            return Ok(response);
+        }
+    }
+
+    // Its own task, a client that disconnects must not stop the funding half way
+    let launched = actix_web::rt::spawn(launch_campaign(
+        store.get_ref().clone(),
+        identity.clone(),
+        coordinator,
+        auth.user_id.clone(),
+        req.into_inner(),
+        Utc::now() + ttl,
+    ))
+    .await;
+    let (campaign_id, links, secrets) = match launched {
+        Ok(Ok(launched)) => launched,
+        Ok(Err(response)) => return Ok(response),
+        Err(e) => {
+            eprintln!("Campaign task failed: {}", e);
+            return Ok(HttpResponse::InternalServerError().body("Failed to create campaign"));
+        }
+    };
+
+    let now = Utc::now();
+    let mut csv = csv_row(&["link_id", "url", "public_key", "mint", "amount", "status", "expires_at"]);
+    for link in &links {
+        csv.push_str(&csv_row(&[
+            &link.id,
+            &link_url(&topology, &link.id, &secrets[&link.id]),
+            &link.public_key,
+            link.mint.as_deref().unwrap_or_default(),
+            &link.amount.to_string(),
+            link.status(now),
+            &link.expires_at.to_rfc3339(),
+        ]));
+    }
+    Ok(csv_response(csv, &format!("campaign-{}-links.csv", campaign_id)))
+}
+
+#[actix_web::get("/campaigns")]
+pub async fn list_campaigns(auth: Payload, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    let campaigns = match locked_store.list_link_campaigns(&auth.user_id).await {
+        Ok(campaigns) => campaigns,
+        Err(e) => return Ok(campaign_error_response(e)),
+    };
+    let mut responses = Vec::with_capacity(campaigns.len());
+    for campaign in campaigns {
+        match locked_store.list_campaign_links(&campaign.id).await {
+            Ok(links) => responses.push(CampaignResponse::new(campaign, &links)),
+            Err(e) => return Ok(link_error_response(e)),
+        }
+    }
+    Ok(HttpResponse::Ok().json(responses))
+}
+
+#[actix_web::get("/campaigns/{id}")]
+pub async fn get_campaign(auth: Payload, path: web::Path<String>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    let campaign = match locked_store.get_link_campaign(&path.into_inner(), &auth.user_id).await {
+        Ok(campaign) => campaign,
+        Err(e) => return Ok(campaign_error_response(e)),
+    };
+    match locked_store.list_campaign_links(&campaign.id).await {
+        Ok(links) => Ok(HttpResponse::Ok().json(CampaignResponse::new(campaign, &links))),
+        Err(e) => Ok(link_error_response(e)),
+    }
+}
+
+/// Where every link of the campaign stands, as CSV. Without the URLs, those were only in the
+/// response that created the campaign.
+#[actix_web::get("/campaigns/{id}/links.csv")]
+pub async fn export_campaign(auth: Payload, path: web::Path<String>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    let campaign = match locked_store.get_link_campaign(&path.into_inner(), &auth.user_id).await {
+        Ok(campaign) => campaign,
+        Err(e) => return Ok(campaign_error_response(e)),
+    };
+    let links = match locked_store.list_campaign_links(&campaign.id).await {
+        Ok(links) => links,
+        Err(e) => return Ok(link_error_response(e)),
+    };
+
+    let now = Utc::now();
+    let mut csv = csv_row(&[
+        "link_id", "public_key", "mint", "amount", "status", "error", "funding_signature", "destination", "claim_signature",
+        "expires_at", "claimed_at", "refunded_at",
+    ]);
+    for link in &links {
+        csv.push_str(&csv_row(&[
+            &link.id,
+            &link.public_key,
+            link.mint.as_deref().unwrap_or_default(),
+            &link.amount.to_string(),
+            link.status(now),
+            link.error.as_deref().unwrap_or_default(),
+            link.funding_signature.as_deref().unwrap_or_default(),
+            link.destination.as_deref().unwrap_or_default(),
+            link.claim_signature.as_deref().unwrap_or_default(),
+            &link.expires_at.to_rfc3339(),
+            &link.claimed_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+            &link.refunded_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+        ]));
+    }
+    Ok(csv_response(csv, &format!("campaign-{}-status.csv", campaign.id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csv_field, csv_row};
+
+    #[test]
+    fn test_csv_quoting() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_row(&["a", "", "b\nc"]), "a,,\"b\nc\"\r\n");
+    }
+}
@@ -0,0 +1,202 @@
+use std::{str::FromStr, sync::{Arc, Mutex}};
+
+use actix_web::{web, HttpResponse, Result};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use store::{export::{ExportError, WalletExport, DEFAULT_EXPORT_DELAY_SECONDS}, Store};
+use topology::{identity::ServiceIdentity, Topology};
+
+use crate::{auth::Payload, mpc::{self, ServiceToken}};
+
+use super::two_factor::require_step_up;
+
+#[derive(Deserialize)]
+pub struct ExportRequest {
+    pub password: String,
+    /// Base58 public key of a keypair the user controls, the shares get encrypted to it
+    pub recipient: String,
+}
+
+#[derive(Deserialize)]
+pub struct ReleaseRequest {
+    pub password: String,
+    /// Current TOTP code, required if the user enabled two-factor authentication
+    pub totp_code: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct WalletExportResponse {
+    pub id: String,
+    pub recipient: String,
+    pub state: String,
+    pub error: Option<String>,
+    pub requested_at: String,
+    pub available_at: String,
+    pub released_at: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ReleaseResponse {
+    pub export: WalletExportResponse,
+    pub public_key: String,
+    /// Sealed shares in the order of the share servers, rebuild the wallet with `mpc::export::reconstruct`
+    pub shares: Vec<serde_json::Value>,
+    pub failures: Vec<String>,
+}
+
+impl From<WalletExport> for WalletExportResponse {
+    fn from(export: WalletExport) -> Self {
+        Self {
+            id: export.id,
+            recipient: export.recipient,
+            state: export.state.as_str().to_string(),
+            error: export.error,
+            requested_at: export.requested_at.to_rfc3339(),
+            available_at: export.available_at.to_rfc3339(),
+            released_at: export.released_at.map(|at| at.to_rfc3339()),
+        }
+    }
+}
+
+fn export_delay() -> chrono::Duration {
+    let seconds = dotenvy::var("WALLET_EXPORT_DELAY_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(DEFAULT_EXPORT_DELAY_SECONDS);
+    chrono::Duration::seconds(seconds)
+}
+
+fn export_error_response(e: ExportError) -> HttpResponse {
+    match e {
+        ExportError::NotFound | ExportError::WrongUser => HttpResponse::NotFound().body(ExportError::NotFound.to_string()),
+        ExportError::NotYetAvailable(_) => HttpResponse::Forbidden().body(e.to_string()),
+        ExportError::ExportExists | ExportError::InvalidState(_) => HttpResponse::Conflict().body(e.to_string()),
+        ExportError::DatabaseError(_) => {
+            eprintln!("Wallet export error: {}", e);
+            HttpResponse::InternalServerError().body("Failed to update wallet export")
+        }
+    }
+}
+
+/// Start exporting the user's wallet. The shares are only released once the delay has passed,
+/// until then the request can be cancelled.
+#[actix_web::post("/wallet/export")]
+pub async fn request_export(auth: Payload, body: web::Json<ExportRequest>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let user_id = auth.user_id;
+    if Pubkey::from_str(&body.recipient).is_err() {
+        return Ok(HttpResponse::BadRequest().body("Invalid recipient public key"));
+    }
+
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    if let Err(err) = locked_store.verify_password(user_id.clone(), body.password.clone()).await {
+        return Ok(HttpResponse::Unauthorized().body(err.to_string()));
+    }
+    match locked_store.create_wallet_export(&user_id, &body.recipient, export_delay()).await {
+        Ok(export) => Ok(HttpResponse::Ok().json(WalletExportResponse::from(export))),
+        Err(e) => Ok(export_error_response(e)),
+    }
+}
+
+#[actix_web::get("/wallet/exports")]
+pub async fn list_exports(auth: Payload, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let user_id = auth.user_id;
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    match locked_store.list_wallet_exports(&user_id).await {
+        Ok(exports) => Ok(HttpResponse::Ok().json(exports.into_iter().map(WalletExportResponse::from).collect::<Vec<_>>())),
+        Err(e) => Ok(export_error_response(e)),
+    }
+}
+
+#[actix_web::delete("/wallet/export/{id}")]
+pub async fn cancel_export(auth: Payload, path: web::Path<String>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let user_id = auth.user_id;
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    match locked_store.cancel_wallet_export(&path.into_inner(), &user_id).await {
+        Ok(export) => Ok(HttpResponse::Ok().json(WalletExportResponse::from(export))),
+        Err(e) => Ok(export_error_response(e)),
+    }
+}
+
+/// Hand out the sealed shares of an export whose delay has passed. The user has to sign in again.
+/// If too few share servers answer nothing is handed out and the export stays pending for a retry.
+#[actix_web::post("/wallet/export/{id}/release")]
+pub async fn release_export(
+    auth: Payload,
+    path: web::Path<String>,
+    body: web::Json<ReleaseRequest>,
+    store: web::Data<Arc<Mutex<Store>>>,
+    topology: web::Data<Topology>,
+    identity: web::Data<ServiceIdentity>,
+) -> Result<HttpResponse> {
+    let user_id = auth.user_id;
+    let coordinator = match topology.coordinator() {
+        Ok(party) => party,
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    let token = ServiceToken::new(&identity, &coordinator.id, &user_id);
+
+    let (user, export) = {
+        let locked_store = match store.lock() {
+            Ok(locked) => locked,
+            Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+        };
+        if let Err(err) = locked_store.verify_password(user_id.clone(), body.password.clone()).await {
+            return Ok(HttpResponse::Unauthorized().body(err.to_string()));
+        }
+        if let Err(response) = require_step_up(&locked_store, &user_id, body.totp_code.as_deref()).await {
+            return Ok(response);
+        }
+        let user = match locked_store.get_user_by_id(user_id.clone()).await {
+            Ok(user) => user,
+            Err(err) => return Ok(HttpResponse::BadRequest().body(err.to_string())),
+        };
+        match locked_store.claim_wallet_export(&path.into_inner(), &user_id).await {
+            Ok(export) => (user, export),
+            Err(e) => return Ok(export_error_response(e)),
+        }
+    };
+
+    let released = mpc::export_wallet(&coordinator.url, &token, &export.id, &user_id, &export.recipient).await;
+
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    let finished = match &released {
+        Ok(released) if !released.complete => {
+            locked_store.reopen_wallet_export(&export.id, &format!("Missing shares: {}", released.failures.join(", "))).await
+        }
+        Ok(_) => locked_store.finish_wallet_export(&export.id, None).await,
+        Err(error_message) => locked_store.finish_wallet_export(&export.id, Some(error_message)).await,
+    };
+    if let Err(e) = finished {
+        eprintln!("Failed to finish wallet export {}: {}", export.id, e);
+    }
+    let export = match locked_store.get_wallet_export(&export.id, &user_id).await {
+        Ok(export) => export,
+        Err(e) => return Ok(export_error_response(e)),
+    };
+
+    match released {
+        Ok(released) if !released.complete => Ok(HttpResponse::ServiceUnavailable().body(format!(
+            "Not enough share servers released their share, try again later: {}",
+            released.failures.join(", ")
+        ))),
+        Ok(released) => Ok(HttpResponse::Ok().json(ReleaseResponse {
+            export: export.into(),
+            public_key: user.public_key,
+            shares: released.shares,
+            failures: released.failures,
+        })),
+        Err(error_message) => Ok(HttpResponse::BadGateway().body(error_message)),
+    }
+}
@@ -0,0 +1,430 @@
+use std::{str::FromStr, sync::{Arc, Mutex}, time::Duration};
+
+use actix_web::{web, HttpResponse, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use store::{link::{ClaimLink, LinkError, NewClaimLink}, transaction::TransactionKind, Store};
+use topology::{identity::ServiceIdentity, Party, Topology};
+
+use crate::{auth::{create_opaque_token, Payload}, mailer::Mailer, mpc::{self, ServiceToken}};
+
+use super::{
+    account::public_url,
+    transaction::tracked,
+    two_factor::require_step_up,
+    user::{create_account, SignUpRequest, SignupOutput},
+};
+
+/// Links expire after 30 days unless asked otherwise, and live at most a year.
+const DEFAULT_LINK_TTL_SECONDS: i64 = 30 * 24 * 60 * 60;
+const MAX_LINK_TTL_SECONDS: i64 = 365 * 24 * 60 * 60;
+/// The link's wallet pays the fees of its claim or refund. A SOL link holds the fee of the one
+/// sweep on top of its amount.
+pub(super) const SOL_LINK_RESERVE_LAMPORTS: u64 = 5_000;
+/// A token link also pays the claimer's token account if it doesn't exist yet, and needs a second
+/// sweep for the SOL left over. Whatever isn't spent goes along with the tokens.
+pub(super) const TOKEN_LINK_RESERVE_LAMPORTS: u64 = 2_500_000;
+/// Amounts are stored as BIGINT, and a SOL link's reserve is added on top when it's funded.
+pub(super) const MAX_LINK_AMOUNT: u64 = i64::MAX as u64 - SOL_LINK_RESERVE_LAMPORTS;
+const LINK_REFUND_INTERVAL: Duration = Duration::from_secs(60);
+const LINK_REFUND_BATCH: i64 = 20;
+
+#[derive(Deserialize)]
+pub struct CreateLinkRequest {
+    /// Lamports, or base units of `mint`
+    pub amount: u64,
+    /// SPL token mint, SOL if missing
+    pub mint: Option<String>,
+    pub expires_in_seconds: Option<i64>,
+    /// Current TOTP code, required if the user enabled two-factor authentication
+    pub totp_code: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ClaimLinkRequest {
+    /// The URL fragment of the link
+    pub secret: String,
+    /// Claim into this address
+    pub destination: Option<String>,
+    /// Or into a new account
+    pub account: Option<SignUpRequest>,
+}
+
+#[derive(Serialize)]
+pub struct ClaimLinkResponse {
+    pub id: String,
+    pub public_key: String,
+    pub mint: Option<String>,
+    pub amount: u64,
+    pub state: String,
+    pub error: Option<String>,
+    pub funding_signature: Option<String>,
+    pub destination: Option<String>,
+    pub claim_signature: Option<String>,
+    pub created_at: String,
+    pub expires_at: String,
+    pub claimed_at: Option<String>,
+    pub refunded_at: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CreateLinkResponse {
+    pub link: ClaimLinkResponse,
+    /// Shown once, it's the only thing needed to claim the funds
+    pub secret: String,
+    pub url: String,
+}
+
+#[derive(Serialize)]
+pub struct ClaimResponse {
+    pub link: ClaimLinkResponse,
+    /// Set for claims into a new account
+    pub account: Option<SignupOutput>,
+}
+
+impl From<ClaimLink> for ClaimLinkResponse {
+    fn from(link: ClaimLink) -> Self {
+        Self {
+            id: link.id,
+            public_key: link.public_key,
+            mint: link.mint,
+            amount: link.amount,
+            state: link.status(Utc::now()).to_string(),
+            error: link.error,
+            funding_signature: link.funding_signature,
+            destination: link.destination,
+            claim_signature: link.claim_signature,
+            created_at: link.created_at.to_rfc3339(),
+            expires_at: link.expires_at.to_rfc3339(),
+            claimed_at: link.claimed_at.map(|at| at.to_rfc3339()),
+            refunded_at: link.refunded_at.map(|at| at.to_rfc3339()),
+        }
+    }
+}
+
+pub(super) fn link_error_response(e: LinkError) -> HttpResponse {
+    match e {
+        LinkError::NotFound => HttpResponse::NotFound().body(e.to_string()),
+        LinkError::InvalidSecret => HttpResponse::Forbidden().body(e.to_string()),
+        LinkError::Expired => HttpResponse::Gone().body(e.to_string()),
+        LinkError::InvalidState(_) => HttpResponse::Conflict().body(e.to_string()),
+        LinkError::DatabaseError(_) => {
+            eprintln!("Claim link error: {}", e);
+            HttpResponse::InternalServerError().body("Failed to update claim link")
+        }
+    }
+}
+
+/// Seconds until new links expire, `None` if the requested lifetime is out of bounds.
+pub(super) fn link_ttl(expires_in_seconds: Option<i64>) -> Option<chrono::Duration> {
+    let ttl = expires_in_seconds.unwrap_or(DEFAULT_LINK_TTL_SECONDS);
+    (ttl > 0 && ttl <= MAX_LINK_TTL_SECONDS).then(|| chrono::Duration::seconds(ttl))
+}
+
+pub(super) fn invalid_amount_response() -> HttpResponse {
+    HttpResponse::BadRequest().body(format!("amount must be between 1 and {}", MAX_LINK_AMOUNT))
+}
+
+pub(super) fn invalid_ttl_response() -> HttpResponse {
+    HttpResponse::BadRequest().body(format!("expires_in_seconds must be between 1 and {}", MAX_LINK_TTL_SECONDS))
+}
+
+/// What gets handed to the recipient, the secret stays in the fragment so it never reaches a server log.
+pub(super) fn link_url(topology: &Topology, link_id: &str, secret: &str) -> String {
+    format!("{}/link/{}#{}", public_url(topology), link_id, secret)
+}
+
+/// Run key generation for a new link's wallet, returns the link id and the wallet's address.
+pub(super) async fn new_link_wallet(coordinator: &Party, identity: &ServiceIdentity) -> Result<(String, String), String> {
+    let link_id = uuid::Uuid::new_v4().to_string();
+    let token = ServiceToken::new(identity, &coordinator.id, &link_id);
+    let public_key = mpc::generate_wallet(&coordinator.url, &token, &link_id).await?;
+    Ok((link_id, public_key))
+}
+
+/// Move the funds from the creator's wallet into the link's. Returns the signature of the
+/// transfer carrying the amount.
+async fn fund_link(store: &Mutex<Store>, coordinator_url: &str, token: &ServiceToken<'_>, creator_id: &str, link: &ClaimLink) -> Result<String, String> {
+    let transfer_lamports = |lamports| {
+        tracked(store, creator_id, TransactionKind::LinkFunding, mpc::transfer_lamports(coordinator_url, token, creator_id, &link.public_key, lamports))
+    };
+    match &link.mint {
+        None => {
+            let broadcast = transfer_lamports(link.amount + SOL_LINK_RESERVE_LAMPORTS).await?;
+            Ok(broadcast.signature)
+        }
+        Some(mint) => {
+            // The fees first, tokens without them would be stuck until the refund fails too
+            transfer_lamports(TOKEN_LINK_RESERVE_LAMPORTS).await?;
+            let input = mpc::TransferTokenInput {
+                user_id: creator_id.to_string(),
+                mint: mint.to_string(),
+                to: link.public_key.clone(),
+                amount: link.amount,
+                memo: None,
+                fee: mpc::FeeStrategy::None,
+                durable_nonce: false,
+            };
+            let broadcast = tracked(store, creator_id, TransactionKind::LinkFunding, mpc::transfer_token(coordinator_url, token, &input)).await?;
+            Ok(broadcast.signature)
+        }
+    }
+}
+
+/// Empty the link's wallet into `to`, tokens first. Returns the signature of the transfer that
+/// moved the link's asset, `None` if the wallet held none of it. The sweeps are recorded as
+/// `kind` for `user_id`.
+async fn sweep_link(
+    store: &Mutex<Store>,
+    coordinator_url: &str,
+    token: &ServiceToken<'_>,
+    link: &ClaimLink,
+    to: &str,
+    user_id: &str,
+    kind: TransactionKind,
+) -> Result<Option<String>, String> {
+    let sweep = |mint| tracked(store, user_id, kind, mpc::sweep(coordinator_url, token, &link.id, to, mint));
+    let Some(mint) = &link.mint else {
+        return Ok(sweep(None).await?.signature);
+    };
+    let swept = sweep(Some(mint.as_str())).await?;
+    // The tokens moved, the leftover SOL must not fail the claim
+    if let Err(e) = sweep(None).await {
+        eprintln!("Failed to sweep remaining SOL of link {}: {}", link.id, e);
+    }
+    Ok(swept.signature)
+}
+
+/// Fund a new claim link from the user's wallet. The response carries the secret, which only
+/// ever lives in the link's URL fragment.
+#[actix_web::post("/links")]
+pub async fn create_link(
+    auth: Payload,
+    req: web::Json<CreateLinkRequest>,
+    store: web::Data<Arc<Mutex<Store>>>,
+    topology: web::Data<Topology>,
+    identity: web::Data<ServiceIdentity>,
+) -> Result<HttpResponse> {
+    if req.amount == 0 || req.amount > MAX_LINK_AMOUNT {
+        return Ok(invalid_amount_response());
+    }
+    if req.mint.as_deref().is_some_and(|mint| Pubkey::from_str(mint).is_err()) {
+        return Ok(HttpResponse::BadRequest().body("Invalid mint public key"));
+    }
+    let Some(ttl) = link_ttl(req.expires_in_seconds) else {
+        return Ok(invalid_ttl_response());
+    };
+    let coordinator = match topology.coordinator() {
+        Ok(party) => party,
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    let creator_token = ServiceToken::new(&identity, &coordinator.id, &auth.user_id);
+
+    {
+        let locked_store = match store.lock() {
+            Ok(locked) => locked,
+            Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+        };
+        if let Err(response) = require_step_up(&locked_store, &auth.user_id, req.totp_code.as_deref()).await {
+            return Ok(response);
+        }
+    }
+
+    let (link_id, public_key) = match new_link_wallet(coordinator, &identity).await {
+        Ok(wallet) => wallet,
+        Err(error_message) => return Ok(HttpResponse::BadGateway().body(error_message)),
+    };
+    let secret = create_opaque_token();
+    let link = {
+        let locked_store = match store.lock() {
+            Ok(locked) => locked,
+            Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+        };
+        let new_link = NewClaimLink {
+            id: &link_id,
+            creator_id: &auth.user_id,
+            campaign_id: None,
+            public_key: &public_key,
+            mint: req.mint.as_deref(),
+            amount: req.amount,
+            secret: &secret,
+            expires_at: Utc::now() + ttl,
+        };
+        match locked_store.create_claim_link(new_link).await {
+            Ok(link) => link,
+            Err(e) => return Ok(link_error_response(e)),
+        }
+    };
+
+    let funded = fund_link(&store, &coordinator.url, &creator_token, &auth.user_id, &link).await;
+
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    let link = match locked_store.finish_link_funding(&link.id, funded.as_deref().map_err(String::as_str)).await {
+        Ok(link) => link,
+        Err(e) => return Ok(link_error_response(e)),
+    };
+    if let Err(error_message) = funded {
+        // Whatever made it into the link's wallet goes back once the link expires
+        return Ok(HttpResponse::BadGateway().body(error_message));
+    }
+    Ok(HttpResponse::Ok().json(CreateLinkResponse {
+        url: link_url(&topology, &link.id, &secret),
+        link: link.into(),
+        secret,
+    }))
+}
+
+#[actix_web::get("/links")]
+pub async fn list_links(auth: Payload, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    match locked_store.list_claim_links(&auth.user_id).await {
+        Ok(links) => Ok(HttpResponse::Ok().json(links.into_iter().map(ClaimLinkResponse::from).collect::<Vec<_>>())),
+        Err(e) => Ok(link_error_response(e)),
+    }
+}
+
+/// What a link holds and whether it can still be claimed, for the claim page.
+#[actix_web::get("/links/{id}")]
+pub async fn get_link(path: web::Path<String>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    match locked_store.get_claim_link(&path.into_inner()).await {
+        Ok(link) => Ok(HttpResponse::Ok().json(ClaimLinkResponse::from(link))),
+        Err(e) => Ok(link_error_response(e)),
+    }
+}
+
+/// Claim a link with its secret, into any address or into a new account. No sign in needed, the
+/// secret is the authorization.
+#[actix_web::post("/links/{id}/claim")]
+pub async fn claim_link(
+    path: web::Path<String>,
+    req: web::Json<ClaimLinkRequest>,
+    store: web::Data<Arc<Mutex<Store>>>,
+    topology: web::Data<Topology>,
+    identity: web::Data<ServiceIdentity>,
+    mailer: web::Data<dyn Mailer>,
+) -> Result<HttpResponse> {
+    let link_id = path.into_inner();
+    match (&req.destination, &req.account) {
+        (Some(destination), None) if Pubkey::from_str(destination).is_ok() => {}
+        (Some(_), None) => return Ok(HttpResponse::BadRequest().body("Invalid destination public key")),
+        (None, Some(_)) => {}
+        _ => return Ok(HttpResponse::BadRequest().body("Give either a destination or an account to create")),
+    }
+    let coordinator = match topology.coordinator() {
+        Ok(party) => party,
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    let token = ServiceToken::new(&identity, &coordinator.id, &link_id);
+
+    let link = {
+        let locked_store = match store.lock() {
+            Ok(locked) => locked,
+            Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+        };
+        match locked_store.begin_link_claim(&link_id, &req.secret).await {
+            Ok(link) => link,
+            Err(e) => return Ok(link_error_response(e)),
+        }
+    };
+
+    // The link is held for this claim from here on, every way out has to finish it
+    let (destination, account) = match (&req.destination, &req.account) {
+        (Some(destination), _) => (destination.clone(), None),
+        (None, Some(account)) => match create_account(&store, &topology, &identity, mailer.get_ref(), account).await {
+            Ok(output) => (output.public_key.clone(), Some(output)),
+            Err(response) => {
+                finish_claim(&store, &link.id, "", Err("Failed to create account")).await;
+                return Ok(response);
+            }
+        },
+        (None, None) => unreachable!("checked above"),
+    };
+    let claimed_by = account.as_ref().map(|account| account.user_id.clone());
+
+    let recipient_id = claimed_by.as_deref().unwrap_or(&link.creator_id);
+    let swept = sweep_link(&store, &coordinator.url, &token, &link, &destination, recipient_id, TransactionKind::LinkClaim).await;
+    let result = match &swept {
+        Ok(Some(signature)) => Ok(signature.as_str()),
+        Ok(None) => Err("The link's wallet is empty"),
+        Err(error_message) => Err(error_message.as_str()),
+    };
+    let link = {
+        let locked_store = match store.lock() {
+            Ok(locked) => locked,
+            Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+        };
+        match locked_store.finish_link_claim(&link.id, &destination, claimed_by.as_deref(), result).await {
+            Ok(link) => link,
+            Err(e) => return Ok(link_error_response(e)),
+        }
+    };
+    match swept {
+        Ok(Some(_)) => Ok(HttpResponse::Ok().json(ClaimResponse { link: link.into(), account })),
+        Ok(None) => Ok(HttpResponse::Conflict().body("The link's wallet is empty")),
+        Err(error_message) => Ok(HttpResponse::BadGateway().body(error_message)),
+    }
+}
+
+async fn finish_claim(store: &Mutex<Store>, link_id: &str, destination: &str, result: Result<&str, &str>) {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return eprintln!("Failed to lock store"),
+    };
+    if let Err(e) = locked_store.finish_link_claim(link_id, destination, None, result).await {
+        eprintln!("Failed to finish claim of link {}: {}", link_id, e);
+    }
+}
+
+/// Send the funds of expired links back to their creators' wallets.
+pub fn spawn_link_refunder(store: Arc<Mutex<Store>>, topology: web::Data<Topology>, identity: web::Data<ServiceIdentity>) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(LINK_REFUND_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = refund_expired_links(&store, &topology, &identity).await {
+                eprintln!("Failed to refund expired links: {}", e);
+            }
+        }
+    });
+}
+
+async fn refund_expired_links(store: &Mutex<Store>, topology: &Topology, identity: &ServiceIdentity) -> Result<(), String> {
+    let coordinator = topology.coordinator().map_err(|e| e.to_string())?;
+    let links = {
+        let locked_store = store.lock().map_err(|_| "Failed to lock store".to_string())?;
+        locked_store.take_expired_links(LINK_REFUND_BATCH).await.map_err(|e| e.to_string())?
+    };
+    for link in links {
+        let refunded = refund_link(store, &coordinator.url, &coordinator.id, identity, &link).await;
+        if let Err(e) = &refunded {
+            eprintln!("Failed to refund link {}: {}", link.id, e);
+        }
+        let locked_store = store.lock().map_err(|_| "Failed to lock store".to_string())?;
+        locked_store
+            .finish_link_refund(&link.id, refunded.as_ref().map(|_| ()).map_err(String::as_str))
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+async fn refund_link(store: &Mutex<Store>, coordinator_url: &str, coordinator_id: &str, identity: &ServiceIdentity, link: &ClaimLink) -> Result<(), String> {
+    let creator = {
+        let locked_store = store.lock().map_err(|_| "Failed to lock store".to_string())?;
+        locked_store.get_user_by_id(link.creator_id.clone()).await.map_err(|e| e.to_string())?
+    };
+    let token = ServiceToken::new(identity, coordinator_id, &link.id);
+    // A link whose funding failed half way may hold nothing of its asset, only SOL
+    sweep_link(store, coordinator_url, &token, link, &creator.public_key, &creator.id, TransactionKind::LinkRefund).await.map(|_| ())
+}
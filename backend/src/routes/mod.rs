@@ -1,5 +1,19 @@
+pub mod account;
 pub mod user;
 pub mod solana;
+pub mod export;
+pub mod session;
+pub mod two_factor;
+pub mod link;
+pub mod campaign;
+pub mod transaction;
 
+pub use account::*;
 pub use user::*;
 pub use solana::*;
+pub use export::*;
+pub use session::*;
+pub use two_factor::*;
+pub use link::*;
+pub use campaign::*;
+pub use transaction::*;
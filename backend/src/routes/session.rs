@@ -0,0 +1,135 @@
+use std::sync::{Arc, Mutex};
+
+use actix_web::{web, HttpResponse, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use store::{user_session::{AccessGrant, UserSession, UserSessionError}, Store};
+
+use crate::auth::{access_token_expiry, create_jwt, create_opaque_token, Payload, REFRESH_TOKEN_TTL_DAYS};
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub token: String,
+    pub refresh_token: String,
+    /// When `token` expires, RFC 3339
+    pub expires_at: String,
+}
+
+#[derive(Serialize)]
+pub struct SessionResponse {
+    pub id: String,
+    pub created_at: String,
+    pub last_used_at: String,
+    pub expires_at: String,
+    /// The session the request was made with
+    pub current: bool,
+}
+
+impl SessionResponse {
+    fn new(session: UserSession, current_session_id: &str) -> Self {
+        Self {
+            current: session.id == current_session_id,
+            id: session.id,
+            created_at: session.created_at.to_rfc3339(),
+            last_used_at: session.last_used_at.to_rfc3339(),
+            expires_at: session.expires_at.to_rfc3339(),
+        }
+    }
+}
+
+fn session_error_response(e: UserSessionError) -> HttpResponse {
+    match e {
+        UserSessionError::NotFound => HttpResponse::NotFound().body(e.to_string()),
+        UserSessionError::InvalidToken | UserSessionError::TokenReused => HttpResponse::Unauthorized().body(e.to_string()),
+        UserSessionError::DatabaseError(_) => {
+            eprintln!("Session error: {}", e);
+            HttpResponse::InternalServerError().body("Failed to update session")
+        }
+    }
+}
+
+/// Open a session for a user who just proved who they are and hand out its first tokens.
+pub async fn start_session(store: &Store, user_id: &str) -> Result<TokenResponse, HttpResponse> {
+    let session_id = uuid::Uuid::new_v4().to_string();
+    let jti = uuid::Uuid::new_v4().to_string();
+    let access_expires_at = access_token_expiry();
+    let token = match create_jwt(user_id, &session_id, &jti, access_expires_at) {
+        Ok(token) => token,
+        Err(_) => return Err(HttpResponse::InternalServerError().body("Failed to create JWT")),
+    };
+    let refresh_token = create_opaque_token();
+    let expires_at = Utc::now() + chrono::Duration::days(REFRESH_TOKEN_TTL_DAYS);
+
+    let access = AccessGrant { jti: &jti, expires_at: access_expires_at };
+    if let Err(e) = store.create_user_session(&session_id, user_id, &refresh_token, access, expires_at).await {
+        return Err(session_error_response(e));
+    }
+    Ok(TokenResponse { token, refresh_token, expires_at: access_expires_at.to_rfc3339() })
+}
+
+/// Trade a refresh token for a new access token and a new refresh token.
+#[actix_web::post("/refresh")]
+pub async fn refresh(req: web::Json<RefreshRequest>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let jti = uuid::Uuid::new_v4().to_string();
+    let access_expires_at = access_token_expiry();
+    let refresh_token = create_opaque_token();
+
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    let access = AccessGrant { jti: &jti, expires_at: access_expires_at };
+    let session = match locked_store.rotate_refresh_token(&req.refresh_token, &refresh_token, access).await {
+        Ok(session) => session,
+        Err(e) => return Ok(session_error_response(e)),
+    };
+    match create_jwt(&session.user_id, &session.id, &jti, access_expires_at) {
+        Ok(token) => Ok(HttpResponse::Ok().json(TokenResponse { token, refresh_token, expires_at: access_expires_at.to_rfc3339() })),
+        Err(_) => Ok(HttpResponse::InternalServerError().body("Failed to create JWT")),
+    }
+}
+
+/// End the session the request was made with.
+#[actix_web::post("/logout")]
+pub async fn logout(auth: Payload, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    match locked_store.revoke_user_session(&auth.sid, &auth.user_id).await {
+        Ok(_) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(session_error_response(e)),
+    }
+}
+
+#[actix_web::get("/sessions")]
+pub async fn list_sessions(auth: Payload, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    match locked_store.list_user_sessions(&auth.user_id).await {
+        Ok(sessions) => Ok(HttpResponse::Ok().json(
+            sessions.into_iter().map(|session| SessionResponse::new(session, &auth.sid)).collect::<Vec<_>>(),
+        )),
+        Err(e) => Ok(session_error_response(e)),
+    }
+}
+
+/// Sign out another device, or this one.
+#[actix_web::delete("/sessions/{id}")]
+pub async fn revoke_session(auth: Payload, path: web::Path<String>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    match locked_store.revoke_user_session(&path.into_inner(), &auth.user_id).await {
+        Ok(_) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(session_error_response(e)),
+    }
+}
@@ -1,9 +1,18 @@
-use std::{sync::{Arc, Mutex}, vec};
+use std::{str::FromStr, sync::{Arc, Mutex}};
 
 use actix_web::{web, HttpResponse, Result};
+use base64::engine::Engine;
 use serde::{Deserialize, Serialize};
-use store::Store;
-use solana_sdk::{pubkey::Pubkey, transaction::Transaction};
+use solana_sdk::{message::VersionedMessage, pubkey::Pubkey};
+use store::{transaction::TransactionKind, Store};
+use topology::{identity::ServiceIdentity, Topology};
+
+use crate::{auth::Payload, jupiter::JupiterClient, mpc::{self, ServiceToken}};
+
+use super::{
+    transaction::{begin_transaction, finish_transaction, mark_signing, tracked},
+    two_factor::require_step_up,
+};
 
 #[derive(Deserialize)]
 pub struct QuoteRequest {
@@ -70,247 +79,325 @@ pub struct SwapInfo {
 
 #[derive(Deserialize)]
 pub struct SwapRequest {
-    to: String,
-    amount: f64,
-    user_id: String,
+    quote_response: QuoteResponse,
+    /// Current TOTP code, required if the user enabled two-factor authentication
+    totp_code: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct SwapResponse {
-    pub signature: Transaction,
+    pub signature: String,
+    pub confirmation_status: Option<String>,
+    pub fee_lamports: u64,
+    pub simulation: mpc::Simulation,
 }
 
-#[derive(Serialize)]
-pub struct BalanceResponse {
-    pub lamports: u64,
+#[derive(Deserialize)]
+pub struct TransferTokenRequest {
+    pub mint: String,
+    /// Wallet of the recipient, its associated token account is created if missing
+    pub to: String,
+    /// In base units of the mint
+    pub amount: u64,
+    pub memo: Option<String>,
+    /// Priority fee, none if missing
+    #[serde(default)]
+    pub fee: mpc::FeeStrategy,
+    /// Sign over the wallet's durable nonce, which has to be created first
+    #[serde(default)]
+    pub durable_nonce: bool,
+    /// Current TOTP code, required if the user enabled two-factor authentication
+    pub totp_code: Option<String>,
 }
 
 #[derive(Serialize)]
-pub struct TokenBalanceResponse {
-    pub amount: u64,
+pub struct TransferResponse {
+    pub signature: String,
+    /// `None` if a durable nonce transfer is still on its way, follow it with `/transactions/{signature}`
+    pub confirmation_status: Option<String>,
+    pub fee_lamports: u64,
+    pub simulation: mpc::Simulation,
+    /// The nonce the next durable transaction signs over
+    pub nonce: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct GetKeyPairInput {
-    pub user_id: String,
+#[derive(Deserialize)]
+pub struct CreateNonceRequest {
+    /// Priority fee, none if missing
+    #[serde(default)]
+    pub fee: mpc::FeeStrategy,
+    /// Current TOTP code, required if the user enabled two-factor authentication
+    pub totp_code: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct AggAndStep1Output {
-    pub agg_message1: String,
-    pub secret_agg_step_one: String,
+#[derive(Serialize)]
+pub struct NonceResponse {
+    pub address: String,
+    pub nonce: String,
+    /// Transaction that created the account, if this request did
+    pub signature: Option<String>,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct AggAndStep2Input {
-    pub keypair_base64: String,
-    pub amount: f64,
-    pub to: String,
-    pub keys: Vec<String>,
-    pub first_messages: Vec<String>,
-    pub secret_state: String,
+#[derive(Deserialize)]
+pub struct SimulateRequest {
+    /// Base64 encoded, bincode serialized legacy or v0 `VersionedMessage`
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct BalanceResponse {
+    pub lamports: u64,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct SignatureAggregationInput {
-    pub amount: f64,
-    pub to: Pubkey,
-    pub keys: Vec<Pubkey>,
-    pub signatures: Vec<String>,
+#[derive(Serialize)]
+pub struct TokenBalanceResponse {
+    pub amount: u64,
 }
 
 #[actix_web::post("/quote")]
 pub async fn quote(req: web::Json<QuoteRequest>) -> Result<HttpResponse> {
-    let input_mint = req.input_mint.clone();
-    let output_mint = req.output_mint.clone();
-    let amount = req.amount;
     let slippage = req.slippage.unwrap_or(50);
-    
-    let client = reqwest::Client::new();
-
-    let target_url = format!("https://quote-api.jup.ag/v6/quote?inputMint={}&outputMint={}&amount={}&slippageBps={}",
-        input_mint, output_mint, amount, slippage);
 
-    match client.get(target_url)
-        .send()
-        .await
-    {
-        Ok(response) => {
-            println!("Received response with status: {}", response.status());
-            if response.status().is_success() {
-                let body = response.text().await.unwrap_or_default();
-                match serde_json::from_str::<QuoteResponse>(&body) {
-                    Ok(parsed_response) => {
-                        return Ok(HttpResponse::Ok().json(parsed_response));
-                    }
-                    Err(e) => {
-                        let error_message = format!("Failed to parse response: {:?}", e);
-                        return Ok(HttpResponse::InternalServerError().body(error_message));
-                    }
-                }
-            } else {
-                return Ok(HttpResponse::InternalServerError().body("Failed to fetch quote from external API"));
-            }
-        }
+    match JupiterClient::from_env().quote(&req.input_mint, &req.output_mint, req.amount, slippage).await {
+        Ok(parsed_response) => Ok(HttpResponse::Ok().json(parsed_response)),
         Err(e) => {
-            let error_message = format!("Error generating public key: {:?}", e);
-            return Ok(HttpResponse::InternalServerError().body(error_message));
+            let error_message = format!("Failed to fetch quote: {}", e);
+            Ok(HttpResponse::InternalServerError().body(error_message))
         }
     }
 }
 
 #[actix_web::post("/swap")]
-pub async fn swap(req: web::Json<SwapRequest>) -> Result<HttpResponse> {
-    let token = match crate::auth::create_jwt_for_communication(req.user_id.clone()) {
-        Ok(t) => t,
+pub async fn swap(
+    auth: Payload,
+    req: web::Json<SwapRequest>,
+    store: web::Data<Arc<Mutex<Store>>>,
+    topology: web::Data<Topology>,
+    identity: web::Data<ServiceIdentity>,
+) -> Result<HttpResponse> {
+    let coordinator = match topology.coordinator() {
+        Ok(party) => party,
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    let token = ServiceToken::new(&identity, &coordinator.id, &auth.user_id);
+
+    let user = {
+        let locked_store = match store.lock() {
+            Ok(locked) => locked,
+            Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+        };
+        if let Err(response) = require_step_up(&locked_store, &auth.user_id, req.totp_code.as_deref()).await {
+            return Ok(response);
+        }
+        match locked_store.get_user_by_id(auth.user_id.clone()).await {
+            Ok(user) => user,
+            Err(err) => return Ok(HttpResponse::BadRequest().body(err.to_string())),
+        }
+    };
+
+    let transaction_id = match begin_transaction(&store, &auth.user_id, TransactionKind::Swap).await {
+        Ok(id) => id,
+        Err(response) => return Ok(response),
+    };
+    let swap = match JupiterClient::from_env().swap_transaction(&req.quote_response, &user.public_key).await {
+        Ok(tx) => tx,
         Err(e) => {
-            let error_message = format!("Error creating JWT: {:?}", e);
+            let error_message = format!("Failed to build swap transaction: {}", e);
+            finish_transaction(&store, &transaction_id, Err(&error_message)).await;
             return Ok(HttpResponse::InternalServerError().body(error_message));
         }
     };
-    let mut keypairs = vec![];
-
-    let client = reqwest::Client::new();
-    let target_url = vec![
-        "http://localhost:9000/getKeyPair",
-        "http://localhost:9001/getKeyPair",
-    ];
-
-    for url in target_url {
-        match client.post(url)
-            .json(&GetKeyPairInput {
-                user_id: req.user_id.clone(),
-            })
-            .bearer_auth(&token)
-            .send()
-            .await
-        {
-            Ok(response) => {
-                if response.status().is_success() {
-                    let response_body = match response.text().await {
-                        Ok(body) => body,
-                        Err(_) => {
-                            let error_message = format!("Failed to read response body from {}", url);
-                            return Ok(HttpResponse::InternalServerError().body(error_message));
-                        }
-                    };
-                    keypairs.push(response_body);
-                } else {
-                    let error_message = format!("Failed to send data to {}: {:?}", url, response.status());
-                    return Ok(HttpResponse::InternalServerError().body(error_message));
-                }
-            }
-            Err(e) => {
-                let error_message = format!("Error sending request to {}: {:?}", url, e);
-                return Ok(HttpResponse::InternalServerError().body(error_message));
-            }
-        }
+
+    mark_signing(&store, &transaction_id).await;
+    let broadcast = mpc::sign_message(&coordinator.url, &token, &auth.user_id, &swap.transaction.message, swap.last_valid_block_height).await;
+    finish_transaction(&store, &transaction_id, broadcast.as_ref().map_err(String::as_str)).await;
+    match broadcast {
+        Ok(broadcast) => Ok(HttpResponse::Ok().json(SwapResponse {
+            signature: broadcast.signature,
+            confirmation_status: broadcast.confirmation_status,
+            fee_lamports: broadcast.fee_lamports,
+            simulation: broadcast.simulation,
+        })),
+        Err(error_message) => Ok(HttpResponse::InternalServerError().body(error_message)),
     }
+}
 
-    let mut step1_response = vec![vec![]; keypairs.len()];
-
-    for (i, keypair) in keypairs.iter().enumerate() {
-        match client.post("http://localhost:8080/agg-send-step1")
-            .json(&keypair)
-            .bearer_auth(&token)
-            .send()
-            .await
-        {
-            Ok(response) => {
-                if response.status().is_success() {
-                    let response_body = match response.json::<AggAndStep1Output>().await {
-                        Ok(body) => body,
-                        Err(_) => {
-                            let error_message = format!("Failed to parse JSON from agg-send-step1");
-                            return Ok(HttpResponse::InternalServerError().body(error_message));
-                        }
-                    };
-                    step1_response[i] = vec![
-                        response_body.agg_message1,
-                        response_body.secret_agg_step_one,
-                    ];
-                } else {
-                    let error_message = format!("Failed to send data to agg-send-step1: {:?}", response.status());
-                    return Ok(HttpResponse::InternalServerError().body(error_message));
-                }
-            }
-            Err(e) => {
-                let error_message = format!("Error sending request to agg-send-step1: {:?}", e);
-                return Ok(HttpResponse::InternalServerError().body(error_message));
-            }
-        };
+#[actix_web::post("/transfer-token")]
+pub async fn transfer_token(
+    auth: Payload,
+    req: web::Json<TransferTokenRequest>,
+    store: web::Data<Arc<Mutex<Store>>>,
+    topology: web::Data<Topology>,
+    identity: web::Data<ServiceIdentity>,
+) -> Result<HttpResponse> {
+    if Pubkey::from_str(&req.mint).is_err() || Pubkey::from_str(&req.to).is_err() {
+        return Ok(HttpResponse::BadRequest().body("Invalid recipient or mint public key"));
+    }
+    if req.amount == 0 {
+        return Ok(HttpResponse::BadRequest().body("Amount must be positive"));
     }
+    let coordinator = match topology.coordinator() {
+        Ok(party) => party,
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    let token = ServiceToken::new(&identity, &coordinator.id, &auth.user_id);
 
-    let mut step2_response = vec![];
-    for i in 0..keypairs.len() {
-        match client.post("http://localhost:8080/agg-send-step2")
-            .json(&AggAndStep2Input{
-                keypair_base64: keypairs[i].clone(),
-                amount: req.amount,
-                to: req.to.clone(),
-                keys: keypairs.clone(),
-                first_messages: step1_response.iter().map(|r| r[0].clone()).collect(),
-                secret_state: step1_response[i][1].clone(),
-            })
-            .bearer_auth(token.clone())
-            .send()
-            .await
-        {
-            Ok(response) => {
-                if response.status().is_success() {
-                    let response_body = match response.text().await {
-                        Ok(body) => body,
-                        Err(_) => {
-                            let error_message = format!("Failed to read response body from agg-send-step2");
-                            return Ok(HttpResponse::InternalServerError().body(error_message));
-                        }
-                    };
-                    step2_response.push(response_body);
-                } else {
-                    let error_message = format!("Failed to send data to agg-send-step2: {:?}", response.status());
-                    return Ok(HttpResponse::InternalServerError().body(error_message));
-                }
-            }
-            Err(e) => {
-                let error_message = format!("Error sending request to agg-send-step2: {:?}", e);
-                return Ok(HttpResponse::InternalServerError().body(error_message));
-            }
+    {
+        let locked_store = match store.lock() {
+            Ok(locked) => locked,
+            Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
         };
+        if let Err(response) = require_step_up(&locked_store, &auth.user_id, req.totp_code.as_deref()).await {
+            return Ok(response);
+        }
     }
 
-    match client.post("http://localhost:8080/aggregate-signatures-broadcast")
-        .json(&SignatureAggregationInput{
-            amount: req.amount as f64,
-            to: req.to.parse().unwrap(),
-            keys: keypairs.iter().map(|k| k.parse().unwrap()).collect(),
-            signatures: step2_response.iter().map(|s| s.parse().unwrap()).collect(),
-        })
-        .bearer_auth(&token)
-        .send()
-        .await
+    let transaction_id = match begin_transaction(&store, &auth.user_id, TransactionKind::TokenTransfer).await {
+        Ok(id) => id,
+        Err(response) => return Ok(response),
+    };
+    let input = mpc::TransferTokenInput {
+        user_id: auth.user_id.clone(),
+        mint: req.mint.clone(),
+        to: req.to.clone(),
+        amount: req.amount,
+        memo: req.memo.clone(),
+        fee: req.fee,
+        durable_nonce: req.durable_nonce,
+    };
+    // The coordinator builds, signs and submits in one call
+    mark_signing(&store, &transaction_id).await;
+    let broadcast = mpc::transfer_token(&coordinator.url, &token, &input).await;
+    finish_transaction(&store, &transaction_id, broadcast.as_ref().map_err(String::as_str)).await;
+    let broadcast = match broadcast {
+        Ok(broadcast) => broadcast,
+        Err(error_message) => return Ok(HttpResponse::BadGateway().body(error_message)),
+    };
+    if let Some(nonce) = &broadcast.nonce {
+        record_nonce(&store, &auth.user_id, nonce).await;
+    }
+    Ok(HttpResponse::Ok().json(TransferResponse {
+        signature: broadcast.signature,
+        confirmation_status: broadcast.confirmation_status,
+        fee_lamports: broadcast.fee_lamports,
+        simulation: broadcast.simulation,
+        nonce: broadcast.nonce,
+    }))
+}
+
+/// Keep the stored nonce in step with the chain after a transaction advanced it. The transfer
+/// already landed, so a failure here is only logged, the next `/nonce` read repairs it.
+async fn record_nonce(store: &Arc<Mutex<Store>>, user_id: &str, nonce: &str) {
+    let Ok(locked_store) = store.lock() else {
+        eprintln!("Failed to lock store to record the durable nonce");
+        return;
+    };
+    let result = match locked_store.get_durable_nonce(user_id).await {
+        Ok(stored) => locked_store.record_durable_nonce(user_id, &stored.address, nonce).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to record durable nonce: {}", e);
+    }
+}
+
+/// The wallet's durable nonce as it is on chain, which also refreshes the stored copy.
+#[actix_web::get("/nonce")]
+pub async fn get_nonce(
+    auth: Payload,
+    store: web::Data<Arc<Mutex<Store>>>,
+    topology: web::Data<Topology>,
+    identity: web::Data<ServiceIdentity>,
+) -> Result<HttpResponse> {
+    let coordinator = match topology.coordinator() {
+        Ok(party) => party,
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    let token = ServiceToken::new(&identity, &coordinator.id, &auth.user_id);
+    let account = match mpc::get_nonce(&coordinator.url, &token, &auth.user_id).await {
+        Ok(account) => account,
+        Err(error_message) => return Ok(HttpResponse::BadGateway().body(error_message)),
+    };
+    let Some(nonce) = account.nonce else {
+        return Ok(HttpResponse::NotFound().body("The wallet has no durable nonce account"));
+    };
+    nonce_response(&store, &auth.user_id, &account.address, &nonce, None).await
+}
+
+/// Create the wallet's durable nonce account. The wallet pays the rent, so this is a signing
+/// action and needs the step-up code like a transfer.
+#[actix_web::post("/nonce")]
+pub async fn create_nonce(
+    auth: Payload,
+    req: web::Json<CreateNonceRequest>,
+    store: web::Data<Arc<Mutex<Store>>>,
+    topology: web::Data<Topology>,
+    identity: web::Data<ServiceIdentity>,
+) -> Result<HttpResponse> {
+    let coordinator = match topology.coordinator() {
+        Ok(party) => party,
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    let token = ServiceToken::new(&identity, &coordinator.id, &auth.user_id);
+
     {
-        Ok(response) => {
-            if response.status().is_success() {
-                let response_body = match response.json::<Transaction>().await {
-                    Ok(body) => body,
-                    Err(_) => {
-                        let error_message = format!("Failed to read response body from aggregate-signatures-broadcast");
-                        return Ok(HttpResponse::InternalServerError().body(error_message));
-                    }
-                };
-                return Ok(HttpResponse::Ok().json(SwapResponse {
-                    signature: response_body,
-                }));
-            } else {
-                let error_message = format!("Failed to send data to aggregate-signatures-broadcast: {:?}", response.status());
-                return Ok(HttpResponse::InternalServerError().body(error_message));
-            }
-        }
-        Err(e) => {
-            let error_message = format!("Error sending request to aggregate-signatures-broadcast: {:?}", e);
-            return Ok(HttpResponse::InternalServerError().body(error_message));
+        let locked_store = match store.lock() {
+            Ok(locked) => locked,
+            Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+        };
+        if let Err(response) = require_step_up(&locked_store, &auth.user_id, req.totp_code.as_deref()).await {
+            return Ok(response);
         }
+    }
+
+    // An existing account is returned as is, only a creation is recorded as a transaction
+    let account = match mpc::get_nonce(&coordinator.url, &token, &auth.user_id).await {
+        Ok(account) if account.nonce.is_some() => account,
+        Ok(_) => match tracked(&store, &auth.user_id, TransactionKind::NonceCreation, mpc::create_nonce(&coordinator.url, &token, &auth.user_id, req.fee)).await {
+            Ok(account) => account,
+            Err(error_message) => return Ok(HttpResponse::BadGateway().body(error_message)),
+        },
+        Err(error_message) => return Ok(HttpResponse::BadGateway().body(error_message)),
     };
+    let Some(nonce) = account.nonce else {
+        return Ok(HttpResponse::BadGateway().body("The durable nonce account was not created"));
+    };
+    nonce_response(&store, &auth.user_id, &account.address, &nonce, account.signature).await
+}
+
+async fn nonce_response(store: &Arc<Mutex<Store>>, user_id: &str, address: &str, nonce: &str, signature: Option<String>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    match locked_store.record_durable_nonce(user_id, address, nonce).await {
+        Ok(stored) => Ok(HttpResponse::Ok().json(NonceResponse { address: stored.address, nonce: stored.nonce, signature })),
+        Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// Preview what signing a message would do: balance changes, logs, compute units and the
+/// error if it would fail. Nothing is signed.
+#[actix_web::post("/simulate")]
+pub async fn simulate(auth: Payload, req: web::Json<SimulateRequest>, topology: web::Data<Topology>, identity: web::Data<ServiceIdentity>) -> Result<HttpResponse> {
+    let message = match base64::engine::general_purpose::STANDARD
+        .decode(&req.message)
+        .ok()
+        .and_then(|bytes| bincode::deserialize::<VersionedMessage>(&bytes).ok())
+    {
+        Some(message) => message,
+        None => return Ok(HttpResponse::BadRequest().body("Invalid message")),
+    };
+    let coordinator = match topology.coordinator() {
+        Ok(party) => party,
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    let token = ServiceToken::new(&identity, &coordinator.id, &auth.user_id);
+
+    match mpc::simulate(&coordinator.url, &token, &auth.user_id, &message).await {
+        Ok(simulation) => Ok(HttpResponse::Ok().json(simulation)),
+        Err(error_message) => Ok(HttpResponse::BadGateway().body(error_message)),
+    }
 }
 
 #[actix_web::get("/sol-balance/{pubkey}")]
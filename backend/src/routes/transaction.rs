@@ -0,0 +1,397 @@
+use std::{future::Future, str::FromStr, sync::{Arc, Mutex}, time::Duration};
+
+use actix_web::{web, HttpResponse, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use store::{
+    transaction::{OutgoingTransaction, SubmittedTransaction, TransactionError, TransactionKind, TransactionState},
+    wallet_transaction::{AssetFilter, TransactionFilter, TransferDirection, WalletTransaction},
+    Store,
+};
+use topology::{identity::ServiceIdentity, Topology};
+
+use crate::{auth::Payload, mpc::{self, ServiceToken}};
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+const TRACKER_INTERVAL: Duration = Duration::from_secs(15);
+const TRACKER_BATCH: i64 = 100;
+/// Longer than signing plus the coordinator's wait for confirmation. A transaction that made no
+/// progress for this long won't land anymore, its blockhash is gone. Durable nonce transactions
+/// don't go stale, they can land until their nonce advances.
+const STALE_AFTER: chrono::Duration = chrono::Duration::minutes(5);
+
+#[derive(Deserialize)]
+pub struct TransactionsQuery {
+    /// `sol` or an SPL token mint, every asset if missing
+    pub mint: Option<String>,
+    /// `in` or `out`
+    pub direction: Option<String>,
+    /// RFC 3339, inclusive
+    pub from: Option<String>,
+    /// RFC 3339, exclusive
+    pub until: Option<String>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct TransactionResponse {
+    pub id: String,
+    pub signature: String,
+    pub slot: u64,
+    pub direction: String,
+    pub counterparty: String,
+    /// `None` for SOL
+    pub mint: Option<String>,
+    /// Lamports or base units of the mint
+    pub amount: u64,
+    pub block_time: String,
+}
+
+#[derive(Serialize)]
+pub struct TransactionsResponse {
+    pub transactions: Vec<TransactionResponse>,
+    pub next_cursor: Option<String>,
+}
+
+impl From<WalletTransaction> for TransactionResponse {
+    fn from(transaction: WalletTransaction) -> Self {
+        Self {
+            id: transaction.id,
+            signature: transaction.signature,
+            slot: transaction.slot,
+            direction: transaction.direction.as_str().to_string(),
+            counterparty: transaction.counterparty,
+            mint: transaction.mint,
+            amount: transaction.amount,
+            block_time: transaction.block_time.to_rfc3339(),
+        }
+    }
+}
+
+fn parse_time(time: &Option<String>, name: &str) -> Result<Option<DateTime<Utc>>, String> {
+    match time {
+        Some(time) => DateTime::parse_from_rfc3339(time)
+            .map(|time| Some(time.with_timezone(&Utc)))
+            .map_err(|_| format!("Invalid {} time, expected RFC 3339", name)),
+        None => Ok(None),
+    }
+}
+
+fn parse_filter(query: &TransactionsQuery) -> Result<TransactionFilter, String> {
+    let asset = match query.mint.as_deref() {
+        Some(mint) if mint.eq_ignore_ascii_case("sol") => Some(AssetFilter::Sol),
+        Some(mint) if Pubkey::from_str(mint).is_ok() => Some(AssetFilter::Token(mint.to_string())),
+        Some(_) => return Err("Invalid mint public key".to_string()),
+        None => None,
+    };
+    let direction = match query.direction.as_deref() {
+        Some(direction) => Some(TransferDirection::parse(direction).ok_or("Direction must be in or out")?),
+        None => None,
+    };
+    let (from, until) = (parse_time(&query.from, "from")?, parse_time(&query.until, "until")?);
+    if let (Some(from), Some(until)) = (from, until) {
+        if from >= until {
+            return Err("from must be before until".to_string());
+        }
+    }
+    Ok(TransactionFilter { asset, direction, from, until })
+}
+
+/// The user's SOL and token transfers as the indexer decoded them, newest first.
+#[actix_web::get("/transactions")]
+pub async fn list_transactions(auth: Payload, query: web::Query<TransactionsQuery>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let filter = match parse_filter(&query) {
+        Ok(filter) => filter,
+        Err(error_message) => return Ok(HttpResponse::BadRequest().body(error_message)),
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    match locked_store.list_wallet_transactions(&auth.user_id, &filter, query.cursor.as_deref(), limit).await {
+        Ok(page) => Ok(HttpResponse::Ok().json(TransactionsResponse {
+            transactions: page.transactions.into_iter().map(TransactionResponse::from).collect(),
+            next_cursor: page.next_cursor,
+        })),
+        Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+#[derive(Serialize)]
+pub struct OutgoingTransactionResponse {
+    pub id: String,
+    pub kind: String,
+    pub state: String,
+    pub signature: Option<String>,
+    pub fee_lamports: Option<u64>,
+    pub error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl From<OutgoingTransaction> for OutgoingTransactionResponse {
+    fn from(transaction: OutgoingTransaction) -> Self {
+        Self {
+            id: transaction.id,
+            kind: transaction.kind.as_str().to_string(),
+            state: transaction.state.as_str().to_string(),
+            signature: transaction.signature,
+            fee_lamports: transaction.fee_lamports,
+            error: transaction.error,
+            created_at: transaction.created_at.to_rfc3339(),
+            updated_at: transaction.updated_at.to_rfc3339(),
+        }
+    }
+}
+
+/// Where a transaction the user's wallet signed is, from building to finality.
+#[actix_web::get("/transactions/{signature}")]
+pub async fn get_transaction(auth: Payload, path: web::Path<String>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    match locked_store.get_transaction_by_signature(&auth.user_id, &path.into_inner()).await {
+        Ok(transaction) => Ok(HttpResponse::Ok().json(OutgoingTransactionResponse::from(transaction))),
+        Err(e @ TransactionError::NotFound) => Ok(HttpResponse::NotFound().body(e.to_string())),
+        Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// Record a transaction the user is about to sign, before anything is built. Nothing gets signed
+/// without a record, so this failing fails the request.
+pub(super) async fn begin_transaction(store: &Mutex<Store>, user_id: &str, kind: TransactionKind) -> Result<String, HttpResponse> {
+    let locked_store = store.lock().map_err(|_| HttpResponse::InternalServerError().body("Failed to lock store"))?;
+    match locked_store.create_transaction(user_id, kind).await {
+        Ok(transaction) => Ok(transaction.id),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// The message goes to the coordinator. Signing goes ahead if this can't be recorded, the
+/// tracker expires the record if it never hears of it again.
+pub(super) async fn mark_signing(store: &Mutex<Store>, transaction_id: &str) {
+    let result = match store.lock() {
+        Ok(locked_store) => locked_store.mark_transaction_signing(transaction_id).await.map_err(|e| e.to_string()),
+        Err(_) => Err("Failed to lock store".to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to mark transaction {} as signing: {}", transaction_id, e);
+    }
+}
+
+/// Record what the coordinator returned for the transaction.
+pub(super) async fn finish_transaction(store: &Mutex<Store>, transaction_id: &str, broadcast: Result<&mpc::BroadcastResponse, &str>) {
+    let submitted = broadcast.map(|broadcast| SubmittedTransaction {
+        signature: &broadcast.signature,
+        state: confirmation_state(broadcast.confirmation_status.as_deref().unwrap_or_default()),
+        fee_lamports: broadcast.fee_lamports,
+        durable_nonce: broadcast.durable_nonce.as_deref(),
+    });
+    record_submission(store, transaction_id, submitted).await;
+}
+
+async fn record_submission(store: &Mutex<Store>, transaction_id: &str, submitted: Result<SubmittedTransaction<'_>, &str>) {
+    let result = match store.lock() {
+        Ok(locked_store) => locked_store.finish_transaction_submission(transaction_id, submitted).await.map_err(|e| e.to_string()),
+        Err(_) => Err("Failed to lock store".to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to record the outcome of transaction {}: {}", transaction_id, e);
+    }
+}
+
+/// A coordinator response that may carry a transaction it submitted.
+pub(super) trait Submission {
+    /// `None` if the call had nothing to submit
+    fn submitted(&self) -> Option<SubmittedTransaction<'_>>;
+}
+
+impl Submission for mpc::BroadcastResponse {
+    fn submitted(&self) -> Option<SubmittedTransaction<'_>> {
+        Some(SubmittedTransaction {
+            signature: &self.signature,
+            state: confirmation_state(self.confirmation_status.as_deref().unwrap_or_default()),
+            fee_lamports: self.fee_lamports,
+            durable_nonce: self.durable_nonce.as_deref(),
+        })
+    }
+}
+
+impl Submission for mpc::SweepResponse {
+    fn submitted(&self) -> Option<SubmittedTransaction<'_>> {
+        self.signature.as_deref().map(|signature| SubmittedTransaction {
+            signature,
+            state: confirmation_state(self.confirmation_status.as_deref().unwrap_or_default()),
+            fee_lamports: self.fee_lamports,
+            durable_nonce: None,
+        })
+    }
+}
+
+impl Submission for mpc::NonceResponse {
+    fn submitted(&self) -> Option<SubmittedTransaction<'_>> {
+        self.signature.as_deref().map(|signature| SubmittedTransaction {
+            signature,
+            state: confirmation_state(self.confirmation_status.as_deref().unwrap_or_default()),
+            fee_lamports: self.fee_lamports,
+            durable_nonce: None,
+        })
+    }
+}
+
+/// Record a transaction the coordinator builds, signs and submits in one call, for `user_id`.
+/// Nothing is submitted if the record can't be created.
+pub(super) async fn tracked<T: Submission>(
+    store: &Mutex<Store>,
+    user_id: &str,
+    kind: TransactionKind,
+    submit: impl Future<Output = Result<T, String>>,
+) -> Result<T, String> {
+    let transaction_id = {
+        let locked_store = store.lock().map_err(|_| "Failed to lock store".to_string())?;
+        locked_store.create_transaction(user_id, kind).await.map_err(|e| e.to_string())?.id
+    };
+    mark_signing(store, &transaction_id).await;
+    let result = submit.await;
+    let submitted = match &result {
+        Ok(response) => response.submitted().ok_or("Nothing was submitted"),
+        Err(error_message) => Err(error_message.as_str()),
+    };
+    record_submission(store, &transaction_id, submitted).await;
+    result
+}
+
+fn confirmation_state(confirmation_status: &str) -> TransactionState {
+    match confirmation_status {
+        "confirmed" => TransactionState::Confirmed,
+        "finalized" => TransactionState::Finalized,
+        _ => TransactionState::Submitted,
+    }
+}
+
+/// Follow in flight transactions until they are finalized, failed or expired.
+pub fn spawn_transaction_tracker(store: Arc<Mutex<Store>>, topology: web::Data<Topology>, identity: web::Data<ServiceIdentity>) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(TRACKER_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = track_transactions(&store, &topology, &identity).await {
+                eprintln!("Failed to track transactions: {}", e);
+            }
+        }
+    });
+}
+
+async fn track_transactions(store: &Mutex<Store>, topology: &Topology, identity: &ServiceIdentity) -> Result<(), String> {
+    let coordinator = topology.coordinator().map_err(|e| e.to_string())?;
+    let transactions = {
+        let locked_store = store.lock().map_err(|_| "Failed to lock store".to_string())?;
+        locked_store.list_in_flight_transactions(TRACKER_BATCH).await.map_err(|e| e.to_string())?
+    };
+    let now = Utc::now();
+    for transaction in transactions {
+        let stale = now - transaction.updated_at > STALE_AFTER;
+        let next = match &transaction.signature {
+            Some(signature) => {
+                let token = ServiceToken::new(identity, &coordinator.id, &transaction.user_id);
+                // The nonce is read before the status, so a transaction landing in between is still seen
+                let expired = match &transaction.durable_nonce {
+                    Some(durable_nonce) => match mpc::get_nonce(&coordinator.url, &token, &transaction.user_id).await {
+                        Ok(account) => account.nonce.as_deref() != Some(durable_nonce.as_str()),
+                        Err(e) => {
+                            eprintln!("Failed to read the durable nonce for transaction {}: {}", signature, e);
+                            continue;
+                        }
+                    },
+                    None => stale,
+                };
+                match mpc::signature_status(&coordinator.url, &token, &transaction.user_id, signature).await {
+                    Ok(status) => next_state(&status, expired),
+                    Err(e) => {
+                        eprintln!("Failed to look up transaction {}: {}", signature, e);
+                        continue;
+                    }
+                }
+            }
+            // Never got a signature back, the request died on the way
+            None if stale => Some((TransactionState::Expired, Some("Abandoned before it was submitted".to_string()))),
+            None => None,
+        };
+        if let Some((state, error)) = next {
+            let locked_store = store.lock().map_err(|_| "Failed to lock store".to_string())?;
+            locked_store
+                .update_transaction_state(&transaction.id, state, error.as_deref())
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// The state a submitted transaction moves to, `None` if it stays where it is. `expired` says
+/// whether it can't land anymore.
+fn next_state(status: &mpc::SignatureStatus, expired: bool) -> Option<(TransactionState, Option<String>)> {
+    if let Some(error) = &status.error {
+        return Some((TransactionState::Failed, Some(error.clone())));
+    }
+    match status.confirmation_status.as_deref() {
+        Some(confirmation_status) => Some((confirmation_state(confirmation_status), None)),
+        None if expired => Some((TransactionState::Expired, None)),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use store::{transaction::TransactionState, wallet_transaction::{AssetFilter, TransferDirection}};
+
+    use super::{next_state, parse_filter, TransactionsQuery};
+    use crate::mpc::SignatureStatus;
+
+    fn query(mint: Option<&str>, direction: Option<&str>, from: Option<&str>, until: Option<&str>) -> TransactionsQuery {
+        TransactionsQuery {
+            mint: mint.map(str::to_string),
+            direction: direction.map(str::to_string),
+            from: from.map(str::to_string),
+            until: until.map(str::to_string),
+            cursor: None,
+            limit: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        let filter = parse_filter(&query(Some("SOL"), Some("in"), Some("2026-01-01T00:00:00Z"), None)).unwrap();
+        assert_eq!(filter.asset, Some(AssetFilter::Sol));
+        assert_eq!(filter.direction, Some(TransferDirection::In));
+        assert!(filter.from.is_some() && filter.until.is_none());
+
+        let usdc = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+        assert_eq!(parse_filter(&query(Some(usdc), None, None, None)).unwrap().asset, Some(AssetFilter::Token(usdc.to_string())));
+
+        assert!(parse_filter(&query(Some("usdc"), None, None, None)).is_err());
+        assert!(parse_filter(&query(None, Some("sideways"), None, None)).is_err());
+        assert!(parse_filter(&query(None, None, Some("yesterday"), None)).is_err());
+        assert!(parse_filter(&query(None, None, Some("2026-02-01T00:00:00Z"), Some("2026-01-01T00:00:00Z"))).is_err());
+    }
+
+    #[test]
+    fn test_next_state() {
+        let status = |confirmation_status: Option<&str>, error: Option<&str>| SignatureStatus {
+            confirmation_status: confirmation_status.map(str::to_string),
+            error: error.map(str::to_string),
+        };
+        assert_eq!(next_state(&status(Some("finalized"), None), false), Some((TransactionState::Finalized, None)));
+        assert_eq!(next_state(&status(Some("processed"), None), true), Some((TransactionState::Submitted, None)));
+        assert_eq!(next_state(&status(None, Some("InstructionError")), false).unwrap().0, TransactionState::Failed);
+        assert_eq!(next_state(&status(None, None), false), None);
+        assert_eq!(next_state(&status(None, None), true), Some((TransactionState::Expired, None)));
+    }
+}
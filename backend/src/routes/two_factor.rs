@@ -0,0 +1,160 @@
+use std::{sync::{Arc, Mutex}, time::{SystemTime, UNIX_EPOCH}};
+
+use actix_web::{web, HttpResponse, Result};
+use serde::{Deserialize, Serialize};
+use store::{two_factor::TwoFactorError, Store};
+
+use crate::{auth::Payload, totp};
+
+#[derive(Deserialize)]
+pub struct ConfirmTotpRequest {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct DisableTotpRequest {
+    pub password: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct EnrollTotpResponse {
+    /// Base32 secret, for authenticators that can't scan the URI
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    /// Only shown once, each works a single time
+    pub recovery_codes: Vec<String>,
+}
+
+fn two_factor_error_response(e: TwoFactorError) -> HttpResponse {
+    match e {
+        TwoFactorError::AlreadyEnabled | TwoFactorError::NotEnrolled => HttpResponse::Conflict().body(e.to_string()),
+        TwoFactorError::DatabaseError(_) => {
+            eprintln!("Two-factor error: {}", e);
+            HttpResponse::InternalServerError().body("Failed to update two-factor authentication")
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// Check a TOTP code and burn its time step.
+async fn verify_totp(store: &Store, user_id: &str, secret: &str, code: &str) -> Result<(), HttpResponse> {
+    let step = match totp::verify(secret, code, unix_time()) {
+        Some(step) => step,
+        None => return Err(HttpResponse::Unauthorized().body("Invalid TOTP code")),
+    };
+    match store.accept_totp_step(user_id, step as i64).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(HttpResponse::Unauthorized().body("TOTP code was already used")),
+        Err(e) => Err(two_factor_error_response(e)),
+    }
+}
+
+/// The second factor at sign in, either a TOTP code or a recovery code. Users without two-factor
+/// authentication pass right away.
+pub async fn check_second_factor(store: &Store, user_id: &str, code: Option<&str>, recovery_code: Option<&str>) -> Result<(), HttpResponse> {
+    let two_factor = match store.get_two_factor(user_id).await {
+        Ok(Some(two_factor)) if two_factor.enabled => two_factor,
+        Ok(_) => return Ok(()),
+        Err(e) => return Err(two_factor_error_response(e)),
+    };
+    if let Some(code) = code {
+        return verify_totp(store, user_id, &two_factor.secret, code).await;
+    }
+    if let Some(recovery_code) = recovery_code {
+        return match store.use_recovery_code(user_id, &totp::normalize_recovery_code(recovery_code)).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(HttpResponse::Unauthorized().body("Invalid recovery code")),
+            Err(e) => Err(two_factor_error_response(e)),
+        };
+    }
+    Err(HttpResponse::Unauthorized().body("TOTP code required"))
+}
+
+/// Step-up before moving funds: users with two-factor authentication have to hand in a current
+/// TOTP code with the request itself, a signed in session is not enough.
+pub async fn require_step_up(store: &Store, user_id: &str, code: Option<&str>) -> Result<(), HttpResponse> {
+    let two_factor = match store.get_two_factor(user_id).await {
+        Ok(Some(two_factor)) if two_factor.enabled => two_factor,
+        Ok(_) => return Ok(()),
+        Err(e) => return Err(two_factor_error_response(e)),
+    };
+    match code {
+        Some(code) => verify_totp(store, user_id, &two_factor.secret, code).await,
+        None => Err(HttpResponse::Unauthorized().body("TOTP code required for this action")),
+    }
+}
+
+/// Start enrolling, the secret is only enforced after `/2fa/totp/confirm`.
+#[actix_web::post("/2fa/totp")]
+pub async fn enroll_totp(auth: Payload, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    let user = match locked_store.get_user_by_id(auth.user_id.clone()).await {
+        Ok(user) => user,
+        Err(err) => return Ok(HttpResponse::BadRequest().body(err.to_string())),
+    };
+    let secret = totp::generate_secret();
+    if let Err(e) = locked_store.start_totp_enrollment(&auth.user_id, &secret).await {
+        return Ok(two_factor_error_response(e));
+    }
+    Ok(HttpResponse::Ok().json(EnrollTotpResponse {
+        provisioning_uri: totp::provisioning_uri(&secret, &user.email),
+        secret,
+    }))
+}
+
+/// Prove the authenticator works, this turns two-factor authentication on and hands out the recovery codes.
+#[actix_web::post("/2fa/totp/confirm")]
+pub async fn confirm_totp(auth: Payload, req: web::Json<ConfirmTotpRequest>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    let two_factor = match locked_store.get_two_factor(&auth.user_id).await {
+        Ok(Some(two_factor)) if !two_factor.enabled => two_factor,
+        Ok(Some(_)) => return Ok(two_factor_error_response(TwoFactorError::AlreadyEnabled)),
+        Ok(None) => return Ok(two_factor_error_response(TwoFactorError::NotEnrolled)),
+        Err(e) => return Ok(two_factor_error_response(e)),
+    };
+    let step = match totp::verify(&two_factor.secret, &req.code, unix_time()) {
+        Some(step) => step,
+        None => return Ok(HttpResponse::Unauthorized().body("Invalid TOTP code")),
+    };
+
+    let recovery_codes = totp::generate_recovery_codes();
+    let normalized: Vec<String> = recovery_codes.iter().map(|code| totp::normalize_recovery_code(code)).collect();
+    match locked_store.enable_totp(&auth.user_id, step as i64, &normalized).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes })),
+        Err(e) => Ok(two_factor_error_response(e)),
+    }
+}
+
+/// Turn two-factor authentication off, needs the password and a second factor.
+#[actix_web::delete("/2fa/totp")]
+pub async fn disable_totp(auth: Payload, req: web::Json<DisableTotpRequest>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+    let locked_store = match store.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    };
+    if let Err(err) = locked_store.verify_password(auth.user_id.clone(), req.password.clone()).await {
+        return Ok(HttpResponse::Unauthorized().body(err.to_string()));
+    }
+    if let Err(response) = check_second_factor(&locked_store, &auth.user_id, req.code.as_deref(), req.recovery_code.as_deref()).await {
+        return Ok(response);
+    }
+    match locked_store.disable_totp(&auth.user_id).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(two_factor_error_response(e)),
+    }
+}
@@ -3,19 +3,32 @@ use std::sync::{Arc, Mutex};
 use actix_web::{web, HttpResponse, Result};
 use serde::{Deserialize, Serialize};
 use store::{Store, user::CreateUserRequest};
+use topology::{identity::ServiceIdentity, Topology};
 
-use crate::auth::create_jwt;
+use crate::{auth::Payload, mailer::Mailer};
+
+use super::{
+    account::{send_email, verification_email},
+    session::{start_session, TokenResponse},
+    two_factor::check_second_factor,
+};
 
 #[derive(Deserialize)]
 pub struct SignUpRequest {
     pub email: String,
     pub password: String,
+    /// "musig2" (default, every share server signs) or "frost" (any `threshold` of them)
+    pub key_scheme: Option<String>,
+    pub threshold: Option<u16>,
 }
 
 #[derive(Deserialize)]
 pub struct SignInRequest {
     pub email: String,
     pub password: String,
+    /// Required once two-factor authentication is enabled, unless a recovery code is given
+    pub totp_code: Option<String>,
+    pub recovery_code: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -28,74 +41,116 @@ pub struct UserResponse {
 
 #[derive(Serialize)]
 pub struct SignupOutput {
-    pub token: String,
+    #[serde(skip)]
+    pub user_id: String,
+    #[serde(flatten)]
+    pub tokens: TokenResponse,
     pub public_key: String,
 }
 
-#[derive(Serialize)]
-pub struct AuthResponse {
-    pub token: String,
-}
-
 #[derive(Serialize, Deserialize)]
 pub struct GeneratePubKeyInput {
     pub user_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<u16>,
 }
 
-#[actix_web::post("/signup")]
-pub async fn sign_up(req: web::Json<SignUpRequest>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+#[derive(Serialize, Deserialize)]
+pub struct GenerateOutput {
+    pub pubkey: String,
+}
+
+/// Create the user's wallet and account and open their first session. The verification mail is
+/// sent before returning.
+pub async fn create_account(
+    store: &Mutex<Store>,
+    topology: &Topology,
+    identity: &ServiceIdentity,
+    mailer: &dyn Mailer,
+    req: &SignUpRequest,
+) -> Result<SignupOutput, HttpResponse> {
     let user_id = uuid::Uuid::new_v4().to_string();
+    let coordinator = match topology.coordinator() {
+        Ok(party) => party,
+        Err(e) => return Err(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    let token = match identity.token(&coordinator.id, &user_id) {
+        Ok(t) => t,
+        Err(e) => return Err(HttpResponse::InternalServerError().body(e.to_string())),
+    };
     let client = reqwest::Client::new();
     let data_to_send = GeneratePubKeyInput {
         user_id: user_id.clone(),
+        scheme: req.key_scheme.clone(),
+        threshold: req.threshold,
     };
 
-    let target_url = "http://localhost:8080/generate";
+    let target_url = format!("{}/generate", coordinator.url);
     let mut pub_keys = vec![];
 
-    match client.post(target_url)
+    match client.post(&target_url)
         .json(&data_to_send)
+        .bearer_auth(&token)
         .send()
         .await
     {
         Ok(response) => {
             if response.status().is_success() {
-                let response_body = response.text().await.unwrap_or_default();
-                pub_keys.push(response_body);
+                let response_body = match response.json::<GenerateOutput>().await {
+                    Ok(body) => body,
+                    Err(_) => return Err(HttpResponse::InternalServerError().body("Failed to read generated public key")),
+                };
+                pub_keys.push(response_body.pubkey);
             } else {
                 let error_message = format!("Failed to generate public key: {:?}", response.status());
-                return Ok(HttpResponse::InternalServerError().body(error_message));
+                return Err(HttpResponse::InternalServerError().body(error_message));
             }
         }
         Err(e) => {
             let error_message = format!("Error generating public key: {:?}", e);
-            return Ok(HttpResponse::InternalServerError().body(error_message));
+            return Err(HttpResponse::InternalServerError().body(error_message));
         }
     }
 
-    let locked_store = match store.lock() {
-        Ok(locked) => locked,
-        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock store")),
+    let (tokens, verification) = {
+        let locked_store = match store.lock() {
+            Ok(locked) => locked,
+            Err(_) => return Err(HttpResponse::InternalServerError().body("Failed to lock store")),
+        };
+        let create_user_request = CreateUserRequest {
+            email: req.email.clone(),
+            password: req.password.clone(),
+            user_id: user_id.clone(),
+            pub_key: pub_keys[0].clone(),
+        };
+        let user = match locked_store.create_user(create_user_request).await {
+            Ok(user) => user,
+            Err(err) => return Err(HttpResponse::BadRequest().body(err.to_string())),
+        };
+        // The account works right away, the address is confirmed through the mailed link
+        let verification = verification_email(&locked_store, topology, &user.id, &user.email).await;
+        (start_session(&locked_store, &user.id).await?, verification)
     };
-    let create_user_request = CreateUserRequest {
-        email: req.email.clone(),
-        password: req.password.clone(),
-        user_id: user_id.clone(),
-        pub_key: pub_keys[0].clone(),
-    };
-    let user = match locked_store.create_user(create_user_request).await {
-        Ok(user) => user,
-        Err(err) => return Ok(HttpResponse::BadRequest().body(err.to_string())),
-    };
-    let jwt = create_jwt(user.id.clone());
-    match jwt {
-        Ok(token) => {
-            let response = SignupOutput { token, public_key: pub_keys[0].clone() };
-            return Ok(HttpResponse::Ok().json(response));
-        }
-        Err(_) => {
-            return Ok(HttpResponse::InternalServerError().body("Failed to create JWT"));
-        }
+    match verification {
+        Ok(email) => send_email(mailer, email).await,
+        Err(e) => eprintln!("Failed to create verification token: {}", e),
+    }
+    Ok(SignupOutput { user_id, tokens, public_key: pub_keys[0].clone() })
+}
+
+#[actix_web::post("/signup")]
+pub async fn sign_up(
+    req: web::Json<SignUpRequest>,
+    store: web::Data<Arc<Mutex<Store>>>,
+    topology: web::Data<Topology>,
+    identity: web::Data<ServiceIdentity>,
+    mailer: web::Data<dyn Mailer>,
+) -> Result<HttpResponse> {
+    match create_account(&store, &topology, &identity, mailer.get_ref(), &req).await {
+        Ok(output) => Ok(HttpResponse::Ok().json(output)),
+        Err(response) => Ok(response),
     }
 }
 
@@ -109,21 +164,21 @@ pub async fn sign_in(req: web::Json<SignInRequest>, store: web::Data<Arc<Mutex<S
         Ok(user) => user,
         Err(err) => return Ok(HttpResponse::BadRequest().body(err.to_string())),
     };
-    let jwt = create_jwt(user.id.clone());
-    match jwt {
-        Ok(token) => {
-            let response = AuthResponse { token };
-            return Ok(HttpResponse::Ok().json(response));
-        }
-        Err(_) => {
-            return Ok(HttpResponse::InternalServerError().body("Failed to create JWT"));
-        }
+    if let Err(response) = check_second_factor(&locked_store, &user.id, req.totp_code.as_deref(), req.recovery_code.as_deref()).await {
+        return Ok(response);
+    }
+    match start_session(&locked_store, &user.id).await {
+        Ok(tokens) => Ok(HttpResponse::Ok().json(tokens)),
+        Err(response) => Ok(response),
     }
 }
 
 #[actix_web::get("/user/{id}")]
-pub async fn get_user(path: web::Path<String>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
+pub async fn get_user(auth: Payload, path: web::Path<String>, store: web::Data<Arc<Mutex<Store>>>) -> Result<HttpResponse> {
     let user_id = path.into_inner();
+    if user_id != auth.user_id {
+        return Ok(HttpResponse::Forbidden().body("Users can only look up themselves"));
+    }
 
     let locked_store = match store.lock() {
         Ok(locked) => locked,
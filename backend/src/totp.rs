@@ -0,0 +1,139 @@
+//! Time based one time passwords (RFC 6238) as produced by authenticator apps: HMAC-SHA1 over
+//! 30 second steps, 6 digits, secrets shared as base32.
+
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+const DIGITS: u32 = 6;
+const PERIOD: u64 = 30;
+const SECRET_LEN: usize = 20;
+/// Codes of the neighbouring steps are accepted too, phone clocks drift
+const ALLOWED_DRIFT: i64 = 1;
+const DEFAULT_ISSUER: &str = "MPC Wallet";
+const RECOVERY_CODES: usize = 10;
+const RECOVERY_CODE_BYTES: usize = 5;
+
+/// A new random secret, base32 encoded.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+/// Single use codes for when the authenticator is gone, formatted as `xxxx-xxxx`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let mut bytes = [0u8; RECOVERY_CODE_BYTES];
+            rand::rngs::OsRng.fill_bytes(&mut bytes);
+            let code = BASE32_NOPAD.encode(&bytes).to_lowercase();
+            format!("{}-{}", &code[..4], &code[4..])
+        })
+        .collect()
+}
+
+/// Recovery codes are compared without separators and case, the way users tend to type them.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_lowercase()
+}
+
+/// The `otpauth://` URI authenticator apps import, usually shown as a QR code. The issuer is
+/// `TOTP_ISSUER`.
+pub fn provisioning_uri(secret: &str, account: &str) -> String {
+    let issuer = dotenvy::var("TOTP_ISSUER").unwrap_or_else(|_| DEFAULT_ISSUER.to_string());
+    let mut uri = reqwest::Url::parse("otpauth://totp/").expect("valid base URI");
+    uri.set_path(&format!("{}:{}", issuer, account));
+    uri.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", &issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &PERIOD.to_string());
+    uri.to_string()
+}
+
+/// The step `unix_time` falls into.
+pub fn step_at(unix_time: u64) -> u64 {
+    unix_time / PERIOD
+}
+
+fn code_at_step(secret: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+    let binary = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]]) & 0x7fff_ffff;
+    format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize)
+}
+
+/// Check `code` against the steps around `unix_time`. Returns the matching step, callers must
+/// refuse steps that were already used so a code can't be replayed.
+pub fn verify(secret: &str, code: &str, unix_time: u64) -> Option<u64> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let current = step_at(unix_time) as i64;
+    (-ALLOWED_DRIFT..=ALLOWED_DRIFT)
+        .map(|drift| current + drift)
+        .filter(|step| *step >= 0)
+        .map(|step| step as u64)
+        .find(|step| code_at_step(&secret, *step) == code)
+}
+
+#[cfg(test)]
+mod tests {
+    use data_encoding::BASE32_NOPAD;
+
+    use super::{code_at_step, generate_recovery_codes, normalize_recovery_code, provisioning_uri, step_at, verify};
+
+    // RFC 6238 appendix B, SHA1 with the seed "12345678901234567890", truncated to 6 digits
+    const SEED: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ] {
+            assert_eq!(code_at_step(SEED, step_at(time)), code);
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let secret = BASE32_NOPAD.encode(SEED);
+        assert_eq!(verify(&secret, "050471", 1111111111), Some(step_at(1111111111)));
+        // One step late is still fine, two are not
+        assert_eq!(verify(&secret, "050471", 1111111111 + 30), Some(step_at(1111111111)));
+        assert_eq!(verify(&secret, "050471", 1111111111 + 60), None);
+        assert_eq!(verify(&secret, "05047", 1111111111), None);
+        assert_eq!(verify("not base32!", "050471", 1111111111), None);
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), 10);
+        for code in &codes {
+            assert_eq!(code.len(), 9);
+            assert_eq!(normalize_recovery_code(&code.to_uppercase().replace('-', " ")), code.replace('-', ""));
+        }
+    }
+
+    #[test]
+    fn test_provisioning_uri() {
+        let uri = provisioning_uri("JBSWY3DPEHPK3PXP", "alice@example.com");
+        assert!(uri.starts_with("otpauth://totp/"));
+        assert!(uri.contains("alice@example.com"));
+        assert!(uri.contains("secret=JBSWY3DPEHPK3PXP"));
+    }
+}
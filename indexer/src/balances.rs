@@ -0,0 +1,102 @@
+//! Turn account updates into balances: lamports for wallets, the amount for token accounts.
+
+use yellowstone_grpc_proto::prelude::SubscribeUpdateAccount;
+
+use crate::transfers::{SYSTEM_PROGRAM_ID, TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID};
+
+/// The mint SOL balances are stored under, same as the backend's.
+pub const NATIVE_MINT: &str = "So11111111111111111111111111111111111111112";
+/// Mint, owner and amount, Token-2022 accounts start with the same layout
+const TOKEN_ACCOUNT_MIN_LEN: usize = 72;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalanceUpdate {
+    pub account: String,
+    /// The wallet the balance belongs to, the account itself for SOL
+    pub wallet: String,
+    pub mint: String,
+    /// Lamports or base units of the mint
+    pub amount: u64,
+    pub slot: u64,
+    /// Orders updates of the same account within a slot
+    pub write_version: u64,
+}
+
+impl BalanceUpdate {
+    /// `None` for accounts that are neither a wallet nor a token account.
+    pub fn from_update(update: &SubscribeUpdateAccount) -> Option<Self> {
+        let info = update.account.as_ref()?;
+        let account = bs58::encode(&info.pubkey).into_string();
+        let owner = bs58::encode(&info.owner).into_string();
+        let (wallet, mint, amount) = match owner.as_str() {
+            SYSTEM_PROGRAM_ID => (account.clone(), NATIVE_MINT.to_string(), info.lamports),
+            TOKEN_PROGRAM_ID | TOKEN_2022_PROGRAM_ID => {
+                let data = info.data.get(..TOKEN_ACCOUNT_MIN_LEN)?;
+                let mint = bs58::encode(&data[..32]).into_string();
+                let wallet = bs58::encode(&data[32..64]).into_string();
+                (wallet, mint, u64::from_le_bytes(data[64..72].try_into().ok()?))
+            }
+            _ => return None,
+        };
+        Some(Self { account, wallet, mint, amount, slot: update.slot, write_version: info.write_version })
+    }
+
+    /// Whether this update is newer than the one stored at `slot` and `write_version`.
+    pub fn supersedes(&self, slot: u64, write_version: u64) -> bool {
+        (self.slot, self.write_version) > (slot, write_version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use yellowstone_grpc_proto::prelude::{SubscribeUpdateAccount, SubscribeUpdateAccountInfo};
+
+    use super::*;
+
+    fn account_update(pubkey: [u8; 32], owner: &str, lamports: u64, data: Vec<u8>, slot: u64, write_version: u64) -> SubscribeUpdateAccount {
+        SubscribeUpdateAccount {
+            account: Some(SubscribeUpdateAccountInfo {
+                pubkey: pubkey.to_vec(),
+                lamports,
+                owner: bs58::decode(owner).into_vec().unwrap(),
+                data,
+                write_version,
+                ..Default::default()
+            }),
+            slot,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_wallet_balance() {
+        let update = BalanceUpdate::from_update(&account_update([1; 32], SYSTEM_PROGRAM_ID, 5_000, vec![], 10, 2)).unwrap();
+        assert_eq!(update.account, update.wallet);
+        assert_eq!(update.mint, NATIVE_MINT);
+        assert_eq!((update.amount, update.slot, update.write_version), (5_000, 10, 2));
+    }
+
+    #[test]
+    fn test_token_balance() {
+        let mut data = vec![0u8; 165];
+        data[..32].copy_from_slice(&[2; 32]);
+        data[32..64].copy_from_slice(&[3; 32]);
+        data[64..72].copy_from_slice(&42u64.to_le_bytes());
+        let update = BalanceUpdate::from_update(&account_update([1; 32], TOKEN_PROGRAM_ID, 2_039_280, data.clone(), 10, 0)).unwrap();
+        assert_eq!(update.mint, bs58::encode([2; 32]).into_string());
+        assert_eq!(update.wallet, bs58::encode([3; 32]).into_string());
+        assert_eq!(update.amount, 42);
+
+        assert!(BalanceUpdate::from_update(&account_update([1; 32], TOKEN_PROGRAM_ID, 0, data[..40].to_vec(), 10, 0)).is_none());
+        assert!(BalanceUpdate::from_update(&account_update([1; 32], "Vote111111111111111111111111111111111111111", 1, vec![], 10, 0)).is_none());
+    }
+
+    #[test]
+    fn test_supersedes() {
+        let update = BalanceUpdate::from_update(&account_update([1; 32], SYSTEM_PROGRAM_ID, 1, vec![], 10, 5)).unwrap();
+        assert!(update.supersedes(9, 100));
+        assert!(update.supersedes(10, 4));
+        assert!(!update.supersedes(10, 5));
+        assert!(!update.supersedes(11, 0));
+    }
+}
@@ -0,0 +1,167 @@
+//! Where the indexer's writes go. The pipeline only sees `IndexerStore`, so it runs the same
+//! against Postgres and against the in-memory store of the tests.
+
+use std::str::FromStr;
+
+use anyhow::Result;
+use futures::{Stream, StreamExt};
+use log::warn;
+use solana_sdk::pubkey::Pubkey;
+use sqlx::{postgres::PgListener, PgPool};
+
+use crate::{
+    balances::BalanceUpdate,
+    transfers::{self, Transaction},
+};
+
+const ASSOCIATED_TOKEN_PROGRAM_ID: &str = "ATokenGPvbdJVy3FVWm5eLJEY1H1m5hhg9H7dSGkbzgT";
+/// Notified with the public key of every inserted user, see `012_notify_new_wallets.sql`
+const NEW_WALLET_CHANNEL: &str = "new_wallet";
+
+pub trait IndexerStore {
+    /// Store a balance unless a newer update of the same account is already stored. Returns
+    /// whether anything was written.
+    async fn upsert_balance(&self, update: &BalanceUpdate) -> Result<bool>;
+
+    /// Store the transfers of one transaction, for whichever side of them is a user's wallet.
+    /// `block_time` is in unix seconds, without one the time of indexing stands in. Replayed
+    /// transactions are ignored.
+    async fn insert_transfers(&self, transaction: &Transaction, block_time: Option<i64>) -> Result<()>;
+
+    /// Remember the last slot the stream got to.
+    async fn checkpoint(&self, name: &str, slot: u64) -> Result<()>;
+}
+
+pub struct PgIndexerStore {
+    pool: PgPool,
+}
+
+impl PgIndexerStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Wallets of all users and their associated token accounts for every listed asset. Incoming
+    /// token transfers only mention the token account, so those have to be subscribed to as well.
+    pub async fn indexed_accounts(&self) -> Result<Vec<String>> {
+        let wallets: Vec<String> = sqlx::query_scalar("SELECT public_key FROM users").fetch_all(&self.pool).await?;
+        let mints = self.mints().await?;
+        Ok(wallets.iter().filter_map(|wallet| Pubkey::from_str(wallet).ok()).flat_map(|wallet| wallet_accounts(&wallet, &mints)).collect())
+    }
+
+    /// The accounts of every wallet created from now on. Notifications sent while the connection
+    /// is down are lost, the accounts are picked up by the next `indexed_accounts`.
+    pub async fn new_wallet_accounts(&self) -> Result<impl Stream<Item = Vec<String>> + '_> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(NEW_WALLET_CHANNEL).await?;
+        Ok(listener.into_stream().filter_map(move |notification| async move {
+            let notification = notification.map_err(|e| warn!("new wallet listener error: {:?}", e)).ok()?;
+            let wallet = Pubkey::from_str(notification.payload()).ok()?;
+            match self.mints().await {
+                Ok(mints) => Some(wallet_accounts(&wallet, &mints)),
+                Err(e) => {
+                    warn!("DB error looking up the accounts of new wallet {}: {:?}", wallet, e);
+                    None
+                }
+            }
+        }))
+    }
+
+    async fn mints(&self) -> Result<Vec<Pubkey>> {
+        let mints: Vec<String> = sqlx::query_scalar("SELECT mint_address FROM assets").fetch_all(&self.pool).await?;
+        Ok(mints.iter().filter_map(|mint| Pubkey::from_str(mint).ok()).collect())
+    }
+}
+
+/// The wallet and its associated token accounts of both token programs for `mints`.
+fn wallet_accounts(wallet: &Pubkey, mints: &[Pubkey]) -> Vec<String> {
+    let associated_token_program = Pubkey::from_str(ASSOCIATED_TOKEN_PROGRAM_ID).unwrap();
+    let token_programs = [Pubkey::from_str(transfers::TOKEN_PROGRAM_ID).unwrap(), Pubkey::from_str(transfers::TOKEN_2022_PROGRAM_ID).unwrap()];
+    let mut accounts = vec![wallet.to_string()];
+    for mint in mints {
+        for token_program in &token_programs {
+            let (ata, _) = Pubkey::find_program_address(&[wallet.as_ref(), token_program.as_ref(), mint.as_ref()], &associated_token_program);
+            accounts.push(ata.to_string());
+        }
+    }
+    accounts
+}
+
+impl IndexerStore for PgIndexerStore {
+    async fn upsert_balance(&self, update: &BalanceUpdate) -> Result<bool> {
+        // Accounts of wallets that aren't users, or of mints that aren't listed, select nothing
+        let result = sqlx::query(
+            r#"
+            INSERT INTO balances (id, account, user_id, asset_id, amount, slot, write_version, updated_at)
+            SELECT gen_random_uuid()::TEXT, $1, users.id, assets.id, $4::NUMERIC, $5, $6, now()
+            FROM users, assets
+            WHERE users.public_key = $2 AND assets.mint_address = $3
+            ON CONFLICT (account, asset_id) DO UPDATE
+            SET amount = EXCLUDED.amount, user_id = EXCLUDED.user_id, slot = EXCLUDED.slot,
+                write_version = EXCLUDED.write_version, updated_at = now()
+            WHERE (balances.slot, balances.write_version) < (EXCLUDED.slot, EXCLUDED.write_version)
+            "#,
+        )
+        .bind(&update.account)
+        .bind(&update.wallet)
+        .bind(&update.mint)
+        .bind(update.amount.to_string())
+        .bind(update.slot as i64)
+        .bind(update.write_version as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn insert_transfers(&self, transaction: &Transaction, block_time: Option<i64>) -> Result<()> {
+        for transfer in transaction.transfers(|_| true) {
+            let Some(user_id) = sqlx::query_scalar::<_, String>("SELECT id FROM users WHERE public_key = $1")
+                .bind(&transfer.wallet)
+                .fetch_optional(&self.pool)
+                .await?
+            else {
+                continue;
+            };
+            // The column is a BIGINT, token amounts above it are valid but can't be stored
+            let Ok(amount) = i64::try_from(transfer.amount) else {
+                warn!("skipped transfer {} of {}, amount {} overflows BIGINT", transfer.instruction_index, transaction.signature, transfer.amount);
+                continue;
+            };
+            sqlx::query(
+                r#"
+                INSERT INTO wallet_transactions
+                    (id, user_id, signature, slot, instruction_index, direction, counterparty, mint, amount, block_time)
+                VALUES (gen_random_uuid()::TEXT, $1, $2, $3, $4, $5, $6, $7, $8, COALESCE(to_timestamp($9::DOUBLE PRECISION), now()))
+                ON CONFLICT (user_id, signature, instruction_index, direction) DO NOTHING
+                "#,
+            )
+            .bind(user_id)
+            .bind(&transaction.signature)
+            .bind(transaction.slot as i64)
+            .bind(transfer.instruction_index as i32)
+            .bind(transfer.direction.as_str())
+            .bind(&transfer.counterparty)
+            .bind(&transfer.mint)
+            .bind(amount)
+            .bind(block_time)
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn checkpoint(&self, name: &str, slot: u64) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO indexer_checkpoints (name, slot, updated_at) VALUES ($1, $2, now())
+            ON CONFLICT (name) DO UPDATE SET slot = EXCLUDED.slot, updated_at = now()
+            WHERE indexer_checkpoints.slot < EXCLUDED.slot
+            "#,
+        )
+        .bind(name)
+        .bind(slot as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
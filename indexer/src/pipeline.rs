@@ -0,0 +1,315 @@
+//! Consume a Yellowstone subscription: balances from account updates, transfers from
+//! transactions dated by their block's meta and a checkpoint from every confirmed slot.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::Result;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use log::{debug, info, warn};
+use tonic::Status;
+use yellowstone_grpc_proto::prelude::{
+    subscribe_update::UpdateOneof, CommitmentLevel, SubscribeRequest, SubscribeRequestFilterAccounts, SubscribeRequestFilterBlocksMeta,
+    SubscribeRequestFilterSlots, SubscribeRequestFilterTransactions, SubscribeRequestPing, SubscribeUpdate,
+};
+
+use crate::{balances::BalanceUpdate, db::IndexerStore, transfers::Transaction};
+
+pub const CHECKPOINT: &str = "yellowstone";
+/// How far behind the latest confirmed slot block times are kept. Transactions still waiting
+/// for their block's meta by then are stored with the time of indexing.
+const BLOCK_TIME_SLOTS: u64 = 64;
+
+/// Account updates and transactions of `accounts`, the meta of every block for the time of
+/// its transactions, plus confirmed slots for the checkpoint.
+pub fn subscribe_request(accounts: Vec<String>) -> SubscribeRequest {
+    let account_filter = SubscribeRequestFilterAccounts { account: accounts.clone(), ..Default::default() };
+    let transaction_filter = SubscribeRequestFilterTransactions {
+        vote: Some(false),
+        failed: Some(false),
+        account_include: accounts,
+        ..Default::default()
+    };
+    let slot_filter = SubscribeRequestFilterSlots { filter_by_commitment: Some(true), ..Default::default() };
+    SubscribeRequest {
+        accounts: HashMap::from([("wallets".to_string(), account_filter)]),
+        transactions: HashMap::from([("wallets".to_string(), transaction_filter)]),
+        slots: HashMap::from([("slots".to_string(), slot_filter)]),
+        blocks_meta: HashMap::from([("blocks".to_string(), SubscribeRequestFilterBlocksMeta {})]),
+        commitment: Some(CommitmentLevel::Confirmed as i32),
+        ..Default::default()
+    }
+}
+
+/// Subscribe to `accounts` and process updates until the stream ends, or fails so the caller
+/// can reconnect. Accounts arriving on `new_accounts` are added by resending the whole request,
+/// a new request replaces the filters of the previous one.
+pub async fn run<S, R, N, B>(updates: S, requests: R, accounts: Vec<String>, new_accounts: N, store: &B) -> Result<()>
+where
+    S: Stream<Item = Result<SubscribeUpdate, Status>>,
+    R: Sink<SubscribeRequest>,
+    R::Error: std::error::Error + Send + Sync + 'static,
+    N: Stream<Item = Vec<String>>,
+    B: IndexerStore,
+{
+    let mut updates = std::pin::pin!(updates);
+    let mut requests = std::pin::pin!(requests);
+    let mut new_accounts = std::pin::pin!(new_accounts);
+    let mut accounts: BTreeSet<String> = accounts.into_iter().collect();
+    let mut block_times = BlockTimes::default();
+    requests.send(subscribe_request(accounts.iter().cloned().collect())).await?;
+    loop {
+        tokio::select! {
+            biased;
+            Some(added) = new_accounts.next() => {
+                let before = accounts.len();
+                accounts.extend(added);
+                if accounts.len() > before {
+                    info!("subscribing to {} new accounts", accounts.len() - before);
+                    requests.send(subscribe_request(accounts.iter().cloned().collect())).await?;
+                }
+            }
+            update = updates.next() => match update {
+                Some(update) => handle_update(update?, &mut requests, store, &mut block_times).await?,
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+/// Block times of recent slots. The stream sends a block's meta separately from its
+/// transactions and not necessarily first, so transactions wait here for it.
+#[derive(Default)]
+struct BlockTimes {
+    /// Unix seconds, `None` if the cluster has no time for the block
+    times: BTreeMap<u64, Option<i64>>,
+    pending: BTreeMap<u64, Vec<Transaction>>,
+}
+
+/// Pings are answered on `requests`, the server drops subscriptions that stay silent. A failed
+/// write is logged and skipped, the next update of the account repairs its balance.
+async fn handle_update<R, B>(update: SubscribeUpdate, requests: &mut R, store: &B, block_times: &mut BlockTimes) -> Result<()>
+where
+    R: Sink<SubscribeRequest> + Unpin,
+    R::Error: std::error::Error + Send + Sync + 'static,
+    B: IndexerStore,
+{
+    match update.update_oneof {
+        Some(UpdateOneof::Account(account)) => {
+            let Some(balance) = BalanceUpdate::from_update(&account) else {
+                return Ok(());
+            };
+            match store.upsert_balance(&balance).await {
+                Ok(true) => {}
+                Ok(false) => debug!("skipped balance of {} at slot {}, stale or not a user's", balance.account, balance.slot),
+                Err(e) => warn!("DB upsert error for {}: {:?}", balance.account, e),
+            }
+        }
+        Some(UpdateOneof::Transaction(transaction)) => {
+            // failed transactions and updates without a transaction move no funds
+            let Some(transaction) = Transaction::from_update(&transaction) else {
+                return Ok(());
+            };
+            match block_times.times.get(&transaction.slot) {
+                Some(&block_time) => insert_transfers(store, &transaction, block_time).await,
+                None => block_times.pending.entry(transaction.slot).or_default().push(transaction),
+            }
+        }
+        Some(UpdateOneof::BlockMeta(meta)) => {
+            let block_time = meta.block_time.map(|time| time.timestamp);
+            block_times.times.insert(meta.slot, block_time);
+            for transaction in block_times.pending.remove(&meta.slot).unwrap_or_default() {
+                insert_transfers(store, &transaction, block_time).await;
+            }
+        }
+        Some(UpdateOneof::Slot(slot)) => {
+            if let Err(e) = store.checkpoint(CHECKPOINT, slot.slot).await {
+                warn!("DB checkpoint error at slot {}: {:?}", slot.slot, e);
+            }
+            let oldest = slot.slot.saturating_sub(BLOCK_TIME_SLOTS);
+            block_times.times = block_times.times.split_off(&oldest);
+            let recent = block_times.pending.split_off(&oldest);
+            for (slot, transactions) in std::mem::replace(&mut block_times.pending, recent) {
+                warn!("no block meta for slot {}, storing {} transactions without block time", slot, transactions.len());
+                for transaction in transactions {
+                    insert_transfers(store, &transaction, None).await;
+                }
+            }
+        }
+        Some(UpdateOneof::Ping(_)) => {
+            requests.send(SubscribeRequest { ping: Some(SubscribeRequestPing { id: 1 }), ..Default::default() }).await?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn insert_transfers<B: IndexerStore>(store: &B, transaction: &Transaction, block_time: Option<i64>) {
+    if let Err(e) = store.insert_transfers(transaction, block_time).await {
+        warn!("DB transfer insert error for {}: {:?}", transaction.signature, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use anyhow::Result;
+    use futures::{channel::mpsc, stream, StreamExt};
+    use tonic::Status;
+    use yellowstone_grpc_proto::prelude::{
+        subscribe_update::UpdateOneof, Message, SubscribeUpdate, SubscribeUpdateAccount, SubscribeUpdateAccountInfo,
+        SubscribeUpdateBlockMeta, SubscribeUpdatePing, SubscribeUpdateSlot, SubscribeUpdateTransaction,
+        SubscribeUpdateTransactionInfo, Transaction as ProtoTransaction, TransactionStatusMeta, UnixTimestamp,
+    };
+
+    use super::{run, CHECKPOINT};
+    use crate::{
+        balances::{BalanceUpdate, NATIVE_MINT},
+        db::IndexerStore,
+        transfers::{Transaction, SYSTEM_PROGRAM_ID},
+    };
+
+    /// Applies the same slot ordering as the `balances` upsert.
+    #[derive(Default)]
+    struct MemoryStore {
+        balances: Mutex<HashMap<(String, String), BalanceUpdate>>,
+        /// Signature and block time of every stored transaction
+        transactions: Mutex<Vec<(String, Option<i64>)>>,
+        checkpoints: Mutex<HashMap<String, u64>>,
+    }
+
+    impl IndexerStore for MemoryStore {
+        async fn upsert_balance(&self, update: &BalanceUpdate) -> Result<bool> {
+            let mut balances = self.balances.lock().unwrap();
+            let key = (update.account.clone(), update.mint.clone());
+            if balances.get(&key).is_some_and(|stored| !update.supersedes(stored.slot, stored.write_version)) {
+                return Ok(false);
+            }
+            balances.insert(key, update.clone());
+            Ok(true)
+        }
+
+        async fn insert_transfers(&self, transaction: &Transaction, block_time: Option<i64>) -> Result<()> {
+            self.transactions.lock().unwrap().push((transaction.signature.clone(), block_time));
+            Ok(())
+        }
+
+        async fn checkpoint(&self, name: &str, slot: u64) -> Result<()> {
+            let mut checkpoints = self.checkpoints.lock().unwrap();
+            let checkpoint = checkpoints.entry(name.to_string()).or_default();
+            *checkpoint = (*checkpoint).max(slot);
+            Ok(())
+        }
+    }
+
+    fn update(update_oneof: UpdateOneof) -> Result<SubscribeUpdate, Status> {
+        Ok(SubscribeUpdate { update_oneof: Some(update_oneof), ..Default::default() })
+    }
+
+    fn lamports(lamports: u64, slot: u64, write_version: u64) -> Result<SubscribeUpdate, Status> {
+        update(UpdateOneof::Account(SubscribeUpdateAccount {
+            account: Some(SubscribeUpdateAccountInfo {
+                pubkey: vec![1; 32],
+                lamports,
+                owner: bs58::decode(SYSTEM_PROGRAM_ID).into_vec().unwrap(),
+                write_version,
+                ..Default::default()
+            }),
+            slot,
+            ..Default::default()
+        }))
+    }
+
+    fn slot(slot: u64) -> Result<SubscribeUpdate, Status> {
+        update(UpdateOneof::Slot(SubscribeUpdateSlot { slot, ..Default::default() }))
+    }
+
+    fn transaction(signature: u8, slot: u64) -> Result<SubscribeUpdate, Status> {
+        update(UpdateOneof::Transaction(SubscribeUpdateTransaction {
+            transaction: Some(SubscribeUpdateTransactionInfo {
+                signature: vec![signature; 64],
+                transaction: Some(ProtoTransaction { message: Some(Message::default()), ..Default::default() }),
+                meta: Some(TransactionStatusMeta::default()),
+                ..Default::default()
+            }),
+            slot,
+        }))
+    }
+
+    fn block_meta(slot: u64, timestamp: i64) -> Result<SubscribeUpdate, Status> {
+        update(UpdateOneof::BlockMeta(SubscribeUpdateBlockMeta {
+            slot,
+            block_time: Some(UnixTimestamp { timestamp }),
+            ..Default::default()
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_balances_in_slot_order() {
+        let store = MemoryStore::default();
+        let (requests, _) = mpsc::unbounded();
+        // The update of slot 12 arrives before the one of slot 11, which must not win
+        let updates = stream::iter(vec![lamports(100, 10, 0), lamports(300, 12, 0), lamports(200, 11, 5), lamports(400, 12, 1), slot(12)]);
+        run(updates, requests, vec![], stream::empty(), &store).await.unwrap();
+
+        let wallet = bs58::encode([1; 32]).into_string();
+        let balances = store.balances.lock().unwrap();
+        let balance = &balances[&(wallet, NATIVE_MINT.to_string())];
+        assert_eq!((balance.amount, balance.slot, balance.write_version), (400, 12, 1));
+        assert_eq!(store.checkpoints.lock().unwrap()[CHECKPOINT], 12);
+    }
+
+    #[tokio::test]
+    async fn test_transactions_get_block_time() {
+        let store = MemoryStore::default();
+        let (requests, _) = mpsc::unbounded();
+        // Slot 10's transaction waits for the meta, slot 11's meta came first, slot 12 never gets one
+        let updates = stream::iter(vec![
+            transaction(1, 10),
+            block_meta(11, 1_700_000_011),
+            block_meta(10, 1_700_000_010),
+            transaction(2, 11),
+            transaction(3, 12),
+            slot(12 + super::BLOCK_TIME_SLOTS + 1),
+        ]);
+        run(updates, requests, vec![], stream::empty(), &store).await.unwrap();
+
+        let signature = |byte: u8| bs58::encode([byte; 64]).into_string();
+        assert_eq!(
+            *store.transactions.lock().unwrap(),
+            vec![(signature(1), Some(1_700_000_010)), (signature(2), Some(1_700_000_011)), (signature(3), None)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_answer_pings() {
+        let store = MemoryStore::default();
+        let (requests, mut sent) = mpsc::unbounded();
+        let updates = stream::iter(vec![update(UpdateOneof::Ping(SubscribeUpdatePing {})), slot(3)]);
+        run(updates, requests, vec![], stream::empty(), &store).await.unwrap();
+        assert!(sent.next().await.unwrap().ping.is_none());
+        assert!(sent.next().await.unwrap().ping.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_resubscribe_with_new_accounts() {
+        let store = MemoryStore::default();
+        let (requests, mut sent) = mpsc::unbounded();
+        let new_accounts = stream::iter(vec![vec!["b".to_string()], vec!["a".to_string()], vec!["c".to_string(), "b".to_string()]]);
+        run(stream::empty(), requests, vec!["a".to_string()], new_accounts, &store).await.unwrap();
+
+        // Every request carries all accounts, one that adds nothing isn't sent
+        let subscribed: Vec<Vec<String>> = sent.map(|request| request.accounts["wallets"].account.clone()).collect().await;
+        assert_eq!(subscribed, vec![vec!["a"], vec!["a", "b"], vec!["a", "b", "c"]]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_error_ends_run() {
+        let store = MemoryStore::default();
+        let (requests, _) = mpsc::unbounded();
+        let updates = stream::iter(vec![slot(1), Err(Status::unavailable("gone")), slot(2)]);
+        assert!(run(updates, requests, vec![], stream::empty(), &store).await.is_err());
+        // Nothing after the error is processed, the reconnect starts over
+        assert_eq!(store.checkpoints.lock().unwrap()[CHECKPOINT], 1);
+    }
+}
@@ -0,0 +1,247 @@
+//! Decode the SOL and SPL token transfers of a transaction that move funds into or out of the
+//! wallets we index. Inner instructions count too, swaps and programs paying out move funds there.
+
+use std::collections::HashMap;
+
+use yellowstone_grpc_proto::prelude::{SubscribeUpdateTransaction, TokenBalance};
+
+pub const SYSTEM_PROGRAM_ID: &str = "11111111111111111111111111111111";
+pub const TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+pub const TOKEN_2022_PROGRAM_ID: &str = "TokenzQdBNbLqP5VGh5aRGcrJQ4wrT5rmd4Q7jDzFvqm";
+
+const SYSTEM_TRANSFER: u32 = 2;
+const SYSTEM_TRANSFER_WITH_SEED: u32 = 11;
+const TOKEN_TRANSFER: u8 = 3;
+const TOKEN_TRANSFER_CHECKED: u8 = 12;
+
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    pub program_id_index: usize,
+    pub accounts: Vec<usize>,
+    pub data: Vec<u8>,
+}
+
+/// Mint and owner of a token account the transaction touched.
+#[derive(Debug, Clone)]
+pub struct TokenAccount {
+    pub mint: String,
+    pub owner: String,
+}
+
+/// What decoding needs of a transaction, independent of how it was received.
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    pub signature: String,
+    pub slot: u64,
+    /// Static keys followed by the writable and readonly ones loaded from lookup tables
+    pub account_keys: Vec<String>,
+    /// Top level instructions in order, each followed by its inner instructions
+    pub instructions: Vec<Instruction>,
+    /// By account index
+    pub token_accounts: HashMap<usize, TokenAccount>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::In => "in",
+            Direction::Out => "out",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transfer {
+    /// The indexed wallet the transfer belongs to
+    pub wallet: String,
+    pub direction: Direction,
+    pub counterparty: String,
+    /// `None` for SOL
+    pub mint: Option<String>,
+    pub amount: u64,
+    /// Position in `Transaction::instructions`
+    pub instruction_index: usize,
+}
+
+impl Transaction {
+    /// `None` for updates without a transaction or for failed transactions, those moved no funds.
+    pub fn from_update(update: &SubscribeUpdateTransaction) -> Option<Self> {
+        let info = update.transaction.as_ref()?;
+        let message = info.transaction.as_ref()?.message.as_ref()?;
+        let meta = info.meta.as_ref()?;
+        if meta.err.is_some() {
+            return None;
+        }
+
+        let account_keys = message
+            .account_keys
+            .iter()
+            .chain(&meta.loaded_writable_addresses)
+            .chain(&meta.loaded_readonly_addresses)
+            .map(|key| bs58::encode(key).into_string())
+            .collect();
+        let compiled = |program_id_index: u32, accounts: &Vec<u8>, data: &Vec<u8>| Instruction {
+            program_id_index: program_id_index as usize,
+            accounts: accounts.iter().map(|&index| usize::from(index)).collect(),
+            data: data.clone(),
+        };
+        let mut instructions = Vec::new();
+        for (index, ix) in message.instructions.iter().enumerate() {
+            instructions.push(compiled(ix.program_id_index, &ix.accounts, &ix.data));
+            for inner in meta.inner_instructions.iter().filter(|inner| inner.index as usize == index) {
+                instructions.extend(inner.instructions.iter().map(|ix| compiled(ix.program_id_index, &ix.accounts, &ix.data)));
+            }
+        }
+        // An account closed by the transaction only shows up in the pre balances
+        let token_accounts = meta
+            .pre_token_balances
+            .iter()
+            .chain(&meta.post_token_balances)
+            .map(|balance: &TokenBalance| {
+                (balance.account_index as usize, TokenAccount { mint: balance.mint.clone(), owner: balance.owner.clone() })
+            })
+            .collect();
+
+        Some(Self {
+            signature: bs58::encode(&info.signature).into_string(),
+            slot: update.slot,
+            account_keys,
+            instructions,
+            token_accounts,
+        })
+    }
+
+    /// Every transfer into or out of a wallet `is_indexed` accepts. A transfer between two indexed
+    /// wallets shows up once for each of them.
+    pub fn transfers(&self, is_indexed: impl Fn(&str) -> bool) -> Vec<Transfer> {
+        let mut transfers = Vec::new();
+        for (instruction_index, ix) in self.instructions.iter().enumerate() {
+            let Some((from, to, mint, amount)) = self.decode(ix) else {
+                continue;
+            };
+            if from == to || amount == 0 {
+                continue;
+            }
+            for (wallet, direction, counterparty) in [(&from, Direction::Out, &to), (&to, Direction::In, &from)] {
+                if is_indexed(wallet) {
+                    transfers.push(Transfer {
+                        wallet: wallet.clone(),
+                        direction,
+                        counterparty: counterparty.clone(),
+                        mint: mint.clone(),
+                        amount,
+                        instruction_index,
+                    });
+                }
+            }
+        }
+        transfers
+    }
+
+    fn key(&self, ix: &Instruction, position: usize) -> Option<&String> {
+        self.account_keys.get(*ix.accounts.get(position)?)
+    }
+
+    /// Sending wallet, receiving wallet, mint and amount. Token accounts are resolved to their owners.
+    fn decode(&self, ix: &Instruction) -> Option<(String, String, Option<String>, u64)> {
+        let program_id = self.account_keys.get(ix.program_id_index)?;
+        match program_id.as_str() {
+            SYSTEM_PROGRAM_ID => {
+                let kind = u32::from_le_bytes(ix.data.get(..4)?.try_into().ok()?);
+                let lamports = u64::from_le_bytes(ix.data.get(4..12)?.try_into().ok()?);
+                let (from, to) = match kind {
+                    SYSTEM_TRANSFER => (0, 1),
+                    SYSTEM_TRANSFER_WITH_SEED => (0, 2),
+                    _ => return None,
+                };
+                Some((self.key(ix, from)?.clone(), self.key(ix, to)?.clone(), None, lamports))
+            }
+            TOKEN_PROGRAM_ID | TOKEN_2022_PROGRAM_ID => {
+                let (source, destination) = match *ix.data.first()? {
+                    TOKEN_TRANSFER => (0, 1),
+                    TOKEN_TRANSFER_CHECKED => (0, 2),
+                    _ => return None,
+                };
+                let amount = u64::from_le_bytes(ix.data.get(1..9)?.try_into().ok()?);
+                let source = self.token_accounts.get(ix.accounts.get(source)?)?;
+                let destination = self.token_accounts.get(ix.accounts.get(destination)?)?;
+                Some((source.owner.clone(), destination.owner.clone(), Some(source.mint.clone()), amount))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const WALLET: &str = "3ucNos4NbumPLZNWztqGHNFFgkHeRMBQAVemeeomsUxv";
+    const OTHER: &str = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+    const MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+    fn system_transfer(lamports: u64) -> Instruction {
+        let mut data = SYSTEM_TRANSFER.to_le_bytes().to_vec();
+        data.extend(lamports.to_le_bytes());
+        Instruction { program_id_index: 2, accounts: vec![0, 1], data }
+    }
+
+    fn token_transfer(amount: u64) -> Instruction {
+        let mut data = vec![TOKEN_TRANSFER_CHECKED];
+        data.extend(amount.to_le_bytes());
+        data.push(6);
+        Instruction { program_id_index: 3, accounts: vec![5, 6, 4, 1], data }
+    }
+
+    fn transaction(instructions: Vec<Instruction>) -> Transaction {
+        let account_keys = [OTHER, WALLET, SYSTEM_PROGRAM_ID, TOKEN_PROGRAM_ID, "wallet-ata", "other-ata", MINT]
+            .map(str::to_string)
+            .to_vec();
+        let token_accounts = HashMap::from([
+            (4, TokenAccount { mint: MINT.to_string(), owner: WALLET.to_string() }),
+            (5, TokenAccount { mint: MINT.to_string(), owner: OTHER.to_string() }),
+        ]);
+        Transaction { signature: "sig".to_string(), slot: 1, account_keys, instructions, token_accounts }
+    }
+
+    #[test]
+    fn test_decode_transfers() {
+        let tx = transaction(vec![system_transfer(5_000), token_transfer(1_000_000)]);
+        let transfers = tx.transfers(|wallet| wallet == WALLET);
+        assert_eq!(transfers, vec![
+            Transfer { wallet: WALLET.to_string(), direction: Direction::In, counterparty: OTHER.to_string(), mint: None, amount: 5_000, instruction_index: 0 },
+            Transfer {
+                wallet: WALLET.to_string(),
+                direction: Direction::In,
+                counterparty: OTHER.to_string(),
+                mint: Some(MINT.to_string()),
+                amount: 1_000_000,
+                instruction_index: 1,
+            },
+        ]);
+
+        // Both ends indexed, each wallet gets its side
+        let transfers = tx.transfers(|_| true);
+        assert_eq!(transfers.len(), 4);
+        assert_eq!(transfers[0].direction, Direction::Out);
+        assert_eq!(transfers[0].wallet, OTHER);
+    }
+
+    #[test]
+    fn test_ignore_other_instructions() {
+        let mut create_account = system_transfer(1);
+        create_account.data[..4].copy_from_slice(&0u32.to_le_bytes());
+        let mut truncated = token_transfer(1);
+        truncated.data.truncate(4);
+        let tx = transaction(vec![create_account, truncated, system_transfer(0)]);
+        assert!(tx.transfers(|_| true).is_empty());
+    }
+}
@@ -1,8 +1,8 @@
 use std::fmt::{Display, Formatter};
 
 use bs58::decode::Error as Bs58Error;
-use solana_client::client_error::ClientError;
 
+use crate::rpc::RpcError;
 use crate::serialization::Error as DeserializationError;
 
 #[derive(Debug)]
@@ -10,15 +10,29 @@ pub enum Error {
     WrongNetwork(String),
     BadBase58(Bs58Error),
     WrongKeyPair(ed25519_dalek::SignatureError),
-    AirdropFailed(ClientError),
-    RecentHashFailed(ClientError),
-    ConfirmingTransactionFailed(ClientError),
-    BalaceFailed(ClientError),
-    SendTransactionFailed(ClientError),
+    AirdropFailed(RpcError),
+    RecentHashFailed(RpcError),
+    ConfirmingTransactionFailed(RpcError),
+    BalaceFailed(RpcError),
+    SendTransactionFailed(RpcError),
     DeserializationFailed { error: DeserializationError, field_name: &'static str },
     MismatchMessages,
     InvalidSignature,
     KeyPairIsNotInKeys,
+    InvalidMessage,
+    AggregatedKeyNotFeePayer,
+    UnexpectedSigners(u8),
+    InvalidThreshold { threshold: u16, max_signers: u16 },
+    InvalidParticipants,
+    InvalidProofOfKnowledge(u16),
+    InvalidShare(u16),
+    UnauthenticatedPackage(u16),
+    InvalidSignatureShare(u16),
+    InvalidKeyShare,
+    InvalidRecipient,
+    ShareEncryptionFailed,
+    ShareDecryptionFailed,
+    WalletMismatch,
 }
 
 impl Display for Error {
@@ -38,6 +52,22 @@ impl Display for Error {
             Self::MismatchMessages => write!(f, "There is a mismatch between first_messages and second_messages"),
             Self::InvalidSignature => write!(f, "The resulting signature doesn't match the transaction"),
             Self::KeyPairIsNotInKeys => write!(f, "The provided keypair is not in the list of pubkeys"),
+            Self::InvalidMessage => write!(f, "The message is malformed"),
+            Self::AggregatedKeyNotFeePayer => write!(f, "The aggregated key is not the fee payer of the message"),
+            Self::UnexpectedSigners(n) => write!(f, "The message requires {} signatures, only the aggregated key can sign", n),
+            Self::InvalidThreshold { threshold, max_signers } => {
+                write!(f, "Invalid threshold {} of {}, it has to be between 2 and the number of parties", threshold, max_signers)
+            }
+            Self::InvalidParticipants => write!(f, "The set of participating parties is invalid"),
+            Self::InvalidProofOfKnowledge(party) => write!(f, "Party {} sent an invalid proof of knowledge", party),
+            Self::InvalidShare(party) => write!(f, "Party {} sent an invalid secret share", party),
+            Self::UnauthenticatedPackage(party) => write!(f, "The round one package of party {} is not signed by its identity key", party),
+            Self::InvalidSignatureShare(party) => write!(f, "Party {} sent an invalid signature share", party),
+            Self::InvalidKeyShare => write!(f, "The stored key share is malformed"),
+            Self::InvalidRecipient => write!(f, "The recipient is not a valid ed25519 public key"),
+            Self::ShareEncryptionFailed => write!(f, "Failed encrypting the key share"),
+            Self::ShareDecryptionFailed => write!(f, "Failed decrypting the key share, it was sealed to a different key or tampered with"),
+            Self::WalletMismatch => write!(f, "The key shares don't belong to the requested wallet"),
         }
     }
 }
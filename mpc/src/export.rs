@@ -0,0 +1,268 @@
+//! Taking a wallet off the share servers. Every share server encrypts its share to a key the user
+//! controls with [`seal_share`], the user collects the sealed shares and rebuilds a wallet that
+//! signs on its own with [`reconstruct`].
+//!
+//! The recipient key is a regular Solana keypair, its public key is what the user hands to the
+//! export request. MuSig2 wallets come back as the set of party keypairs, which sign by running
+//! both MuSig2 rounds locally. FROST wallets come back as the group's secret scalar. Neither has
+//! an ed25519 seed behind it, so they can't be turned into a `Keypair`, but both implement
+//! [`Signer`] and work with any Solana tooling that takes one.
+
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use base64::engine::{general_purpose::STANDARD, Engine};
+use curv::arithmetic::Converter;
+use curv::elliptic::curves::{Ed25519, Point, Scalar};
+use curv::BigInt;
+use rand07::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer, SignerError};
+
+use crate::error::Error;
+use crate::frost::{self, KeyShare};
+use crate::tss;
+
+const CONTEXT: &[u8] = b"MPC-WALLET-EXPORT-v1";
+const NONCE_LEN: usize = 12;
+
+/// What a single share server releases for a wallet.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "scheme", rename_all = "lowercase")]
+pub enum ExportedShare {
+    /// The party's base58 encoded keypair
+    Musig2 { keypair: String },
+    Frost { key_share: KeyShare },
+}
+
+/// An [`ExportedShare`] encrypted to the recipient's public key with an ephemeral Diffie-Hellman
+/// exchange and AES-256-GCM.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SealedShare {
+    pub ephemeral_key: Point<Ed25519>,
+    /// Base64 of the nonce followed by the ciphertext
+    pub ciphertext: String,
+}
+
+/// A wallet rebuilt from its shares, it no longer needs the share servers to sign.
+pub enum ExportedWallet {
+    /// The keypairs of all parties, in the order the wallet was created with
+    Musig2 { keypairs: Vec<Keypair> },
+    Frost { secret: Scalar<Ed25519>, pubkey: Pubkey },
+}
+
+impl ExportedWallet {
+    pub fn pubkey(&self) -> Pubkey {
+        match self {
+            ExportedWallet::Frost { pubkey, .. } => *pubkey,
+            ExportedWallet::Musig2 { keypairs } => {
+                let keys = keypairs.iter().map(|keypair| keypair.pubkey()).collect();
+                let aggkey = tss::key_agg(keys, None).expect("the keys were aggregated when the wallet was rebuilt");
+                Pubkey::new(&*aggkey.agg_public_key.to_bytes(true))
+            }
+        }
+    }
+}
+
+impl Signer for ExportedWallet {
+    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+        Ok(self.pubkey())
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        match self {
+            ExportedWallet::Frost { secret, .. } => Ok(frost::sign_with_secret(secret, message)),
+            ExportedWallet::Musig2 { keypairs } => {
+                tss::sign_locally(keypairs, message).map_err(|e| SignerError::Custom(e.to_string()))
+            }
+        }
+    }
+
+    fn is_interactive(&self) -> bool {
+        false
+    }
+}
+
+/// Encrypt a share so only the holder of `recipient`'s secret key can read it.
+pub fn seal_share(recipient: &Pubkey, share: &ExportedShare) -> Result<SealedShare, Error> {
+    let recipient_point = recipient_point(recipient)?;
+    let ephemeral_secret = Scalar::<Ed25519>::random();
+    let ephemeral_key = Point::generator() * &ephemeral_secret;
+    let cipher = share_cipher(&(&recipient_point * &ephemeral_secret), &ephemeral_key, recipient);
+
+    let plaintext = serde_json::to_vec(share).map_err(|_| Error::InvalidKeyShare)?;
+    let mut nonce = [0u8; NONCE_LEN];
+    rand07::rngs::OsRng.fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: &plaintext, aad: CONTEXT })
+        .map_err(|_| Error::ShareEncryptionFailed)?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(SealedShare { ephemeral_key, ciphertext: STANDARD.encode(sealed) })
+}
+
+/// Decrypt a share sealed to `recipient`.
+pub fn open_share(recipient: &Keypair, sealed: &SealedShare) -> Result<ExportedShare, Error> {
+    let secret = recipient_secret(recipient);
+    let cipher = share_cipher(&(&sealed.ephemeral_key * &secret), &sealed.ephemeral_key, &recipient.pubkey());
+
+    let bytes = STANDARD.decode(&sealed.ciphertext).map_err(|_| Error::ShareDecryptionFailed)?;
+    if bytes.len() <= NONCE_LEN {
+        return Err(Error::ShareDecryptionFailed);
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: CONTEXT })
+        .map_err(|_| Error::ShareDecryptionFailed)?;
+    serde_json::from_slice(&plaintext).map_err(|_| Error::InvalidKeyShare)
+}
+
+/// Open the shares released by the share servers and rebuild the wallet `wallet` from them.
+/// MuSig2 shares have to be in the order of the parties in the topology.
+pub fn reconstruct(recipient: &Keypair, wallet: &Pubkey, sealed: &[SealedShare]) -> Result<ExportedWallet, Error> {
+    let shares = sealed.iter().map(|share| open_share(recipient, share)).collect::<Result<Vec<_>, _>>()?;
+
+    let exported = match shares.first() {
+        None => return Err(Error::InvalidParticipants),
+        Some(ExportedShare::Musig2 { .. }) => {
+            let keypairs = shares
+                .iter()
+                .map(|share| match share {
+                    ExportedShare::Musig2 { keypair } => Ok(Keypair::from_bytes(&bs58::decode(keypair).into_vec()?)?),
+                    ExportedShare::Frost { .. } => Err(Error::InvalidKeyShare),
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            tss::key_agg(keypairs.iter().map(|keypair| keypair.pubkey()).collect(), None)?;
+            ExportedWallet::Musig2 { keypairs }
+        }
+        Some(ExportedShare::Frost { .. }) => {
+            let key_shares = shares
+                .iter()
+                .map(|share| match share {
+                    ExportedShare::Frost { key_share } => Ok(key_share.clone()),
+                    ExportedShare::Musig2 { .. } => Err(Error::InvalidKeyShare),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let secret = frost::reconstruct_secret(&key_shares)?;
+            ExportedWallet::Frost { secret, pubkey: key_shares[0].group_pubkey() }
+        }
+    };
+    if exported.pubkey() != *wallet {
+        return Err(Error::WalletMismatch);
+    }
+    Ok(exported)
+}
+
+fn recipient_point(recipient: &Pubkey) -> Result<Point<Ed25519>, Error> {
+    let point = Point::<Ed25519>::from_bytes(recipient.as_ref()).map_err(|_| Error::InvalidRecipient)?;
+    if point.is_zero() {
+        return Err(Error::InvalidRecipient);
+    }
+    Ok(point)
+}
+
+/// The ed25519 secret scalar behind a keypair: the clamped first half of SHA-512 of the seed.
+fn recipient_secret(recipient: &Keypair) -> Scalar<Ed25519> {
+    let digest = Sha512::digest(recipient.secret().as_bytes());
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&digest[..32]);
+    bytes[0] &= 248;
+    bytes[31] &= 127;
+    bytes[31] |= 64;
+    bytes.reverse();
+    Scalar::from_bigint(&BigInt::from_bytes(&bytes))
+}
+
+fn share_cipher(shared: &Point<Ed25519>, ephemeral_key: &Point<Ed25519>, recipient: &Pubkey) -> Aes256Gcm {
+    let key = Sha256::new()
+        .chain_update(CONTEXT)
+        .chain_update(&*shared.to_bytes(true))
+        .chain_update(&*ephemeral_key.to_bytes(true))
+        .chain_update(recipient.as_ref())
+        .finalize();
+    Aes256Gcm::new(&key)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use solana_sdk::signature::{Keypair, Signer};
+
+    use super::{open_share, reconstruct, seal_share, ExportedShare, ExportedWallet};
+    use crate::error::Error;
+    use crate::frost::{dkg_part1, dkg_part2, dkg_part3, round1_message, DkgParty, DkgRound2Package, KeyShare};
+    use crate::tss::key_agg;
+
+    fn run_dkg(threshold: u16, max_signers: u16) -> Vec<KeyShare> {
+        let identities: Vec<_> = (0..max_signers).map(|_| Keypair::new()).collect();
+        let parties: BTreeMap<_, _> = identities
+            .iter()
+            .zip(1..)
+            .map(|(identity, id)| (id, DkgParty { party_id: format!("share-{}", id), identity_key: identity.pubkey() }))
+            .collect();
+        let (secrets, round1): (Vec<_>, Vec<_>) = identities
+            .iter()
+            .zip(1..)
+            .map(|(identity, id)| {
+                let (secret, mut package) = dkg_part1(id, threshold, parties.clone(), b"export").unwrap();
+                package.signature = identity.sign_message(&round1_message(b"export", &package));
+                (secret, package)
+            })
+            .unzip();
+        let round2: Vec<DkgRound2Package> =
+            secrets.iter().flat_map(|secret| dkg_part2(secret, &round1).unwrap()).collect();
+        secrets.iter().map(|secret| dkg_part3(secret, &round1, &round2).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_wrong_recipient() {
+        let recipient = Keypair::new();
+        let share = ExportedShare::Musig2 { keypair: Keypair::new().to_base58_string() };
+        let sealed = seal_share(&recipient.pubkey(), &share).unwrap();
+        assert!(open_share(&recipient, &sealed).is_ok());
+        assert!(matches!(open_share(&Keypair::new(), &sealed), Err(Error::ShareDecryptionFailed)));
+    }
+
+    #[test]
+    fn test_reconstruct_musig2() {
+        let recipient = Keypair::new();
+        let parties = [Keypair::new(), Keypair::new()];
+        let aggkey = key_agg(parties.iter().map(|party| party.pubkey()).collect(), None).unwrap();
+        let wallet = solana_sdk::pubkey::Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
+        let sealed: Vec<_> = parties
+            .iter()
+            .map(|party| seal_share(&recipient.pubkey(), &ExportedShare::Musig2 { keypair: party.to_base58_string() }).unwrap())
+            .collect();
+
+        let exported = reconstruct(&recipient, &wallet, &sealed).unwrap();
+        assert!(matches!(exported, ExportedWallet::Musig2 { .. }));
+        let message = b"exported musig2 wallet";
+        assert!(exported.sign_message(message).verify(wallet.as_ref(), message));
+
+        assert!(matches!(reconstruct(&recipient, &Keypair::new().pubkey(), &sealed), Err(Error::WalletMismatch)));
+        assert!(matches!(reconstruct(&Keypair::new(), &wallet, &sealed), Err(Error::ShareDecryptionFailed)));
+    }
+
+    #[test]
+    fn test_reconstruct_frost() {
+        let recipient = Keypair::new();
+        let shares = run_dkg(2, 3);
+        let wallet = shares[0].group_pubkey();
+        let sealed: Vec<_> = shares[1..]
+            .iter()
+            .map(|share| seal_share(&recipient.pubkey(), &ExportedShare::Frost { key_share: share.clone() }).unwrap())
+            .collect();
+
+        let exported = reconstruct(&recipient, &wallet, &sealed).unwrap();
+        assert_eq!(exported.pubkey(), wallet);
+        let message = b"exported frost wallet";
+        assert!(exported.sign_message(message).verify(wallet.as_ref(), message));
+
+        let other_wallet = run_dkg(2, 3)[0].group_pubkey();
+        assert!(matches!(reconstruct(&recipient, &other_wallet, &sealed), Err(Error::WalletMismatch)));
+        assert!(matches!(reconstruct(&recipient, &wallet, &sealed[..1]), Err(Error::InvalidParticipants)));
+    }
+}
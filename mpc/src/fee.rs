@@ -0,0 +1,195 @@
+//! Priority fees for coordinator-built transactions: how much to bid per compute unit, how many
+//! units to ask for, and what a message ends up costing.
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    compute_budget::{self, ComputeBudgetInstruction},
+    instruction::{AccountMeta, Instruction},
+    message::{Message, VersionedMessage},
+};
+
+use crate::{nonce, SIGNATURE_FEE_LAMPORTS};
+
+pub const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+/// What the runtime grants every instruction without a `SetComputeUnitLimit`
+const DEFAULT_INSTRUCTION_COMPUTE_UNITS: u32 = 200_000;
+const MICRO_LAMPORTS_PER_LAMPORT: u128 = 1_000_000;
+/// Headroom over a simulation's consumption, the units used can shift between simulation and landing
+const COMPUTE_UNIT_MARGIN_PERCENT: u64 = 20;
+const MIN_COMPUTE_UNIT_MARGIN: u64 = 1_000;
+
+const SET_COMPUTE_UNIT_LIMIT: u8 = 2;
+const SET_COMPUTE_UNIT_PRICE: u8 = 3;
+
+/// How the coordinator prices a transaction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "lowercase")]
+pub enum FeeStrategy {
+    /// No compute budget instructions, the base fee only
+    #[default]
+    None,
+    Fixed { micro_lamports: u64 },
+    /// This percentile of the fees recently paid to write the transaction's accounts
+    Percentile { percentile: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComputeBudget {
+    pub unit_limit: u32,
+    /// Price per compute unit
+    pub micro_lamports: u64,
+}
+
+impl ComputeBudget {
+    /// Enough units for what a simulation consumed, with some headroom.
+    pub fn for_units_consumed(units_consumed: u64, micro_lamports: u64) -> Self {
+        let margin = (units_consumed * COMPUTE_UNIT_MARGIN_PERCENT / 100).max(MIN_COMPUTE_UNIT_MARGIN);
+        let unit_limit = (units_consumed + margin).min(u64::from(MAX_COMPUTE_UNIT_LIMIT)) as u32;
+        Self { unit_limit, micro_lamports }
+    }
+
+    /// Lamports paid on top of the signature fees, the whole limit is charged whatever is used.
+    pub fn priority_fee(&self) -> u64 {
+        let micro_lamports = u128::from(self.unit_limit) * u128::from(self.micro_lamports);
+        micro_lamports.div_ceil(MICRO_LAMPORTS_PER_LAMPORT) as u64
+    }
+}
+
+/// `percentile` of the recently paid fees, 0 if nothing was paid.
+pub fn percentile_fee(mut fees: Vec<u64>, percentile: u8) -> u64 {
+    if fees.is_empty() {
+        return 0;
+    }
+    fees.sort_unstable();
+    let index = (fees.len() - 1) * usize::from(percentile.min(100)) / 100;
+    fees[index]
+}
+
+/// Prepend the compute unit limit and price to a message, replacing any budget it already had.
+/// Fee payer and blockhash stay the same, as does a durable nonce advance in front.
+pub fn with_compute_budget(message: &Message, budget: &ComputeBudget) -> Message {
+    let (advance, rest): (Vec<_>, Vec<_>) = decompile(message)
+        .into_iter()
+        .filter(|ix| ix.program_id != compute_budget::id())
+        .enumerate()
+        .partition(|(index, ix)| *index == 0 && nonce::is_advance_nonce(&ix.program_id, &ix.data));
+    let mut instructions: Vec<Instruction> = advance.into_iter().map(|(_, ix)| ix).collect();
+    instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(budget.unit_limit));
+    instructions.push(ComputeBudgetInstruction::set_compute_unit_price(budget.micro_lamports));
+    instructions.extend(rest.into_iter().map(|(_, ix)| ix));
+    Message::new_with_blockhash(&instructions, message.account_keys.first(), &message.recent_blockhash)
+}
+
+pub(crate) fn decompile(message: &Message) -> Vec<Instruction> {
+    message
+        .instructions
+        .iter()
+        .map(|ix| Instruction {
+            program_id: message.account_keys[usize::from(ix.program_id_index)],
+            accounts: ix
+                .accounts
+                .iter()
+                .map(|&index| {
+                    let index = usize::from(index);
+                    AccountMeta {
+                        pubkey: message.account_keys[index],
+                        is_signer: message.is_signer(index),
+                        is_writable: message.is_writable(index),
+                    }
+                })
+                .collect(),
+            data: ix.data.clone(),
+        })
+        .collect()
+}
+
+/// Everything the fee payer is charged for `message`: the signatures plus the priority fee of
+/// its compute budget, if it sets one.
+pub fn message_fee(message: &VersionedMessage) -> u64 {
+    let keys = message.static_account_keys();
+    let mut unit_limit = None;
+    let mut micro_lamports = 0;
+    let mut other_instructions = 0u32;
+    for ix in message.instructions() {
+        if keys.get(usize::from(ix.program_id_index)) != Some(&compute_budget::id()) {
+            other_instructions += 1;
+            continue;
+        }
+        match ix.data.split_first() {
+            Some((&SET_COMPUTE_UNIT_LIMIT, units)) => {
+                unit_limit = units.try_into().ok().map(u32::from_le_bytes);
+            }
+            Some((&SET_COMPUTE_UNIT_PRICE, price)) => {
+                micro_lamports = price.try_into().map(u64::from_le_bytes).unwrap_or(0);
+            }
+            _ => {}
+        }
+    }
+    let unit_limit = unit_limit
+        .unwrap_or(other_instructions.saturating_mul(DEFAULT_INSTRUCTION_COMPUTE_UNITS))
+        .min(MAX_COMPUTE_UNIT_LIMIT);
+    let signatures = u64::from(message.header().num_required_signatures);
+    signatures * SIGNATURE_FEE_LAMPORTS + ComputeBudget { unit_limit, micro_lamports }.priority_fee()
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{compute_budget, hash::Hash, message::{Message, VersionedMessage}, pubkey::Pubkey, system_instruction};
+
+    use super::*;
+
+    #[test]
+    fn test_compute_budget_is_prepended() {
+        let (payer, to) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let blockhash = Hash::new_unique();
+        let message = Message::new_with_blockhash(&[system_instruction::transfer(&payer, &to, 1_000)], Some(&payer), &blockhash);
+        let budget = ComputeBudget { unit_limit: 1_450, micro_lamports: 10_000 };
+
+        let budgeted = with_compute_budget(&message, &budget);
+        assert_eq!(budgeted.account_keys[0], payer);
+        assert_eq!(budgeted.recent_blockhash, blockhash);
+        assert_eq!(budgeted.instructions.len(), 3);
+        assert_eq!(budgeted.account_keys[usize::from(budgeted.instructions[0].program_id_index)], compute_budget::id());
+        // Applying it again replaces the budget instead of stacking another one
+        let rebudgeted = with_compute_budget(&budgeted, &ComputeBudget { unit_limit: 2_000, micro_lamports: 1 });
+        assert_eq!(rebudgeted.instructions.len(), 3);
+
+        assert_eq!(message_fee(&VersionedMessage::Legacy(message)), 5_000);
+        // 1_450 units at 10_000 micro-lamports are 14.5 lamports, rounded up
+        assert_eq!(message_fee(&VersionedMessage::Legacy(budgeted)), 5_015);
+    }
+
+    #[test]
+    fn test_default_unit_limit() {
+        let payer = Pubkey::new_unique();
+        let instructions = [
+            ComputeBudgetInstruction::set_compute_unit_price(1_000_000),
+            system_instruction::transfer(&payer, &Pubkey::new_unique(), 1),
+            system_instruction::transfer(&payer, &Pubkey::new_unique(), 1),
+        ];
+        let message = VersionedMessage::Legacy(Message::new(&instructions, Some(&payer)));
+        // Two instructions at 200_000 units, one lamport per unit
+        assert_eq!(message_fee(&message), 5_000 + 400_000);
+    }
+
+    #[test]
+    fn test_units_and_percentiles() {
+        assert_eq!(ComputeBudget::for_units_consumed(150, 1).unit_limit, 1_150);
+        assert_eq!(ComputeBudget::for_units_consumed(100_000, 1).unit_limit, 120_000);
+        assert_eq!(ComputeBudget::for_units_consumed(1_300_000, 1).unit_limit, MAX_COMPUTE_UNIT_LIMIT);
+
+        assert_eq!(percentile_fee(vec![], 75), 0);
+        assert_eq!(percentile_fee(vec![50, 10, 0, 40, 20], 50), 20);
+        assert_eq!(percentile_fee(vec![50, 10, 0, 40, 20], 100), 50);
+        assert_eq!(percentile_fee(vec![50, 10, 0, 40, 20], 0), 0);
+    }
+
+    #[test]
+    fn test_fee_strategy_json() {
+        let fixed: FeeStrategy = serde_json::from_str(r#"{"strategy": "fixed", "micro_lamports": 5000}"#).unwrap();
+        assert_eq!(fixed, FeeStrategy::Fixed { micro_lamports: 5_000 });
+        let percentile: FeeStrategy = serde_json::from_str(r#"{"strategy": "percentile", "percentile": 75}"#).unwrap();
+        assert_eq!(percentile, FeeStrategy::Percentile { percentile: 75 });
+        assert_eq!(serde_json::from_str::<FeeStrategy>(r#"{"strategy": "none"}"#).unwrap(), FeeStrategy::None);
+    }
+}
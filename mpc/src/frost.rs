@@ -0,0 +1,690 @@
+#![allow(non_snake_case)]
+
+//! Threshold signatures over Ed25519 following FROST(Ed25519, SHA-512) from RFC 9591.
+//! Any `threshold` of the `max_signers` parties can sign, and the result is a plain ed25519
+//! signature under the group key, so Solana can't tell it apart from any other signature.
+//! Keys are created with a Pedersen DKG, no party ever holds the full secret key. Every round one
+//! package is signed with its sender's identity key, so whoever relays the packages can't swap the
+//! keys the secret shares are encrypted to.
+
+use std::collections::BTreeMap;
+
+use curv::arithmetic::Converter;
+use curv::elliptic::curves::{Ed25519, Point, Scalar};
+use curv::BigInt;
+use rand07::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use solana_sdk::message::VersionedMessage;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::VersionedTransaction;
+
+use crate::error::Error;
+use crate::tss::verify_signer;
+
+const CONTEXT: &[u8] = b"FROST-ED25519-SHA512-v1";
+
+/// Parties are numbered from 1, identifier 0 would reveal the secret.
+pub type Identifier = u16;
+
+/// What a party keeps after the DKG, its share of the group's secret key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyShare {
+    pub identifier: Identifier,
+    pub threshold: u16,
+    pub signing_share: Scalar<Ed25519>,
+    pub verifying_share: Point<Ed25519>,
+    pub group_key: Point<Ed25519>,
+    /// Party id behind every identifier, fixed by the DKG
+    pub parties: BTreeMap<Identifier, String>,
+    /// Public counterpart of every party's signing share, pins an invalid signature share on its signer
+    pub verifying_shares: BTreeMap<Identifier, Point<Ed25519>>,
+}
+
+impl KeyShare {
+    pub fn group_pubkey(&self) -> Pubkey {
+        group_pubkey(&self.group_key)
+    }
+}
+
+pub fn group_pubkey(group_key: &Point<Ed25519>) -> Pubkey {
+    Pubkey::new(&*group_key.to_bytes(true))
+}
+
+/// Broadcast by every party in the first DKG round.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DkgRound1Package {
+    pub sender: Identifier,
+    /// Commitments to the coefficients of the sender's secret polynomial
+    pub commitments: Vec<Point<Ed25519>>,
+    /// Proof of knowledge of the constant term, stops a party from cancelling out the others' keys
+    pub proof_R: Point<Ed25519>,
+    pub proof_z: Scalar<Ed25519>,
+    /// Ephemeral key the other parties encrypt their round two shares to
+    pub ephemeral_key: Point<Ed25519>,
+    /// The sender's identity key over `round1_message`, set by the sender after `dkg_part1`
+    pub signature: Signature,
+}
+
+/// A party of a key generation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DkgParty {
+    pub party_id: String,
+    /// The party's identity key from the topology, its round one package has to be signed with it
+    pub identity_key: Pubkey,
+}
+
+/// Sent from one party to another in the second DKG round, only the receiver can decrypt the share.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DkgRound2Package {
+    pub sender: Identifier,
+    pub receiver: Identifier,
+    pub encrypted_share: [u8; 32],
+}
+
+/// The secret state a party keeps between the DKG rounds.
+pub struct DkgSecret {
+    identifier: Identifier,
+    threshold: u16,
+    parties: BTreeMap<Identifier, DkgParty>,
+    context: Vec<u8>,
+    coefficients: Vec<Scalar<Ed25519>>,
+    ephemeral_secret: Scalar<Ed25519>,
+}
+
+/// Start the DKG: pick a random polynomial of degree `threshold - 1` and commit to it.
+/// `parties` maps the identifier of every party taking part to the party, `context` names the key
+/// generation. The package still has to be signed over `round1_message` before it is sent.
+pub fn dkg_part1(
+    identifier: Identifier,
+    threshold: u16,
+    parties: BTreeMap<Identifier, DkgParty>,
+    context: &[u8],
+) -> Result<(DkgSecret, DkgRound1Package), Error> {
+    let max_signers = u16::try_from(parties.len()).map_err(|_| Error::InvalidParticipants)?;
+    if threshold < 2 || threshold > max_signers {
+        return Err(Error::InvalidThreshold { threshold, max_signers });
+    }
+    if parties.contains_key(&0) || !parties.contains_key(&identifier) {
+        return Err(Error::InvalidParticipants);
+    }
+
+    let coefficients: Vec<_> = (0..threshold).map(|_| Scalar::<Ed25519>::random()).collect();
+    let commitments: Vec<_> = coefficients.iter().map(|a| Point::generator() * a).collect();
+
+    let k = Scalar::<Ed25519>::random();
+    let proof_R = Point::generator() * &k;
+    let c = dkg_challenge(identifier, &commitments[0], &proof_R);
+    let proof_z = &k + &coefficients[0] * &c;
+
+    let ephemeral_secret = Scalar::<Ed25519>::random();
+    let ephemeral_key = Point::generator() * &ephemeral_secret;
+
+    let package = DkgRound1Package { sender: identifier, commitments, proof_R, proof_z, ephemeral_key, signature: Signature::default() };
+    let secret = DkgSecret { identifier, threshold, parties, context: context.to_vec(), coefficients, ephemeral_secret };
+    Ok((secret, package))
+}
+
+/// What the sender of a round one package signs with its identity key. Covers everything but the
+/// signature itself and is bound to one key generation by `context`.
+pub fn round1_message(context: &[u8], package: &DkgRound1Package) -> Vec<u8> {
+    let mut hasher = Sha512::new()
+        .chain_update(CONTEXT)
+        .chain_update(b"round1")
+        .chain_update((context.len() as u64).to_le_bytes())
+        .chain_update(context)
+        .chain_update(encode_identifier(package.sender));
+    for commitment in &package.commitments {
+        hasher.update(&*commitment.to_bytes(true));
+    }
+    hasher.update(&*package.proof_R.to_bytes(true));
+    hasher.update(&*package.proof_z.to_bytes());
+    hasher.update(&*package.ephemeral_key.to_bytes(true));
+    hasher.finalize().to_vec()
+}
+
+/// Check everyone's round one package and hand each other party its share of our polynomial.
+pub fn dkg_part2(secret: &DkgSecret, round1: &[DkgRound1Package]) -> Result<Vec<DkgRound2Package>, Error> {
+    verify_round1(secret, round1)?;
+
+    Ok(round1
+        .iter()
+        .filter(|package| package.sender != secret.identifier)
+        .map(|package| {
+            let share = evaluate_polynomial(&secret.coefficients, package.sender);
+            let pad = share_pad(secret, package, secret.identifier, package.sender);
+            let mut encrypted_share = [0u8; 32];
+            for (out, (s, p)) in encrypted_share.iter_mut().zip(share.to_bytes().iter().zip(pad.iter())) {
+                *out = s ^ p;
+            }
+            DkgRound2Package { sender: secret.identifier, receiver: package.sender, encrypted_share }
+        })
+        .collect())
+}
+
+/// Decrypt and verify the shares sent to us and combine them into our key share.
+pub fn dkg_part3(secret: &DkgSecret, round1: &[DkgRound1Package], round2: &[DkgRound2Package]) -> Result<KeyShare, Error> {
+    verify_round1(secret, round1)?;
+
+    let mut signing_share = evaluate_polynomial(&secret.coefficients, secret.identifier);
+    for package in round1.iter().filter(|package| package.sender != secret.identifier) {
+        let mut received = round2
+            .iter()
+            .filter(|share| share.sender == package.sender && share.receiver == secret.identifier);
+        let (Some(share), None) = (received.next(), received.next()) else {
+            return Err(Error::InvalidShare(package.sender));
+        };
+
+        let pad = share_pad(secret, package, package.sender, secret.identifier);
+        let mut share_bytes = [0u8; 32];
+        for (out, (s, p)) in share_bytes.iter_mut().zip(share.encrypted_share.iter().zip(pad.iter())) {
+            *out = s ^ p;
+        }
+        let share = Scalar::<Ed25519>::from_bytes(&share_bytes).map_err(|_| Error::InvalidShare(package.sender))?;
+
+        // The share has to lie on the polynomial the sender committed to
+        if Point::generator() * &share != evaluate_commitments(&package.commitments, secret.identifier) {
+            return Err(Error::InvalidShare(package.sender));
+        }
+        signing_share = signing_share + share;
+    }
+
+    let group_key = round1.iter().fold(Point::zero(), |acc, package| acc + &package.commitments[0]);
+    let verifying_share = Point::generator() * &signing_share;
+    let verifying_shares = secret
+        .parties
+        .keys()
+        .map(|&identifier| {
+            let share = round1.iter().fold(Point::zero(), |acc, package| acc + evaluate_commitments(&package.commitments, identifier));
+            (identifier, share)
+        })
+        .collect();
+    Ok(KeyShare {
+        identifier: secret.identifier,
+        threshold: secret.threshold,
+        signing_share,
+        verifying_share,
+        group_key,
+        parties: secret.parties.iter().map(|(identifier, party)| (*identifier, party.party_id.clone())).collect(),
+        verifying_shares,
+    })
+}
+
+fn verify_round1(secret: &DkgSecret, round1: &[DkgRound1Package]) -> Result<(), Error> {
+    let mut senders: Vec<_> = round1.iter().map(|package| package.sender).collect();
+    senders.sort_unstable();
+    if senders != secret.parties.keys().copied().collect::<Vec<_>>() {
+        return Err(Error::InvalidParticipants);
+    }
+    for package in round1 {
+        let party = secret.parties.get(&package.sender).ok_or(Error::InvalidParticipants)?;
+        if !package.signature.verify(party.identity_key.as_ref(), &round1_message(&secret.context, package)) {
+            return Err(Error::UnauthenticatedPackage(package.sender));
+        }
+        if package.commitments.len() != usize::from(secret.threshold) {
+            return Err(Error::InvalidProofOfKnowledge(package.sender));
+        }
+        let c = dkg_challenge(package.sender, &package.commitments[0], &package.proof_R);
+        if Point::generator() * &package.proof_z != &package.proof_R + &package.commitments[0] * &c {
+            return Err(Error::InvalidProofOfKnowledge(package.sender));
+        }
+    }
+    // Make sure nobody replaced our own package on the way
+    let own = round1.iter().find(|package| package.sender == secret.identifier).ok_or(Error::InvalidParticipants)?;
+    if own.commitments[0] != Point::generator() * &secret.coefficients[0]
+        || own.ephemeral_key != Point::generator() * &secret.ephemeral_secret
+    {
+        return Err(Error::InvalidParticipants);
+    }
+    Ok(())
+}
+
+/// One time pad for the share sent from `sender` to `receiver`, derived from a Diffie-Hellman
+/// exchange between the two parties' ephemeral keys. The keys are authenticated by the package
+/// signatures checked in `verify_round1`.
+fn share_pad(secret: &DkgSecret, other: &DkgRound1Package, sender: Identifier, receiver: Identifier) -> [u8; 32] {
+    let shared = &other.ephemeral_key * &secret.ephemeral_secret;
+    let digest = Sha512::new()
+        .chain_update(CONTEXT)
+        .chain_update(b"share")
+        .chain_update(&*shared.to_bytes(true))
+        .chain_update(sender.to_le_bytes())
+        .chain_update(receiver.to_le_bytes())
+        .finalize();
+    let mut pad = [0u8; 32];
+    pad.copy_from_slice(&digest[..32]);
+    pad
+}
+
+fn dkg_challenge(identifier: Identifier, commitment: &Point<Ed25519>, R: &Point<Ed25519>) -> Scalar<Ed25519> {
+    hash_to_scalar(&[CONTEXT, b"dkg", &encode_identifier(identifier), &commitment.to_bytes(true), &R.to_bytes(true)])
+}
+
+fn evaluate_polynomial(coefficients: &[Scalar<Ed25519>], identifier: Identifier) -> Scalar<Ed25519> {
+    let x = Scalar::<Ed25519>::from(identifier);
+    coefficients.iter().rev().fold(Scalar::zero(), |acc, coefficient| acc * &x + coefficient)
+}
+
+fn evaluate_commitments(commitments: &[Point<Ed25519>], identifier: Identifier) -> Point<Ed25519> {
+    let x = Scalar::<Ed25519>::from(identifier);
+    commitments.iter().rev().fold(Point::zero(), |acc, commitment| acc * &x + commitment)
+}
+
+/// The private nonces of one signing round, they must be used for a single signature only.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SigningNonces {
+    pub hiding: Scalar<Ed25519>,
+    pub binding: Scalar<Ed25519>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SigningCommitments {
+    pub identifier: Identifier,
+    pub hiding: Point<Ed25519>,
+    pub binding: Point<Ed25519>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignatureShare {
+    pub identifier: Identifier,
+    pub share: Scalar<Ed25519>,
+}
+
+/// First signing round: generate the nonces and the commitments to publish.
+pub fn commit(key_share: &KeyShare) -> (SigningNonces, SigningCommitments) {
+    let hiding = generate_nonce(&key_share.signing_share);
+    let binding = generate_nonce(&key_share.signing_share);
+    let commitments = SigningCommitments {
+        identifier: key_share.identifier,
+        hiding: Point::generator() * &hiding,
+        binding: Point::generator() * &binding,
+    };
+    (SigningNonces { hiding, binding }, commitments)
+}
+
+/// Second signing round: sign `message` together with the signers that published `commitments`.
+pub fn sign(
+    key_share: &KeyShare,
+    nonces: SigningNonces,
+    commitments: &[SigningCommitments],
+    message: &[u8],
+) -> Result<SignatureShare, Error> {
+    verify_commitments(commitments, key_share.threshold)?;
+    let index = commitments
+        .iter()
+        .position(|commitment| commitment.identifier == key_share.identifier)
+        .ok_or(Error::KeyPairIsNotInKeys)?;
+    let own = &commitments[index];
+    if own.hiding != Point::generator() * &nonces.hiding || own.binding != Point::generator() * &nonces.binding {
+        return Err(Error::MismatchMessages);
+    }
+
+    let binding_factors = binding_factors(&key_share.group_key, commitments, message);
+    let R = group_commitment(commitments, &binding_factors);
+    let c = challenge(&R, &key_share.group_key, message);
+    let signers: Vec<_> = commitments.iter().map(|commitment| commitment.identifier).collect();
+    let lambda = lagrange_coefficient(key_share.identifier, &signers)?;
+
+    let share = nonces.hiding + nonces.binding * &binding_factors[index] + lambda * &key_share.signing_share * c;
+    Ok(SignatureShare { identifier: key_share.identifier, share })
+}
+
+/// Check every signature share against its signer's verifying share, then add them up into an
+/// ed25519 signature and check that against the group key.
+pub fn aggregate(
+    group_key: &Point<Ed25519>,
+    threshold: u16,
+    verifying_shares: &BTreeMap<Identifier, Point<Ed25519>>,
+    commitments: &[SigningCommitments],
+    message: &[u8],
+    shares: &[SignatureShare],
+) -> Result<Signature, Error> {
+    verify_commitments(commitments, threshold)?;
+    let mut share_ids: Vec<_> = shares.iter().map(|share| share.identifier).collect();
+    share_ids.sort_unstable();
+    let signers: Vec<_> = commitments.iter().map(|commitment| commitment.identifier).collect();
+    if share_ids != signers {
+        return Err(Error::MismatchMessages);
+    }
+
+    let binding_factors = binding_factors(group_key, commitments, message);
+    let R = group_commitment(commitments, &binding_factors);
+    let c = challenge(&R, group_key, message);
+    for share in shares {
+        let index = signers.iter().position(|&signer| signer == share.identifier).ok_or(Error::MismatchMessages)?;
+        let commitment = &commitments[index];
+        let verifying_share = verifying_shares.get(&share.identifier).ok_or(Error::InvalidParticipants)?;
+        let lambda = lagrange_coefficient(share.identifier, &signers)?;
+        let R_i = &commitment.hiding + &commitment.binding * &binding_factors[index];
+        if Point::generator() * &share.share != R_i + verifying_share * (&c * lambda) {
+            return Err(Error::InvalidSignatureShare(share.identifier));
+        }
+    }
+    let z = shares.iter().fold(Scalar::<Ed25519>::zero(), |acc, share| acc + &share.share);
+
+    let mut sig_bytes = [0u8; 64];
+    sig_bytes[..32].copy_from_slice(&*R.to_bytes(true));
+    sig_bytes[32..].copy_from_slice(&z.to_bytes());
+    let signature = Signature::new(&sig_bytes);
+    if !signature.verify(&*group_key.to_bytes(true), message) {
+        return Err(Error::InvalidSignature);
+    }
+    Ok(signature)
+}
+
+/// Aggregate the signature shares over `message` into a fully signed transaction.
+pub fn sign_message(
+    message: VersionedMessage,
+    group_key: &Point<Ed25519>,
+    threshold: u16,
+    verifying_shares: &BTreeMap<Identifier, Point<Ed25519>>,
+    commitments: &[SigningCommitments],
+    shares: &[SignatureShare],
+) -> Result<VersionedTransaction, Error> {
+    verify_signer(&message, &group_pubkey(group_key))?;
+    let signature = aggregate(group_key, threshold, verifying_shares, commitments, &message.serialize(), shares)?;
+    let tx = VersionedTransaction { signatures: vec![signature], message };
+
+    if !tx.verify_with_results().into_iter().all(|valid| valid) {
+        return Err(Error::InvalidSignature);
+    }
+    Ok(tx)
+}
+
+/// Interpolate the group's secret key from at least `threshold` key shares of the same wallet.
+/// Only meant for taking a wallet off the share servers, the shares never meet otherwise.
+pub fn reconstruct_secret(shares: &[KeyShare]) -> Result<Scalar<Ed25519>, Error> {
+    let first = shares.first().ok_or(Error::InvalidParticipants)?;
+    let mut signers: Vec<_> = shares.iter().map(|share| share.identifier).collect();
+    signers.sort_unstable();
+    signers.dedup();
+    if signers.len() != shares.len() || signers.len() < usize::from(first.threshold) || signers[0] == 0 {
+        return Err(Error::InvalidParticipants);
+    }
+
+    let mut secret = Scalar::<Ed25519>::zero();
+    for share in shares {
+        if share.group_key != first.group_key || share.threshold != first.threshold {
+            return Err(Error::InvalidKeyShare);
+        }
+        if Point::generator() * &share.signing_share != share.verifying_share {
+            return Err(Error::InvalidShare(share.identifier));
+        }
+        secret = secret + lagrange_coefficient(share.identifier, &signers)? * &share.signing_share;
+    }
+    if Point::generator() * &secret != first.group_key {
+        return Err(Error::InvalidKeyShare);
+    }
+    Ok(secret)
+}
+
+/// A regular ed25519 signature made with a bare secret scalar, as there is no seed behind a FROST group key.
+/// The nonce is derived from the secret and the message like in ed25519 itself.
+pub fn sign_with_secret(secret: &Scalar<Ed25519>, message: &[u8]) -> Signature {
+    let group_key = Point::generator() * secret;
+    let r = hash_to_scalar(&[CONTEXT, b"secret-nonce", &secret.to_bytes(), message]);
+    let R = Point::generator() * &r;
+    let z = r + challenge(&R, &group_key, message) * secret;
+
+    let mut sig_bytes = [0u8; 64];
+    sig_bytes[..32].copy_from_slice(&*R.to_bytes(true));
+    sig_bytes[32..].copy_from_slice(&z.to_bytes());
+    Signature::new(&sig_bytes)
+}
+
+/// Commitments have to be sorted by identifier, without duplicates, from at least `threshold` signers.
+fn verify_commitments(commitments: &[SigningCommitments], threshold: u16) -> Result<(), Error> {
+    if commitments.len() < usize::from(threshold) {
+        return Err(Error::InvalidParticipants);
+    }
+    if commitments.iter().any(|commitment| commitment.identifier == 0)
+        || !commitments.windows(2).all(|pair| pair[0].identifier < pair[1].identifier)
+    {
+        return Err(Error::InvalidParticipants);
+    }
+    Ok(())
+}
+
+fn binding_factors(group_key: &Point<Ed25519>, commitments: &[SigningCommitments], message: &[u8]) -> Vec<Scalar<Ed25519>> {
+    let msg_hash = Sha512::new().chain_update(CONTEXT).chain_update(b"msg").chain_update(message).finalize();
+    let mut encoded_commitments = Sha512::new().chain_update(CONTEXT).chain_update(b"com");
+    for commitment in commitments {
+        encoded_commitments.update(encode_identifier(commitment.identifier));
+        encoded_commitments.update(&*commitment.hiding.to_bytes(true));
+        encoded_commitments.update(&*commitment.binding.to_bytes(true));
+    }
+    let commitments_hash = encoded_commitments.finalize();
+    let group_key_bytes = group_key.to_bytes(true);
+
+    commitments
+        .iter()
+        .map(|commitment| {
+            hash_to_scalar(&[
+                CONTEXT,
+                b"rho",
+                &group_key_bytes,
+                &msg_hash,
+                &commitments_hash,
+                &encode_identifier(commitment.identifier),
+            ])
+        })
+        .collect()
+}
+
+fn group_commitment(commitments: &[SigningCommitments], binding_factors: &[Scalar<Ed25519>]) -> Point<Ed25519> {
+    commitments
+        .iter()
+        .zip(binding_factors)
+        .fold(Point::zero(), |acc, (commitment, rho)| acc + &commitment.hiding + &commitment.binding * rho)
+}
+
+/// The regular ed25519 challenge, which is what makes the result a plain ed25519 signature.
+fn challenge(R: &Point<Ed25519>, group_key: &Point<Ed25519>, message: &[u8]) -> Scalar<Ed25519> {
+    hash_to_scalar(&[&R.to_bytes(true), &group_key.to_bytes(true), message])
+}
+
+fn lagrange_coefficient(identifier: Identifier, signers: &[Identifier]) -> Result<Scalar<Ed25519>, Error> {
+    let x_i = Scalar::<Ed25519>::from(identifier);
+    let mut numerator = Scalar::<Ed25519>::from(1u16);
+    let mut denominator = Scalar::<Ed25519>::from(1u16);
+    for &signer in signers.iter().filter(|&&signer| signer != identifier) {
+        let x_j = Scalar::<Ed25519>::from(signer);
+        numerator = numerator * &x_j;
+        denominator = denominator * (&x_j - &x_i);
+    }
+    let inverse = denominator.invert().ok_or(Error::InvalidParticipants)?;
+    Ok(numerator * inverse)
+}
+
+/// Hedged nonce generation, stays safe even if the random number generator is weak.
+fn generate_nonce(secret: &Scalar<Ed25519>) -> Scalar<Ed25519> {
+    let mut random_bytes = [0u8; 32];
+    rand07::rngs::OsRng.fill_bytes(&mut random_bytes);
+    hash_to_scalar(&[CONTEXT, b"nonce", &random_bytes, &secret.to_bytes()])
+}
+
+fn encode_identifier(identifier: Identifier) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes[..2].copy_from_slice(&identifier.to_le_bytes());
+    bytes
+}
+
+/// SHA-512 interpreted as a little endian integer, the same way ed25519 derives scalars.
+fn hash_to_scalar(parts: &[&[u8]]) -> Scalar<Ed25519> {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    let mut digest = hasher.finalize().to_vec();
+    digest.reverse();
+    Scalar::from_bigint(&BigInt::from_bytes(&digest))
+}
+
+/// Parse a key share stored by a share server.
+pub fn key_share_from_json(key_share: &str) -> Result<KeyShare, Error> {
+    serde_json::from_str(key_share).map_err(|_| Error::InvalidKeyShare)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use curv::elliptic::curves::{Ed25519, Point, Scalar};
+    use solana_sdk::signature::{Keypair, Signature, Signer};
+
+    use super::{
+        aggregate, commit, dkg_part1, dkg_part2, dkg_part3, reconstruct_secret, round1_message, sign, sign_with_secret, DkgParty,
+        DkgRound1Package, DkgRound2Package, DkgSecret, Identifier, KeyShare,
+    };
+    use crate::error::Error;
+
+    const DKG_CONTEXT: &[u8] = b"session/user";
+
+    fn parties(identities: &[Keypair]) -> BTreeMap<Identifier, DkgParty> {
+        identities
+            .iter()
+            .zip(1..)
+            .map(|(identity, id)| (id, DkgParty { party_id: format!("share-{}", id), identity_key: identity.pubkey() }))
+            .collect()
+    }
+
+    fn round_one(threshold: u16, identities: &[Keypair]) -> (Vec<DkgSecret>, Vec<DkgRound1Package>) {
+        identities
+            .iter()
+            .zip(1..)
+            .map(|(identity, id)| {
+                let (secret, mut package) = dkg_part1(id, threshold, parties(identities), DKG_CONTEXT).unwrap();
+                package.signature = identity.sign_message(&round1_message(DKG_CONTEXT, &package));
+                (secret, package)
+            })
+            .unzip()
+    }
+
+    fn run_dkg(threshold: u16, max_signers: u16) -> Vec<KeyShare> {
+        let identities: Vec<_> = (0..max_signers).map(|_| Keypair::new()).collect();
+        let (secrets, round1) = round_one(threshold, &identities);
+        let round2: Vec<DkgRound2Package> =
+            secrets.iter().flat_map(|secret| dkg_part2(secret, &round1).unwrap()).collect();
+        secrets.iter().map(|secret| dkg_part3(secret, &round1, &round2).unwrap()).collect()
+    }
+
+    fn sign_with(signers: &[&KeyShare], message: &[u8]) -> Result<Signature, Error> {
+        let (nonces, commitments): (Vec<_>, Vec<_>) = signers.iter().map(|share| commit(share)).unzip();
+        let shares = signers
+            .iter()
+            .zip(nonces)
+            .map(|(share, nonces)| sign(share, nonces, &commitments, message))
+            .collect::<Result<Vec<_>, _>>()?;
+        let key = signers[0];
+        aggregate(&key.group_key, key.threshold, &key.verifying_shares, &commitments, message, &shares)
+    }
+
+    #[test]
+    fn test_any_two_of_three_sign() {
+        let shares = run_dkg(2, 3);
+        assert!(shares.iter().all(|share| share.group_key == shares[0].group_key));
+        assert!(shares.iter().all(|share| share.parties == shares[0].parties && share.verifying_shares == shares[0].verifying_shares));
+        assert_eq!(shares[1].parties[&2], "share-2");
+        assert!(shares.iter().all(|share| shares[0].verifying_shares[&share.identifier] == share.verifying_share));
+
+        let message = b"threshold message";
+        for signers in [[&shares[0], &shares[1]], [&shares[0], &shares[2]], [&shares[1], &shares[2]]] {
+            let signature = sign_with(&signers, message).unwrap();
+            assert!(signature.verify(shares[0].group_pubkey().as_ref(), message));
+        }
+        let signature = sign_with(&[&shares[0], &shares[1], &shares[2]], message).unwrap();
+        assert!(signature.verify(shares[0].group_pubkey().as_ref(), message));
+    }
+
+    #[test]
+    fn test_invalid_signature_share() {
+        let shares = run_dkg(2, 3);
+        let message = b"threshold message";
+        let (nonces, commitments): (Vec<_>, Vec<_>) = [&shares[0], &shares[2]].iter().map(|share| commit(share)).unzip();
+        let mut signature_shares: Vec<_> = [&shares[0], &shares[2]]
+            .iter()
+            .zip(nonces)
+            .map(|(share, nonces)| sign(share, nonces, &commitments, message).unwrap())
+            .collect();
+        signature_shares[1].share = &signature_shares[1].share + Scalar::<Ed25519>::from(1u16);
+        let key = &shares[0];
+        assert!(matches!(
+            aggregate(&key.group_key, key.threshold, &key.verifying_shares, &commitments, message, &signature_shares),
+            Err(Error::InvalidSignatureShare(3))
+        ));
+    }
+
+    #[test]
+    fn test_reconstruct_secret() {
+        let shares = run_dkg(2, 3);
+        let secret = reconstruct_secret(&[shares[2].clone(), shares[0].clone()]).unwrap();
+        assert_eq!(reconstruct_secret(&shares).unwrap(), secret);
+
+        let message = b"exported wallet";
+        let signature = sign_with_secret(&secret, message);
+        assert!(signature.verify(shares[0].group_pubkey().as_ref(), message));
+
+        assert!(matches!(reconstruct_secret(&shares[..1]), Err(Error::InvalidParticipants)));
+        assert!(matches!(reconstruct_secret(&[shares[0].clone(), shares[0].clone()]), Err(Error::InvalidParticipants)));
+        let mut tampered = shares[1].clone();
+        tampered.signing_share = shares[0].signing_share.clone();
+        assert!(matches!(reconstruct_secret(&[shares[0].clone(), tampered]), Err(Error::InvalidShare(2))));
+    }
+
+    #[test]
+    fn test_below_threshold() {
+        let shares = run_dkg(3, 4);
+        assert!(matches!(sign_with(&[&shares[0], &shares[1]], b"message"), Err(Error::InvalidParticipants)));
+    }
+
+    #[test]
+    fn test_invalid_threshold() {
+        let identities: Vec<_> = (0..3).map(|_| Keypair::new()).collect();
+        assert!(matches!(dkg_part1(1, 1, parties(&identities), DKG_CONTEXT), Err(Error::InvalidThreshold { .. })));
+        assert!(matches!(dkg_part1(1, 4, parties(&identities), DKG_CONTEXT), Err(Error::InvalidThreshold { .. })));
+        assert!(matches!(dkg_part1(4, 2, parties(&identities), DKG_CONTEXT), Err(Error::InvalidParticipants)));
+    }
+
+    #[test]
+    fn test_tampered_share() {
+        let identities: Vec<_> = (0..3).map(|_| Keypair::new()).collect();
+        let (secrets, round1) = round_one(2, &identities);
+        let mut round2: Vec<DkgRound2Package> =
+            secrets.iter().flat_map(|secret| dkg_part2(secret, &round1).unwrap()).collect();
+        let tampered = round2.iter_mut().find(|share| share.sender == 2 && share.receiver == 1).unwrap();
+        tampered.encrypted_share[0] ^= 1;
+        assert!(matches!(dkg_part3(&secrets[0], &round1, &round2), Err(Error::InvalidShare(2))));
+    }
+
+    #[test]
+    fn test_tampered_proof() {
+        let identities: Vec<_> = (0..3).map(|_| Keypair::new()).collect();
+        let (secrets, mut round1) = round_one(2, &identities);
+        round1[2].proof_z = round1[1].proof_z.clone();
+        round1[2].signature = identities[2].sign_message(&round1_message(DKG_CONTEXT, &round1[2]));
+        assert!(matches!(dkg_part2(&secrets[0], &round1), Err(Error::InvalidProofOfKnowledge(3))));
+    }
+
+    #[test]
+    fn test_swapped_ephemeral_key() {
+        let identities: Vec<_> = (0..3).map(|_| Keypair::new()).collect();
+        let (secrets, mut round1) = round_one(2, &identities);
+        // A relay that swaps in its own key could decrypt the shares sent to party 2
+        round1[1].ephemeral_key = Point::generator() * &Scalar::<Ed25519>::random();
+        assert!(matches!(dkg_part2(&secrets[0], &round1), Err(Error::UnauthenticatedPackage(2))));
+        assert!(matches!(dkg_part3(&secrets[0], &round1, &[]), Err(Error::UnauthenticatedPackage(2))));
+
+        // Signed by a key that isn't the sender's
+        let (secrets, mut round1) = round_one(2, &identities);
+        round1[1].signature = identities[0].sign_message(&round1_message(DKG_CONTEXT, &round1[1]));
+        assert!(matches!(dkg_part2(&secrets[0], &round1), Err(Error::UnauthenticatedPackage(2))));
+
+        // A package of a different key generation
+        let (secrets, round1) = round_one(2, &identities);
+        let (_, mut other) = dkg_part1(2, 2, parties(&identities), b"other").unwrap();
+        other.signature = identities[1].sign_message(&round1_message(b"other", &other));
+        let replaced: Vec<_> = round1.iter().map(|package| if package.sender == 2 { other.clone() } else { package.clone() }).collect();
+        assert!(matches!(dkg_part2(&secrets[0], &replaced), Err(Error::UnauthenticatedPackage(2))));
+    }
+}
@@ -0,0 +1,192 @@
+pub mod error;
+pub mod export;
+pub mod fee;
+pub mod frost;
+pub mod middleware;
+pub mod nonce;
+pub mod protocol;
+pub mod rpc;
+pub mod serialization;
+pub mod simulation;
+pub mod token;
+pub mod tss;
+
+pub use error::Error;
+
+use base64::engine::Engine;
+use solana_sdk::{
+    instruction::Instruction,
+    message::{Message, VersionedMessage},
+    native_token,
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    signature::Signature,
+    system_instruction,
+    transaction::Transaction,
+};
+
+/// Fee of a transaction with a single signature, which is what every coordinator-built
+/// transaction carries.
+pub const SIGNATURE_FEE_LAMPORTS: u64 = 5_000;
+
+pub fn create_unsigned_transaction(amount: f64, to: &Pubkey, memo: Option<String>, payer: &Pubkey) -> Transaction {
+    create_unsigned_lamports_transaction(native_token::sol_to_lamports(amount), to, memo, payer)
+}
+
+/// Same as `create_unsigned_transaction` for an exact amount of lamports.
+pub fn create_unsigned_lamports_transaction(amount: u64, to: &Pubkey, memo: Option<String>, payer: &Pubkey) -> Transaction {
+    let transfer_ins = system_instruction::transfer(payer, to, amount);
+    let msg = match memo {
+        None => Message::new(&[transfer_ins], Some(payer)),
+        Some(memo) => {
+            let memo_ins = Instruction { program_id: spl_memo::id(), accounts: Vec::new(), data: memo.into_bytes() };
+            Message::new(&[transfer_ins, memo_ins], Some(payer))
+        }
+    };
+    Transaction::new_unsigned(msg)
+}
+
+/// One recipient of a batch, `amount` is in base units of the batch's mint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchTransfer {
+    pub to: Pubkey,
+    pub lamports: u64,
+    pub amount: u64,
+}
+
+/// A mint as far as building transfers goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenMint {
+    pub mint: Pubkey,
+    pub program: Pubkey,
+    pub decimals: u8,
+}
+
+/// Pay many recipients in one transaction. Each gets its lamports and, with a mint, its tokens
+/// in an associated token account created on the way.
+pub fn create_unsigned_batch_transaction(transfers: &[BatchTransfer], mint: Option<&TokenMint>, payer: &Pubkey) -> Transaction {
+    let mut instructions = Vec::new();
+    for transfer in transfers {
+        if transfer.lamports > 0 {
+            instructions.push(system_instruction::transfer(payer, &transfer.to, transfer.lamports));
+        }
+        if let Some(mint) = mint.filter(|_| transfer.amount > 0) {
+            instructions.push(token::create_associated_token_account_idempotent(payer, &transfer.to, &mint.mint, &mint.program));
+            instructions.push(token::transfer_checked(
+                &mint.program,
+                &token::associated_token_address(payer, &mint.mint, &mint.program),
+                &mint.mint,
+                &token::associated_token_address(&transfer.to, &mint.mint, &mint.program),
+                payer,
+                transfer.amount,
+                mint.decimals,
+            ));
+        }
+    }
+    Transaction::new_unsigned(Message::new(&instructions, Some(payer)))
+}
+
+/// Send `amount` base units of `mint` from the payer's associated token account to `to`'s. The
+/// recipient's account is created first unless it's known to exist.
+pub fn create_unsigned_token_transaction(
+    mint: &TokenMint,
+    to: &Pubkey,
+    amount: u64,
+    create_account: bool,
+    memo: Option<String>,
+    payer: &Pubkey,
+) -> Transaction {
+    let mut instructions = Vec::new();
+    if create_account {
+        instructions.push(token::create_associated_token_account_idempotent(payer, to, &mint.mint, &mint.program));
+    }
+    instructions.push(token::transfer_checked(
+        &mint.program,
+        &token::associated_token_address(payer, &mint.mint, &mint.program),
+        &mint.mint,
+        &token::associated_token_address(to, &mint.mint, &mint.program),
+        payer,
+        amount,
+        mint.decimals,
+    ));
+    if let Some(memo) = memo {
+        instructions.push(Instruction { program_id: spl_memo::id(), accounts: Vec::new(), data: memo.into_bytes() });
+    }
+    Transaction::new_unsigned(Message::new(&instructions, Some(payer)))
+}
+
+/// Whether a message signed by its fee payer alone fits into a single packet.
+pub fn fits_in_packet(message: &Message) -> bool {
+    let tx = Transaction { signatures: vec![Signature::default()], message: message.clone() };
+    bincode::serialized_size(&tx).is_ok_and(|size| size as usize <= PACKET_DATA_SIZE)
+}
+
+/// Messages travel between the parties as base64 encoded, bincode serialized `VersionedMessage`s.
+pub fn encode_message(message: &VersionedMessage) -> String {
+    let message_bytes = bincode::serialize(message).expect("messages always serialize");
+    base64::engine::general_purpose::STANDARD.encode(message_bytes)
+}
+
+/// Accepts both legacy and v0 messages, the version is encoded in the first byte.
+pub fn decode_message(message_base64: &str) -> Result<VersionedMessage, Error> {
+    let message_bytes = base64::engine::general_purpose::STANDARD
+        .decode(message_base64)
+        .map_err(|_| Error::InvalidMessage)?;
+    bincode::deserialize::<VersionedMessage>(&message_bytes).map_err(|_| Error::InvalidMessage)
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{pubkey::Pubkey, system_program};
+
+    use super::{create_unsigned_batch_transaction, create_unsigned_token_transaction, fits_in_packet, token, BatchTransfer, TokenMint};
+
+    fn transfers(count: usize, amount: u64) -> Vec<BatchTransfer> {
+        (0..count).map(|_| BatchTransfer { to: Pubkey::new_unique(), lamports: 10_000, amount }).collect()
+    }
+
+    #[test]
+    fn test_batch_of_sol() {
+        let payer = Pubkey::new_unique();
+        let tx = create_unsigned_batch_transaction(&transfers(3, 0), None, &payer);
+        assert_eq!(tx.message.instructions.len(), 3);
+        assert!(tx.message.instructions.iter().all(|ix| tx.message.account_keys[ix.program_id_index as usize] == system_program::id()));
+        assert_eq!(tx.message.account_keys[0], payer);
+        assert!(fits_in_packet(&tx.message));
+    }
+
+    #[test]
+    fn test_batch_of_tokens() {
+        let payer = Pubkey::new_unique();
+        let mint = TokenMint { mint: Pubkey::new_unique(), program: token::TOKEN_PROGRAM_ID, decimals: 6 };
+        let tx = create_unsigned_batch_transaction(&transfers(2, 500), Some(&mint), &payer);
+        // SOL, token account and tokens for every recipient
+        assert_eq!(tx.message.instructions.len(), 6);
+        assert_eq!(tx.message.header.num_required_signatures, 1);
+    }
+
+    #[test]
+    fn test_oversized_batch() {
+        let payer = Pubkey::new_unique();
+        assert!(!fits_in_packet(&create_unsigned_batch_transaction(&transfers(40, 0), None, &payer).message));
+    }
+
+    #[test]
+    fn test_token_transfer() {
+        let (payer, to) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mint = TokenMint { mint: Pubkey::new_unique(), program: token::TOKEN_2022_PROGRAM_ID, decimals: 9 };
+        let program = |tx: &solana_sdk::transaction::Transaction, index: usize| tx.message.account_keys[tx.message.instructions[index].program_id_index as usize];
+
+        let tx = create_unsigned_token_transaction(&mint, &to, 1_000, true, None, &payer);
+        assert_eq!(tx.message.instructions.len(), 2);
+        assert_eq!(program(&tx, 0), token::ASSOCIATED_TOKEN_PROGRAM_ID);
+        assert_eq!(program(&tx, 1), token::TOKEN_2022_PROGRAM_ID);
+        assert!(tx.message.account_keys.contains(&token::associated_token_address(&to, &mint.mint, &token::TOKEN_2022_PROGRAM_ID)));
+
+        let tx = create_unsigned_token_transaction(&mint, &to, 1_000, false, Some("rent".to_string()), &payer);
+        assert_eq!(tx.message.instructions.len(), 2);
+        assert_eq!(program(&tx, 0), token::TOKEN_2022_PROGRAM_ID);
+        assert_eq!(program(&tx, 1), spl_memo::id());
+        assert_eq!(tx.message.header.num_required_signatures, 1);
+    }
+}
@@ -1,210 +1,855 @@
 use actix_web::{web::{self, post}, App, Error, HttpResponse, HttpServer};
 use serde::{Deserialize, Serialize};
 
-pub mod error;
-pub mod serialization;
-pub mod tss;
-pub mod auth;
-pub mod middleware;
-
-use crate::{serialization::PartialSignature, tss::{key_agg, sign_and_broadcast, step_one, step_two}};
-use solana_sdk::{instruction::Instruction, message::Message, native_token, signature::Keypair, system_instruction, transaction::Transaction};
+mod parties;
+
+use mpc::{
+    create_unsigned_batch_transaction, create_unsigned_lamports_transaction, create_unsigned_token_transaction, decode_message,
+    error,
+    fee::{message_fee, percentile_fee, with_compute_budget, ComputeBudget, FeeStrategy, MAX_COMPUTE_UNIT_LIMIT},
+    fits_in_packet,
+    middleware::{self, ServiceAuth},
+    nonce::{create_nonce_account, nonce_address, uses_durable_nonce, with_durable_nonce, NonceAccount, NONCE_ACCOUNT_SIZE},
+    protocol::{ExportInput, GenerateOutput, PublicKeyInput},
+    rpc::{ConfirmationStatus, RpcClient, RpcError},
+    simulation::{Simulation, Simulator},
+    token,
+    tss::key_agg,
+    BatchTransfer, TokenMint, SIGNATURE_FEE_LAMPORTS,
+};
+use parties::{collect_export, run_dkg, share_servers, wallet_pubkey, ExportRelease, PartyClient, PartyShare, SigningRound};
+use solana_sdk::{hash::Hash, message::{Message, VersionedMessage}, native_token::sol_to_lamports, signature::Signature};
 use solana_sdk::pubkey::Pubkey;
-use std::str::FromStr;
-use crate::serialization::{AggMessage1, SecretAggStepOne};
-use base64::engine::Engine;
+use std::{str::FromStr, sync::Arc};
+use topology::{identity::{ServiceIdentity, ServiceVerifier}, Role, Topology};
 
 #[derive(Serialize, Deserialize)]
 pub struct GeneratePubKeyInput {
     pub user_id: String,
+    #[serde(default)]
+    pub scheme: KeyScheme,
+    /// Number of parties needed to sign a FROST wallet, defaults to a majority of the parties
+    pub threshold: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyScheme {
+    /// Every party has to sign
+    #[default]
+    Musig2,
+    /// Any `threshold` of the parties can sign
+    Frost,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TransferInput {
+    pub user_id: String,
+    pub amount: f64,
+    pub to: String,
+    pub memo: Option<String>,
+    /// Exact amount, used instead of `amount` when set
+    #[serde(default)]
+    pub lamports: Option<u64>,
+    #[serde(default)]
+    pub fee: FeeStrategy,
+    /// Use the wallet's durable nonce instead of a recent blockhash
+    #[serde(default)]
+    pub durable_nonce: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TransferTokenInput {
+    pub user_id: String,
+    pub mint: String,
+    /// Owner of the receiving token account, not the token account itself
+    pub to: String,
+    /// In base units of the mint
+    pub amount: u64,
+    #[serde(default)]
+    pub memo: Option<String>,
+    #[serde(default)]
+    pub fee: FeeStrategy,
+    /// Use the wallet's durable nonce instead of a recent blockhash
+    #[serde(default)]
+    pub durable_nonce: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BatchTransferInput {
+    pub user_id: String,
+    /// Token sent along with the lamports, in base units of the mint
+    pub mint: Option<String>,
+    pub transfers: Vec<BatchTransferItem>,
+    #[serde(default)]
+    pub fee: FeeStrategy,
+    /// Use the wallet's durable nonce instead of a recent blockhash
+    #[serde(default)]
+    pub durable_nonce: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BatchTransferItem {
+    pub to: String,
+    #[serde(default)]
+    pub lamports: u64,
+    #[serde(default)]
+    pub amount: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SweepInput {
+    pub user_id: String,
+    pub to: String,
+    /// Sweep this token instead of SOL
+    pub mint: Option<String>,
+    /// A SOL sweep pays the priority fee out of the swept amount
+    #[serde(default)]
+    pub fee: FeeStrategy,
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct AggAndStep1Input {
-    pub keypair_base64: String,
+pub struct SweepResponse {
+    /// `None` if there was nothing to sweep
+    pub signature: Option<String>,
+    pub confirmation_status: Option<ConfirmationStatus>,
+    /// What was moved, in lamports or base units of the mint
+    pub amount: u64,
+    pub fee_lamports: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SignMessageInput {
+    pub user_id: String,
+    /// Base64 encoded, bincode serialized legacy or v0 `VersionedMessage`
+    pub message: String,
+    /// Last block height the message's blockhash is valid for, used to stop waiting for confirmation
+    pub last_valid_block_height: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SimulateInput {
+    pub user_id: String,
+    /// Base64 encoded, bincode serialized legacy or v0 `VersionedMessage`
+    pub message: String,
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct AggAndStep1Output {
-    agg_message1: AggMessage1,
-    secret_agg_step_one: SecretAggStepOne,
+pub struct BroadcastResponse {
+    pub signature: String,
+    /// `None` if a durable nonce transaction didn't land while the coordinator waited, it still can
+    pub confirmation_status: Option<ConfirmationStatus>,
+    /// Signature and priority fees paid by the wallet
+    pub fee_lamports: u64,
+    /// The dry run the transaction passed before it was signed
+    pub simulation: Simulation,
+    /// The wallet's next durable nonce, if the transaction used one and landed
+    pub nonce: Option<String>,
+    /// The durable nonce the transaction was signed over, it can land until that nonce advances
+    pub durable_nonce: Option<String>,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct AggAndStep2Input {
-    pub keypair_base64: String,
-    pub amount: f64,
-    pub to: String,
-    pub keys: Vec<String>,
-    pub first_messages: Vec<AggMessage1>,
-    pub secret_state: SecretAggStepOne,
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SignatureStatusInput {
+    pub user_id: String,
+    pub signature: String,
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct AggAndStep2Output {
-    pub partial_signature: PartialSignature,
+pub struct SignatureStatusResponse {
+    /// `None` while the cluster has not seen the transaction
+    pub confirmation_status: Option<ConfirmationStatus>,
+    /// Why the transaction failed on chain
+    pub error: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
-pub struct SignatureAggregationInput {
-    pub amount: f64,
-    pub to: Pubkey,
-    pub keys: Vec<Pubkey>,
-    pub signatures: Vec<PartialSignature>,
+pub struct NonceInput {
+    pub user_id: String,
+    #[serde(default)]
+    pub fee: FeeStrategy,
 }
 
 #[derive(Serialize, Deserialize)]
-struct BroadcastResponse {
-    signature: Transaction,
+pub struct NonceResponse {
+    pub address: String,
+    /// `None` while the account doesn't exist
+    pub nonce: Option<String>,
+    /// Transaction that created the account, if this request did
+    pub signature: Option<String>,
+    pub confirmation_status: Option<ConfirmationStatus>,
+    /// Fees paid for creating the account
+    pub fee_lamports: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportResponse {
+    /// Empty unless `complete`, a partial export is never handed out
+    pub shares: Vec<PartyShare>,
+    /// Parties that failed to release their share
+    pub failures: Vec<String>,
+    /// Whether the shares are enough to rebuild the wallet
+    pub complete: bool,
 }
 
 #[actix_web::main]
 async fn main() -> Result<(), std::io::Error> {
-    HttpServer::new(|| {
+    let topology = match Topology::load() {
+        Ok(topology) => topology,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let coordinator = match topology.coordinator() {
+        Ok(party) => party.clone(),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let bind_address = match coordinator.bind_address() {
+        Ok(address) => address,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let identity = match ServiceIdentity::load(&topology, &coordinator.id) {
+        Ok(identity) => web::Data::new(identity),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // Only the backend talks to the coordinator
+    let verifier = match ServiceVerifier::new(&topology, &coordinator.id, &[Role::Backend]) {
+        Ok(verifier) => Arc::new(verifier),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let topology = web::Data::new(topology);
+    let rpc = RpcClient::from_env();
+    let simulator = Simulator::from_env();
+    HttpServer::new(move || {
         App::new()
-        .route("/generate", post().to(generate).wrap(middleware::AuthMiddleware))
-        .route("/agg-send-step1", post().to(agg_send_step1).wrap(middleware::AuthMiddleware))
-        .route("/agg-send-step2", post().to(agg_send_step2).wrap(middleware::AuthMiddleware))
-        .route(
-            "/aggregate-signatures-broadcast",
-            post().to(aggregate_signatures_broadcast).wrap(middleware::AuthMiddleware),
-        )
+        .app_data(web::Data::new(rpc.clone()))
+        .app_data(web::Data::new(simulator.clone()))
+        .app_data(topology.clone())
+        .app_data(identity.clone())
+        .wrap(middleware::AuthMiddleware::new(verifier.clone()))
+        .route("/generate", post().to(generate))
+        .route("/transfer", post().to(transfer))
+        .route("/transfer-token", post().to(transfer_token))
+        .route("/transfer-batch", post().to(transfer_batch))
+        .route("/sweep", post().to(sweep))
+        .route("/sign-message", post().to(sign_message_broadcast))
+        .route("/simulate", post().to(simulate))
+        .route("/signature-status", post().to(signature_status))
+        .route("/nonce", post().to(get_nonce))
+        .route("/nonce/create", post().to(create_nonce))
+        .route("/export", post().to(export))
     })
-    
-        .bind("127.0.0.1:8080")?
+
+        .bind(bind_address)?
         .run()
         .await
 }
 
-async fn generate(data: web::Json<GeneratePubKeyInput>) -> Result<HttpResponse, Error> {
-    let token = match auth::create_jwt_for_communication(data.user_id.clone()) {
-        Ok(t) => t,
-        Err(e) => {
-            let error_message = format!("Error creating JWT: {:?}", e);
-            return Ok(HttpResponse::InternalServerError().body(error_message));
-        }
-    };
-    let mut pub_keys = vec![];
-    let client = reqwest::Client::new();
-    let data_to_send = GeneratePubKeyInput {
-        user_id: data.user_id.clone(),
-    };
+async fn generate(auth: ServiceAuth, data: web::Json<GeneratePubKeyInput>, topology: web::Data<Topology>, identity: web::Data<ServiceIdentity>) -> Result<HttpResponse, Error> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    if data.scheme == KeyScheme::Frost {
+        let parties = topology.share_servers().len() as u16;
+        let threshold = data.threshold.unwrap_or(parties / 2 + 1);
+        return match run_dkg(&topology, identity.into_inner(), &data.user_id, threshold).await {
+            Ok(pubkey) => Ok(HttpResponse::Ok().json(GenerateOutput { pubkey: pubkey.to_string() })),
+            Err(error_message) => Ok(HttpResponse::InternalServerError().body(error_message)),
+        };
+    }
 
-    let target_url = vec![
-        "http://localhost:9000/generatePubKey",
-        "http://localhost:9001/generatePubKey",
-    ];
+    let mut pub_keys = vec![];
+    let client = PartyClient::new(identity.into_inner(), &data.user_id);
+    let data_to_send = PublicKeyInput { user_id: data.user_id.clone() };
 
-    for url in target_url {
-        match client.post(url)
-            .json(&data_to_send)
-            .bearer_auth(&token)
-            .send()
-            .await
-        {
-            Ok(response) => {
-                if response.status().is_success() {
-                    let response_body = match response.text().await {
-                        Ok(body) => body,
-                        Err(_) => {
-                            let error_message = format!("Failed to read response body from {}", url);
-                            return Ok(HttpResponse::InternalServerError().body(error_message));
-                        }
-                    };
-                    pub_keys.push(response_body);
-                } else {
-                    let error_message = format!("Failed to send data to {}: {:?}", url, response.status());
-                    return Ok(HttpResponse::InternalServerError().body(error_message));
-                }
-            }
-            Err(e) => {
-                let error_message = format!("Error sending request to {}: {:?}", url, e);
-                return Ok(HttpResponse::InternalServerError().body(error_message));
-            }
+    for party in share_servers(&topology) {
+        match client.post::<_, GenerateOutput>(&party, "/generatePubKey", &data_to_send).await {
+            Ok(response_body) => pub_keys.push(response_body.pubkey),
+            Err(e) => return Ok(HttpResponse::InternalServerError().body(e.message)),
         }
     }
 
-    let pub_keys: Vec<Pubkey> = pub_keys.into_iter().map(|key| Pubkey::from_str(&key).unwrap()).collect();
+    let pub_keys: Vec<Pubkey> = match pub_keys.iter().map(|key| Pubkey::from_str(key)).collect() {
+        Ok(keys) => keys,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Share server returned an invalid public key")),
+    };
     let final_pub_key = match key_agg(pub_keys, None) {
-        Ok(key) => key,
+        Ok(key) => Pubkey::new(&*key.agg_public_key.to_bytes(true)),
         Err(e) => {
             return Ok(HttpResponse::InternalServerError().body(format!("Error aggregating keys: {:?}", e)));
         }
     };
 
-    Ok(HttpResponse::Ok().json(&final_pub_key))
+    Ok(HttpResponse::Ok().json(GenerateOutput { pubkey: final_pub_key.to_string() }))
 }
 
-async fn agg_send_step1(data: web::Json<AggAndStep1Input>) -> Result<HttpResponse, Error> {
-    let keypair_bytes = match base64::engine::general_purpose::STANDARD.decode(&data.keypair_base64) {
-        Ok(bytes) => bytes,
-        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid base64 for keypair bytes")),
+async fn transfer(auth: ServiceAuth, data: web::Json<TransferInput>, rpc: web::Data<RpcClient>, simulator: web::Data<Simulator>, topology: web::Data<Topology>, identity: web::Data<ServiceIdentity>) -> Result<HttpResponse, Error> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    let to = match Pubkey::from_str(&data.to) {
+        Ok(pk) => pk,
+        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid recipient public key")),
+    };
+    let identity = identity.into_inner();
+    let payer = match wallet_pubkey(&topology, identity.clone(), &data.user_id).await {
+        Ok(pk) => pk,
+        Err(error_message) => return Ok(HttpResponse::InternalServerError().body(error_message)),
     };
-    let keypair = match Keypair::from_bytes(keypair_bytes.as_slice()) {
-        Ok(kp) => kp,
-        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid keypair bytes")),
+    // Every party signs over the same blockhash, so it is fetched once for the whole session.
+    let lifetime = match message_lifetime(&rpc, &payer, data.durable_nonce).await {
+        Ok(lifetime) => lifetime,
+        Err(response) => return Ok(response),
     };
-    let response = step_one(keypair);
-    Ok(HttpResponse::Ok().json(AggAndStep1Output {
-        agg_message1: response.0,
-        secret_agg_step_one: response.1,
-    }))
+    let lamports = data.lamports.unwrap_or_else(|| sol_to_lamports(data.amount));
+    let message = create_unsigned_lamports_transaction(lamports, &to, data.memo.clone(), &payer).message;
+    let message = match with_fee_strategy(&rpc, &simulator, &data.fee, lifetime.apply(message)).await {
+        Ok(message) => message,
+        Err(response) => return Ok(response),
+    };
+
+    sign_and_submit(&rpc, &simulator, &topology, identity, &data.user_id, VersionedMessage::Legacy(message), lifetime.last_valid_block_height).await
 }
 
-async fn agg_send_step2(data: web::Json<AggAndStep2Input>) -> Result<HttpResponse, Error> {
-    let keypair_bytes = match base64::engine::general_purpose::STANDARD.decode(&data.keypair_base64) {
-        Ok(bytes) => bytes,
-        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid base64 for keypair bytes")),
+/// The token program owning `mint` and the mint's decimals.
+async fn token_mint(rpc: &RpcClient, mint: &Pubkey) -> Result<(Pubkey, u8), HttpResponse> {
+    let account = match rpc.get_account(mint).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return Err(HttpResponse::BadRequest().body("Mint account not found")),
+        Err(e) => return Err(HttpResponse::BadGateway().body(e.to_string())),
+    };
+    if !token::is_token_program(&account.owner) {
+        return Err(HttpResponse::BadRequest().body("Mint is not owned by a token program"));
+    }
+    match token::mint_decimals(&account.data) {
+        Some(decimals) => Ok((account.owner, decimals)),
+        None => Err(HttpResponse::BadRequest().body("Invalid mint account")),
+    }
+}
+
+async fn transfer_token(auth: ServiceAuth, data: web::Json<TransferTokenInput>, rpc: web::Data<RpcClient>, simulator: web::Data<Simulator>, topology: web::Data<Topology>, identity: web::Data<ServiceIdentity>) -> Result<HttpResponse, Error> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    if data.amount == 0 {
+        return Ok(HttpResponse::BadRequest().body("Amount must be positive"));
+    }
+    let (to, mint) = match (Pubkey::from_str(&data.to), Pubkey::from_str(&data.mint)) {
+        (Ok(to), Ok(mint)) => (to, mint),
+        _ => return Ok(HttpResponse::BadRequest().body("Invalid recipient or mint public key")),
+    };
+    let mint = match token_mint(&rpc, &mint).await {
+        Ok((program, decimals)) => TokenMint { mint, program, decimals },
+        Err(response) => return Ok(response),
+    };
+    let identity = identity.into_inner();
+    let payer = match wallet_pubkey(&topology, identity.clone(), &data.user_id).await {
+        Ok(pk) => pk,
+        Err(error_message) => return Ok(HttpResponse::InternalServerError().body(error_message)),
+    };
+
+    // Fail here rather than with a program error after both signing rounds
+    let source = token::associated_token_address(&payer, &mint.mint, &mint.program);
+    let balance = match rpc.get_account(&source).await {
+        Ok(account) => account.and_then(|account| token::token_account_amount(&account.data)).unwrap_or(0),
+        Err(e) => return Ok(HttpResponse::BadGateway().body(e.to_string())),
+    };
+    if balance < data.amount {
+        return Ok(HttpResponse::BadRequest().body("Insufficient token balance"));
+    }
+    let destination = token::associated_token_address(&to, &mint.mint, &mint.program);
+    let create_account = match rpc.get_account(&destination).await {
+        Ok(account) => account.is_none(),
+        Err(e) => return Ok(HttpResponse::BadGateway().body(e.to_string())),
+    };
+
+    let lifetime = match message_lifetime(&rpc, &payer, data.durable_nonce).await {
+        Ok(lifetime) => lifetime,
+        Err(response) => return Ok(response),
+    };
+    let message = create_unsigned_token_transaction(&mint, &to, data.amount, create_account, data.memo.clone(), &payer).message;
+    let message = match with_fee_strategy(&rpc, &simulator, &data.fee, lifetime.apply(message)).await {
+        Ok(message) => message,
+        Err(response) => return Ok(response),
+    };
+
+    sign_and_submit(&rpc, &simulator, &topology, identity, &data.user_id, VersionedMessage::Legacy(message), lifetime.last_valid_block_height).await
+}
+
+/// Pay many recipients in a single transaction, as many as fit into one packet.
+async fn transfer_batch(auth: ServiceAuth, data: web::Json<BatchTransferInput>, rpc: web::Data<RpcClient>, simulator: web::Data<Simulator>, topology: web::Data<Topology>, identity: web::Data<ServiceIdentity>) -> Result<HttpResponse, Error> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    if data.transfers.is_empty() {
+        return Ok(HttpResponse::BadRequest().body("No transfers"));
+    }
+    let transfers: Vec<BatchTransfer> = match data
+        .transfers
+        .iter()
+        .map(|item| Pubkey::from_str(&item.to).map(|to| BatchTransfer { to, lamports: item.lamports, amount: item.amount }))
+        .collect()
+    {
+        Ok(transfers) => transfers,
+        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid recipient public key")),
     };
-    let keypair = match Keypair::from_bytes(keypair_bytes.as_slice()) {
-        Ok(kp) => kp,
-        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid keypair bytes")),
+    let mint = match data.mint.as_deref().map(Pubkey::from_str).transpose() {
+        Ok(Some(mint)) => match token_mint(&rpc, &mint).await {
+            Ok((program, decimals)) => Some(TokenMint { mint, program, decimals }),
+            Err(response) => return Ok(response),
+        },
+        Ok(None) => None,
+        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid mint public key")),
     };
+    let identity = identity.into_inner();
+    let payer = match wallet_pubkey(&topology, identity.clone(), &data.user_id).await {
+        Ok(pk) => pk,
+        Err(error_message) => return Ok(HttpResponse::InternalServerError().body(error_message)),
+    };
+    let message = create_unsigned_batch_transaction(&transfers, mint.as_ref(), &payer).message;
+    if !fits_in_packet(&message) {
+        return Ok(HttpResponse::BadRequest().body("Too many transfers for one transaction"));
+    }
+    let lifetime = match message_lifetime(&rpc, &payer, data.durable_nonce).await {
+        Ok(lifetime) => lifetime,
+        Err(response) => return Ok(response),
+    };
+    let message = match with_fee_strategy(&rpc, &simulator, &data.fee, lifetime.apply(message)).await {
+        Ok(message) => message,
+        Err(response) => return Ok(response),
+    };
+    // The compute budget takes a few bytes of its own
+    if !fits_in_packet(&message) {
+        return Ok(HttpResponse::BadRequest().body("Too many transfers for one transaction"));
+    }
+
+    sign_and_submit(&rpc, &simulator, &topology, identity, &data.user_id, VersionedMessage::Legacy(message), lifetime.last_valid_block_height).await
+}
+
+/// What a new message is anchored to, a recent blockhash or the wallet's durable nonce.
+struct Lifetime {
+    blockhash: Hash,
+    last_valid_block_height: u64,
+    nonce_account: Option<Pubkey>,
+}
+
+impl Lifetime {
+    fn apply(&self, mut message: Message) -> Message {
+        match &self.nonce_account {
+            Some(nonce_account) => with_durable_nonce(&message, nonce_account, self.blockhash),
+            None => {
+                message.recent_blockhash = self.blockhash;
+                message
+            }
+        }
+    }
+}
+
+async fn message_lifetime(rpc: &RpcClient, payer: &Pubkey, durable_nonce: bool) -> Result<Lifetime, HttpResponse> {
+    if !durable_nonce {
+        return match rpc.get_latest_blockhash().await {
+            Ok((blockhash, last_valid_block_height)) => Ok(Lifetime { blockhash, last_valid_block_height, nonce_account: None }),
+            Err(e) => Err(HttpResponse::BadGateway().body(error::Error::RecentHashFailed(e).to_string())),
+        };
+    }
+    let address = nonce_address(payer);
+    match read_nonce(rpc, &address).await? {
+        // Never expires by block height, waiting for confirmation is bounded by time alone
+        Some(nonce) if nonce.authority == *payer => {
+            Ok(Lifetime { blockhash: nonce.nonce, last_valid_block_height: u64::MAX, nonce_account: Some(address) })
+        }
+        Some(_) => Err(HttpResponse::Conflict().body("The durable nonce account has another authority")),
+        None => Err(HttpResponse::BadRequest().body("The wallet has no durable nonce account")),
+    }
+}
+
+async fn read_nonce(rpc: &RpcClient, address: &Pubkey) -> Result<Option<NonceAccount>, HttpResponse> {
+    match rpc.get_account(address).await {
+        Ok(account) => Ok(account.and_then(|account| NonceAccount::parse(&account.data))),
+        Err(e) => Err(HttpResponse::BadGateway().body(e.to_string())),
+    }
+}
+
+/// Where the wallet's durable nonce account is and its current nonce.
+async fn get_nonce(auth: ServiceAuth, data: web::Json<NonceInput>, rpc: web::Data<RpcClient>, topology: web::Data<Topology>, identity: web::Data<ServiceIdentity>) -> Result<HttpResponse, Error> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    let payer = match wallet_pubkey(&topology, identity.into_inner(), &data.user_id).await {
+        Ok(pk) => pk,
+        Err(error_message) => return Ok(HttpResponse::InternalServerError().body(error_message)),
+    };
+    let address = nonce_address(&payer);
+    match read_nonce(&rpc, &address).await {
+        Ok(nonce) => Ok(HttpResponse::Ok().json(NonceResponse {
+            address: address.to_string(),
+            nonce: nonce.map(|nonce| nonce.nonce.to_string()),
+            signature: None,
+            confirmation_status: None,
+            fee_lamports: 0,
+        })),
+        Err(response) => Ok(response),
+    }
+}
+
+/// Create the wallet's durable nonce account, paid by the wallet. Does nothing if it exists.
+async fn create_nonce(auth: ServiceAuth, data: web::Json<NonceInput>, rpc: web::Data<RpcClient>, simulator: web::Data<Simulator>, topology: web::Data<Topology>, identity: web::Data<ServiceIdentity>) -> Result<HttpResponse, Error> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    let identity = identity.into_inner();
+    let payer = match wallet_pubkey(&topology, identity.clone(), &data.user_id).await {
+        Ok(pk) => pk,
+        Err(error_message) => return Ok(HttpResponse::InternalServerError().body(error_message)),
+    };
+    let address = nonce_address(&payer);
+    match read_nonce(&rpc, &address).await {
+        Ok(Some(nonce)) => {
+            return Ok(HttpResponse::Ok().json(NonceResponse {
+                address: address.to_string(),
+                nonce: Some(nonce.nonce.to_string()),
+                signature: None,
+                confirmation_status: None,
+                fee_lamports: 0,
+            }));
+        }
+        Ok(None) => {}
+        Err(response) => return Ok(response),
+    }
+
+    let lamports = match rpc.get_minimum_balance_for_rent_exemption(NONCE_ACCOUNT_SIZE).await {
+        Ok(lamports) => lamports,
+        Err(e) => return Ok(HttpResponse::BadGateway().body(e.to_string())),
+    };
+    let lifetime = match message_lifetime(&rpc, &payer, false).await {
+        Ok(lifetime) => lifetime,
+        Err(response) => return Ok(response),
+    };
+    let message = Message::new_with_blockhash(&create_nonce_account(&payer, lamports), Some(&payer), &lifetime.blockhash);
+    let message = match with_fee_strategy(&rpc, &simulator, &data.fee, message).await {
+        Ok(message) => message,
+        Err(response) => return Ok(response),
+    };
+    let broadcast = match sign_and_broadcast(&rpc, &simulator, &topology, identity, &data.user_id, VersionedMessage::Legacy(message), lifetime.last_valid_block_height).await {
+        Ok(broadcast) => broadcast,
+        Err(response) => return Ok(response),
+    };
+    let nonce = match read_nonce(&rpc, &address).await {
+        Ok(nonce) => nonce.map(|nonce| nonce.nonce.to_string()),
+        Err(response) => return Ok(response),
+    };
+    Ok(HttpResponse::Ok().json(NonceResponse {
+        address: address.to_string(),
+        nonce,
+        signature: Some(broadcast.signature),
+        confirmation_status: broadcast.confirmation_status,
+        fee_lamports: broadcast.fee_lamports,
+    }))
+}
+
+/// Move everything the wallet holds of SOL or of one token to `to`. A token sweep closes the
+/// wallet's token account, SOL left in the wallet needs a sweep of its own.
+async fn sweep(auth: ServiceAuth, data: web::Json<SweepInput>, rpc: web::Data<RpcClient>, simulator: web::Data<Simulator>, topology: web::Data<Topology>, identity: web::Data<ServiceIdentity>) -> Result<HttpResponse, Error> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
     let to = match Pubkey::from_str(&data.to) {
         Ok(pk) => pk,
         Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid recipient public key")),
     };
-    let keys: Vec<Pubkey> = match data.keys.iter().map(|k| Pubkey::from_str(k)).collect() {
-        Ok(ks) => ks,
-        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid public key in keys array")),
+    let mint = match data.mint.as_deref().map(Pubkey::from_str).transpose() {
+        Ok(mint) => mint,
+        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid mint public key")),
+    };
+
+    let swept = match mint {
+        Some(mint) => sweep_token(&rpc, &simulator, &topology, identity.into_inner(), &data, &to, &mint).await,
+        None => sweep_lamports(&rpc, &simulator, &topology, identity.into_inner(), &data, &to).await,
+    };
+    match swept {
+        Ok(Some((broadcast, amount))) => Ok(HttpResponse::Ok().json(SweepResponse {
+            signature: Some(broadcast.signature),
+            confirmation_status: broadcast.confirmation_status,
+            amount,
+            fee_lamports: broadcast.fee_lamports,
+        })),
+        Ok(None) => Ok(HttpResponse::Ok().json(SweepResponse { signature: None, confirmation_status: None, amount: 0, fee_lamports: 0 })),
+        Err(response) => Ok(response),
+    }
+}
+
+async fn sweep_lamports(rpc: &RpcClient, simulator: &Simulator, topology: &Topology, identity: Arc<ServiceIdentity>, data: &SweepInput, to: &Pubkey) -> Result<Option<(BroadcastResponse, u64)>, HttpResponse> {
+    let payer = wallet_pubkey(topology, identity.clone(), &data.user_id).await.map_err(|e| HttpResponse::InternalServerError().body(e))?;
+    let balance = rpc.get_balance(&payer).await.map_err(|e| HttpResponse::BadGateway().body(e.to_string()))?;
+    if balance <= SIGNATURE_FEE_LAMPORTS {
+        return Ok(None);
+    }
+    let (recent_block_hash, last_valid_block_height) = rpc
+        .get_latest_blockhash()
+        .await
+        .map_err(|e| HttpResponse::BadGateway().body(error::Error::RecentHashFailed(e).to_string()))?;
+    let sweep_message = |amount| {
+        let mut message = create_unsigned_lamports_transaction(amount, to, None, &payer).message;
+        message.recent_blockhash = recent_block_hash;
+        message
     };
-    let recent_block_hash = solana_sdk::hash::Hash::new_unique();
-    let first_messages = data.first_messages.clone();
-    let secret_state = data.secret_state.clone();
-    let response = step_two(keypair, data.amount, to, None, recent_block_hash, keys, first_messages, secret_state);
-    match response {
-        Ok(sig) => Ok(HttpResponse::Ok().json(AggAndStep2Output { partial_signature: sig })),
-        Err(e) => Ok(HttpResponse::InternalServerError().body(format!("Error in step two: {:?}", e))),
+
+    let mut amount = balance - SIGNATURE_FEE_LAMPORTS;
+    let mut message = sweep_message(amount);
+    // The priority fee comes out of the swept amount, so the budget has to be known first
+    if let Some(budget) = compute_budget(rpc, simulator, &data.fee, &message).await? {
+        let fee = SIGNATURE_FEE_LAMPORTS + budget.priority_fee();
+        if balance <= fee {
+            return Ok(None);
+        }
+        amount = balance - fee;
+        message = with_compute_budget(&sweep_message(amount), &budget);
     }
+
+    let broadcast = sign_and_broadcast(rpc, simulator, topology, identity, &data.user_id, VersionedMessage::Legacy(message), last_valid_block_height).await?;
+    Ok(Some((broadcast, amount)))
 }
 
-async fn aggregate_signatures_broadcast(data: web::Json<SignatureAggregationInput>) -> Result<HttpResponse, Error> {
-    let recent_block_hash = solana_sdk::hash::Hash::new_unique();
-    let keys = data.keys.clone();
-    let signatures = data.signatures.clone();
-    let response = sign_and_broadcast(data.amount, data.to, None, recent_block_hash, keys, signatures);
+async fn sweep_token(rpc: &RpcClient, simulator: &Simulator, topology: &Topology, identity: Arc<ServiceIdentity>, data: &SweepInput, to: &Pubkey, mint: &Pubkey) -> Result<Option<(BroadcastResponse, u64)>, HttpResponse> {
+    let (token_program, decimals) = token_mint(rpc, mint).await?;
+    let payer = wallet_pubkey(topology, identity.clone(), &data.user_id).await.map_err(|e| HttpResponse::InternalServerError().body(e))?;
+    let source = token::associated_token_address(&payer, mint, &token_program);
+    let amount = match rpc.get_account(&source).await {
+        Ok(Some(account)) => token::token_account_amount(&account.data).unwrap_or_default(),
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(HttpResponse::BadGateway().body(e.to_string())),
+    };
+    if amount == 0 {
+        return Ok(None);
+    }
+    let (recent_block_hash, last_valid_block_height) = rpc
+        .get_latest_blockhash()
+        .await
+        .map_err(|e| HttpResponse::BadGateway().body(error::Error::RecentHashFailed(e).to_string()))?;
+    let instructions = [
+        token::create_associated_token_account_idempotent(&payer, to, mint, &token_program),
+        token::transfer_checked(
+            &token_program,
+            &source,
+            mint,
+            &token::associated_token_address(to, mint, &token_program),
+            &payer,
+            amount,
+            decimals,
+        ),
+        token::close_account(&token_program, &source, to, &payer),
+    ];
+    let message = Message::new_with_blockhash(&instructions, Some(&payer), &recent_block_hash);
+    let message = with_fee_strategy(rpc, simulator, &data.fee, message).await?;
+
+    let broadcast = sign_and_broadcast(rpc, simulator, topology, identity, &data.user_id, VersionedMessage::Legacy(message), last_valid_block_height).await?;
+    Ok(Some((broadcast, amount)))
+}
 
-    match response {
-        Ok(sig) => {
-            let broadcast_response = BroadcastResponse {
-                signature: sig,
-            };
-            Ok(HttpResponse::Ok().json(broadcast_response))
+/// The compute budget `fee` asks for, `None` if it asks for none. The unit limit is what a
+/// simulation of `message` consumed, plus some headroom.
+async fn compute_budget(rpc: &RpcClient, simulator: &Simulator, fee: &FeeStrategy, message: &Message) -> Result<Option<ComputeBudget>, HttpResponse> {
+    let micro_lamports = match *fee {
+        FeeStrategy::None => return Ok(None),
+        FeeStrategy::Fixed { micro_lamports } => micro_lamports,
+        FeeStrategy::Percentile { percentile } if percentile > 100 => {
+            return Err(HttpResponse::BadRequest().body("Percentile must be between 0 and 100"));
+        }
+        FeeStrategy::Percentile { percentile } => {
+            let writable: Vec<Pubkey> = message
+                .account_keys
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| message.is_writable(*index))
+                .map(|(_, key)| *key)
+                .collect();
+            match rpc.get_recent_prioritization_fees(&writable).await {
+                Ok(fees) => percentile_fee(fees, percentile),
+                Err(e) => return Err(HttpResponse::BadGateway().body(e.to_string())),
+            }
         }
-        Err(e) => Ok(HttpResponse::InternalServerError().body(format!("Error aggregating signatures and broadcasting: {:?}", e))),
+    };
+    // Priced at zero, so the probe doesn't fail for a fee the wallet can't pay for the full limit
+    let probe = with_compute_budget(message, &ComputeBudget { unit_limit: MAX_COMPUTE_UNIT_LIMIT, micro_lamports: 0 });
+    let units_consumed = match simulator.simulate(&VersionedMessage::Legacy(probe)).await {
+        Ok(simulation) if simulation.succeeded() => simulation.units_consumed.unwrap_or(u64::from(MAX_COMPUTE_UNIT_LIMIT)),
+        Ok(simulation) => return Err(HttpResponse::UnprocessableEntity().json(simulation)),
+        Err(e) => return Err(HttpResponse::BadGateway().body(format!("Simulation failed: {}", e))),
+    };
+    Ok(Some(ComputeBudget::for_units_consumed(units_consumed, micro_lamports)))
+}
+
+/// `message` with the compute budget `fee` asks for prepended.
+async fn with_fee_strategy(rpc: &RpcClient, simulator: &Simulator, fee: &FeeStrategy, message: Message) -> Result<Message, HttpResponse> {
+    match compute_budget(rpc, simulator, fee, &message).await? {
+        Some(budget) => Ok(with_compute_budget(&message, &budget)),
+        None => Ok(message),
     }
 }
 
+async fn sign_message_broadcast(auth: ServiceAuth, data: web::Json<SignMessageInput>, rpc: web::Data<RpcClient>, simulator: web::Data<Simulator>, topology: web::Data<Topology>, identity: web::Data<ServiceIdentity>) -> Result<HttpResponse, Error> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    let message = match decode_message(&data.message) {
+        Ok(msg) => msg,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+    };
 
-pub fn create_unsigned_transaction(amount: f64, to: &Pubkey, memo: Option<String>, payer: &Pubkey) -> Transaction {
-    let amount = native_token::sol_to_lamports(amount);
-    let transfer_ins = system_instruction::transfer(payer, to, amount);
-    let msg = match memo {
-        None => Message::new(&[transfer_ins], Some(payer)),
-        Some(memo) => {
-            let memo_ins = Instruction { program_id: spl_memo::id(), accounts: Vec::new(), data: memo.into_bytes() };
-            Message::new(&[transfer_ins, memo_ins], Some(payer))
+    sign_and_submit(&rpc, &simulator, &topology, identity.into_inner(), &data.user_id, message, data.last_valid_block_height).await
+}
+
+/// Dry run a message the user is about to sign, nothing is signed or sent.
+async fn simulate(auth: ServiceAuth, data: web::Json<SimulateInput>, simulator: web::Data<Simulator>) -> Result<HttpResponse, Error> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    let message = match decode_message(&data.message) {
+        Ok(msg) => msg,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+    };
+    match simulator.simulate(&message).await {
+        Ok(simulation) => Ok(HttpResponse::Ok().json(simulation)),
+        Err(e) => Ok(HttpResponse::BadGateway().body(e.to_string())),
+    }
+}
+
+/// Where a submitted transaction is, for callers tracking it after `sign_and_broadcast` returned.
+async fn signature_status(auth: ServiceAuth, data: web::Json<SignatureStatusInput>, rpc: web::Data<RpcClient>) -> Result<HttpResponse, Error> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    let signature = match Signature::from_str(&data.signature) {
+        Ok(signature) => signature,
+        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid signature")),
+    };
+    match rpc.get_signature_status(&signature).await {
+        Ok(confirmation_status) => Ok(HttpResponse::Ok().json(SignatureStatusResponse { confirmation_status, error: None })),
+        Err(RpcError::TransactionFailed(err)) => {
+            Ok(HttpResponse::Ok().json(SignatureStatusResponse { confirmation_status: None, error: Some(err.to_string()) }))
         }
+        Err(e) => Ok(HttpResponse::BadGateway().body(e.to_string())),
+    }
+}
+
+async fn export(auth: ServiceAuth, data: web::Json<ExportInput>, topology: web::Data<Topology>, identity: web::Data<ServiceIdentity>) -> Result<HttpResponse, Error> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    if Pubkey::from_str(&data.recipient).is_err() {
+        return Ok(HttpResponse::BadRequest().body("Invalid recipient public key"));
+    }
+    match collect_export(&topology, identity.into_inner(), &data.export_id, &data.user_id, &data.recipient).await {
+        Ok(ExportRelease::Complete { shares, failures }) => Ok(HttpResponse::Ok().json(ExportResponse { shares, failures, complete: true })),
+        Ok(ExportRelease::Incomplete { failures }) => Ok(HttpResponse::Ok().json(ExportResponse { shares: vec![], failures, complete: false })),
+        Err(error_message) => Ok(HttpResponse::InternalServerError().body(error_message)),
+    }
+}
+
+async fn sign_and_submit(
+    rpc: &RpcClient,
+    simulator: &Simulator,
+    topology: &Topology,
+    identity: Arc<ServiceIdentity>,
+    user_id: &str,
+    message: VersionedMessage,
+    last_valid_block_height: u64,
+) -> Result<HttpResponse, Error> {
+    match sign_and_broadcast(rpc, simulator, topology, identity, user_id, message, last_valid_block_height).await {
+        Ok(broadcast) => Ok(HttpResponse::Ok().json(broadcast)),
+        Err(response) => Ok(response),
+    }
+}
+
+/// Simulate the message, then sign and land it. A message that would fail never reaches the
+/// share servers, so no nonces are committed and no fee is paid for it.
+async fn sign_and_broadcast(
+    rpc: &RpcClient,
+    simulator: &Simulator,
+    topology: &Topology,
+    identity: Arc<ServiceIdentity>,
+    user_id: &str,
+    message: VersionedMessage,
+    last_valid_block_height: u64,
+) -> Result<BroadcastResponse, HttpResponse> {
+    let simulation = match simulator.simulate(&message).await {
+        Ok(simulation) if simulation.succeeded() => simulation,
+        Ok(simulation) => return Err(HttpResponse::UnprocessableEntity().json(simulation)),
+        Err(e) => return Err(HttpResponse::BadGateway().body(format!("Simulation failed: {}", e))),
+    };
+    let fee_lamports = message_fee(&message);
+    let round = match SigningRound::start(topology, identity, user_id).await {
+        Ok(round) => round,
+        Err(error_message) => return Err(HttpResponse::InternalServerError().body(error_message)),
+    };
+    let signatures = match round.partial_signatures(&message).await {
+        Ok(signatures) => signatures,
+        Err(error_message) => return Err(HttpResponse::InternalServerError().body(error_message)),
+    };
+    let tx = match round.aggregate(message.clone(), signatures) {
+        Ok(tx) => tx,
+        Err(e) if is_rejected_message(&e) => return Err(HttpResponse::BadRequest().body(e.to_string())),
+        Err(e) => return Err(HttpResponse::InternalServerError().body(format!("Error aggregating signatures: {:?}", e))),
     };
-    Transaction::new_unsigned(msg)
-}
\ No newline at end of file
+    // The signature is already valid at this point, a party failing to close its session must not block the broadcast.
+    if let Err(error_message) = round.finalize(&message, &tx.signatures[0]).await {
+        eprintln!("Failed to finalize signing session: {}", error_message);
+    }
+    let tx_bytes = match bincode::serialize(&tx) {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(HttpResponse::InternalServerError().body(format!("Error serializing transaction: {:?}", e))),
+    };
+
+    let durable_nonce = uses_durable_nonce(&message).then(|| message.recent_blockhash().to_string());
+    let (signature, confirmation_status) = match broadcast(rpc, &tx_bytes, last_valid_block_height, durable_nonce.is_some()).await {
+        Ok(sent) => sent,
+        Err(e) => return Err(HttpResponse::BadGateway().body(e.to_string())),
+    };
+    // The transaction advanced the nonce, the caller needs the new one for its next transaction
+    let nonce = if durable_nonce.is_some() && confirmation_status.is_some() {
+        let address = nonce_address(&message.static_account_keys()[0]);
+        read_nonce(rpc, &address).await.ok().flatten().map(|nonce| nonce.nonce.to_string())
+    } else {
+        None
+    };
+    Ok(BroadcastResponse {
+        signature: signature.to_string(),
+        confirmation_status,
+        fee_lamports,
+        simulation,
+        nonce,
+        durable_nonce,
+    })
+}
+
+/// Send the transaction and wait for it to confirm. A durable nonce transaction never expires by
+/// block height, running out of time to wait for it leaves it submitted rather than failed.
+async fn broadcast(rpc: &RpcClient, tx_bytes: &[u8], last_valid_block_height: u64, durable_nonce: bool) -> Result<(Signature, Option<ConfirmationStatus>), error::Error> {
+    let signature = rpc.send_transaction(tx_bytes).await.map_err(error::Error::SendTransactionFailed)?;
+    match rpc.confirm_transaction(&signature, last_valid_block_height).await {
+        Ok(confirmation_status) => Ok((signature, Some(confirmation_status))),
+        Err(RpcError::Timeout) if durable_nonce => Ok((signature, None)),
+        Err(e) => Err(error::Error::ConfirmingTransactionFailed(e)),
+    }
+}
+
+/// Errors caused by the caller handing in a message the aggregated key must not sign.
+fn is_rejected_message(e: &error::Error) -> bool {
+    matches!(
+        e,
+        error::Error::InvalidMessage | error::Error::AggregatedKeyNotFeePayer | error::Error::UnexpectedSigners(_)
+    )
+}
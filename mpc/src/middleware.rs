@@ -1,15 +1,27 @@
+//! Service token authentication of the coordinator and the share servers, both only take requests
+//! from parties listed in the topology.
+
 use actix_web::{
     body::BoxBody,
-    dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpResponse,
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::ErrorUnauthorized,
+    Error, FromRequest, HttpMessage, HttpRequest, HttpResponse,
 };
-use chrono::Utc;
-use futures::future::{ok, Ready, LocalBoxFuture};
-use std::rc::Rc;
+use futures::future::{err, ok, Ready, LocalBoxFuture};
+use std::{rc::Rc, sync::Arc};
+use topology::identity::{ServiceClaims, ServiceVerifier};
 
-use crate::auth::verify_jwt;
+/// Only lets through requests with a service token addressed to this party. The verified
+/// `ServiceClaims` are put into the request extensions.
+pub struct AuthMiddleware {
+    verifier: Arc<ServiceVerifier>,
+}
 
-pub struct AuthMiddleware;
+impl AuthMiddleware {
+    pub fn new(verifier: Arc<ServiceVerifier>) -> Self {
+        Self { verifier }
+    }
+}
 
 impl<S> Transform<S, ServiceRequest> for AuthMiddleware
 where
@@ -25,12 +37,14 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ok(AuthMiddlewareService {
             service: Rc::new(service),
+            verifier: self.verifier.clone(),
         })
     }
 }
 
 pub struct AuthMiddlewareService<S> {
     service: Rc<S>,
+    verifier: Arc<ServiceVerifier>,
 }
 
 impl<S> Service<ServiceRequest> for AuthMiddlewareService<S>
@@ -50,6 +64,7 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let service = self.service.clone();
+        let verifier = self.verifier.clone();
 
         Box::pin(async move {
             // Check for Authorization header
@@ -58,33 +73,23 @@ where
             if let Some(auth_header) = auth_header {
                 if let Ok(token) = auth_header.to_str() {
                     // Remove "Bearer " prefix if present
-                    let token = if token.starts_with("Bearer ") {
-                        &token[7..]
-                    } else {
-                        token
-                    };
-
-                    match verify_jwt(token) {
-                        Ok(_payload) => {
-                            if _payload.exp < Utc::now().timestamp() as usize {
-                                let response = HttpResponse::Unauthorized()
-                                    .json(serde_json::json!({"error": "Token has expired"}));
-                                let srv_resp = req.into_response(response.map_into_boxed_body());
-                                return Ok(srv_resp);
-                            }
-                            if _payload.id.is_empty() {
+                    let token = token.strip_prefix("Bearer ").unwrap_or(token);
+
+                    // Expiry, issuer and audience are checked by the verifier, user tokens never pass
+                    match verifier.verify(token) {
+                        Ok(claims) => {
+                            if claims.sub.is_empty() {
                                 let response = HttpResponse::Unauthorized()
                                     .json(serde_json::json!({"error": "Invalid user ID in token"}));
                                 let srv_resp = req.into_response(response.map_into_boxed_body());
                                 return Ok(srv_resp);
                             }
+                            req.extensions_mut().insert(claims);
                             service.call(req).await
                         }
-                        Err(_) => {
-                            // Token is invalid -> build ServiceResponse<BoxBody>
+                        Err(e) => {
                             let response = HttpResponse::Unauthorized()
-                                .json(serde_json::json!({"error": "Invalid token"}));
-                            // Convert ServiceRequest + HttpResponse -> ServiceResponse<BoxBody>
+                                .json(serde_json::json!({"error": e.to_string()}));
                             let srv_resp = req.into_response(response.map_into_boxed_body());
                             Ok(srv_resp)
                         }
@@ -106,3 +111,49 @@ where
         })
     }
 }
+
+/// The verified service token of a request, taken from the extensions `AuthMiddleware` filled.
+pub struct ServiceAuth(pub ServiceClaims);
+
+impl ServiceAuth {
+    /// The token is minted for a single user, it can't be used to act on anyone else's key.
+    pub fn authorize(&self, user_id: &str) -> Result<(), HttpResponse> {
+        if self.0.sub != user_id {
+            return Err(HttpResponse::Forbidden().json(serde_json::json!({"error": "Token is not authorized for this user"})));
+        }
+        Ok(())
+    }
+}
+
+impl FromRequest for ServiceAuth {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.extensions().get::<ServiceClaims>() {
+            Some(claims) => ok(ServiceAuth(claims.clone())),
+            None => err(ErrorUnauthorized("Missing service token")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use topology::identity::ServiceClaims;
+
+    use super::ServiceAuth;
+
+    #[test]
+    fn test_authorize() {
+        let auth = ServiceAuth(ServiceClaims {
+            iss: "coordinator".to_string(),
+            aud: "share-1".to_string(),
+            sub: "alice".to_string(),
+            iat: 0,
+            exp: 0,
+            jti: "1".to_string(),
+        });
+        assert!(auth.authorize("alice").is_ok());
+        assert_eq!(auth.authorize("bob").unwrap_err().status(), actix_web::http::StatusCode::FORBIDDEN);
+    }
+}
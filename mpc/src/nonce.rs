@@ -0,0 +1,137 @@
+//! Durable nonce accounts, so a transaction stays valid for as long as the MuSig2 rounds take
+//! instead of the ~60 seconds of a recent blockhash. Every wallet has at most one, at an address
+//! derived from the wallet's key, with the wallet as its authority.
+
+use solana_sdk::{
+    hash::Hash,
+    instruction::Instruction,
+    message::{Message, VersionedMessage},
+    pubkey::Pubkey,
+    system_instruction, system_program,
+};
+
+const NONCE_SEED: &str = "durable-nonce";
+/// Size of a nonce account: version, state, authority, nonce and fee calculator
+pub const NONCE_ACCOUNT_SIZE: usize = 80;
+const INITIALIZED: u32 = 1;
+const ADVANCE_NONCE_ACCOUNT: u32 = 4;
+
+/// Where `authority`'s nonce account lives. Derived with a seed, so creating it only needs the
+/// wallet's own signature.
+pub fn nonce_address(authority: &Pubkey) -> Pubkey {
+    Pubkey::create_with_seed(authority, NONCE_SEED, &system_program::id()).expect("the seed is short enough")
+}
+
+/// Create and initialize `authority`'s nonce account, paid by `authority`.
+pub fn create_nonce_account(authority: &Pubkey, lamports: u64) -> Vec<Instruction> {
+    system_instruction::create_nonce_account_with_seed(
+        authority,
+        &nonce_address(authority),
+        authority,
+        NONCE_SEED,
+        authority,
+        lamports,
+    )
+}
+
+/// An initialized nonce account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NonceAccount {
+    pub authority: Pubkey,
+    /// Used as the recent blockhash of the next transaction, changes every time it is used
+    pub nonce: Hash,
+}
+
+impl NonceAccount {
+    /// `None` for anything but an initialized nonce account.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() != NONCE_ACCOUNT_SIZE {
+            return None;
+        }
+        let state = u32::from_le_bytes(data[4..8].try_into().ok()?);
+        if state != INITIALIZED {
+            return None;
+        }
+        Some(Self {
+            authority: Pubkey::new_from_array(data[8..40].try_into().ok()?),
+            nonce: Hash::new_from_array(data[40..72].try_into().ok()?),
+        })
+    }
+}
+
+/// Make `message` use the nonce instead of a recent blockhash. The nonce is advanced by the
+/// message's first instruction, authorized by the fee payer.
+pub fn with_durable_nonce(message: &Message, nonce_account: &Pubkey, nonce: Hash) -> Message {
+    let payer = message.account_keys[0];
+    let mut instructions = vec![system_instruction::advance_nonce_account(nonce_account, &payer)];
+    instructions.extend(crate::fee::decompile(message));
+    Message::new_with_blockhash(&instructions, Some(&payer), &nonce)
+}
+
+pub fn is_advance_nonce(program_id: &Pubkey, data: &[u8]) -> bool {
+    *program_id == system_program::id() && data.get(..4) == Some(&ADVANCE_NONCE_ACCOUNT.to_le_bytes()[..])
+}
+
+/// Whether the message's blockhash is a durable nonce rather than a recent blockhash.
+pub fn uses_durable_nonce(message: &VersionedMessage) -> bool {
+    message.instructions().first().is_some_and(|ix| {
+        message
+            .static_account_keys()
+            .get(usize::from(ix.program_id_index))
+            .is_some_and(|program_id| is_advance_nonce(program_id, &ix.data))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{hash::Hash, message::{Message, VersionedMessage}, pubkey::Pubkey, system_instruction};
+
+    use super::*;
+    use crate::fee::{with_compute_budget, ComputeBudget};
+
+    #[test]
+    fn test_nonce_account_layout() {
+        let (authority, nonce) = (Pubkey::new_unique(), Hash::new_unique());
+        let mut data = vec![0u8; NONCE_ACCOUNT_SIZE];
+        data[0..4].copy_from_slice(&1u32.to_le_bytes());
+        data[4..8].copy_from_slice(&1u32.to_le_bytes());
+        data[8..40].copy_from_slice(authority.as_ref());
+        data[40..72].copy_from_slice(nonce.as_ref());
+        assert_eq!(NonceAccount::parse(&data), Some(NonceAccount { authority, nonce }));
+
+        data[4..8].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(NonceAccount::parse(&data), None);
+        assert_eq!(NonceAccount::parse(&data[..40]), None);
+    }
+
+    #[test]
+    fn test_durable_message() {
+        let (payer, to) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let nonce = Hash::new_unique();
+        let message = Message::new(&[system_instruction::transfer(&payer, &to, 1_000)], Some(&payer));
+        assert!(!uses_durable_nonce(&VersionedMessage::Legacy(message.clone())));
+
+        let durable = with_durable_nonce(&message, &nonce_address(&payer), nonce);
+        assert_eq!(durable.recent_blockhash, nonce);
+        assert_eq!(durable.account_keys[0], payer);
+        assert_eq!(durable.header.num_required_signatures, 1);
+        assert!(uses_durable_nonce(&VersionedMessage::Legacy(durable.clone())));
+
+        // A compute budget goes after the nonce, which has to stay the first instruction
+        let budgeted = with_compute_budget(&durable, &ComputeBudget { unit_limit: 10_000, micro_lamports: 1 });
+        assert_eq!(budgeted.instructions.len(), 4);
+        assert_eq!(budgeted.recent_blockhash, nonce);
+        assert!(uses_durable_nonce(&VersionedMessage::Legacy(budgeted)));
+    }
+
+    #[test]
+    fn test_nonce_account_creation() {
+        let authority = Pubkey::new_unique();
+        let instructions = create_nonce_account(&authority, 1_447_680);
+        assert_eq!(instructions.len(), 2);
+        let message = Message::new(&instructions, Some(&authority));
+        // Seeded from the wallet, so the wallet is the only signer
+        assert_eq!(message.header.num_required_signatures, 1);
+        assert!(message.account_keys.contains(&nonce_address(&authority)));
+    }
+}
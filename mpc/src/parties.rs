@@ -0,0 +1,376 @@
+use curv::elliptic::curves::{Ed25519, Point};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{message::VersionedMessage, pubkey::Pubkey, signature::Signature, transaction::VersionedTransaction};
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use mpc::{
+    encode_message,
+    export::SealedShare,
+    frost::{self, Identifier, SigningCommitments},
+    protocol::{
+        DkgFinishInput, DkgRoundOneInput, DkgRoundOneOutput, DkgRoundTwoInput, DkgRoundTwoOutput, ExportInput, ExportOutput,
+        FinalizeInput, GenerateOutput, PublicKeyInput, PublicKeyOutput, SecondRound, StepOneInput, StepOneOutput, StepTwoInput,
+        StepTwoOutput,
+    },
+    serialization::AggMessage1,
+    tss::{self, key_agg},
+};
+
+use topology::{identity::ServiceIdentity, Party, Topology};
+
+/// The share servers. A new FROST wallet numbers them in this order, starting at 1, and keeps the
+/// numbering, so the list can change later without breaking the wallet.
+pub fn share_servers(topology: &Topology) -> Vec<Party> {
+    topology.share_servers().into_iter().cloned().collect()
+}
+
+/// A share server answered with an error or could not be reached.
+pub struct PartyError {
+    pub status: Option<reqwest::StatusCode>,
+    pub message: String,
+}
+
+impl From<PartyError> for String {
+    fn from(e: PartyError) -> Self {
+        e.message
+    }
+}
+
+/// Run a FROST key generation across all parties, any `threshold` of them can sign afterwards.
+/// The coordinator only relays the packages, the round two shares are encrypted to a key their
+/// receiver signed in round one, so the coordinator can't read or redirect them.
+pub async fn run_dkg(topology: &Topology, identity: Arc<ServiceIdentity>, user_id: &str, threshold: u16) -> Result<Pubkey, String> {
+    let client = PartyClient::new(identity, user_id);
+    let session_id = uuid::Uuid::new_v4().to_string();
+    let parties = share_servers(topology);
+    if parties.len() > usize::from(u16::MAX) {
+        return Err("Too many parties".to_string());
+    }
+    // Stored with every share, signing looks the identifiers up by party id
+    let identifiers: BTreeMap<_, _> = parties.iter().enumerate().map(|(index, party)| (index as u16 + 1, party.id.clone())).collect();
+
+    let mut round1_packages = vec![];
+    for party in &parties {
+        let body: DkgRoundOneOutput = client.post(party, "/dkg/round-one", &DkgRoundOneInput {
+            session_id: session_id.clone(),
+            user_id: user_id.to_string(),
+            threshold,
+            parties: identifiers.clone(),
+        }).await?;
+        round1_packages.push(body.package);
+    }
+
+    let mut round2_packages = vec![];
+    for party in &parties {
+        let body: DkgRoundTwoOutput = client.post(party, "/dkg/round-two", &DkgRoundTwoInput {
+            session_id: session_id.clone(),
+            user_id: user_id.to_string(),
+            round1_packages: round1_packages.clone(),
+        }).await?;
+        round2_packages.extend(body.round2_packages);
+    }
+
+    let mut group_keys = vec![];
+    for (index, party) in parties.iter().enumerate() {
+        let identifier = index as u16 + 1;
+        let body: GenerateOutput = client.post(party, "/dkg/finish", &DkgFinishInput {
+            session_id: session_id.clone(),
+            user_id: user_id.to_string(),
+            round1_packages: round1_packages.clone(),
+            round2_packages: round2_packages.iter().filter(|share| share.receiver == identifier).cloned().collect(),
+        }).await?;
+        group_keys.push(body.pubkey);
+    }
+
+    if !group_keys.iter().all(|key| *key == group_keys[0]) {
+        return Err("Parties ended the DKG with different group keys".to_string());
+    }
+    group_keys[0].parse().map_err(|_| "Share server returned an invalid group key".to_string())
+}
+
+/// A share released by one party, still encrypted to the user's key.
+#[derive(Serialize, Deserialize)]
+pub struct PartyShare {
+    pub party: String,
+    pub share: SealedShare,
+}
+
+/// What came of asking the share servers for an export.
+pub enum ExportRelease {
+    /// Enough shares to rebuild the wallet, `failures` lists parties whose share is not needed
+    Complete { shares: Vec<PartyShare>, failures: Vec<String> },
+    /// Too few parties released their share, none are handed out so the export can be retried
+    Incomplete { failures: Vec<String> },
+}
+
+/// Ask every share server for its share of the wallet, sealed to `recipient`. Shares come back in
+/// the order of the topology, which MuSig2 wallets need to be rebuilt. A MuSig2 wallet needs the
+/// share of every party, a FROST wallet only `threshold` of them.
+pub async fn collect_export(topology: &Topology, identity: Arc<ServiceIdentity>, export_id: &str, user_id: &str, recipient: &str) -> Result<ExportRelease, String> {
+    let client = PartyClient::new(identity, user_id);
+    let input = ExportInput { export_id: export_id.to_string(), user_id: user_id.to_string(), recipient: recipient.to_string() };
+
+    let mut shares = vec![];
+    let mut failures = vec![];
+    let mut threshold = None;
+    for party in topology.share_servers() {
+        match client.post::<_, ExportOutput>(party, "/export", &input).await {
+            Ok(body) => {
+                threshold = threshold.or(body.threshold);
+                shares.push(PartyShare { party: party.id.clone(), share: body.share });
+            }
+            Err(e) if e.status == Some(reqwest::StatusCode::NOT_FOUND) => continue,
+            Err(e) => failures.push(e.message),
+        }
+    }
+    if shares.is_empty() && failures.is_empty() {
+        return Err("No party holds a key for this user".to_string());
+    }
+    let complete = match threshold {
+        Some(threshold) => shares.len() >= threshold as usize,
+        None => !shares.is_empty() && failures.is_empty(),
+    };
+    if !complete {
+        return Ok(ExportRelease::Incomplete { failures });
+    }
+    Ok(ExportRelease::Complete { shares, failures })
+}
+
+/// The wallet's public key, asked of the parties without committing any nonces. Lets a
+/// transaction be built and checked before a signing round starts.
+pub async fn wallet_pubkey(topology: &Topology, identity: Arc<ServiceIdentity>, user_id: &str) -> Result<Pubkey, String> {
+    let client = PartyClient::new(identity, user_id);
+    let input = PublicKeyInput { user_id: user_id.to_string() };
+
+    let mut keys = vec![];
+    let mut failures = vec![];
+    for party in topology.share_servers() {
+        match client.post::<_, PublicKeyOutput>(party, "/public-key", &input).await {
+            // Any party of a FROST wallet knows the group key
+            Ok(PublicKeyOutput::Frost { group_key }) => return Ok(frost::group_pubkey(&group_key)),
+            Ok(PublicKeyOutput::Musig2 { pubkey }) => keys.push(pubkey),
+            Err(e) if e.status == Some(reqwest::StatusCode::NOT_FOUND) => continue,
+            Err(e) => failures.push(e.message),
+        }
+    }
+    if !failures.is_empty() {
+        return Err(failures.join(", "));
+    }
+    if keys.is_empty() {
+        return Err("No party holds a key for this user".to_string());
+    }
+    let aggkey = key_agg(keys, None).map_err(|e| format!("Error aggregating keys: {:?}", e))?;
+    Ok(Pubkey::new(&*aggkey.agg_public_key.to_bytes(true)))
+}
+
+enum RoundKind {
+    Musig2 { keys: Vec<Pubkey>, first_messages: Vec<AggMessage1> },
+    Frost {
+        group_key: Point<Ed25519>,
+        threshold: u16,
+        verifying_shares: BTreeMap<Identifier, Point<Ed25519>>,
+        commitments: Vec<SigningCommitments>,
+    },
+}
+
+/// One signing round across the share servers. The coordinator only ever sees the public
+/// commitments and the partial signatures, the nonces stay on the parties.
+/// MuSig2 wallets need every party, FROST wallets any `threshold` of them.
+pub struct SigningRound {
+    client: PartyClient,
+    session_id: String,
+    user_id: String,
+    signers: Vec<Party>,
+    kind: RoundKind,
+}
+
+impl SigningRound {
+    /// Ask every party to commit to its nonces for a new session. Parties that joined after
+    /// the wallet was created don't hold a share of it and are left out.
+    pub async fn start(topology: &Topology, identity: Arc<ServiceIdentity>, user_id: &str) -> Result<Self, String> {
+        let client = PartyClient::new(identity, user_id);
+        let session_id = uuid::Uuid::new_v4().to_string();
+        let parties = share_servers(topology);
+        let participants: Vec<String> = parties.iter().map(|party| party.id.clone()).collect();
+
+        let mut responses = vec![];
+        let mut failures = vec![];
+        for party in &parties {
+            let input = StepOneInput {
+                session_id: session_id.clone(),
+                user_id: user_id.to_string(),
+                participants: participants.clone(),
+            };
+            match client.post::<_, StepOneOutput>(party, "/step-one", &input).await {
+                Ok(body) => responses.push((party.clone(), body)),
+                Err(e) if e.status == Some(reqwest::StatusCode::NOT_FOUND) => continue,
+                Err(e) => failures.push(e.message),
+            }
+        }
+
+        let kind = match responses.first() {
+            None if failures.is_empty() => return Err("No party holds a key for this user".to_string()),
+            None => return Err(failures.join(", ")),
+            Some((_, StepOneOutput::Musig2 { .. })) => {
+                if let Some(error_message) = failures.into_iter().next() {
+                    return Err(error_message);
+                }
+                let mut first_messages = vec![];
+                for (_, body) in &responses {
+                    match body {
+                        StepOneOutput::Musig2 { agg_message1 } => first_messages.push(agg_message1.clone()),
+                        StepOneOutput::Frost { .. } => return Err("Parties disagree on the wallet's key scheme".to_string()),
+                    }
+                }
+                let keys = first_messages.iter().map(|msg| msg.sender).collect();
+                RoundKind::Musig2 { keys, first_messages }
+            }
+            Some((_, StepOneOutput::Frost { group_key, threshold, parties: identifiers, verifying_shares, .. })) => {
+                let (group_key, threshold) = (group_key.clone(), *threshold);
+                let (identifiers, verifying_shares) = (identifiers.clone(), verifying_shares.clone());
+                let mut commitments = vec![];
+                for (party, body) in &responses {
+                    match body {
+                        StepOneOutput::Frost { commitments: c, group_key: key, parties: ids, verifying_shares: shares, .. }
+                            if *key == group_key && *ids == identifiers && *shares == verifying_shares =>
+                        {
+                            // The identifier is the one the DKG gave this party, not its place in the topology
+                            if identifiers.get(&c.identifier) != Some(&party.id) {
+                                return Err(format!("Party {} signs with the identifier of another party", party.id));
+                            }
+                            commitments.push(c.clone())
+                        }
+                        _ => return Err("Parties disagree on the wallet's key".to_string()),
+                    }
+                }
+                if commitments.len() < usize::from(threshold) {
+                    return Err(format!(
+                        "Only {} of the {} required parties are available: {}",
+                        commitments.len(),
+                        threshold,
+                        failures.join(", ")
+                    ));
+                }
+                // Unreachable parties simply sit this round out
+                for error_message in failures {
+                    eprintln!("Signing without a party: {}", error_message);
+                }
+                commitments.sort_by_key(|c| c.identifier);
+                RoundKind::Frost { group_key, threshold, verifying_shares, commitments }
+            }
+        };
+        let signers = responses.into_iter().map(|(party, _)| party).collect();
+
+        Ok(Self { client, session_id, user_id: user_id.to_string(), signers, kind })
+    }
+
+    /// Hand `message` to every signer and collect their partial signatures over it.
+    pub async fn partial_signatures(&self, message: &VersionedMessage) -> Result<Vec<StepTwoOutput>, String> {
+        let message = encode_message(message);
+        let round = match &self.kind {
+            RoundKind::Musig2 { keys, first_messages } => {
+                SecondRound::Musig2 { keys: keys.clone(), first_messages: first_messages.clone() }
+            }
+            RoundKind::Frost { commitments, .. } => SecondRound::Frost { commitments: commitments.clone() },
+        };
+        let mut signatures = vec![];
+        for party in &self.signers {
+            let body = self.client.post(party, "/step-two", &StepTwoInput {
+                session_id: self.session_id.clone(),
+                user_id: self.user_id.clone(),
+                message: message.clone(),
+                round: round.clone(),
+            }).await?;
+            signatures.push(body);
+        }
+        Ok(signatures)
+    }
+
+    /// Combine the partial signatures into a fully signed transaction.
+    pub fn aggregate(&self, message: VersionedMessage, signatures: Vec<StepTwoOutput>) -> Result<VersionedTransaction, mpc::Error> {
+        match &self.kind {
+            RoundKind::Musig2 { keys, .. } => {
+                let signatures = signatures
+                    .into_iter()
+                    .map(|output| match output {
+                        StepTwoOutput::Musig2 { partial_signature } => Ok(partial_signature),
+                        StepTwoOutput::Frost { .. } => Err(mpc::Error::MismatchMessages),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                tss::sign_message(message, keys.clone(), signatures)
+            }
+            RoundKind::Frost { group_key, threshold, verifying_shares, commitments } => {
+                let shares = signatures
+                    .into_iter()
+                    .map(|output| match output {
+                        StepTwoOutput::Frost { signature_share } => Ok(signature_share),
+                        StepTwoOutput::Musig2 { .. } => Err(mpc::Error::MismatchMessages),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                frost::sign_message(message, group_key, *threshold, verifying_shares, commitments, &shares)
+            }
+        }
+    }
+
+    /// Tell every signer the aggregated signature so they close the session.
+    pub async fn finalize(&self, message: &VersionedMessage, signature: &Signature) -> Result<(), String> {
+        let message = encode_message(message);
+        let keys = match &self.kind {
+            RoundKind::Musig2 { keys, .. } => keys.clone(),
+            RoundKind::Frost { .. } => vec![],
+        };
+        for party in &self.signers {
+            self.client.send(party, "/finalize", &FinalizeInput {
+                session_id: self.session_id.clone(),
+                user_id: self.user_id.clone(),
+                message: message.clone(),
+                keys: keys.clone(),
+                signature: signature.to_string(),
+            }).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Calls the share servers for one user. Every request carries a fresh service token addressed
+/// to the party it goes to, so a token can't be replayed against another party.
+pub struct PartyClient {
+    client: reqwest::Client,
+    identity: Arc<ServiceIdentity>,
+    user_id: String,
+}
+
+impl PartyClient {
+    pub fn new(identity: Arc<ServiceIdentity>, user_id: &str) -> Self {
+        Self { client: reqwest::Client::new(), identity, user_id: user_id.to_string() }
+    }
+
+    async fn send<I: Serialize>(&self, party: &Party, path: &str, input: &I) -> Result<reqwest::Response, PartyError> {
+        let url = format!("{}{}", party.url, path);
+        let token = self.identity
+            .token(&party.id, &self.user_id)
+            .map_err(|e| PartyError { status: None, message: format!("Error creating service token: {}", e) })?;
+        let response = self.client.post(&url)
+            .json(input)
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(|e| PartyError { status: None, message: format!("Error sending request to {}: {:?}", url, e) })?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(PartyError { status: Some(status), message: format!("Failed to send data to {}: {:?} {}", url, status, body) });
+        }
+        Ok(response)
+    }
+
+    pub async fn post<I: Serialize, O: for<'de> Deserialize<'de>>(&self, party: &Party, path: &str, input: &I) -> Result<O, PartyError> {
+        let url = format!("{}{}", party.url, path);
+        self.send(party, path, input)
+            .await?
+            .json::<O>()
+            .await
+            .map_err(|_| PartyError { status: None, message: format!("Failed to parse JSON from {}", url) })
+    }
+}
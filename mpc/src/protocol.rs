@@ -0,0 +1,142 @@
+//! Requests and responses between the coordinator and the share servers. Both sides use these
+//! types, so a change to one end of a call is a change to the other.
+
+use std::collections::BTreeMap;
+
+use curv::elliptic::curves::{Ed25519, Point};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    export::SealedShare,
+    frost::{DkgRound1Package, DkgRound2Package, Identifier, SignatureShare, SigningCommitments},
+    serialization::{AggMessage1, PartialSignature},
+};
+
+/// Body of `/generatePubKey` and `/public-key`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PublicKeyInput {
+    pub user_id: String,
+}
+
+/// A new wallet's public key, of the party's key for MuSig2 and of the group for FROST.
+#[derive(Serialize, Deserialize)]
+pub struct GenerateOutput {
+    pub pubkey: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "scheme", rename_all = "lowercase")]
+pub enum PublicKeyOutput {
+    Musig2 { pubkey: Pubkey },
+    Frost { group_key: Point<Ed25519> },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StepOneInput {
+    pub session_id: String,
+    pub user_id: String,
+    pub participants: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "scheme", rename_all = "lowercase")]
+pub enum StepOneOutput {
+    Musig2 { agg_message1: AggMessage1 },
+    Frost {
+        commitments: SigningCommitments,
+        group_key: Point<Ed25519>,
+        threshold: u16,
+        /// Party id behind every identifier, as stored with the wallet
+        parties: BTreeMap<Identifier, String>,
+        /// Lets the coordinator check every signature share on its own
+        verifying_shares: BTreeMap<Identifier, Point<Ed25519>>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StepTwoInput {
+    pub session_id: String,
+    pub user_id: String,
+    pub message: String,
+    #[serde(flatten)]
+    pub round: SecondRound,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "scheme", rename_all = "lowercase")]
+pub enum SecondRound {
+    Musig2 { keys: Vec<Pubkey>, first_messages: Vec<AggMessage1> },
+    /// Commitments of the signers taking part, sorted by identifier
+    Frost { commitments: Vec<SigningCommitments> },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "scheme", rename_all = "lowercase")]
+pub enum StepTwoOutput {
+    Musig2 { partial_signature: PartialSignature },
+    Frost { signature_share: SignatureShare },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FinalizeInput {
+    pub session_id: String,
+    pub user_id: String,
+    pub message: String,
+    /// Keys of all parties for MuSig2 wallets, FROST wallets are checked against the stored group key
+    #[serde(default)]
+    pub keys: Vec<Pubkey>,
+    pub signature: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DkgRoundOneInput {
+    pub session_id: String,
+    pub user_id: String,
+    pub threshold: u16,
+    /// The identifier of every party taking part, each share server finds its own by its party id
+    pub parties: BTreeMap<Identifier, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DkgRoundOneOutput {
+    pub package: DkgRound1Package,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DkgRoundTwoInput {
+    pub session_id: String,
+    pub user_id: String,
+    pub round1_packages: Vec<DkgRound1Package>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DkgRoundTwoOutput {
+    pub round2_packages: Vec<DkgRound2Package>,
+}
+
+/// Answered with the group key as a `GenerateOutput`.
+#[derive(Serialize, Deserialize)]
+pub struct DkgFinishInput {
+    pub session_id: String,
+    pub user_id: String,
+    pub round1_packages: Vec<DkgRound1Package>,
+    /// The shares the other parties sent to this one
+    pub round2_packages: Vec<DkgRound2Package>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportInput {
+    /// The backend's export request, a share server only releases it again to the same recipient
+    pub export_id: String,
+    pub user_id: String,
+    /// Base58 public key to encrypt the share to
+    pub recipient: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportOutput {
+    pub share: SealedShare,
+    /// Shares needed to rebuild a FROST wallet, a MuSig2 wallet needs the share of every party
+    pub threshold: Option<u16>,
+}
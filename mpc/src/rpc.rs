@@ -0,0 +1,430 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+use base64::engine::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use solana_sdk::{hash::Hash, pubkey::Pubkey, signature::Signature};
+
+const DEFAULT_RPC_URL: &str = "https://api.devnet.solana.com";
+const CONFIRM_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub enum RpcError {
+    Request(reqwest::Error),
+    Rpc { code: i64, message: String },
+    InvalidResponse(String),
+    TransactionFailed(Value),
+    BlockhashExpired,
+    Timeout,
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Request(e) => write!(f, "RPC request failed: {}", e),
+            Self::Rpc { code, message } => write!(f, "RPC error {}: {}", code, message),
+            Self::InvalidResponse(msg) => write!(f, "Invalid RPC response: {}", msg),
+            Self::TransactionFailed(err) => write!(f, "Transaction failed: {}", err),
+            Self::BlockhashExpired => write!(f, "Blockhash expired before the transaction was confirmed"),
+            Self::Timeout => write!(f, "Timed out waiting for confirmation"),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfirmationStatus {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<Value>,
+    error: Option<RpcErrorObject>,
+}
+
+#[derive(Deserialize)]
+struct RpcErrorObject {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct WithContext<T> {
+    value: T,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LatestBlockhash {
+    blockhash: String,
+    last_valid_block_height: u64,
+}
+
+#[derive(Deserialize)]
+struct RpcAccount {
+    lamports: u64,
+    owner: String,
+    /// `[base64 data, "base64"]`
+    data: (String, String),
+}
+
+/// An on-chain account as far as the coordinator cares about it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountInfo {
+    pub lamports: u64,
+    pub owner: Pubkey,
+    pub data: Vec<u8>,
+}
+
+fn account_info(account: RpcAccount) -> Result<AccountInfo, RpcError> {
+    Ok(AccountInfo {
+        lamports: account.lamports,
+        owner: Pubkey::from_str(&account.owner).map_err(|e| RpcError::InvalidResponse(e.to_string()))?,
+        data: base64::engine::general_purpose::STANDARD
+            .decode(&account.data.0)
+            .map_err(|e| RpcError::InvalidResponse(e.to_string()))?,
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcSimulation {
+    err: Option<Value>,
+    logs: Option<Vec<String>>,
+    accounts: Option<Vec<Option<RpcAccount>>>,
+    units_consumed: Option<u64>,
+}
+
+/// What a transaction would do, `accounts` are the requested accounts after it ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedTransaction {
+    pub err: Option<Value>,
+    pub logs: Vec<String>,
+    pub units_consumed: Option<u64>,
+    pub accounts: Vec<Option<AccountInfo>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PrioritizationFee {
+    prioritization_fee: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SignatureStatus {
+    err: Option<Value>,
+    confirmation_status: Option<ConfirmationStatus>,
+}
+
+/// Minimal Solana JSON-RPC client, only what the coordinator needs to land a transaction.
+/// Pointed at `SOLANA_RPC_URL`, so it works the same against a cluster, `solana-test-validator`
+/// or a mock server.
+#[derive(Clone)]
+pub struct RpcClient {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl RpcClient {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { client: reqwest::Client::new(), url: url.into() }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(dotenvy::var("SOLANA_RPC_URL").unwrap_or_else(|_| DEFAULT_RPC_URL.to_string()))
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let response = self.client.post(&self.url)
+            .json(&json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params}))
+            .send()
+            .await
+            .map_err(RpcError::Request)?
+            .json::<RpcResponse>()
+            .await
+            .map_err(RpcError::Request)?;
+        if let Some(error) = response.error {
+            return Err(RpcError::Rpc { code: error.code, message: error.message });
+        }
+        response.result.ok_or_else(|| RpcError::InvalidResponse(format!("{} returned no result", method)))
+    }
+
+    /// Returns the latest blockhash together with the last block height it is valid for.
+    pub async fn get_latest_blockhash(&self) -> Result<(Hash, u64), RpcError> {
+        let result = self.call("getLatestBlockhash", json!([{"commitment": "confirmed"}])).await?;
+        let latest: WithContext<LatestBlockhash> =
+            serde_json::from_value(result).map_err(|e| RpcError::InvalidResponse(e.to_string()))?;
+        let hash = Hash::from_str(&latest.value.blockhash).map_err(|e| RpcError::InvalidResponse(e.to_string()))?;
+        Ok((hash, latest.value.last_valid_block_height))
+    }
+
+    pub async fn get_block_height(&self) -> Result<u64, RpcError> {
+        let result = self.call("getBlockHeight", json!([{"commitment": "confirmed"}])).await?;
+        serde_json::from_value(result).map_err(|e| RpcError::InvalidResponse(e.to_string()))
+    }
+
+    pub async fn get_balance(&self, pubkey: &Pubkey) -> Result<u64, RpcError> {
+        let result = self.call("getBalance", json!([pubkey.to_string(), {"commitment": "confirmed"}])).await?;
+        let balance: WithContext<u64> =
+            serde_json::from_value(result).map_err(|e| RpcError::InvalidResponse(e.to_string()))?;
+        Ok(balance.value)
+    }
+
+    pub async fn get_minimum_balance_for_rent_exemption(&self, data_len: usize) -> Result<u64, RpcError> {
+        let result = self.call("getMinimumBalanceForRentExemption", json!([data_len, {"commitment": "confirmed"}])).await?;
+        serde_json::from_value(result).map_err(|e| RpcError::InvalidResponse(e.to_string()))
+    }
+
+    /// Returns `None` for accounts that don't exist.
+    pub async fn get_account(&self, pubkey: &Pubkey) -> Result<Option<AccountInfo>, RpcError> {
+        let result = self.call("getAccountInfo", json!([pubkey.to_string(), {"commitment": "confirmed", "encoding": "base64"}])).await?;
+        let account: WithContext<Option<RpcAccount>> =
+            serde_json::from_value(result).map_err(|e| RpcError::InvalidResponse(e.to_string()))?;
+        account.value.map(account_info).transpose()
+    }
+
+    /// Same as `get_account` for many accounts in one request, in the order of `pubkeys`.
+    pub async fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> Result<Vec<Option<AccountInfo>>, RpcError> {
+        let pubkeys: Vec<String> = pubkeys.iter().map(Pubkey::to_string).collect();
+        let result = self.call("getMultipleAccounts", json!([pubkeys, {"commitment": "confirmed", "encoding": "base64"}])).await?;
+        let accounts: WithContext<Vec<Option<RpcAccount>>> =
+            serde_json::from_value(result).map_err(|e| RpcError::InvalidResponse(e.to_string()))?;
+        accounts.value.into_iter().map(|account| account.map(account_info).transpose()).collect()
+    }
+
+    /// Per compute unit prices, in micro-lamports, paid in recent slots by transactions that wrote
+    /// any of `accounts`.
+    pub async fn get_recent_prioritization_fees(&self, accounts: &[Pubkey]) -> Result<Vec<u64>, RpcError> {
+        let accounts: Vec<String> = accounts.iter().map(Pubkey::to_string).collect();
+        let result = self.call("getRecentPrioritizationFees", json!([accounts])).await?;
+        let fees: Vec<PrioritizationFee> =
+            serde_json::from_value(result).map_err(|e| RpcError::InvalidResponse(e.to_string()))?;
+        Ok(fees.into_iter().map(|fee| fee.prioritization_fee).collect())
+    }
+
+    /// Run a transaction without landing it. Signatures aren't checked, so it can be simulated
+    /// before anybody signed. The blockhash is kept, an expired one fails the simulation.
+    pub async fn simulate_transaction(&self, tx_bytes: &[u8], addresses: &[Pubkey]) -> Result<SimulatedTransaction, RpcError> {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(tx_bytes);
+        let addresses: Vec<String> = addresses.iter().map(Pubkey::to_string).collect();
+        let result = self.call(
+            "simulateTransaction",
+            json!([encoded, {
+                "encoding": "base64",
+                "commitment": "confirmed",
+                "sigVerify": false,
+                "replaceRecentBlockhash": false,
+                "accounts": {"encoding": "base64", "addresses": addresses},
+            }]),
+        ).await?;
+        let simulation: WithContext<RpcSimulation> =
+            serde_json::from_value(result).map_err(|e| RpcError::InvalidResponse(e.to_string()))?;
+        let simulation = simulation.value;
+        Ok(SimulatedTransaction {
+            err: simulation.err,
+            logs: simulation.logs.unwrap_or_default(),
+            units_consumed: simulation.units_consumed,
+            accounts: simulation
+                .accounts
+                .unwrap_or_default()
+                .into_iter()
+                .map(|account| account.map(account_info).transpose())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Submit a bincode serialized (legacy or versioned) transaction.
+    pub async fn send_transaction(&self, tx_bytes: &[u8]) -> Result<Signature, RpcError> {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(tx_bytes);
+        let result = self.call(
+            "sendTransaction",
+            json!([encoded, {"encoding": "base64", "preflightCommitment": "confirmed"}]),
+        ).await?;
+        let signature = result.as_str().ok_or_else(|| RpcError::InvalidResponse("signature is not a string".to_string()))?;
+        Signature::from_str(signature).map_err(|e| RpcError::InvalidResponse(e.to_string()))
+    }
+
+    /// Returns `None` while the cluster has not seen the transaction yet.
+    pub async fn get_signature_status(&self, signature: &Signature) -> Result<Option<ConfirmationStatus>, RpcError> {
+        let result = self.call("getSignatureStatuses", json!([[signature.to_string()]])).await?;
+        let statuses: WithContext<Vec<Option<SignatureStatus>>> =
+            serde_json::from_value(result).map_err(|e| RpcError::InvalidResponse(e.to_string()))?;
+        match statuses.value.into_iter().next().flatten() {
+            None => Ok(None),
+            Some(SignatureStatus { err: Some(err), .. }) => Err(RpcError::TransactionFailed(err)),
+            Some(status) => Ok(status.confirmation_status.or(Some(ConfirmationStatus::Processed))),
+        }
+    }
+
+    /// Poll until the transaction is confirmed, failed, or its blockhash expired.
+    pub async fn confirm_transaction(&self, signature: &Signature, last_valid_block_height: u64) -> Result<ConfirmationStatus, RpcError> {
+        let started = std::time::Instant::now();
+        loop {
+            match self.get_signature_status(signature).await? {
+                Some(status @ (ConfirmationStatus::Confirmed | ConfirmationStatus::Finalized)) => return Ok(status),
+                Some(ConfirmationStatus::Processed) => {}
+                None => {
+                    if self.get_block_height().await? > last_valid_block_height {
+                        return Err(RpcError::BlockhashExpired);
+                    }
+                }
+            }
+            if started.elapsed() > CONFIRM_TIMEOUT {
+                return Err(RpcError::Timeout);
+            }
+            tokio::time::sleep(CONFIRM_POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use base64::engine::Engine;
+    use serde_json::{json, Value};
+    use solana_sdk::{hash::Hash, pubkey::Pubkey, signature::Signature};
+
+    use super::{ConfirmationStatus, RpcClient, RpcError};
+    use crate::token::TOKEN_PROGRAM_ID;
+
+    const BLOCKHASH: &str = "4sGjMW1sUnHzSxGspuhpqLDx6wiyjNtZAMdL4VZHirAn";
+    const MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+    /// Answers like a JSON-RPC node whose signature status only turns `confirmed` on the second poll.
+    fn start_mock_rpc(tx_err: Option<Value>) -> String {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let server = HttpServer::new(move || {
+            let polls = polls.clone();
+            let tx_err = tx_err.clone();
+            App::new().route("/", web::post().to(move |body: web::Json<Value>| {
+                let polls = polls.clone();
+                let tx_err = tx_err.clone();
+                async move {
+                    let result = match body["method"].as_str().unwrap() {
+                        "getLatestBlockhash" => json!({"context": {"slot": 1}, "value": {"blockhash": BLOCKHASH, "lastValidBlockHeight": 150}}),
+                        "getBlockHeight" => json!(100),
+                        "getBalance" => json!({"context": {"slot": 1}, "value": 2_000_000}),
+                        "getMinimumBalanceForRentExemption" => json!(1_447_680),
+                        "getAccountInfo" => match body["params"][0].as_str().unwrap() {
+                            MINT => json!({"context": {"slot": 1}, "value": {
+                                "lamports": 1_461_600,
+                                "owner": TOKEN_PROGRAM_ID.to_string(),
+                                "data": [base64::engine::general_purpose::STANDARD.encode([0u8; 82]), "base64"],
+                                "executable": false,
+                                "rentEpoch": 0,
+                            }}),
+                            _ => json!({"context": {"slot": 1}, "value": null}),
+                        },
+                        "getMultipleAccounts" => {
+                            let accounts: Vec<Value> = body["params"][0]
+                                .as_array()
+                                .unwrap()
+                                .iter()
+                                .map(|_| json!({"lamports": 1_000_000, "owner": "11111111111111111111111111111111", "data": ["", "base64"], "executable": false, "rentEpoch": 0}))
+                                .collect();
+                            json!({"context": {"slot": 1}, "value": accounts})
+                        }
+                        "simulateTransaction" => {
+                            let accounts: Vec<Value> = body["params"][1]["accounts"]["addresses"]
+                                .as_array()
+                                .unwrap()
+                                .iter()
+                                .map(|_| json!({"lamports": 994_000, "owner": "11111111111111111111111111111111", "data": ["", "base64"], "executable": false, "rentEpoch": 0}))
+                                .collect();
+                            json!({"context": {"slot": 1}, "value": {
+                                "err": tx_err,
+                                "logs": ["Program 11111111111111111111111111111111 invoke [1]", "Program 11111111111111111111111111111111 success"],
+                                "accounts": accounts,
+                                "unitsConsumed": 150,
+                            }})
+                        }
+                        "getRecentPrioritizationFees" => json!([
+                            {"slot": 1, "prioritizationFee": 0},
+                            {"slot": 2, "prioritizationFee": 12_000},
+                        ]),
+                        "sendTransaction" => json!(Signature::new(&[1; 64]).to_string()),
+                        "getSignatureStatuses" => {
+                            let status = match polls.fetch_add(1, Ordering::SeqCst) {
+                                0 => Value::Null,
+                                _ => json!({"slot": 2, "confirmations": 1, "err": tx_err, "confirmationStatus": "confirmed"}),
+                            };
+                            json!({"context": {"slot": 2}, "value": [status]})
+                        }
+                        _ => return HttpResponse::Ok().json(json!({"jsonrpc": "2.0", "id": 1, "error": {"code": -32601, "message": "Method not found"}})),
+                    };
+                    HttpResponse::Ok().json(json!({"jsonrpc": "2.0", "id": 1, "result": result}))
+                }
+            }))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{}", addr)
+    }
+
+    #[actix_web::test]
+    async fn test_blockhash_send_and_confirm() {
+        let rpc = RpcClient::new(start_mock_rpc(None));
+
+        let (hash, last_valid_block_height) = rpc.get_latest_blockhash().await.unwrap();
+        assert_eq!(hash, BLOCKHASH.parse::<Hash>().unwrap());
+        assert_eq!(last_valid_block_height, 150);
+
+        let signature = rpc.send_transaction(&[0; 8]).await.unwrap();
+        assert_eq!(signature, Signature::new(&[1; 64]));
+
+        let status = rpc.confirm_transaction(&signature, last_valid_block_height).await.unwrap();
+        assert_eq!(status, ConfirmationStatus::Confirmed);
+    }
+
+    #[actix_web::test]
+    async fn test_failed_transaction() {
+        let rpc = RpcClient::new(start_mock_rpc(Some(json!({"InstructionError": [0, "Custom"]}))));
+        let signature = rpc.send_transaction(&[0; 8]).await.unwrap();
+        let result = rpc.confirm_transaction(&signature, 150).await;
+        assert!(matches!(result, Err(RpcError::TransactionFailed(_))));
+    }
+
+    #[actix_web::test]
+    async fn test_balance_and_accounts() {
+        let rpc = RpcClient::new(start_mock_rpc(None));
+        assert_eq!(rpc.get_balance(&Pubkey::new_unique()).await.unwrap(), 2_000_000);
+        assert_eq!(rpc.get_minimum_balance_for_rent_exemption(80).await.unwrap(), 1_447_680);
+
+        let mint = rpc.get_account(&MINT.parse().unwrap()).await.unwrap().unwrap();
+        assert_eq!(mint.owner, TOKEN_PROGRAM_ID);
+        assert_eq!(mint.data.len(), 82);
+        assert_eq!(rpc.get_account(&Pubkey::new_unique()).await.unwrap(), None);
+        assert_eq!(rpc.get_recent_prioritization_fees(&[Pubkey::new_unique()]).await.unwrap(), vec![0, 12_000]);
+    }
+
+    #[actix_web::test]
+    async fn test_simulate() {
+        let rpc = RpcClient::new(start_mock_rpc(None));
+        let payer = Pubkey::new_unique();
+        assert_eq!(rpc.get_multiple_accounts(&[payer]).await.unwrap()[0].as_ref().unwrap().lamports, 1_000_000);
+
+        let simulation = rpc.simulate_transaction(&[0; 8], &[payer]).await.unwrap();
+        assert_eq!(simulation.err, None);
+        assert_eq!(simulation.units_consumed, Some(150));
+        assert_eq!(simulation.logs.len(), 2);
+        assert_eq!(simulation.accounts[0].as_ref().unwrap().lamports, 994_000);
+
+        let rpc = RpcClient::new(start_mock_rpc(Some(json!({"InstructionError": [0, {"Custom": 1}]}))));
+        assert!(rpc.simulate_transaction(&[0; 8], &[payer]).await.unwrap().err.is_some());
+    }
+}
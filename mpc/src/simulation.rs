@@ -0,0 +1,153 @@
+//! Dry runs of transactions before the share servers are asked to sign them.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use solana_sdk::{
+    message::VersionedMessage,
+    pubkey::Pubkey,
+    signature::Signature,
+    transaction::VersionedTransaction,
+};
+
+use crate::{
+    rpc::{AccountInfo, RpcClient, RpcError},
+    token,
+};
+
+/// How one account's balance would move. Lamports when `mint` is missing, base units of the
+/// mint for token accounts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceChange {
+    pub account: String,
+    pub mint: Option<String>,
+    pub pre: u64,
+    pub post: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Simulation {
+    /// The transaction error the cluster reported, `None` if it would succeed
+    pub err: Option<Value>,
+    pub logs: Vec<String>,
+    pub units_consumed: Option<u64>,
+    pub balance_changes: Vec<BalanceChange>,
+}
+
+impl Simulation {
+    pub fn succeeded(&self) -> bool {
+        self.err.is_none()
+    }
+}
+
+/// Simulates against `SIMULATION_RPC_URL`, or the cluster the transactions go to if unset.
+#[derive(Clone)]
+pub struct Simulator {
+    rpc: RpcClient,
+}
+
+impl Simulator {
+    pub fn new(rpc: RpcClient) -> Self {
+        Self { rpc }
+    }
+
+    pub fn from_env() -> Self {
+        match dotenvy::var("SIMULATION_RPC_URL") {
+            Ok(url) => Self::new(RpcClient::new(url)),
+            Err(_) => Self::new(RpcClient::from_env()),
+        }
+    }
+
+    pub async fn simulate(&self, message: &VersionedMessage) -> Result<Simulation, RpcError> {
+        let addresses = writable_accounts(message);
+        let pre = self.rpc.get_multiple_accounts(&addresses).await?;
+
+        let tx = VersionedTransaction {
+            signatures: vec![Signature::default(); usize::from(message.header().num_required_signatures)],
+            message: message.clone(),
+        };
+        let tx_bytes = bincode::serialize(&tx).map_err(|e| RpcError::InvalidResponse(e.to_string()))?;
+        let simulated = self.rpc.simulate_transaction(&tx_bytes, &addresses).await?;
+
+        Ok(Simulation {
+            err: simulated.err,
+            logs: simulated.logs,
+            units_consumed: simulated.units_consumed,
+            balance_changes: balance_changes(&addresses, &pre, &simulated.accounts),
+        })
+    }
+}
+
+/// Accounts a message can change, accounts loaded from lookup tables aren't known up front.
+fn writable_accounts(message: &VersionedMessage) -> Vec<Pubkey> {
+    message
+        .static_account_keys()
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| message.is_maybe_writable(*index))
+        .map(|(_, key)| *key)
+        .collect()
+}
+
+/// Lamport and token balance movements between the accounts before and after the simulation.
+/// Accounts that don't change are left out, missing accounts count as empty.
+pub fn balance_changes(addresses: &[Pubkey], pre: &[Option<AccountInfo>], post: &[Option<AccountInfo>]) -> Vec<BalanceChange> {
+    let mut changes = Vec::new();
+    for (index, address) in addresses.iter().enumerate() {
+        let pre = pre.get(index).and_then(Option::as_ref);
+        let post = post.get(index).and_then(Option::as_ref);
+        let lamports = |account: Option<&AccountInfo>| account.map_or(0, |account| account.lamports);
+        if lamports(pre) != lamports(post) {
+            changes.push(BalanceChange { account: address.to_string(), mint: None, pre: lamports(pre), post: lamports(post) });
+        }
+
+        let token_balance = |account: Option<&AccountInfo>| {
+            let account = account.filter(|account| token::is_token_program(&account.owner))?;
+            Some((token::token_account_mint(&account.data)?, token::token_account_amount(&account.data)?))
+        };
+        let Some(mint) = token_balance(post).or(token_balance(pre)).map(|(mint, _)| mint) else {
+            continue;
+        };
+        let amount = |account| token_balance(account).map_or(0, |(_, amount)| amount);
+        if amount(pre) != amount(post) {
+            changes.push(BalanceChange { account: address.to_string(), mint: Some(mint.to_string()), pre: amount(pre), post: amount(post) });
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{pubkey::Pubkey, system_program};
+
+    use super::{balance_changes, BalanceChange};
+    use crate::{rpc::AccountInfo, token::TOKEN_PROGRAM_ID};
+
+    fn token_account(mint: &Pubkey, amount: u64) -> AccountInfo {
+        let mut data = vec![0u8; 165];
+        data[..32].copy_from_slice(mint.as_ref());
+        data[64..72].copy_from_slice(&amount.to_le_bytes());
+        AccountInfo { lamports: 2_039_280, owner: TOKEN_PROGRAM_ID, data }
+    }
+
+    #[test]
+    fn test_balance_changes() {
+        let (payer, unchanged, source, destination, mint) =
+            (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let wallet = |lamports| Some(AccountInfo { lamports, owner: system_program::id(), data: vec![] });
+
+        let changes = balance_changes(
+            &[payer, unchanged, source, destination],
+            &[wallet(10_000_000), wallet(5), Some(token_account(&mint, 700)), None],
+            &[wallet(7_955_720), wallet(5), Some(token_account(&mint, 200)), Some(token_account(&mint, 500))],
+        );
+        assert_eq!(
+            changes,
+            vec![
+                BalanceChange { account: payer.to_string(), mint: None, pre: 10_000_000, post: 7_955_720 },
+                BalanceChange { account: source.to_string(), mint: Some(mint.to_string()), pre: 700, post: 200 },
+                BalanceChange { account: destination.to_string(), mint: None, pre: 0, post: 2_039_280 },
+                BalanceChange { account: destination.to_string(), mint: Some(mint.to_string()), pre: 0, post: 500 },
+            ]
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! The few SPL token instructions the coordinator builds, for both the Token and the Token-2022
+//! program. Both share the instruction layout of the original program for everything used here.
+
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+
+pub const TOKEN_PROGRAM_ID: Pubkey = solana_sdk::pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+pub const TOKEN_2022_PROGRAM_ID: Pubkey = solana_sdk::pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey = solana_sdk::pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+
+const TRANSFER_CHECKED: u8 = 12;
+const CLOSE_ACCOUNT: u8 = 9;
+const CREATE_IDEMPOTENT: u8 = 1;
+
+const MINT_DECIMALS_OFFSET: usize = 44;
+const TOKEN_ACCOUNT_AMOUNT_OFFSET: usize = 64;
+
+pub fn is_token_program(program_id: &Pubkey) -> bool {
+    *program_id == TOKEN_PROGRAM_ID || *program_id == TOKEN_2022_PROGRAM_ID
+}
+
+pub fn associated_token_address(owner: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[owner.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    )
+    .0
+}
+
+/// Create `owner`'s associated token account, succeeds as well if it already exists.
+pub fn create_associated_token_account_idempotent(payer: &Pubkey, owner: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: ASSOCIATED_TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(associated_token_address(owner, mint, token_program), false),
+            AccountMeta::new_readonly(*owner, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+        data: vec![CREATE_IDEMPOTENT],
+    }
+}
+
+pub fn transfer_checked(
+    token_program: &Pubkey,
+    source: &Pubkey,
+    mint: &Pubkey,
+    destination: &Pubkey,
+    authority: &Pubkey,
+    amount: u64,
+    decimals: u8,
+) -> Instruction {
+    let mut data = vec![TRANSFER_CHECKED];
+    data.extend_from_slice(&amount.to_le_bytes());
+    data.push(decimals);
+    Instruction {
+        program_id: *token_program,
+        accounts: vec![
+            AccountMeta::new(*source, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new_readonly(*authority, true),
+        ],
+        data,
+    }
+}
+
+/// Close an empty token account, its rent goes to `destination`.
+pub fn close_account(token_program: &Pubkey, account: &Pubkey, destination: &Pubkey, owner: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *token_program,
+        accounts: vec![
+            AccountMeta::new(*account, false),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new_readonly(*owner, true),
+        ],
+        data: vec![CLOSE_ACCOUNT],
+    }
+}
+
+/// Decimals of a mint account, Token-2022 extensions come after the base layout.
+pub fn mint_decimals(data: &[u8]) -> Option<u8> {
+    data.get(MINT_DECIMALS_OFFSET).copied()
+}
+
+pub fn token_account_mint(data: &[u8]) -> Option<Pubkey> {
+    let bytes: [u8; 32] = data.get(..32)?.try_into().ok()?;
+    Some(Pubkey::new_from_array(bytes))
+}
+
+pub fn token_account_amount(data: &[u8]) -> Option<u64> {
+    let bytes = data.get(TOKEN_ACCOUNT_AMOUNT_OFFSET..TOKEN_ACCOUNT_AMOUNT_OFFSET + 8)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+
+    #[test]
+    fn test_transfer_checked_layout() {
+        let (source, mint, destination, authority) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let ix = transfer_checked(&TOKEN_2022_PROGRAM_ID, &source, &mint, &destination, &authority, 1_500, 6);
+        assert_eq!(ix.program_id, TOKEN_2022_PROGRAM_ID);
+        assert_eq!(ix.data, [vec![12], 1_500u64.to_le_bytes().to_vec(), vec![6]].concat());
+        assert_eq!(ix.accounts.iter().filter(|meta| meta.is_signer).map(|meta| meta.pubkey).collect::<Vec<_>>(), vec![authority]);
+    }
+
+    #[test]
+    fn test_associated_token_address_depends_on_program() {
+        let (owner, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        assert_ne!(
+            associated_token_address(&owner, &mint, &TOKEN_PROGRAM_ID),
+            associated_token_address(&owner, &mint, &TOKEN_2022_PROGRAM_ID)
+        );
+    }
+
+    #[test]
+    fn test_account_layouts() {
+        let mut mint_account = vec![0u8; 82];
+        mint_account[44] = 9;
+        assert_eq!(mint_decimals(&mint_account), Some(9));
+        assert_eq!(mint_decimals(&[0u8; 10]), None);
+
+        let mut account = vec![0u8; 165];
+        let mint = Pubkey::new_unique();
+        account[..32].copy_from_slice(mint.as_ref());
+        account[64..72].copy_from_slice(&42u64.to_le_bytes());
+        assert_eq!(token_account_mint(&account), Some(mint));
+        assert_eq!(token_account_amount(&account), Some(42));
+        assert_eq!(token_account_amount(&account[..70]), None);
+    }
+}
@@ -3,7 +3,9 @@
 use curv::elliptic::curves::{Ed25519, Point, Scalar};
 use multi_party_eddsa::protocols::musig2::{self, PrivatePartialNonces, PublicPartialNonces};
 use multi_party_eddsa::protocols::ExpandedKeyPair;
+use solana_sdk::message::VersionedMessage;
 use solana_sdk::signature::{Keypair, Signature, Signer, SignerError};
+use solana_sdk::transaction::VersionedTransaction;
 use solana_sdk::{hash::Hash, pubkey::Pubkey, transaction::Transaction};
 
 use crate::error::Error;
@@ -46,26 +48,27 @@ pub fn step_two(
     first_messages: Vec<AggMessage1>,
     secret_state: SecretAggStepOne,
 ) -> Result<PartialSignature, Error> {
-    let other_nonces: Vec<_> = first_messages.into_iter().map(|msg1| msg1.public_nonces.R).collect();
-
-    // Generate the aggregate key together with the coefficient of the current keypair
-    let aggkey = key_agg(keys, Some(keypair.pubkey()))?;
+    let aggkey = key_agg(keys.clone(), None)?;
     let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
-    let extended_kepair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
+    let message = transfer_message(amount, &to, memo, &aggpubkey, recent_block_hash);
+    step_two_message(keypair, &message, keys, first_messages, secret_state)
+}
 
-    // Create the unsigned transaction
-    let mut tx = create_unsigned_transaction(amount, &to, memo, &aggpubkey);
+/// Partially sign an arbitrary (legacy or v0) message.
+/// The aggregated key has to be the fee payer and the only required signer of the message,
+/// the recent blockhash is taken from the message as is.
+pub fn step_two_message(
+    keypair: Keypair,
+    message: &VersionedMessage,
+    keys: Vec<Pubkey>,
+    first_messages: Vec<AggMessage1>,
+    secret_state: SecretAggStepOne,
+) -> Result<PartialSignature, Error> {
+    let signer = partial_signer(&keypair, keys, first_messages, secret_state)?;
+    verify_signer(message, &signer.pubkey())?;
 
-    let signer = PartialSigner {
-        signer_private_nonce: secret_state.private_nonces,
-        signer_public_nonce: secret_state.public_nonces,
-        other_nonces,
-        extended_kepair,
-        aggregated_pubkey: aggkey,
-    };
-    // Sign the transaction using a custom `PartialSigner`, this is required to comply with Solana's API.
-    tx.sign(&[&signer], recent_block_hash);
-    let sig = tx.signatures[0];
+    // Sign the message using a custom `PartialSigner`, this is required to comply with Solana's API.
+    let sig = signer.try_sign_message(&message.serialize()).map_err(|_| Error::InvalidSignature)?;
     Ok(PartialSignature(sig))
 }
 
@@ -77,9 +80,117 @@ pub fn sign_and_broadcast(
     keys: Vec<Pubkey>,
     signatures: Vec<PartialSignature>,
 ) -> Result<Transaction, Error> {
+    let aggkey = key_agg(keys.clone(), None)?;
+    let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
+
+    // Create the same transaction again
+    let message = transfer_message(amount, &to, memo, &aggpubkey, recent_block_hash);
+    let tx = sign_message(message, keys, signatures)?;
+    tx.into_legacy_transaction().ok_or(Error::InvalidSignature)
+}
+
+/// Aggregate the partial signatures over `message` into a fully signed transaction.
+pub fn sign_message(
+    message: VersionedMessage,
+    keys: Vec<Pubkey>,
+    signatures: Vec<PartialSignature>,
+) -> Result<VersionedTransaction, Error> {
     let aggkey = key_agg(keys, None)?;
     let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
+    verify_signer(&message, &aggpubkey)?;
+
+    let sig = aggregate_signatures(&signatures)?;
+    let tx = VersionedTransaction { signatures: vec![sig], message };
+
+    // Make sure the resulting transaction is actually valid.
+    if !tx.verify_with_results().into_iter().all(|valid| valid) {
+        return Err(Error::InvalidSignature);
+    }
+    Ok(tx)
+}
+
+/// Run both MuSig2 rounds for all `keypairs` in one place, e.g. for a wallet taken off the share servers.
+/// The keypairs have to be in the order the wallet was created with.
+pub fn sign_locally(keypairs: &[Keypair], message: &[u8]) -> Result<Signature, Error> {
+    let clone_keypair = |keypair: &Keypair| Keypair::from_bytes(&keypair.to_bytes()).map_err(Error::from);
+    let keys: Vec<_> = keypairs.iter().map(|keypair| keypair.pubkey()).collect();
+    let aggkey = key_agg(keys.clone(), None)?;
+
+    let mut first_messages = vec![];
+    let mut secret_states = vec![];
+    for keypair in keypairs {
+        let (first_message, secret_state) = step_one(clone_keypair(keypair)?);
+        first_messages.push(first_message);
+        secret_states.push(secret_state);
+    }
+    let signatures = keypairs
+        .iter()
+        .zip(secret_states)
+        .map(|(keypair, secret_state)| {
+            let signer = partial_signer(keypair, keys.clone(), first_messages.clone(), secret_state)?;
+            signer.try_sign_message(message).map(PartialSignature).map_err(|_| Error::InvalidSignature)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let sig = aggregate_signatures(&signatures)?;
+    if !sig.verify(&*aggkey.agg_public_key.to_bytes(true), message) {
+        return Err(Error::InvalidSignature);
+    }
+    Ok(sig)
+}
+
+/// Make sure the aggregated key pays for the message and that no other signature is required,
+/// so the aggregated signature alone yields a complete transaction.
+pub fn verify_signer(message: &VersionedMessage, aggpubkey: &Pubkey) -> Result<(), Error> {
+    if message.sanitize().is_err() {
+        return Err(Error::InvalidMessage);
+    }
+    if message.static_account_keys().first() != Some(aggpubkey) {
+        return Err(Error::AggregatedKeyNotFeePayer);
+    }
+    let num_required_signatures = message.header().num_required_signatures;
+    if num_required_signatures != 1 {
+        return Err(Error::UnexpectedSigners(num_required_signatures));
+    }
+    Ok(())
+}
+
+fn transfer_message(amount: f64, to: &Pubkey, memo: Option<String>, payer: &Pubkey, recent_block_hash: Hash) -> VersionedMessage {
+    let mut message = create_unsigned_transaction(amount, to, memo, payer).message;
+    message.recent_blockhash = recent_block_hash;
+    VersionedMessage::Legacy(message)
+}
 
+fn partial_signer(
+    keypair: &Keypair,
+    keys: Vec<Pubkey>,
+    first_messages: Vec<AggMessage1>,
+    secret_state: SecretAggStepOne,
+) -> Result<PartialSigner, Error> {
+    // Our own first message may be part of the list, only the other parties' nonces are needed.
+    let other_nonces: Vec<_> = first_messages
+        .into_iter()
+        .filter(|msg1| msg1.sender != keypair.pubkey())
+        .map(|msg1| msg1.public_nonces.R)
+        .collect();
+
+    // Generate the aggregate key together with the coefficient of the current keypair
+    let aggkey = key_agg(keys, Some(keypair.pubkey()))?;
+    let extended_kepair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
+
+    Ok(PartialSigner {
+        signer_private_nonce: secret_state.private_nonces,
+        signer_public_nonce: secret_state.public_nonces,
+        other_nonces,
+        extended_kepair,
+        aggregated_pubkey: aggkey,
+    })
+}
+
+fn aggregate_signatures(signatures: &[PartialSignature]) -> Result<Signature, Error> {
+    if signatures.is_empty() {
+        return Err(Error::MismatchMessages);
+    }
     // Make sure all the `R`s are the same
     if !signatures[1..].iter().map(|s| &s.0.as_ref()[..32]).all(|s| s == &signatures[0].0.as_ref()[..32]) {
         return Err(Error::MismatchMessages);
@@ -111,20 +222,7 @@ pub fn sign_and_broadcast(
     let mut sig_bytes = [0u8; 64];
     sig_bytes[..32].copy_from_slice(&*full_sig.R.to_bytes(true));
     sig_bytes[32..].copy_from_slice(&full_sig.s.to_bytes());
-    let sig = Signature::new(&sig_bytes);
-
-    // Create the same transaction again
-    let mut tx = create_unsigned_transaction(amount, &to, memo, &aggpubkey);
-    // Insert the recent_block_hash and the signature to the right places
-    tx.message.recent_blockhash = recent_block_hash;
-    assert_eq!(tx.signatures.len(), 1);
-    tx.signatures[0] = sig;
-
-    // Make sure the resulting transaction is actually valid.
-    if tx.verify().is_err() {
-        return Err(Error::InvalidSignature);
-    }
-    Ok(tx)
+    Ok(Signature::new(&sig_bytes))
 }
 
 struct PartialSigner {
@@ -160,6 +258,84 @@ impl Signer for PartialSigner {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use solana_sdk::hash::Hash;
+    use solana_sdk::instruction::{AccountMeta, Instruction};
+    use solana_sdk::message::{v0, Message, VersionedMessage};
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::{Keypair, Signer};
+    use solana_sdk::system_instruction;
+
+    use crate::error::Error;
+    use crate::tss::{key_agg, sign_message, step_one, step_two_message};
+
+    fn clone_keypair(k: &Keypair) -> Keypair {
+        Keypair::from_bytes(&k.to_bytes()).unwrap()
+    }
+
+    fn sign_with(keys: &[Keypair], message: &VersionedMessage) -> Result<Vec<crate::serialization::PartialSignature>, Error> {
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        let (first_msgs, first_secrets): (Vec<_>, Vec<_>) = keys.iter().map(clone_keypair).map(step_one).unzip();
+        keys.iter()
+            .map(clone_keypair)
+            .zip(first_secrets)
+            .map(|(key, secret)| step_two_message(key, message, pubkeys.clone(), first_msgs.clone(), secret))
+            .collect()
+    }
+
+    fn aggregated_pubkey(keys: &[Keypair]) -> Pubkey {
+        let aggkey = key_agg(keys.iter().map(|k| k.pubkey()).collect(), None).unwrap();
+        Pubkey::new(&*aggkey.agg_public_key.to_bytes(true))
+    }
+
+    #[test]
+    fn test_sign_legacy_message() {
+        let keys: Vec<_> = (0..3).map(|_| Keypair::new()).collect();
+        let payer = aggregated_pubkey(&keys);
+        let ix = system_instruction::transfer(&payer, &Pubkey::new_unique(), 1000);
+        let message = VersionedMessage::Legacy(Message::new_with_blockhash(&[ix], Some(&payer), &Hash::new_unique()));
+
+        let signatures = sign_with(&keys, &message).unwrap();
+        let tx = sign_message(message, keys.iter().map(|k| k.pubkey()).collect(), signatures).unwrap();
+        assert!(tx.verify_with_results().into_iter().all(|valid| valid));
+        assert!(tx.into_legacy_transaction().is_some());
+    }
+
+    #[test]
+    fn test_sign_v0_message() {
+        let keys: Vec<_> = (0..2).map(|_| Keypair::new()).collect();
+        let payer = aggregated_pubkey(&keys);
+        let ix = Instruction::new_with_bytes(Pubkey::new_unique(), &[7; 16], vec![AccountMeta::new(payer, true)]);
+        let message = VersionedMessage::V0(v0::Message::try_compile(&payer, &[ix], &[], Hash::new_unique()).unwrap());
+
+        let signatures = sign_with(&keys, &message).unwrap();
+        let tx = sign_message(message, keys.iter().map(|k| k.pubkey()).collect(), signatures).unwrap();
+        assert!(tx.verify_with_results().into_iter().all(|valid| valid));
+    }
+
+    #[test]
+    fn test_reject_foreign_fee_payer() {
+        let keys: Vec<_> = (0..2).map(|_| Keypair::new()).collect();
+        let payer = Pubkey::new_unique();
+        let ix = system_instruction::transfer(&payer, &Pubkey::new_unique(), 1000);
+        let message = VersionedMessage::Legacy(Message::new_with_blockhash(&[ix], Some(&payer), &Hash::new_unique()));
+
+        assert!(matches!(sign_with(&keys, &message), Err(Error::AggregatedKeyNotFeePayer)));
+    }
+
+    #[test]
+    fn test_reject_additional_signers() {
+        let keys: Vec<_> = (0..2).map(|_| Keypair::new()).collect();
+        let payer = aggregated_pubkey(&keys);
+        let other = Pubkey::new_unique();
+        let ix = system_instruction::transfer(&other, &payer, 1000);
+        let message = VersionedMessage::Legacy(Message::new_with_blockhash(&[ix], Some(&payer), &Hash::new_unique()));
+
+        assert!(matches!(sign_with(&keys, &message), Err(Error::UnexpectedSigners(2))));
+    }
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use crate::native_token::lamports_to_sol;
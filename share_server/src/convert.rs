@@ -0,0 +1,23 @@
+use mpc::frost::{self, KeyShare};
+use solana_sdk::{signature::Keypair, signer::Signer};
+
+pub fn keypair_from_base64(keypair_b64: &str, public_key: &str) -> Result<Keypair, Box<dyn std::error::Error>> {
+    use base64::{Engine as _, engine::general_purpose};
+
+    let keypair_bytes = general_purpose::STANDARD.decode(keypair_b64)?;
+    let keypair = Keypair::from_bytes(&keypair_bytes)?;
+    if keypair.pubkey().to_string() != public_key {
+        return Err("Stored public key does not match the keypair".into());
+    }
+
+    Ok(keypair)
+}
+
+pub fn key_share_from_json(key_share: &str, group_key: &str) -> Result<KeyShare, Box<dyn std::error::Error>> {
+    let key_share = frost::key_share_from_json(key_share)?;
+    if key_share.group_pubkey().to_string() != group_key {
+        return Err("Stored group key does not match the key share".into());
+    }
+
+    Ok(key_share)
+}
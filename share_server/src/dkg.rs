@@ -0,0 +1,158 @@
+use actix_web::{web, HttpResponse, Result};
+use mpc::{
+    frost::{self, DkgParty, DkgSecret, Identifier},
+    middleware::ServiceAuth,
+    protocol::{DkgFinishInput, DkgRoundOneInput, DkgRoundOneOutput, DkgRoundTwoInput, DkgRoundTwoOutput, GenerateOutput},
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use sqlx::PgPool;
+use store::{crypto::KeyRing, mpc::KeyScheme, Store};
+use std::{collections::{BTreeMap, HashMap}, str::FromStr, sync::Mutex};
+use topology::{identity::ServiceIdentity, Role, Topology};
+
+/// Secret polynomials of the key generations in progress, keyed by session id. They are only
+/// needed until the DKG finishes, if the server restarts in between the wallet creation fails.
+#[derive(Default)]
+pub struct DkgSessions {
+    sessions: Mutex<HashMap<String, (String, DkgSecret)>>,
+}
+
+#[actix_web::post("/dkg/round-one")]
+pub async fn round_one(
+    sessions: web::Data<DkgSessions>,
+    topology: web::Data<Topology>,
+    identity: web::Data<ServiceIdentity>,
+    auth: ServiceAuth,
+    data: web::Json<DkgRoundOneInput>,
+) -> Result<HttpResponse> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    let parties = match dkg_parties(&topology, &data.parties) {
+        Ok(parties) => parties,
+        Err(error_message) => return Ok(HttpResponse::BadRequest().body(error_message)),
+    };
+    let Some(identifier) = data.parties.iter().find(|(_, party_id)| *party_id == identity.party_id()).map(|(identifier, _)| *identifier) else {
+        return Ok(HttpResponse::BadRequest().body("This party does not take part in the DKG"));
+    };
+    let context = dkg_context(&data.session_id, &data.user_id);
+    let (secret, mut package) = match frost::dkg_part1(identifier, data.threshold, parties, &context) {
+        Ok(round) => round,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+    };
+    // The coordinator relays the package, the signature keeps it from swapping our ephemeral key
+    package.signature = match identity.sign(&frost::round1_message(&context, &package)) {
+        Ok(signature) => Signature::new(&signature),
+        Err(e) => {
+            eprintln!("Failed to sign DKG package: {}", e);
+            return Ok(HttpResponse::InternalServerError().body("Failed to sign DKG package"));
+        }
+    };
+    let mut sessions = match sessions.sessions.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock DKG sessions")),
+    };
+    if sessions.contains_key(&data.session_id) {
+        return Ok(HttpResponse::Conflict().body("DKG session already exists"));
+    }
+    sessions.insert(data.session_id.clone(), (data.user_id.clone(), secret));
+
+    Ok(HttpResponse::Ok().json(DkgRoundOneOutput { package }))
+}
+
+/// The parties of a DKG with the identity keys from our own topology, never from the request.
+fn dkg_parties(topology: &Topology, parties: &BTreeMap<Identifier, String>) -> Result<BTreeMap<Identifier, DkgParty>, String> {
+    let mut dkg_parties = BTreeMap::new();
+    for (identifier, party_id) in parties {
+        if parties.values().filter(|other| *other == party_id).count() > 1 {
+            return Err(format!("Party {} has more than one identifier", party_id));
+        }
+        let party = topology.party(party_id).map_err(|e| e.to_string())?;
+        if party.role != Role::ShareServer {
+            return Err(format!("Party {} is not a share server", party_id));
+        }
+        let identity_key = party
+            .identity_key
+            .as_deref()
+            .and_then(|key| Pubkey::from_str(key).ok())
+            .ok_or_else(|| format!("Party {} has no identity_key in the topology", party_id))?;
+        dkg_parties.insert(*identifier, DkgParty { party_id: party_id.clone(), identity_key });
+    }
+    Ok(dkg_parties)
+}
+
+/// Binds the signed round one packages to one key generation.
+fn dkg_context(session_id: &str, user_id: &str) -> Vec<u8> {
+    format!("{}/{}", session_id, user_id).into_bytes()
+}
+
+#[actix_web::post("/dkg/round-two")]
+pub async fn round_two(sessions: web::Data<DkgSessions>, auth: ServiceAuth, data: web::Json<DkgRoundTwoInput>) -> Result<HttpResponse> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    let sessions = match sessions.sessions.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock DKG sessions")),
+    };
+    let secret = match sessions.get(&data.session_id) {
+        Some((user_id, secret)) if *user_id == data.user_id => secret,
+        Some(_) => return Ok(HttpResponse::Forbidden().body("DKG session belongs to a different user")),
+        None => return Ok(HttpResponse::NotFound().body("DKG session not found")),
+    };
+
+    match frost::dkg_part2(secret, &data.round1_packages) {
+        Ok(round2_packages) => Ok(HttpResponse::Ok().json(DkgRoundTwoOutput { round2_packages })),
+        Err(e) => Ok(HttpResponse::BadRequest().body(e.to_string())),
+    }
+}
+
+#[actix_web::post("/dkg/finish")]
+pub async fn finish(
+    pool: web::Data<PgPool>,
+    keys: web::Data<KeyRing>,
+    sessions: web::Data<DkgSessions>,
+    data: web::Json<DkgFinishInput>,
+    auth: ServiceAuth,
+) -> Result<HttpResponse> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    // The secret polynomial is used up either way
+    let secret = {
+        let mut sessions = match sessions.sessions.lock() {
+            Ok(locked) => locked,
+            Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to lock DKG sessions")),
+        };
+        match sessions.remove(&data.session_id) {
+            Some((user_id, secret)) if user_id == data.user_id => secret,
+            Some(_) => return Ok(HttpResponse::Forbidden().body("DKG session belongs to a different user")),
+            None => return Ok(HttpResponse::NotFound().body("DKG session not found")),
+        }
+    };
+    let key_share = match frost::dkg_part3(&secret, &data.round1_packages, &data.round2_packages) {
+        Ok(key_share) => key_share,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+    };
+    let serialized = match serde_json::to_string(&key_share) {
+        Ok(serialized) => serialized,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to serialize key share")),
+    };
+
+    let stored = match Store::store_keypair(
+        &pool,
+        &keys,
+        KeyScheme::Frost,
+        &key_share.group_pubkey().to_string(),
+        &serialized,
+        &data.user_id,
+    ).await {
+        Ok(stored) => stored,
+        Err(e) => {
+            eprintln!("Failed to insert key share: {}", e);
+            return Ok(HttpResponse::InternalServerError().body("Failed to insert key share"));
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(GenerateOutput { pubkey: stored.public_key }))
+}
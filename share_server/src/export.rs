@@ -0,0 +1,44 @@
+use actix_web::{web, HttpResponse, Result};
+use mpc::{
+    export::{seal_share, ExportedShare},
+    middleware::ServiceAuth,
+    protocol::{ExportInput, ExportOutput},
+};
+use solana_sdk::pubkey::Pubkey;
+use sqlx::PgPool;
+use store::{crypto::KeyRing, export::ExportError, mpc::KeyScheme, Store};
+use std::str::FromStr;
+
+use crate::signing::{load_wallet, Wallet};
+
+/// Release this party's share of a wallet, encrypted to a key of the user. The release is audited
+/// before the share leaves the server. Asking again for the same export and recipient releases the
+/// share again, so an export the other parties failed on can be retried.
+#[actix_web::post("/export")]
+pub async fn export(pool: web::Data<PgPool>, keys: web::Data<KeyRing>, auth: ServiceAuth, data: web::Json<ExportInput>) -> Result<HttpResponse> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    let recipient = match Pubkey::from_str(&data.recipient) {
+        Ok(pk) => pk,
+        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid recipient public key")),
+    };
+    let (scheme, threshold, share) = match load_wallet(&pool, &keys, &data.user_id).await {
+        Ok(Wallet::Musig2(keypair)) => (KeyScheme::Musig2, None, ExportedShare::Musig2 { keypair: keypair.to_base58_string() }),
+        Ok(Wallet::Frost(key_share)) => (KeyScheme::Frost, Some(key_share.threshold), ExportedShare::Frost { key_share }),
+        Err(response) => return Ok(response),
+    };
+    let share = match seal_share(&recipient, &share) {
+        Ok(sealed) => sealed,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+    };
+
+    match Store::record_key_export(&pool, &data.export_id, &data.user_id, &data.recipient, scheme).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(ExportOutput { share, threshold })),
+        Err(ExportError::ExportExists) => Ok(HttpResponse::Conflict().body(ExportError::ExportExists.to_string())),
+        Err(e) => {
+            eprintln!("Failed to record key export: {}", e);
+            Ok(HttpResponse::InternalServerError().body("Failed to record key export"))
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! A share server holds one party's key shares and signing nonces. Every party runs the same
+//! binary against its own database; which party it is comes from the topology, see `main.rs`.
+
+use std::time::Duration;
+
+use actix_web::web::{self, Data};
+use sqlx::PgPool;
+use store::{crypto::KeyRing, Store};
+use topology::{identity::ServiceIdentity, Topology};
+
+pub mod convert;
+pub mod dkg;
+pub mod export;
+pub mod signing;
+
+const SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Register the share server routes and state. Kept apart from `main` so tests can run any number
+/// of parties in one process. `identity` signs this party's DKG packages, the other parties'
+/// packages are checked against the identity keys in `topology`.
+pub fn configure(
+    cfg: &mut web::ServiceConfig,
+    pool: Data<PgPool>,
+    keys: Data<KeyRing>,
+    dkg_sessions: Data<dkg::DkgSessions>,
+    topology: Data<Topology>,
+    identity: Data<ServiceIdentity>,
+) {
+    cfg.app_data(pool)
+        .app_data(keys)
+        .app_data(dkg_sessions)
+        .app_data(topology)
+        .app_data(identity)
+        .service(signing::generate)
+        .service(dkg::round_one)
+        .service(dkg::round_two)
+        .service(dkg::finish)
+        .service(signing::public_key)
+        .service(signing::step_one)
+        .service(signing::step_two)
+        .service(signing::finalize)
+        .service(export::export);
+}
+
+/// Drop the nonces of sessions that were never finished.
+pub fn spawn_session_sweeper(pool: PgPool) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(SESSION_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = Store::expire_signing_sessions(&pool).await {
+                eprintln!("Failed to expire signing sessions: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,81 @@
+//! Runs one share server. The party is picked with `MPC_PARTY_ID` (default `share-1`) and has to be
+//! a share server in the topology. Its database is `SHARE_SERVER_DATABASE_URL` and its master keys
+//! are `SHARE_SERVER_MASTER_KEY` and `SHARE_SERVER_PREVIOUS_MASTER_KEY`. Requests are only accepted
+//! with a service token of the coordinator, see `topology::identity`. The party's own identity key
+//! is required as well, it signs the party's DKG packages.
+
+use actix_web::{web::Data, App, HttpServer};
+use mpc::middleware;
+use share_server::{configure, dkg::DkgSessions, spawn_session_sweeper};
+use store::crypto::KeyRing;
+use std::sync::Arc;
+use topology::{identity::{ServiceIdentity, ServiceVerifier}, Role, Topology};
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let pool = match store::connect_env("SHARE_SERVER_DATABASE_URL", "share server").await {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("Failed to initialize the store: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let keys = match KeyRing::from_env("SHARE_SERVER") {
+        Ok(keys) => Data::new(keys),
+        Err(e) => {
+            eprintln!("Failed to load master keys: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let topology = match Topology::load() {
+        Ok(topology) => topology,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let party = match topology.local_share_server("share-1") {
+        Ok(party) => party,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let bind_address = match party.bind_address() {
+        Ok(address) => address,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // Only the coordinator talks to the share servers
+    let verifier = match ServiceVerifier::new(&topology, &party.id, &[Role::Coordinator]) {
+        Ok(verifier) => Arc::new(verifier),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // Signs this party's DKG packages, which reach the other share servers through the coordinator
+    let identity = match ServiceIdentity::load(&topology, &party.id) {
+        Ok(identity) => Data::new(identity),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let dkg_sessions = Data::new(DkgSessions::default());
+    let topology = Data::new(topology.clone());
+
+    spawn_session_sweeper(pool.clone());
+
+    let pool = Data::new(pool);
+    HttpServer::new(move || {
+        App::new()
+            .wrap(middleware::AuthMiddleware::new(verifier.clone()))
+            .configure(|cfg| configure(cfg, pool.clone(), keys.clone(), dkg_sessions.clone(), topology.clone(), identity.clone()))
+    })
+    .bind(bind_address)?
+    .run()
+    .await
+}
@@ -0,0 +1,238 @@
+use actix_web::{web, HttpResponse, Result};
+use mpc::{
+    decode_message,
+    frost::{self, KeyShare, SigningNonces},
+    middleware::ServiceAuth,
+    protocol::{FinalizeInput, GenerateOutput, PublicKeyInput, PublicKeyOutput, SecondRound, StepOneInput, StepOneOutput, StepTwoInput, StepTwoOutput},
+    serialization::{SecretAggStepOne, Serialize as _},
+    tss,
+};
+use solana_sdk::{hash::hash, pubkey::Pubkey, signature::{Keypair, Signature}, signer::Signer};
+use sqlx::PgPool;
+use store::{crypto::KeyRing, mpc::{KeyScheme, MpcServerError}, session::SessionError, Store};
+use base64::engine::Engine;
+use std::str::FromStr;
+
+use crate::convert;
+
+/// A user's key material on this share server.
+pub enum Wallet {
+    Musig2(Keypair),
+    Frost(KeyShare),
+}
+
+#[actix_web::post("/generatePubKey")]
+pub async fn generate(pool: web::Data<PgPool>, keys: web::Data<KeyRing>, auth: ServiceAuth, data: web::Json<PublicKeyInput>) -> Result<HttpResponse> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    let user_id = data.user_id.clone();
+    let keypair = Keypair::new();
+    let keypair = match Store::store_keypair(
+        &pool,
+        &keys,
+        KeyScheme::Musig2,
+        &keypair.pubkey().to_string(),
+        &base64::engine::general_purpose::STANDARD.encode(keypair.to_bytes()),
+        &user_id,
+    ).await {
+        Ok(kp) => kp,
+        Err(e) => {
+            eprintln!("Failed to insert keypair: {}", e);
+            return Ok(HttpResponse::InternalServerError().body("Failed to insert keypair"));
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(GenerateOutput {
+        pubkey: keypair.public_key,
+    }))
+}
+
+/// This party's public key of the wallet, lets the coordinator build a transaction before any
+/// nonces are committed.
+#[actix_web::post("/public-key")]
+pub async fn public_key(pool: web::Data<PgPool>, keys: web::Data<KeyRing>, auth: ServiceAuth, data: web::Json<PublicKeyInput>) -> Result<HttpResponse> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    match load_wallet(&pool, &keys, &data.user_id).await {
+        Ok(Wallet::Musig2(keypair)) => Ok(HttpResponse::Ok().json(PublicKeyOutput::Musig2 { pubkey: keypair.pubkey() })),
+        Ok(Wallet::Frost(key_share)) => Ok(HttpResponse::Ok().json(PublicKeyOutput::Frost { group_key: key_share.group_key })),
+        Err(response) => Ok(response),
+    }
+}
+
+#[actix_web::post("/step-one")]
+pub async fn step_one(pool: web::Data<PgPool>, keys: web::Data<KeyRing>, auth: ServiceAuth, data: web::Json<StepOneInput>) -> Result<HttpResponse> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    let wallet = match load_wallet(&pool, &keys, &data.user_id).await {
+        Ok(wallet) => wallet,
+        Err(response) => return Ok(response),
+    };
+    let (output, secret_nonces) = match wallet {
+        Wallet::Musig2(keypair) => {
+            let (agg_message1, secret_state) = tss::step_one(keypair);
+            (StepOneOutput::Musig2 { agg_message1 }, secret_state.serialize_bs58())
+        }
+        Wallet::Frost(key_share) => {
+            let (nonces, commitments) = frost::commit(&key_share);
+            let nonces = match serde_json::to_string(&nonces) {
+                Ok(nonces) => nonces,
+                Err(_) => return Ok(HttpResponse::InternalServerError().body("Failed to serialize nonces")),
+            };
+            let output = StepOneOutput::Frost {
+                commitments,
+                group_key: key_share.group_key.clone(),
+                threshold: key_share.threshold,
+                parties: key_share.parties.clone(),
+                verifying_shares: key_share.verifying_shares.clone(),
+            };
+            (output, nonces)
+        }
+    };
+
+    if let Err(e) = Store::commit_nonces(
+        &pool,
+        &data.session_id,
+        &data.user_id,
+        &data.participants,
+        &secret_nonces,
+    ).await {
+        return Ok(session_error_response(e));
+    }
+
+    Ok(HttpResponse::Ok().json(output))
+}
+
+#[actix_web::post("/step-two")]
+pub async fn step_two(pool: web::Data<PgPool>, keys: web::Data<KeyRing>, auth: ServiceAuth, data: web::Json<StepTwoInput>) -> Result<HttpResponse> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    let message = match decode_message(&data.message) {
+        Ok(msg) => msg,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+    };
+    if let SecondRound::Musig2 { keys, first_messages } = &data.round {
+        if keys.len() != first_messages.len() {
+            return Ok(HttpResponse::BadRequest().body("Every party needs exactly one first message"));
+        }
+    }
+    let message_hash = hash(&message.serialize()).to_string();
+
+    // Take the nonces out of the session first, whatever happens next they must not be used again.
+    let secret_nonces = match Store::consume_nonces(&pool, &data.session_id, &data.user_id, &message_hash).await {
+        Ok(nonces) => nonces,
+        Err(e) => return Ok(session_error_response(e)),
+    };
+    let wallet = match load_wallet(&pool, &keys, &data.user_id).await {
+        Ok(wallet) => wallet,
+        Err(response) => return Ok(response),
+    };
+
+    match (wallet, &data.round) {
+        (Wallet::Musig2(keypair), SecondRound::Musig2 { keys, first_messages }) => {
+            let secret_state = match SecretAggStepOne::deserialize_bs58(&secret_nonces) {
+                Ok(state) => state,
+                Err(e) => return Ok(HttpResponse::InternalServerError().body(format!("Failed to load nonces: {}", e))),
+            };
+            match tss::step_two_message(keypair, &message, keys.clone(), first_messages.clone(), secret_state) {
+                Ok(partial_signature) => Ok(HttpResponse::Ok().json(StepTwoOutput::Musig2 { partial_signature })),
+                Err(e) => Ok(HttpResponse::BadRequest().body(e.to_string())),
+            }
+        }
+        (Wallet::Frost(key_share), SecondRound::Frost { commitments }) => {
+            let nonces: SigningNonces = match serde_json::from_str(&secret_nonces) {
+                Ok(nonces) => nonces,
+                Err(e) => return Ok(HttpResponse::InternalServerError().body(format!("Failed to load nonces: {}", e))),
+            };
+            if let Err(e) = tss::verify_signer(&message, &key_share.group_pubkey()) {
+                return Ok(HttpResponse::BadRequest().body(e.to_string()));
+            }
+            match frost::sign(&key_share, nonces, commitments, &message.serialize()) {
+                Ok(signature_share) => Ok(HttpResponse::Ok().json(StepTwoOutput::Frost { signature_share })),
+                Err(e) => Ok(HttpResponse::BadRequest().body(e.to_string())),
+            }
+        }
+        _ => Ok(HttpResponse::BadRequest().body("Signing round does not match the wallet's key scheme")),
+    }
+}
+
+#[actix_web::post("/finalize")]
+pub async fn finalize(pool: web::Data<PgPool>, keys: web::Data<KeyRing>, auth: ServiceAuth, data: web::Json<FinalizeInput>) -> Result<HttpResponse> {
+    if let Err(response) = auth.authorize(&data.user_id) {
+        return Ok(response);
+    }
+    let message = match decode_message(&data.message) {
+        Ok(msg) => msg,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+    };
+    let signature = match Signature::from_str(&data.signature) {
+        Ok(sig) => sig,
+        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid signature")),
+    };
+    let aggpubkey = match load_wallet(&pool, &keys, &data.user_id).await {
+        Ok(Wallet::Frost(key_share)) => key_share.group_pubkey(),
+        Ok(Wallet::Musig2(_)) => match tss::key_agg(data.keys.clone(), None) {
+            Ok(key) => Pubkey::new(&*key.agg_public_key.to_bytes(true)),
+            Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+        },
+        Err(response) => return Ok(response),
+    };
+    let message_bytes = message.serialize();
+    if !signature.verify(aggpubkey.as_ref(), &message_bytes) {
+        return Ok(HttpResponse::BadRequest().body("Signature does not match the message"));
+    }
+
+    match Store::mark_session_aggregated(
+        &pool,
+        &data.session_id,
+        &data.user_id,
+        &hash(&message_bytes).to_string(),
+        &data.signature,
+    ).await {
+        Ok(()) => Ok(HttpResponse::Ok().finish()),
+        Err(e) => Ok(session_error_response(e)),
+    }
+}
+
+fn session_error_response(e: SessionError) -> HttpResponse {
+    match e {
+        SessionError::SessionExists | SessionError::NonceReused | SessionError::InvalidTransition { .. } => {
+            HttpResponse::Conflict().body(e.to_string())
+        }
+        SessionError::NotFound => HttpResponse::NotFound().body(e.to_string()),
+        SessionError::WrongUser => HttpResponse::Forbidden().body(e.to_string()),
+        SessionError::Expired => HttpResponse::Gone().body(e.to_string()),
+        SessionError::MessageMismatch => HttpResponse::BadRequest().body(e.to_string()),
+        SessionError::DatabaseError(_) => {
+            eprintln!("Signing session error: {}", e);
+            HttpResponse::InternalServerError().body("Failed to update signing session")
+        }
+    }
+}
+
+pub(crate) async fn load_wallet(pool: &PgPool, keys: &KeyRing, user_id: &str) -> Result<Wallet, HttpResponse> {
+    let response = match Store::get_keypair(pool, keys, user_id).await {
+        Ok(kps) => kps,
+        // This party joined after the wallet was created
+        Err(MpcServerError::InvalidInput(msg)) => return Err(HttpResponse::NotFound().body(msg)),
+        Err(e) => {
+            eprintln!("Failed to retrieve keypairs: {}", e);
+            return Err(HttpResponse::InternalServerError().body("Failed to retrieve keypairs"));
+        }
+    };
+    let wallet = match response.scheme {
+        KeyScheme::Musig2 => convert::keypair_from_base64(&response.secret_key, &response.pub_key).map(Wallet::Musig2),
+        KeyScheme::Frost => convert::key_share_from_json(&response.secret_key, &response.pub_key).map(Wallet::Frost),
+    };
+    match wallet {
+        Ok(wallet) => Ok(wallet),
+        Err(e) => {
+            eprintln!("Failed to convert keypair: {}", e);
+            Err(HttpResponse::InternalServerError().body("Failed to convert keypair"))
+        }
+    }
+}
@@ -0,0 +1,16 @@
+-- wallet export requests, kept as the user facing audit trail
+CREATE TABLE wallet_exports (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- base58 public key the shares get encrypted to
+    recipient TEXT NOT NULL,
+    state TEXT NOT NULL,
+    error TEXT,
+    requested_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- the shares are not released before this point, so the user has time to cancel
+    available_at TIMESTAMPTZ NOT NULL,
+    released_at TIMESTAMPTZ,
+    updated_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_wallet_exports_user_id ON wallet_exports(user_id);
@@ -0,0 +1,31 @@
+-- one row per sign in, the refresh token rotates every time it is used
+CREATE TABLE user_sessions (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- SHA-256 of the current refresh token, the token itself is never stored
+    refresh_token_hash TEXT NOT NULL UNIQUE,
+    -- the newest access token handed out for the session, revoked together with it
+    access_jti TEXT NOT NULL,
+    access_expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_user_sessions_user_id ON user_sessions(user_id);
+
+-- every refresh token a session rotated out, seeing any of them again means a refresh token was stolen
+CREATE TABLE retired_refresh_tokens (
+    token_hash TEXT PRIMARY KEY,
+    session_id TEXT NOT NULL REFERENCES user_sessions(id) ON DELETE CASCADE,
+    retired_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_retired_refresh_tokens_session_id ON retired_refresh_tokens(session_id);
+
+-- access tokens that must not be accepted before they expire
+CREATE TABLE revoked_tokens (
+    jti TEXT PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL
+);
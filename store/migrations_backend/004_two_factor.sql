@@ -0,0 +1,16 @@
+-- TOTP secret, set on enrollment and only enforced once the user confirmed a code
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
+ALTER TABLE users ADD COLUMN totp_enabled_at TIMESTAMPTZ;
+-- the last accepted time step, codes can't be replayed
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT;
+
+-- single use codes for when the authenticator is lost, stored as SHA-256
+CREATE TABLE recovery_codes (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    used_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_recovery_codes_user_id ON recovery_codes(user_id);
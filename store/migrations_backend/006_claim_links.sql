@@ -0,0 +1,29 @@
+-- claim links, each holds its funds in an MPC wallet of its own whose owner id is the link id
+CREATE TABLE claim_links (
+    id TEXT PRIMARY KEY,
+    creator_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- aggregated key of the link's wallet
+    public_key TEXT NOT NULL,
+    -- NULL for SOL
+    mint TEXT,
+    -- lamports or base units of the mint
+    amount BIGINT NOT NULL,
+    -- SHA-256 of the secret in the link's URL fragment
+    secret_hash TEXT NOT NULL UNIQUE,
+    state TEXT NOT NULL,
+    error TEXT,
+    funding_signature TEXT,
+    -- where the funds went and, for claims into a new account, the user it was made for
+    destination TEXT,
+    claimed_by TEXT REFERENCES users(id) ON DELETE SET NULL,
+    claim_signature TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    funded_at TIMESTAMPTZ,
+    claimed_at TIMESTAMPTZ,
+    refunded_at TIMESTAMPTZ,
+    updated_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_claim_links_creator_id ON claim_links(creator_id);
+CREATE INDEX idx_claim_links_expires_at ON claim_links(expires_at) WHERE state IN ('unfunded', 'funded');
@@ -0,0 +1,19 @@
+-- claim links minted in bulk, all with the same mint and amount
+CREATE TABLE link_campaigns (
+    id TEXT PRIMARY KEY,
+    creator_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- NULL for SOL
+    mint TEXT,
+    -- per link, lamports or base units of the mint
+    amount BIGINT NOT NULL,
+    link_count INT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_link_campaigns_creator_id ON link_campaigns(creator_id);
+
+ALTER TABLE claim_links ADD COLUMN campaign_id TEXT REFERENCES link_campaigns(id) ON DELETE CASCADE;
+
+CREATE INDEX idx_claim_links_campaign_id ON claim_links(campaign_id);
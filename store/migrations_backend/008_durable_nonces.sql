@@ -0,0 +1,9 @@
+-- a wallet's durable nonce account, so transactions can wait on slow signing or on the user
+CREATE TABLE durable_nonces (
+    user_id TEXT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    address TEXT NOT NULL,
+    -- the blockhash the next transaction has to use, changes with every transaction
+    nonce TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,23 @@
+-- SOL and SPL token transfers into and out of user wallets, written by the indexer
+CREATE TABLE wallet_transactions (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    signature TEXT NOT NULL,
+    slot BIGINT NOT NULL,
+    -- position of the transfer among the transaction's instructions, inner ones included
+    instruction_index INT NOT NULL,
+    -- 'in' or 'out'
+    direction TEXT NOT NULL,
+    -- the other wallet, the owner for token accounts
+    counterparty TEXT NOT NULL,
+    -- NULL for SOL
+    mint TEXT,
+    -- lamports or base units of the mint
+    amount BIGINT NOT NULL,
+    -- time of the block the transaction landed in, the time of indexing if the cluster reported none
+    block_time TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (user_id, signature, instruction_index, direction)
+);
+
+CREATE INDEX idx_wallet_transactions_user_time ON wallet_transactions(user_id, block_time DESC, id DESC);
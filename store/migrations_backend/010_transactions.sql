@@ -0,0 +1,22 @@
+-- transactions the user's wallet signed through the coordinator, from building to finality
+CREATE TABLE transactions (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- 'swap', 'token_transfer', 'link_funding', 'campaign_funding', 'link_claim', 'link_refund' or 'nonce_creation'
+    kind TEXT NOT NULL,
+    -- 'building', 'signing', 'submitted', 'confirmed', 'finalized', 'failed' or 'expired'
+    state TEXT NOT NULL,
+    -- set once the coordinator returned it
+    signature TEXT UNIQUE,
+    fee_lamports BIGINT,
+    -- the durable nonce the transaction was signed over, such a transaction never goes stale and
+    -- only expires once the nonce advanced without it
+    durable_nonce TEXT,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_transactions_user_id ON transactions(user_id);
+-- the confirmation worker only looks at transactions that are still in flight
+CREATE INDEX idx_transactions_in_flight ON transactions(updated_at) WHERE state IN ('building', 'signing', 'submitted', 'confirmed');
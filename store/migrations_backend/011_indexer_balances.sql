@@ -0,0 +1,20 @@
+-- balances are written by the indexer, one row per account and asset: the wallet itself for SOL,
+-- each of its token accounts for tokens
+ALTER TABLE balances ADD COLUMN account TEXT;
+-- position of the update the row holds, older updates arriving late must not overwrite it
+ALTER TABLE balances ADD COLUMN slot BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE balances ADD COLUMN write_version BIGINT NOT NULL DEFAULT 0;
+
+CREATE UNIQUE INDEX idx_balances_account_asset ON balances(account, asset_id);
+
+-- SOL is indexed under the native mint
+INSERT INTO assets (id, mint_address, decimals, name, symbol, updated_at)
+VALUES ('sol', 'So11111111111111111111111111111111111111112', 9, 'Solana', 'SOL', NOW())
+ON CONFLICT (mint_address) DO NOTHING;
+
+-- the last slot the indexer processed, per stream
+CREATE TABLE indexer_checkpoints (
+    name TEXT PRIMARY KEY,
+    slot BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
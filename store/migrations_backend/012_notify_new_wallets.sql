@@ -0,0 +1,12 @@
+-- the indexer subscribes to the accounts of wallets created while it runs, the notification is
+-- delivered when the inserting transaction commits
+CREATE FUNCTION notify_new_wallet() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('new_wallet', NEW.public_key);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_notify_new_wallet
+AFTER INSERT ON users
+FOR EACH ROW EXECUTE FUNCTION notify_new_wallet();
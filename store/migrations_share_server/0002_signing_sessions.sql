@@ -0,0 +1,16 @@
+CREATE TABLE signing_sessions (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    participants TEXT[] NOT NULL,
+    message_hash TEXT,
+    state TEXT NOT NULL,
+    -- private nonces, wiped as soon as they are used or the session expires
+    secret_nonces TEXT,
+    signature TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_signing_sessions_user_id ON signing_sessions(user_id);
+CREATE INDEX idx_signing_sessions_expires_at ON signing_sessions(expires_at);
@@ -0,0 +1,4 @@
+-- secret_key now holds the share encrypted with a per-row data key, wrapped_key holds that
+-- data key encrypted with the server's master key. Version 0 marks rows still in plaintext.
+ALTER TABLE keyshares ADD COLUMN wrapped_key TEXT;
+ALTER TABLE keyshares ADD COLUMN key_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- 'musig2' rows hold an independent ed25519 key, 'frost' rows a threshold key share
+-- with the group key as public_key.
+ALTER TABLE keyshares ADD COLUMN scheme TEXT NOT NULL DEFAULT 'musig2';
@@ -0,0 +1,11 @@
+-- audit trail of every key share released to a user, written before the share leaves the server
+CREATE TABLE key_exports (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    -- base58 public key the share was encrypted to
+    recipient TEXT NOT NULL,
+    scheme TEXT NOT NULL,
+    exported_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_key_exports_user_id ON key_exports(user_id);
@@ -0,0 +1,35 @@
+//! Re-encrypt the key shares of one share server under its current master key.
+//!
+//! Run it with the same environment as the share server. To rotate, configure the new key as
+//! `SHARE_SERVER_MASTER_KEY` (with a higher `SHARE_SERVER_MASTER_KEY_VERSION`) and the old one as
+//! `SHARE_SERVER_PREVIOUS_MASTER_KEY`, restart the server, run `reencrypt_keyshares` and drop the
+//! previous key afterwards. Running it once after upgrading also encrypts shares that were stored
+//! in plaintext.
+
+use store::{crypto::KeyRing, Store};
+
+#[tokio::main]
+async fn main() {
+    let keys = match KeyRing::from_env("SHARE_SERVER") {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Failed to load master keys: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let pool = match store::connect_env("SHARE_SERVER_DATABASE_URL", "share server").await {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("Failed to initialize the store: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match Store::reencrypt_keyshares(&pool, &keys).await {
+        Ok(updated) => println!("Re-encrypted {} key shares under key version {}", updated, keys.current_version()),
+        Err(e) => {
+            eprintln!("Failed to re-encrypt key shares: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+use crate::Store;
+
+#[derive(Debug, Clone)]
+pub struct LinkCampaign {
+    pub id: String,
+    pub creator_id: String,
+    pub name: String,
+    pub mint: Option<String>,
+    /// Per link
+    pub amount: u64,
+    pub link_count: u32,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub struct NewLinkCampaign<'a> {
+    pub id: &'a str,
+    pub creator_id: &'a str,
+    pub name: &'a str,
+    pub mint: Option<&'a str>,
+    pub amount: u64,
+    pub link_count: u32,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum CampaignError {
+    NotFound,
+    DatabaseError(String),
+}
+
+impl std::fmt::Display for CampaignError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CampaignError::NotFound => write!(f, "Campaign not found"),
+            CampaignError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CampaignError {}
+
+fn campaign_from_row(row: &sqlx::postgres::PgRow) -> Result<LinkCampaign, CampaignError> {
+    let get_err = |e: sqlx::Error| CampaignError::DatabaseError(e.to_string());
+    let amount: i64 = row.try_get("amount").map_err(get_err)?;
+    let link_count: i32 = row.try_get("link_count").map_err(get_err)?;
+    Ok(LinkCampaign {
+        id: row.try_get("id").map_err(get_err)?,
+        creator_id: row.try_get("creator_id").map_err(get_err)?,
+        name: row.try_get("name").map_err(get_err)?,
+        mint: row.try_get("mint").map_err(get_err)?,
+        amount: amount as u64,
+        link_count: link_count as u32,
+        created_at: row.try_get("created_at").map_err(get_err)?,
+        expires_at: row.try_get("expires_at").map_err(get_err)?,
+    })
+}
+
+const CAMPAIGN_COLUMNS: &str = "id, creator_id, name, mint, amount, link_count, created_at, expires_at";
+
+impl Store {
+    pub async fn create_link_campaign(&self, campaign: NewLinkCampaign<'_>) -> Result<LinkCampaign, CampaignError> {
+        let row = sqlx::query(&format!(
+            "INSERT INTO link_campaigns (id, creator_id, name, mint, amount, link_count, created_at, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6, NOW(), $7)
+             RETURNING {}",
+            CAMPAIGN_COLUMNS
+        ))
+        .bind(campaign.id)
+        .bind(campaign.creator_id)
+        .bind(campaign.name)
+        .bind(campaign.mint)
+        .bind(campaign.amount as i64)
+        .bind(campaign.link_count as i32)
+        .bind(campaign.expires_at)
+        .fetch_one(&self.backend)
+        .await
+        .map_err(|e| CampaignError::DatabaseError(e.to_string()))?;
+        campaign_from_row(&row)
+    }
+
+    /// Campaigns of other users look like they don't exist.
+    pub async fn get_link_campaign(&self, campaign_id: &str, creator_id: &str) -> Result<LinkCampaign, CampaignError> {
+        let row = sqlx::query(&format!("SELECT {} FROM link_campaigns WHERE id = $1 AND creator_id = $2", CAMPAIGN_COLUMNS))
+            .bind(campaign_id)
+            .bind(creator_id)
+            .fetch_optional(&self.backend)
+            .await
+            .map_err(|e| CampaignError::DatabaseError(e.to_string()))?;
+        match row {
+            Some(row) => campaign_from_row(&row),
+            None => Err(CampaignError::NotFound),
+        }
+    }
+
+    /// Every campaign the user ran, newest first.
+    pub async fn list_link_campaigns(&self, creator_id: &str) -> Result<Vec<LinkCampaign>, CampaignError> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM link_campaigns WHERE creator_id = $1 ORDER BY created_at DESC",
+            CAMPAIGN_COLUMNS
+        ))
+        .bind(creator_id)
+        .fetch_all(&self.backend)
+        .await
+        .map_err(|e| CampaignError::DatabaseError(e.to_string()))?;
+        rows.iter().map(campaign_from_row).collect()
+    }
+}
@@ -0,0 +1,241 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::engine::Engine;
+
+/// Key version of rows written before shares were encrypted, their secret is stored as is.
+pub const PLAINTEXT_KEY_VERSION: i32 = 0;
+
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, PartialEq)]
+pub enum CryptoError {
+    MissingMasterKey(String),
+    InvalidMasterKey(String),
+    UnknownKeyVersion(i32),
+    InvalidCiphertext,
+    /// Authentication failed, the row was tampered with, moved to another user or the key is wrong.
+    DecryptionFailed,
+    EncryptionFailed,
+}
+
+impl std::fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CryptoError::MissingMasterKey(var) => write!(f, "Master key not configured, set {} or {}_FILE", var, var),
+            CryptoError::InvalidMasterKey(msg) => write!(f, "Invalid master key: {}", msg),
+            CryptoError::UnknownKeyVersion(version) => write!(f, "No master key loaded for version {}", version),
+            CryptoError::InvalidCiphertext => write!(f, "Invalid ciphertext"),
+            CryptoError::DecryptionFailed => write!(f, "Failed to decrypt key share"),
+            CryptoError::EncryptionFailed => write!(f, "Failed to encrypt key share"),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+/// A key share as it is stored in the `keyshares` table. The share is encrypted with a random
+/// data key, and only that data key is encrypted with the server's master key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncryptedShare {
+    pub ciphertext: String,
+    pub wrapped_key: String,
+    pub key_version: i32,
+}
+
+pub struct MasterKey {
+    version: i32,
+    key: Key<Aes256Gcm>,
+}
+
+impl MasterKey {
+    pub fn new(version: i32, key: [u8; 32]) -> Self {
+        Self { version, key: key.into() }
+    }
+
+    pub fn from_base64(version: i32, encoded: &str) -> Result<Self, CryptoError> {
+        if version == PLAINTEXT_KEY_VERSION {
+            return Err(CryptoError::InvalidMasterKey(format!("version {} is reserved for plaintext rows", version)));
+        }
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| CryptoError::InvalidMasterKey(e.to_string()))?;
+        let key: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| CryptoError::InvalidMasterKey("expected 32 bytes".to_string()))?;
+        Ok(Self::new(version, key))
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    /// Read `{var}` or the file named by `{var}_FILE`, and the version from `{var}_VERSION` (defaults to 1).
+    fn from_env(var: &str) -> Result<Option<Self>, CryptoError> {
+        let encoded = match dotenvy::var(var) {
+            Ok(key) => key,
+            Err(_) => match dotenvy::var(format!("{}_FILE", var)) {
+                Ok(path) => std::fs::read_to_string(&path)
+                    .map_err(|e| CryptoError::InvalidMasterKey(format!("failed to read {}: {}", path, e)))?,
+                Err(_) => return Ok(None),
+            },
+        };
+        let version = match dotenvy::var(format!("{}_VERSION", var)) {
+            Ok(version) => version
+                .parse()
+                .map_err(|_| CryptoError::InvalidMasterKey(format!("{}_VERSION is not a number", var)))?,
+            Err(_) => 1,
+        };
+        Self::from_base64(version, &encoded).map(Some)
+    }
+}
+
+/// The master key new shares are encrypted with, plus the previous one while a rotation is in progress.
+pub struct KeyRing {
+    current: MasterKey,
+    previous: Option<MasterKey>,
+}
+
+impl KeyRing {
+    pub fn new(current: MasterKey) -> Self {
+        Self { current, previous: None }
+    }
+
+    pub fn with_previous(mut self, previous: MasterKey) -> Self {
+        self.previous = Some(previous);
+        self
+    }
+
+    /// Load the keys of a share server, e.g. `SHARE_SERVER_MASTER_KEY` and `SHARE_SERVER_PREVIOUS_MASTER_KEY`.
+    pub fn from_env(prefix: &str) -> Result<Self, CryptoError> {
+        let var = format!("{}_MASTER_KEY", prefix);
+        let current = MasterKey::from_env(&var)?.ok_or(CryptoError::MissingMasterKey(var))?;
+        let keys = Self::new(current);
+        match MasterKey::from_env(&format!("{}_PREVIOUS_MASTER_KEY", prefix))? {
+            Some(previous) => Ok(keys.with_previous(previous)),
+            None => Ok(keys),
+        }
+    }
+
+    pub fn current_version(&self) -> i32 {
+        self.current.version
+    }
+
+    fn key(&self, version: i32) -> Result<&MasterKey, CryptoError> {
+        if self.current.version == version {
+            return Ok(&self.current);
+        }
+        match &self.previous {
+            Some(previous) if previous.version == version => Ok(previous),
+            _ => Err(CryptoError::UnknownKeyVersion(version)),
+        }
+    }
+
+    pub fn encrypt(&self, user_id: &str, secret: &[u8]) -> Result<EncryptedShare, CryptoError> {
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        let ciphertext = seal(&data_key, user_id, secret)?;
+        let wrapped_key = seal(&self.current.key, user_id, data_key.as_slice())?;
+        Ok(EncryptedShare { ciphertext, wrapped_key, key_version: self.current.version })
+    }
+
+    pub fn decrypt(&self, user_id: &str, share: &EncryptedShare) -> Result<Vec<u8>, CryptoError> {
+        let data_key = self.unwrap_data_key(user_id, share)?;
+        open(&data_key, user_id, &share.ciphertext)
+    }
+
+    /// Re-wrap the data key of a share under the current master key. The share itself is not touched.
+    pub fn rewrap(&self, user_id: &str, share: &EncryptedShare) -> Result<EncryptedShare, CryptoError> {
+        let data_key = self.unwrap_data_key(user_id, share)?;
+        Ok(EncryptedShare {
+            ciphertext: share.ciphertext.clone(),
+            wrapped_key: seal(&self.current.key, user_id, data_key.as_slice())?,
+            key_version: self.current.version,
+        })
+    }
+
+    fn unwrap_data_key(&self, user_id: &str, share: &EncryptedShare) -> Result<Key<Aes256Gcm>, CryptoError> {
+        let master_key = self.key(share.key_version)?;
+        let data_key = open(&master_key.key, user_id, &share.wrapped_key)?;
+        if data_key.len() != 32 {
+            return Err(CryptoError::InvalidCiphertext);
+        }
+        Ok(Key::<Aes256Gcm>::clone_from_slice(&data_key))
+    }
+}
+
+/// Encrypt with the user id as associated data, so a row copied to another user fails to decrypt.
+fn seal(key: &Key<Aes256Gcm>, user_id: &str, plaintext: &[u8]) -> Result<String, CryptoError> {
+    let cipher = Aes256Gcm::new(key);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: plaintext, aad: user_id.as_bytes() })
+        .map_err(|_| CryptoError::EncryptionFailed)?;
+
+    let mut out = nonce.to_vec();
+    out.extend_from_slice(&ciphertext);
+    Ok(base64::engine::general_purpose::STANDARD.encode(out))
+}
+
+fn open(key: &Key<Aes256Gcm>, user_id: &str, encoded: &str) -> Result<Vec<u8>, CryptoError> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|_| CryptoError::InvalidCiphertext)?;
+    if bytes.len() < NONCE_LEN {
+        return Err(CryptoError::InvalidCiphertext);
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    Aes256Gcm::new(key)
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: user_id.as_bytes() })
+        .map_err(|_| CryptoError::DecryptionFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CryptoError, KeyRing, MasterKey};
+
+    #[test]
+    fn test_roundtrip() {
+        let keys = KeyRing::new(MasterKey::new(1, [7; 32]));
+        let share = keys.encrypt("user-1", b"secret share").unwrap();
+        assert_eq!(share.key_version, 1);
+        assert_eq!(keys.decrypt("user-1", &share).unwrap(), b"secret share");
+    }
+
+    #[test]
+    fn test_share_is_bound_to_user() {
+        let keys = KeyRing::new(MasterKey::new(1, [7; 32]));
+        let share = keys.encrypt("user-1", b"secret share").unwrap();
+        assert_eq!(keys.decrypt("user-2", &share), Err(CryptoError::DecryptionFailed));
+    }
+
+    #[test]
+    fn test_wrong_master_key() {
+        let share = KeyRing::new(MasterKey::new(1, [7; 32])).encrypt("user-1", b"secret share").unwrap();
+        let other = KeyRing::new(MasterKey::new(1, [8; 32]));
+        assert_eq!(other.decrypt("user-1", &share), Err(CryptoError::DecryptionFailed));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let old = KeyRing::new(MasterKey::new(1, [7; 32]));
+        let share = old.encrypt("user-1", b"secret share").unwrap();
+
+        let rotating = KeyRing::new(MasterKey::new(2, [8; 32])).with_previous(MasterKey::new(1, [7; 32]));
+        assert_eq!(rotating.decrypt("user-1", &share).unwrap(), b"secret share");
+        let rewrapped = rotating.rewrap("user-1", &share).unwrap();
+        assert_eq!(rewrapped.key_version, 2);
+        assert_eq!(rewrapped.ciphertext, share.ciphertext);
+
+        let new = KeyRing::new(MasterKey::new(2, [8; 32]));
+        assert_eq!(new.decrypt("user-1", &rewrapped).unwrap(), b"secret share");
+        assert_eq!(new.decrypt("user-1", &share), Err(CryptoError::UnknownKeyVersion(1)));
+    }
+
+    #[test]
+    fn test_plaintext_version_is_reserved() {
+        let encoded = format!("{}=", "A".repeat(43));
+        assert!(MasterKey::from_base64(1, &encoded).is_ok());
+        assert!(MasterKey::from_base64(0, &encoded).is_err());
+    }
+}
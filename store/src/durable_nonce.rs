@@ -0,0 +1,73 @@
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+use crate::Store;
+
+/// The last known state of a wallet's durable nonce account. The coordinator owns the account,
+/// this is what the backend saw after the last transaction that advanced it.
+#[derive(Debug, Clone)]
+pub struct DurableNonce {
+    pub user_id: String,
+    pub address: String,
+    pub nonce: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum DurableNonceError {
+    NotFound,
+    DatabaseError(String),
+}
+
+impl std::fmt::Display for DurableNonceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DurableNonceError::NotFound => write!(f, "The wallet has no durable nonce account"),
+            DurableNonceError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DurableNonceError {}
+
+fn nonce_from_row(row: &sqlx::postgres::PgRow) -> Result<DurableNonce, DurableNonceError> {
+    let get_err = |e: sqlx::Error| DurableNonceError::DatabaseError(e.to_string());
+    Ok(DurableNonce {
+        user_id: row.try_get("user_id").map_err(get_err)?,
+        address: row.try_get("address").map_err(get_err)?,
+        nonce: row.try_get("nonce").map_err(get_err)?,
+        updated_at: row.try_get("updated_at").map_err(get_err)?,
+    })
+}
+
+impl Store {
+    pub async fn get_durable_nonce(&self, user_id: &str) -> Result<DurableNonce, DurableNonceError> {
+        let row = sqlx::query("SELECT user_id, address, nonce, updated_at FROM durable_nonces WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.backend)
+            .await
+            .map_err(|e| DurableNonceError::DatabaseError(e.to_string()))?;
+        match row {
+            Some(row) => nonce_from_row(&row),
+            None => Err(DurableNonceError::NotFound),
+        }
+    }
+
+    /// Remember the nonce account's current value, called after creating it and after every
+    /// transaction that advanced it.
+    pub async fn record_durable_nonce(&self, user_id: &str, address: &str, nonce: &str) -> Result<DurableNonce, DurableNonceError> {
+        let row = sqlx::query(
+            "INSERT INTO durable_nonces (user_id, address, nonce, created_at, updated_at) VALUES ($1, $2, $3, $4, $4)
+             ON CONFLICT (user_id) DO UPDATE SET address = EXCLUDED.address, nonce = EXCLUDED.nonce, updated_at = EXCLUDED.updated_at
+             RETURNING user_id, address, nonce, updated_at",
+        )
+        .bind(user_id)
+        .bind(address)
+        .bind(nonce)
+        .bind(Utc::now())
+        .fetch_one(&self.backend)
+        .await
+        .map_err(|e| DurableNonceError::DatabaseError(e.to_string()))?;
+        nonce_from_row(&row)
+    }
+}
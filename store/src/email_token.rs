@@ -0,0 +1,112 @@
+use chrono::{Duration, Utc};
+use sqlx::Row;
+
+use crate::{user_session::hash_token, Store};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl EmailTokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailTokenPurpose::VerifyEmail => "verify_email",
+            EmailTokenPurpose::ResetPassword => "reset_password",
+        }
+    }
+
+    pub fn parse(purpose: &str) -> Option<Self> {
+        match purpose {
+            "verify_email" => Some(EmailTokenPurpose::VerifyEmail),
+            "reset_password" => Some(EmailTokenPurpose::ResetPassword),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EmailTokenError {
+    InvalidToken,
+    DatabaseError(String),
+}
+
+impl std::fmt::Display for EmailTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmailTokenError::InvalidToken => write!(f, "Invalid or expired token"),
+            EmailTokenError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for EmailTokenError {}
+
+impl Store {
+    /// Record a token mailed to the user. Older unused tokens for the same purpose stop working,
+    /// only the newest mail counts.
+    pub async fn create_email_token(&self, user_id: &str, purpose: EmailTokenPurpose, token: &str, ttl: Duration) -> Result<(), EmailTokenError> {
+        let mut tx = self.backend.begin().await.map_err(|e| EmailTokenError::DatabaseError(e.to_string()))?;
+        sqlx::query("UPDATE email_tokens SET used_at = NOW() WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL")
+            .bind(user_id)
+            .bind(purpose.as_str())
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| EmailTokenError::DatabaseError(e.to_string()))?;
+        let created_at = Utc::now();
+        sqlx::query(
+            "INSERT INTO email_tokens (id, user_id, purpose, token_hash, created_at, expires_at) VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(user_id)
+        .bind(purpose.as_str())
+        .bind(hash_token(token))
+        .bind(created_at)
+        .bind(created_at + ttl)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| EmailTokenError::DatabaseError(e.to_string()))?;
+        tx.commit().await.map_err(|e| EmailTokenError::DatabaseError(e.to_string()))
+    }
+
+    /// Use up a token, returns the user it was mailed to.
+    pub async fn consume_email_token(&self, purpose: EmailTokenPurpose, token: &str) -> Result<String, EmailTokenError> {
+        let row = sqlx::query(
+            "UPDATE email_tokens SET used_at = NOW()
+             WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > NOW()
+             RETURNING user_id",
+        )
+        .bind(hash_token(token))
+        .bind(purpose.as_str())
+        .fetch_optional(&self.backend)
+        .await
+        .map_err(|e| EmailTokenError::DatabaseError(e.to_string()))?;
+        match row {
+            Some(row) => row.try_get("user_id").map_err(|e| EmailTokenError::DatabaseError(e.to_string())),
+            None => Err(EmailTokenError::InvalidToken),
+        }
+    }
+
+    pub async fn mark_email_verified(&self, user_id: &str) -> Result<(), EmailTokenError> {
+        sqlx::query("UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()), updated_at = NOW() WHERE id = $1")
+            .bind(user_id)
+            .execute(&self.backend)
+            .await
+            .map_err(|e| EmailTokenError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmailTokenPurpose;
+
+    #[test]
+    fn test_purpose_roundtrip() {
+        for purpose in [EmailTokenPurpose::VerifyEmail, EmailTokenPurpose::ResetPassword] {
+            assert_eq!(EmailTokenPurpose::parse(purpose.as_str()), Some(purpose));
+        }
+        assert_eq!(EmailTokenPurpose::parse("login"), None);
+    }
+}
@@ -0,0 +1,268 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{PgPool, Row};
+
+use crate::{mpc::KeyScheme, Store};
+
+/// How long a requested export waits before the shares are released, unless `WALLET_EXPORT_DELAY_SECONDS` is set.
+pub const DEFAULT_EXPORT_DELAY_SECONDS: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportState {
+    /// Waiting for the delay to pass, can still be cancelled
+    Pending,
+    /// The shares are being fetched from the share servers
+    Releasing,
+    Released,
+    Failed,
+    Cancelled,
+}
+
+impl ExportState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportState::Pending => "pending",
+            ExportState::Releasing => "releasing",
+            ExportState::Released => "released",
+            ExportState::Failed => "failed",
+            ExportState::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(state: &str) -> Option<Self> {
+        match state {
+            "pending" => Some(ExportState::Pending),
+            "releasing" => Some(ExportState::Releasing),
+            "released" => Some(ExportState::Released),
+            "failed" => Some(ExportState::Failed),
+            "cancelled" => Some(ExportState::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WalletExport {
+    pub id: String,
+    pub user_id: String,
+    pub recipient: String,
+    pub state: ExportState,
+    pub error: Option<String>,
+    pub requested_at: DateTime<Utc>,
+    pub available_at: DateTime<Utc>,
+    pub released_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    ExportExists,
+    NotFound,
+    WrongUser,
+    NotYetAvailable(DateTime<Utc>),
+    InvalidState(ExportState),
+    DatabaseError(String),
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::ExportExists => write!(f, "Wallet export already exists"),
+            ExportError::NotFound => write!(f, "Wallet export not found"),
+            ExportError::WrongUser => write!(f, "Wallet export belongs to a different user"),
+            ExportError::NotYetAvailable(at) => write!(f, "Wallet export is available from {}", at.to_rfc3339()),
+            ExportError::InvalidState(state) => write!(f, "Wallet export is {}", state.as_str()),
+            ExportError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+fn export_from_row(row: &sqlx::postgres::PgRow) -> Result<WalletExport, ExportError> {
+    let get_err = |e: sqlx::Error| ExportError::DatabaseError(e.to_string());
+    let state: String = row.try_get("state").map_err(get_err)?;
+    Ok(WalletExport {
+        id: row.try_get("id").map_err(get_err)?,
+        user_id: row.try_get("user_id").map_err(get_err)?,
+        recipient: row.try_get("recipient").map_err(get_err)?,
+        state: ExportState::parse(&state)
+            .ok_or_else(|| ExportError::DatabaseError(format!("Unknown export state {}", state)))?,
+        error: row.try_get("error").map_err(get_err)?,
+        requested_at: row.try_get("requested_at").map_err(get_err)?,
+        available_at: row.try_get("available_at").map_err(get_err)?,
+        released_at: row.try_get("released_at").map_err(get_err)?,
+    })
+}
+
+const EXPORT_COLUMNS: &str = "id, user_id, recipient, state, error, requested_at, available_at, released_at";
+
+impl Store {
+    /// Record a user's request to export their wallet. The shares can be released after `delay`.
+    pub async fn create_wallet_export(&self, user_id: &str, recipient: &str, delay: Duration) -> Result<WalletExport, ExportError> {
+        let requested_at = Utc::now();
+        let row = sqlx::query(&format!(
+            "INSERT INTO wallet_exports (id, user_id, recipient, state, requested_at, available_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $5)
+             RETURNING {}",
+            EXPORT_COLUMNS
+        ))
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(user_id)
+        .bind(recipient)
+        .bind(ExportState::Pending.as_str())
+        .bind(requested_at)
+        .bind(requested_at + delay)
+        .fetch_one(&self.backend)
+        .await
+        .map_err(|e| ExportError::DatabaseError(e.to_string()))?;
+        export_from_row(&row)
+    }
+
+    pub async fn get_wallet_export(&self, export_id: &str, user_id: &str) -> Result<WalletExport, ExportError> {
+        let row = sqlx::query(&format!("SELECT {} FROM wallet_exports WHERE id = $1", EXPORT_COLUMNS))
+            .bind(export_id)
+            .fetch_optional(&self.backend)
+            .await
+            .map_err(|e| ExportError::DatabaseError(e.to_string()))?;
+        let export = match row {
+            Some(row) => export_from_row(&row)?,
+            None => return Err(ExportError::NotFound),
+        };
+        if export.user_id != user_id {
+            return Err(ExportError::WrongUser);
+        }
+        Ok(export)
+    }
+
+    /// Every export the user ever requested, newest first.
+    pub async fn list_wallet_exports(&self, user_id: &str) -> Result<Vec<WalletExport>, ExportError> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM wallet_exports WHERE user_id = $1 ORDER BY requested_at DESC",
+            EXPORT_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_all(&self.backend)
+        .await
+        .map_err(|e| ExportError::DatabaseError(e.to_string()))?;
+        rows.iter().map(export_from_row).collect()
+    }
+
+    /// Atomically move a pending export whose delay has passed to `Releasing`, so only one release
+    /// of a request runs at a time.
+    pub async fn claim_wallet_export(&self, export_id: &str, user_id: &str) -> Result<WalletExport, ExportError> {
+        let row = sqlx::query(&format!(
+            "UPDATE wallet_exports SET state = $3, updated_at = NOW()
+             WHERE id = $1 AND user_id = $2 AND state = $4 AND available_at <= NOW()
+             RETURNING {}",
+            EXPORT_COLUMNS
+        ))
+        .bind(export_id)
+        .bind(user_id)
+        .bind(ExportState::Releasing.as_str())
+        .bind(ExportState::Pending.as_str())
+        .fetch_optional(&self.backend)
+        .await
+        .map_err(|e| ExportError::DatabaseError(e.to_string()))?;
+        if let Some(row) = row {
+            return export_from_row(&row);
+        }
+
+        // Nothing was updated, find out why
+        let export = self.get_wallet_export(export_id, user_id).await?;
+        match export.state {
+            ExportState::Pending => Err(ExportError::NotYetAvailable(export.available_at)),
+            state => Err(ExportError::InvalidState(state)),
+        }
+    }
+
+    /// Close a claimed export, `error` is `None` if the shares were handed to the user.
+    pub async fn finish_wallet_export(&self, export_id: &str, error: Option<&str>) -> Result<(), ExportError> {
+        let (state, released_at) = match error {
+            None => (ExportState::Released, Some(Utc::now())),
+            Some(_) => (ExportState::Failed, None),
+        };
+        sqlx::query(
+            "UPDATE wallet_exports SET state = $2, error = $3, released_at = $4, updated_at = NOW() WHERE id = $1 AND state = $5",
+        )
+        .bind(export_id)
+        .bind(state.as_str())
+        .bind(error)
+        .bind(released_at)
+        .bind(ExportState::Releasing.as_str())
+        .execute(&self.backend)
+        .await
+        .map_err(|e| ExportError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Hand a claimed export back to `Pending` after too few share servers released their share,
+    /// so it can be claimed again once they are back.
+    pub async fn reopen_wallet_export(&self, export_id: &str, error: &str) -> Result<(), ExportError> {
+        sqlx::query("UPDATE wallet_exports SET state = $2, error = $3, updated_at = NOW() WHERE id = $1 AND state = $4")
+            .bind(export_id)
+            .bind(ExportState::Pending.as_str())
+            .bind(error)
+            .bind(ExportState::Releasing.as_str())
+            .execute(&self.backend)
+            .await
+            .map_err(|e| ExportError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    pub async fn cancel_wallet_export(&self, export_id: &str, user_id: &str) -> Result<WalletExport, ExportError> {
+        let row = sqlx::query(&format!(
+            "UPDATE wallet_exports SET state = $3, updated_at = NOW() WHERE id = $1 AND user_id = $2 AND state = $4 RETURNING {}",
+            EXPORT_COLUMNS
+        ))
+        .bind(export_id)
+        .bind(user_id)
+        .bind(ExportState::Cancelled.as_str())
+        .bind(ExportState::Pending.as_str())
+        .fetch_optional(&self.backend)
+        .await
+        .map_err(|e| ExportError::DatabaseError(e.to_string()))?;
+        match row {
+            Some(row) => export_from_row(&row),
+            None => Err(ExportError::InvalidState(self.get_wallet_export(export_id, user_id).await?.state)),
+        }
+    }
+
+    /// Audit a key share leaving a share server. An export id is only ever released to the user and
+    /// recipient it was first released to, repeating it keeps the time of the first release.
+    pub async fn record_key_export(pool: &PgPool, export_id: &str, user_id: &str, recipient: &str, scheme: KeyScheme) -> Result<(), ExportError> {
+        let result = sqlx::query(
+            "INSERT INTO key_exports (id, user_id, recipient, scheme, exported_at) VALUES ($1, $2, $3, $4, NOW())
+             ON CONFLICT (id) DO UPDATE SET exported_at = key_exports.exported_at
+             WHERE key_exports.user_id = EXCLUDED.user_id AND key_exports.recipient = EXCLUDED.recipient",
+        )
+        .bind(export_id)
+        .bind(user_id)
+        .bind(recipient)
+        .bind(scheme.as_str())
+        .execute(pool)
+        .await
+        .map_err(|e| ExportError::DatabaseError(e.to_string()))?;
+        if result.rows_affected() == 0 {
+            return Err(ExportError::ExportExists);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExportState;
+
+    #[test]
+    fn test_state_roundtrip() {
+        for state in [
+            ExportState::Pending,
+            ExportState::Releasing,
+            ExportState::Released,
+            ExportState::Failed,
+            ExportState::Cancelled,
+        ] {
+            assert_eq!(ExportState::parse(state.as_str()), Some(state));
+        }
+        assert_eq!(ExportState::parse("exported"), None);
+    }
+}
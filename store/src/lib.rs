@@ -1,5 +1,16 @@
 pub mod user;
 pub mod mpc;
+pub mod crypto;
+pub mod session;
+pub mod export;
+pub mod user_session;
+pub mod two_factor;
+pub mod email_token;
+pub mod link;
+pub mod campaign;
+pub mod durable_nonce;
+pub mod wallet_transaction;
+pub mod transaction;
 
 use std::time::Duration;
 
@@ -7,54 +18,32 @@ use sqlx::{postgres::PgPoolOptions, PgPool};
 
 pub struct Store {
     pub backend: PgPool,
-    pub mpc_server_1: PgPool,
-    pub mpc_server_2: PgPool,
 }
 
 impl Store {
     pub async fn new() -> Result<Self, sqlx::Error> {
-        let backend_url = dotenvy::var("BACKEND_DATABASE_URL")
-            .map_err(|e| sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::NotFound, e)))?;
-        
-        let mpc1_url = dotenvy::var("MPC_SERVER_1_DATABASE_URL")
-            .map_err(|e| sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::NotFound, e)))?;
-        
-        let mpc2_url = dotenvy::var("MPC_SERVER_2_DATABASE_URL")
-            .map_err(|e| sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::NotFound, e)))?;
-
-        let backend = PgPoolOptions::new()
-            .max_connections(3) 
-            .min_connections(1)  
-            .acquire_timeout(Duration::from_secs(30))
-            .connect(&backend_url)
-            .await
-            .map_err(|e| {
-                eprintln!("Failed to connect to backend database: {}", e);
-                e
-            })?;
-
-        let mpc_server_1 = PgPoolOptions::new()
-            .max_connections(3)
-            .min_connections(1)
-            .acquire_timeout(Duration::from_secs(30))
-            .connect(&mpc1_url)
-            .await
-            .map_err(|e| {
-                eprintln!("Failed to connect to MPC server 1 database: {}", e);
-                e
-            })?;
+        let backend = connect_env("BACKEND_DATABASE_URL", "backend").await?;
+        Ok(Self { backend })
+    }
+}
 
-        let mpc_server_2 = PgPoolOptions::new()
-            .max_connections(3)
-            .min_connections(1)
-            .acquire_timeout(Duration::from_secs(30))
-            .connect(&mpc2_url)
-            .await
-            .map_err(|e| {
-                eprintln!("Failed to connect to MPC server 2 database: {}", e);
-                e
-            })?;
+/// Connect to the database in the environment variable `var`. Share servers keep one pool each and
+/// pass it to the keyshare and signing session functions on `Store`.
+pub async fn connect_env(var: &str, name: &str) -> Result<PgPool, sqlx::Error> {
+    let url = dotenvy::var(var)
+        .map_err(|e| sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::NotFound, e)))?;
+    connect(&url, name).await
+}
 
-        Ok(Self { backend, mpc_server_1, mpc_server_2 })
-    }
-}
\ No newline at end of file
+pub async fn connect(url: &str, name: &str) -> Result<PgPool, sqlx::Error> {
+    PgPoolOptions::new()
+        .max_connections(3)
+        .min_connections(1)
+        .acquire_timeout(Duration::from_secs(30))
+        .connect(url)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to connect to {} database: {}", name, e);
+            e
+        })
+}
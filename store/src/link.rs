@@ -0,0 +1,409 @@
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+use crate::{user_session::hash_token, Store};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    /// The link's wallet was created, the funds are not there (yet)
+    Unfunded,
+    Funded,
+    /// A claim is moving the funds out
+    Claiming,
+    Claimed,
+    /// Expired, the funds are going back to the creator
+    Refunding,
+    Refunded,
+}
+
+impl LinkState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkState::Unfunded => "unfunded",
+            LinkState::Funded => "funded",
+            LinkState::Claiming => "claiming",
+            LinkState::Claimed => "claimed",
+            LinkState::Refunding => "refunding",
+            LinkState::Refunded => "refunded",
+        }
+    }
+
+    pub fn parse(state: &str) -> Option<Self> {
+        match state {
+            "unfunded" => Some(LinkState::Unfunded),
+            "funded" => Some(LinkState::Funded),
+            "claiming" => Some(LinkState::Claiming),
+            "claimed" => Some(LinkState::Claimed),
+            "refunding" => Some(LinkState::Refunding),
+            "refunded" => Some(LinkState::Refunded),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClaimLink {
+    pub id: String,
+    pub creator_id: String,
+    pub campaign_id: Option<String>,
+    pub public_key: String,
+    pub mint: Option<String>,
+    pub amount: u64,
+    pub state: LinkState,
+    pub error: Option<String>,
+    pub funding_signature: Option<String>,
+    pub destination: Option<String>,
+    pub claimed_by: Option<String>,
+    pub claim_signature: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub funded_at: Option<DateTime<Utc>>,
+    pub claimed_at: Option<DateTime<Utc>>,
+    pub refunded_at: Option<DateTime<Utc>>,
+}
+
+pub struct NewClaimLink<'a> {
+    pub id: &'a str,
+    pub creator_id: &'a str,
+    pub campaign_id: Option<&'a str>,
+    pub public_key: &'a str,
+    pub mint: Option<&'a str>,
+    pub amount: u64,
+    /// Only its hash is stored
+    pub secret: &'a str,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl ClaimLink {
+    /// The state as users see it: a link past its expiry shows as expired until its refund went through.
+    pub fn status(&self, now: DateTime<Utc>) -> &'static str {
+        match self.state {
+            LinkState::Unfunded | LinkState::Funded if self.expires_at <= now => "expired",
+            LinkState::Refunding => "expired",
+            state => state.as_str(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LinkError {
+    NotFound,
+    InvalidSecret,
+    Expired,
+    InvalidState(LinkState),
+    DatabaseError(String),
+}
+
+impl std::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkError::NotFound => write!(f, "Link not found"),
+            LinkError::InvalidSecret => write!(f, "Invalid link secret"),
+            LinkError::Expired => write!(f, "Link expired"),
+            LinkError::InvalidState(state) => write!(f, "Link is {}", state.as_str()),
+            LinkError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+fn link_from_row(row: &sqlx::postgres::PgRow) -> Result<ClaimLink, LinkError> {
+    let get_err = |e: sqlx::Error| LinkError::DatabaseError(e.to_string());
+    let state: String = row.try_get("state").map_err(get_err)?;
+    let amount: i64 = row.try_get("amount").map_err(get_err)?;
+    Ok(ClaimLink {
+        id: row.try_get("id").map_err(get_err)?,
+        creator_id: row.try_get("creator_id").map_err(get_err)?,
+        campaign_id: row.try_get("campaign_id").map_err(get_err)?,
+        public_key: row.try_get("public_key").map_err(get_err)?,
+        mint: row.try_get("mint").map_err(get_err)?,
+        amount: amount as u64,
+        state: LinkState::parse(&state).ok_or_else(|| LinkError::DatabaseError(format!("Unknown link state {}", state)))?,
+        error: row.try_get("error").map_err(get_err)?,
+        funding_signature: row.try_get("funding_signature").map_err(get_err)?,
+        destination: row.try_get("destination").map_err(get_err)?,
+        claimed_by: row.try_get("claimed_by").map_err(get_err)?,
+        claim_signature: row.try_get("claim_signature").map_err(get_err)?,
+        created_at: row.try_get("created_at").map_err(get_err)?,
+        expires_at: row.try_get("expires_at").map_err(get_err)?,
+        funded_at: row.try_get("funded_at").map_err(get_err)?,
+        claimed_at: row.try_get("claimed_at").map_err(get_err)?,
+        refunded_at: row.try_get("refunded_at").map_err(get_err)?,
+    })
+}
+
+const LINK_COLUMNS: &str = "id, creator_id, campaign_id, public_key, mint, amount, state, error, funding_signature, destination, claimed_by, \
+    claim_signature, created_at, expires_at, funded_at, claimed_at, refunded_at";
+
+impl Store {
+    pub async fn create_claim_link(&self, link: NewClaimLink<'_>) -> Result<ClaimLink, LinkError> {
+        let row = sqlx::query(&format!(
+            "INSERT INTO claim_links (id, creator_id, campaign_id, public_key, mint, amount, secret_hash, state, created_at, expires_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW(), $9, NOW())
+             RETURNING {}",
+            LINK_COLUMNS
+        ))
+        .bind(link.id)
+        .bind(link.creator_id)
+        .bind(link.campaign_id)
+        .bind(link.public_key)
+        .bind(link.mint)
+        .bind(link.amount as i64)
+        .bind(hash_token(link.secret))
+        .bind(LinkState::Unfunded.as_str())
+        .bind(link.expires_at)
+        .fetch_one(&self.backend)
+        .await
+        .map_err(|e| LinkError::DatabaseError(e.to_string()))?;
+        link_from_row(&row)
+    }
+
+    pub async fn get_claim_link(&self, link_id: &str) -> Result<ClaimLink, LinkError> {
+        let row = sqlx::query(&format!("SELECT {} FROM claim_links WHERE id = $1", LINK_COLUMNS))
+            .bind(link_id)
+            .fetch_optional(&self.backend)
+            .await
+            .map_err(|e| LinkError::DatabaseError(e.to_string()))?;
+        match row {
+            Some(row) => link_from_row(&row),
+            None => Err(LinkError::NotFound),
+        }
+    }
+
+    /// Every link the user created, newest first.
+    pub async fn list_claim_links(&self, creator_id: &str) -> Result<Vec<ClaimLink>, LinkError> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM claim_links WHERE creator_id = $1 ORDER BY created_at DESC",
+            LINK_COLUMNS
+        ))
+        .bind(creator_id)
+        .fetch_all(&self.backend)
+        .await
+        .map_err(|e| LinkError::DatabaseError(e.to_string()))?;
+        rows.iter().map(link_from_row).collect()
+    }
+
+    /// The links of a campaign in the order they were created.
+    pub async fn list_campaign_links(&self, campaign_id: &str) -> Result<Vec<ClaimLink>, LinkError> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM claim_links WHERE campaign_id = $1 ORDER BY created_at, id",
+            LINK_COLUMNS
+        ))
+        .bind(campaign_id)
+        .fetch_all(&self.backend)
+        .await
+        .map_err(|e| LinkError::DatabaseError(e.to_string()))?;
+        rows.iter().map(link_from_row).collect()
+    }
+
+    /// Record how funding an unfunded link went, `Err` holds why it failed.
+    pub async fn finish_link_funding(&self, link_id: &str, result: Result<&str, &str>) -> Result<ClaimLink, LinkError> {
+        let (state, signature, error) = match result {
+            Ok(signature) => (LinkState::Funded, Some(signature), None),
+            Err(error) => (LinkState::Unfunded, None, Some(error)),
+        };
+        let row = sqlx::query(&format!(
+            "UPDATE claim_links
+             SET state = $2, funding_signature = $3, error = $4,
+                 funded_at = CASE WHEN $3 IS NULL THEN NULL ELSE NOW() END, updated_at = NOW()
+             WHERE id = $1 AND state = $5
+             RETURNING {}",
+            LINK_COLUMNS
+        ))
+        .bind(link_id)
+        .bind(state.as_str())
+        .bind(signature)
+        .bind(error)
+        .bind(LinkState::Unfunded.as_str())
+        .fetch_optional(&self.backend)
+        .await
+        .map_err(|e| LinkError::DatabaseError(e.to_string()))?;
+        match row {
+            Some(row) => link_from_row(&row),
+            None => Err(LinkError::InvalidState(self.get_claim_link(link_id).await?.state)),
+        }
+    }
+
+    /// Atomically move a funded, unexpired link to `Claiming` if `secret` is the link's. Only one
+    /// claim can ever get past this, no matter how often the link is opened.
+    pub async fn begin_link_claim(&self, link_id: &str, secret: &str) -> Result<ClaimLink, LinkError> {
+        let row = sqlx::query(&format!(
+            "UPDATE claim_links SET state = $3, error = NULL, updated_at = NOW()
+             WHERE id = $1 AND secret_hash = $2 AND state = $4 AND expires_at > NOW()
+             RETURNING {}",
+            LINK_COLUMNS
+        ))
+        .bind(link_id)
+        .bind(hash_token(secret))
+        .bind(LinkState::Claiming.as_str())
+        .bind(LinkState::Funded.as_str())
+        .fetch_optional(&self.backend)
+        .await
+        .map_err(|e| LinkError::DatabaseError(e.to_string()))?;
+        if let Some(row) = row {
+            return link_from_row(&row);
+        }
+
+        // Nothing was updated, find out why without telling a wrong secret anything else
+        let row = sqlx::query("SELECT secret_hash FROM claim_links WHERE id = $1")
+            .bind(link_id)
+            .fetch_optional(&self.backend)
+            .await
+            .map_err(|e| LinkError::DatabaseError(e.to_string()))?;
+        let secret_hash: String = match row {
+            Some(row) => row.try_get("secret_hash").map_err(|e| LinkError::DatabaseError(e.to_string()))?,
+            None => return Err(LinkError::NotFound),
+        };
+        if secret_hash != hash_token(secret) {
+            return Err(LinkError::InvalidSecret);
+        }
+        let link = self.get_claim_link(link_id).await?;
+        match link.state {
+            LinkState::Funded => Err(LinkError::Expired),
+            state => Err(LinkError::InvalidState(state)),
+        }
+    }
+
+    /// Close a claim, `Err` puts the link back up for claiming.
+    pub async fn finish_link_claim(&self, link_id: &str, destination: &str, claimed_by: Option<&str>, result: Result<&str, &str>) -> Result<ClaimLink, LinkError> {
+        let claimed = format!(
+            "UPDATE claim_links
+             SET state = $2, destination = $3, claimed_by = $4, claim_signature = $5, error = NULL, claimed_at = NOW(), updated_at = NOW()
+             WHERE id = $1 AND state = $6
+             RETURNING {}",
+            LINK_COLUMNS
+        );
+        let failed = format!(
+            "UPDATE claim_links SET state = $2, error = $3, updated_at = NOW() WHERE id = $1 AND state = $4 RETURNING {}",
+            LINK_COLUMNS
+        );
+        let query = match result {
+            Ok(signature) => sqlx::query(&claimed)
+                .bind(link_id)
+                .bind(LinkState::Claimed.as_str())
+                .bind(destination)
+                .bind(claimed_by)
+                .bind(signature),
+            Err(error) => sqlx::query(&failed)
+                .bind(link_id)
+                .bind(LinkState::Funded.as_str())
+                .bind(error),
+        };
+        let row = query
+            .bind(LinkState::Claiming.as_str())
+            .fetch_optional(&self.backend)
+            .await
+            .map_err(|e| LinkError::DatabaseError(e.to_string()))?;
+        match row {
+            Some(row) => link_from_row(&row),
+            None => Err(LinkError::InvalidState(self.get_claim_link(link_id).await?.state)),
+        }
+    }
+
+    /// Hand out up to `limit` expired links that still hold funds, moved to `Refunding` so no
+    /// claim and no other refund can touch them.
+    pub async fn take_expired_links(&self, limit: i64) -> Result<Vec<ClaimLink>, LinkError> {
+        let rows = sqlx::query(&format!(
+            "UPDATE claim_links SET state = $1, updated_at = NOW()
+             WHERE id IN (
+                 SELECT id FROM claim_links
+                 WHERE state IN ($2, $3) AND expires_at <= NOW()
+                 ORDER BY expires_at
+                 LIMIT $4
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING {}",
+            LINK_COLUMNS
+        ))
+        .bind(LinkState::Refunding.as_str())
+        .bind(LinkState::Funded.as_str())
+        .bind(LinkState::Unfunded.as_str())
+        .bind(limit)
+        .fetch_all(&self.backend)
+        .await
+        .map_err(|e| LinkError::DatabaseError(e.to_string()))?;
+        rows.iter().map(link_from_row).collect()
+    }
+
+    /// Close a refund, `Err` puts the link back the way it was so the next pass retries it.
+    pub async fn finish_link_refund(&self, link_id: &str, result: Result<(), &str>) -> Result<(), LinkError> {
+        let query = match result {
+            Ok(()) => sqlx::query(
+                "UPDATE claim_links SET state = $2, error = NULL, refunded_at = NOW(), updated_at = NOW() WHERE id = $1 AND state = $3",
+            )
+            .bind(link_id)
+            .bind(LinkState::Refunded.as_str())
+            .bind(LinkState::Refunding.as_str()),
+            Err(error) => sqlx::query(
+                "UPDATE claim_links
+                 SET state = CASE WHEN funded_at IS NULL THEN $2 ELSE $3 END, error = $4, updated_at = NOW()
+                 WHERE id = $1 AND state = $5",
+            )
+            .bind(link_id)
+            .bind(LinkState::Unfunded.as_str())
+            .bind(LinkState::Funded.as_str())
+            .bind(error)
+            .bind(LinkState::Refunding.as_str()),
+        };
+        query
+            .execute(&self.backend)
+            .await
+            .map_err(|e| LinkError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::{ClaimLink, LinkState};
+
+    fn link(state: LinkState, expires_in: Duration) -> ClaimLink {
+        let now = Utc::now();
+        ClaimLink {
+            id: "link".to_string(),
+            creator_id: "creator".to_string(),
+            campaign_id: None,
+            public_key: "key".to_string(),
+            mint: None,
+            amount: 1,
+            state,
+            error: None,
+            funding_signature: None,
+            destination: None,
+            claimed_by: None,
+            claim_signature: None,
+            created_at: now,
+            expires_at: now + expires_in,
+            funded_at: None,
+            claimed_at: None,
+            refunded_at: None,
+        }
+    }
+
+    #[test]
+    fn test_status() {
+        let now = Utc::now();
+        assert_eq!(link(LinkState::Funded, Duration::hours(1)).status(now), "funded");
+        assert_eq!(link(LinkState::Funded, Duration::hours(-1)).status(now), "expired");
+        assert_eq!(link(LinkState::Refunding, Duration::hours(-1)).status(now), "expired");
+        assert_eq!(link(LinkState::Claimed, Duration::hours(-1)).status(now), "claimed");
+    }
+
+    #[test]
+    fn test_state_roundtrip() {
+        for state in [
+            LinkState::Unfunded,
+            LinkState::Funded,
+            LinkState::Claiming,
+            LinkState::Claimed,
+            LinkState::Refunding,
+            LinkState::Refunded,
+        ] {
+            assert_eq!(LinkState::parse(state.as_str()), Some(state));
+        }
+        assert_eq!(LinkState::parse("expired"), None);
+    }
+}
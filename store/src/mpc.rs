@@ -1,12 +1,13 @@
 use chrono::Utc;
-use crate::Store;
-use sqlx::Row;
+use crate::{crypto::{CryptoError, EncryptedShare, KeyRing, PLAINTEXT_KEY_VERSION}, Store};
+use sqlx::{PgPool, Row};
 
 #[derive(Debug)]
 pub enum MpcServerError {
     UserExists,
     InvalidInput(String),
     DatabaseError(String),
+    EncryptionError(CryptoError),
 }
 
 impl std::fmt::Display for MpcServerError {
@@ -15,6 +16,33 @@ impl std::fmt::Display for MpcServerError {
             MpcServerError::UserExists => write!(f, "User already exists"),
             MpcServerError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
             MpcServerError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
+            MpcServerError::EncryptionError(e) => write!(f, "Encryption error: {}", e),
+        }
+    }
+}
+
+/// How a wallet's key is split across the share servers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyScheme {
+    /// n-of-n, every share server holds an independent ed25519 key
+    Musig2,
+    /// t-of-n, every share server holds a FROST key share from a DKG
+    Frost,
+}
+
+impl KeyScheme {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyScheme::Musig2 => "musig2",
+            KeyScheme::Frost => "frost",
+        }
+    }
+
+    pub fn parse(scheme: &str) -> Option<Self> {
+        match scheme {
+            "musig2" => Some(KeyScheme::Musig2),
+            "frost" => Some(KeyScheme::Frost),
+            _ => None,
         }
     }
 }
@@ -28,119 +56,140 @@ pub struct StoredKeypair {
 pub struct GetKeyPairOutput {
     pub pub_key: String,
     pub secret_key: String,
+    pub scheme: KeyScheme,
 }
 
 impl Store {
-    pub async fn store_keypair_mpc_1(&self, public_key: &str, private_key: &str, user_id: &str) -> Result<StoredKeypair, MpcServerError> {
-        // Store the key pair in the MPC server databases
-        let created_at = Utc::now();
-        
-        let existing_user = sqlx::query(
-            "SELECT id FROM keyshares WHERE user_id = $1"
-        )
-        .bind(user_id)
-        .fetch_optional(&self.mpc_server_1)
-        .await
-        .map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
-
-        if existing_user.is_some() {
-            return Err(MpcServerError::UserExists);
-        }
-
-        // Store in MPC Server 1
-        sqlx::query(
-            "INSERT INTO keyshares (user_id, public_key, secret_key, created_at) VALUES ($1, $2, $3, $4)",
-        )
-        .bind(user_id)
-        .bind(public_key)
-        .bind(private_key)
-        .bind(created_at)
-        .execute(&self.mpc_server_1)
-        .await
-        .map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
-
-        Ok(StoredKeypair {
-            public_key: public_key.to_string(),
-        })
-        
+    /// For FROST wallets `public_key` is the group key and `private_key` the serialized key share.
+    pub async fn store_keypair(pool: &PgPool, keys: &KeyRing, scheme: KeyScheme, public_key: &str, private_key: &str, user_id: &str) -> Result<StoredKeypair, MpcServerError> {
+        store_keypair(pool, keys, scheme, public_key, private_key, user_id).await
     }
 
-    pub async fn store_keypair_mpc_2(&self, public_key: &str, private_key: &str, user_id: &str) -> Result<StoredKeypair, MpcServerError> {
-        // Store the key pair in the MPC server databases
-        let created_at = Utc::now();
+    pub async fn get_keypair(pool: &PgPool, keys: &KeyRing, user_id: &str) -> Result<GetKeyPairOutput, MpcServerError> {
+        get_keypair(pool, keys, user_id).await
+    }
 
-        // Store in MPC Server 2
-        let existing_user = sqlx::query(
-            "SELECT id FROM keyshares WHERE user_id = $1"
+    /// Bring every share of a server under its current master key: plaintext rows get encrypted and
+    /// rows of an older key version get their data key re-wrapped. Returns the number of updated rows.
+    pub async fn reencrypt_keyshares(pool: &PgPool, keys: &KeyRing) -> Result<u64, MpcServerError> {
+        let rows = sqlx::query(
+            "SELECT id, user_id, secret_key, wrapped_key, key_version FROM keyshares WHERE key_version <> $1"
         )
-        .bind(user_id)
-        .fetch_optional(&self.mpc_server_2)
+        .bind(keys.current_version())
+        .fetch_all(pool)
         .await
         .map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
 
-        if existing_user.is_some() {
-            return Err(MpcServerError::UserExists);
+        let mut updated = 0;
+        for row in rows {
+            let id: i32 = row.try_get("id").map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
+            let user_id: String = row.try_get("user_id").map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
+            let stored = stored_share(&row)?;
+
+            let share = match stored {
+                None => {
+                    let secret_key: String = row.try_get("secret_key").map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
+                    keys.encrypt(&user_id, secret_key.as_bytes())
+                }
+                Some(share) => keys.rewrap(&user_id, &share),
+            }
+            .map_err(MpcServerError::EncryptionError)?;
+
+            // Only overwrite the row if nobody rotated it in the meantime
+            let result = sqlx::query(
+                "UPDATE keyshares SET secret_key = $2, wrapped_key = $3, key_version = $4 WHERE id = $1 AND key_version = $5"
+            )
+            .bind(id)
+            .bind(&share.ciphertext)
+            .bind(&share.wrapped_key)
+            .bind(share.key_version)
+            .bind(row.try_get::<i32, _>("key_version").map_err(|e| MpcServerError::DatabaseError(e.to_string()))?)
+            .execute(pool)
+            .await
+            .map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
+            updated += result.rows_affected();
         }
-
-        // Store in MPC Server 1
-        sqlx::query(
-            "INSERT INTO keyshares (user_id, public_key, secret_key, created_at) VALUES ($1, $2, $3, $4)",
-        )
-        .bind(user_id)
-        .bind(public_key)
-        .bind(private_key)
-        .bind(created_at)
-        .execute(&self.mpc_server_2)
-        .await
-        .map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
-
-        Ok(StoredKeypair {
-            public_key: public_key.to_string(),
-        })
-        
+        Ok(updated)
     }
+}
 
-    pub async fn get_keypair_mpc_1(&self, user_id: &str) -> Result<GetKeyPairOutput, MpcServerError> {
-        let rows = sqlx::query(
-            "SELECT public_key, secret_key FROM keyshares WHERE user_id = $1"
-        )
-        .bind(user_id)
-        .fetch_all(&self.mpc_server_1)
-        .await
-        .map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
+async fn store_keypair(pool: &PgPool, keys: &KeyRing, scheme: KeyScheme, public_key: &str, private_key: &str, user_id: &str) -> Result<StoredKeypair, MpcServerError> {
+    let created_at = Utc::now();
 
-        if rows.is_empty() {
-            return Err(MpcServerError::InvalidInput("User ID not found".to_string()));
-        }
-        let row = &rows[0];
-        let public_key: String = row.try_get("public_key").map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
-        let secret_key: String = row.try_get("secret_key").map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;   
-        Ok(GetKeyPairOutput {
-            pub_key: public_key,
-            secret_key,
-        })
+    let existing_user = sqlx::query(
+        "SELECT id FROM keyshares WHERE user_id = $1"
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
 
+    if existing_user.is_some() {
+        return Err(MpcServerError::UserExists);
     }
 
-    pub async fn get_keypair_mpc_2(&self, user_id: &str) -> Result<GetKeyPairOutput, MpcServerError> {
-        let rows = sqlx::query(
-            "SELECT public_key, secret_key FROM keyshares WHERE user_id = $1"
-        )
-        .bind(user_id)
-        .fetch_all(&self.mpc_server_2)
-        .await
-        .map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
+    let share = keys.encrypt(user_id, private_key.as_bytes()).map_err(MpcServerError::EncryptionError)?;
+    sqlx::query(
+        "INSERT INTO keyshares (user_id, public_key, secret_key, wrapped_key, key_version, scheme, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(user_id)
+    .bind(public_key)
+    .bind(&share.ciphertext)
+    .bind(&share.wrapped_key)
+    .bind(share.key_version)
+    .bind(scheme.as_str())
+    .bind(created_at)
+    .execute(pool)
+    .await
+    .map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
+
+    Ok(StoredKeypair {
+        public_key: public_key.to_string(),
+    })
+}
 
-        if rows.is_empty() {
-            return Err(MpcServerError::InvalidInput("User ID not found".to_string()));
+async fn get_keypair(pool: &PgPool, keys: &KeyRing, user_id: &str) -> Result<GetKeyPairOutput, MpcServerError> {
+    let rows = sqlx::query(
+        "SELECT public_key, secret_key, wrapped_key, key_version, scheme FROM keyshares WHERE user_id = $1"
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
+
+    if rows.is_empty() {
+        return Err(MpcServerError::InvalidInput("User ID not found".to_string()));
+    }
+    let row = &rows[0];
+    let public_key: String = row.try_get("public_key").map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
+    let scheme: String = row.try_get("scheme").map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
+    let scheme = KeyScheme::parse(&scheme)
+        .ok_or_else(|| MpcServerError::DatabaseError(format!("Unknown key scheme {}", scheme)))?;
+    let secret_key = match stored_share(row)? {
+        Some(share) => {
+            let secret = keys.decrypt(user_id, &share).map_err(MpcServerError::EncryptionError)?;
+            String::from_utf8(secret).map_err(|_| MpcServerError::EncryptionError(CryptoError::InvalidCiphertext))?
         }
-        let row = &rows[0];
-        let public_key: String = row.try_get("public_key").map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
-        let secret_key: String = row.try_get("secret_key").map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;   
-        Ok(GetKeyPairOutput {
-            pub_key: public_key,
-            secret_key,
-        })
+        // Written before encryption at rest, `reencrypt_keyshares` takes care of these
+        None => row.try_get("secret_key").map_err(|e| MpcServerError::DatabaseError(e.to_string()))?,
+    };
+    Ok(GetKeyPairOutput {
+        pub_key: public_key,
+        secret_key,
+        scheme,
+    })
+}
 
+/// The encrypted share of a row, or `None` for rows still stored in plaintext.
+fn stored_share(row: &sqlx::postgres::PgRow) -> Result<Option<EncryptedShare>, MpcServerError> {
+    let key_version: i32 = row.try_get("key_version").map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
+    if key_version == PLAINTEXT_KEY_VERSION {
+        return Ok(None);
     }
+    let wrapped_key: Option<String> = row.try_get("wrapped_key").map_err(|e| MpcServerError::DatabaseError(e.to_string()))?;
+    Ok(Some(EncryptedShare {
+        ciphertext: row.try_get("secret_key").map_err(|e| MpcServerError::DatabaseError(e.to_string()))?,
+        wrapped_key: wrapped_key.ok_or(MpcServerError::EncryptionError(CryptoError::InvalidCiphertext))?,
+        key_version,
+    }))
 }
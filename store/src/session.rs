@@ -0,0 +1,260 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{PgPool, Row};
+
+use crate::Store;
+
+/// How long a party keeps its nonces around waiting for step two.
+pub const SESSION_TTL_SECONDS: i64 = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    NoncesCommitted,
+    PartiallySigned,
+    Aggregated,
+    Expired,
+}
+
+impl SessionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionState::NoncesCommitted => "nonces_committed",
+            SessionState::PartiallySigned => "partially_signed",
+            SessionState::Aggregated => "aggregated",
+            SessionState::Expired => "expired",
+        }
+    }
+
+    pub fn parse(state: &str) -> Option<Self> {
+        match state {
+            "nonces_committed" => Some(SessionState::NoncesCommitted),
+            "partially_signed" => Some(SessionState::PartiallySigned),
+            "aggregated" => Some(SessionState::Aggregated),
+            "expired" => Some(SessionState::Expired),
+            _ => None,
+        }
+    }
+
+    /// Sessions only ever move forward, nonces are committed once and used at most once.
+    pub fn can_transition_to(&self, next: SessionState) -> bool {
+        matches!(
+            (self, next),
+            (SessionState::NoncesCommitted, SessionState::PartiallySigned)
+                | (SessionState::NoncesCommitted, SessionState::Expired)
+                | (SessionState::PartiallySigned, SessionState::Aggregated)
+                | (SessionState::PartiallySigned, SessionState::Expired)
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SigningSession {
+    pub id: String,
+    pub user_id: String,
+    pub participants: Vec<String>,
+    pub message_hash: Option<String>,
+    pub state: SessionState,
+    pub signature: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    SessionExists,
+    NotFound,
+    WrongUser,
+    Expired,
+    /// The nonces of this session were already used to sign, signing again would leak the key share.
+    NonceReused,
+    InvalidTransition { from: SessionState, to: SessionState },
+    MessageMismatch,
+    DatabaseError(String),
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionError::SessionExists => write!(f, "Signing session already exists"),
+            SessionError::NotFound => write!(f, "Signing session not found"),
+            SessionError::WrongUser => write!(f, "Signing session belongs to a different user"),
+            SessionError::Expired => write!(f, "Signing session expired"),
+            SessionError::NonceReused => write!(f, "Nonces of this signing session were already used"),
+            SessionError::InvalidTransition { from, to } => {
+                write!(f, "Signing session cannot move from {} to {}", from.as_str(), to.as_str())
+            }
+            SessionError::MessageMismatch => write!(f, "Signing session was used for a different message"),
+            SessionError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+fn session_from_row(row: &sqlx::postgres::PgRow) -> Result<SigningSession, SessionError> {
+    let get_err = |e: sqlx::Error| SessionError::DatabaseError(e.to_string());
+    let state: String = row.try_get("state").map_err(get_err)?;
+    Ok(SigningSession {
+        id: row.try_get("id").map_err(get_err)?,
+        user_id: row.try_get("user_id").map_err(get_err)?,
+        participants: row.try_get("participants").map_err(get_err)?,
+        message_hash: row.try_get("message_hash").map_err(get_err)?,
+        state: SessionState::parse(&state)
+            .ok_or_else(|| SessionError::DatabaseError(format!("Unknown session state {}", state)))?,
+        signature: row.try_get("signature").map_err(get_err)?,
+        created_at: row.try_get("created_at").map_err(get_err)?,
+        expires_at: row.try_get("expires_at").map_err(get_err)?,
+    })
+}
+
+impl Store {
+    /// Record the private nonces of a freshly started session.
+    pub async fn commit_nonces(pool: &PgPool, session_id: &str, user_id: &str, participants: &[String], secret_nonces: &str) -> Result<SigningSession, SessionError> {
+        let created_at = Utc::now();
+        let expires_at = created_at + Duration::seconds(SESSION_TTL_SECONDS);
+
+        let row = sqlx::query(
+            "INSERT INTO signing_sessions (id, user_id, participants, state, secret_nonces, created_at, updated_at, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $6, $7)
+             ON CONFLICT (id) DO NOTHING
+             RETURNING id, user_id, participants, message_hash, state, signature, created_at, expires_at",
+        )
+        .bind(session_id)
+        .bind(user_id)
+        .bind(participants)
+        .bind(SessionState::NoncesCommitted.as_str())
+        .bind(secret_nonces)
+        .bind(created_at)
+        .bind(expires_at)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| SessionError::DatabaseError(e.to_string()))?;
+
+        match row {
+            Some(row) => session_from_row(&row),
+            None => Err(SessionError::SessionExists),
+        }
+    }
+
+    /// Atomically take the private nonces out of a session and move it to `PartiallySigned`.
+    /// The nonces are wiped in the same statement, so a second call can never get them back.
+    pub async fn consume_nonces(pool: &PgPool, session_id: &str, user_id: &str, message_hash: &str) -> Result<String, SessionError> {
+        let row = sqlx::query(
+            "UPDATE signing_sessions s
+             SET state = $4, secret_nonces = NULL, message_hash = $3, updated_at = NOW()
+             FROM (SELECT id, secret_nonces FROM signing_sessions WHERE id = $1 FOR UPDATE) old
+             WHERE s.id = old.id AND s.user_id = $2 AND s.state = $5 AND s.expires_at > NOW()
+             RETURNING old.secret_nonces",
+        )
+        .bind(session_id)
+        .bind(user_id)
+        .bind(message_hash)
+        .bind(SessionState::PartiallySigned.as_str())
+        .bind(SessionState::NoncesCommitted.as_str())
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| SessionError::DatabaseError(e.to_string()))?;
+
+        if let Some(row) = row {
+            let secret_nonces: Option<String> = row.try_get(0).map_err(|e| SessionError::DatabaseError(e.to_string()))?;
+            return secret_nonces.ok_or(SessionError::NonceReused);
+        }
+
+        // Nothing was updated, find out why
+        let session = Self::get_signing_session(pool, session_id).await?;
+        if session.user_id != user_id {
+            return Err(SessionError::WrongUser);
+        }
+        match session.state {
+            SessionState::PartiallySigned | SessionState::Aggregated => Err(SessionError::NonceReused),
+            SessionState::Expired => Err(SessionError::Expired),
+            SessionState::NoncesCommitted => Err(SessionError::Expired),
+        }
+    }
+
+    /// Mark a partially signed session as done once the coordinator aggregated the signatures.
+    pub async fn mark_session_aggregated(pool: &PgPool, session_id: &str, user_id: &str, message_hash: &str, signature: &str) -> Result<(), SessionError> {
+        let session = Self::get_signing_session(pool, session_id).await?;
+        if session.user_id != user_id {
+            return Err(SessionError::WrongUser);
+        }
+        if !session.state.can_transition_to(SessionState::Aggregated) {
+            return Err(SessionError::InvalidTransition { from: session.state, to: SessionState::Aggregated });
+        }
+        if session.message_hash.as_deref() != Some(message_hash) {
+            return Err(SessionError::MessageMismatch);
+        }
+
+        sqlx::query(
+            "UPDATE signing_sessions SET state = $2, signature = $3, updated_at = NOW() WHERE id = $1 AND state = $4",
+        )
+        .bind(session_id)
+        .bind(SessionState::Aggregated.as_str())
+        .bind(signature)
+        .bind(SessionState::PartiallySigned.as_str())
+        .execute(pool)
+        .await
+        .map_err(|e| SessionError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    pub async fn get_signing_session(pool: &PgPool, session_id: &str) -> Result<SigningSession, SessionError> {
+        let row = sqlx::query(
+            "SELECT id, user_id, participants, message_hash, state, signature, created_at, expires_at FROM signing_sessions WHERE id = $1",
+        )
+        .bind(session_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| SessionError::DatabaseError(e.to_string()))?;
+
+        match row {
+            Some(row) => session_from_row(&row),
+            None => Err(SessionError::NotFound),
+        }
+    }
+
+    /// Expire sessions past their deadline and drop any nonces they still hold.
+    pub async fn expire_signing_sessions(pool: &PgPool) -> Result<u64, SessionError> {
+        let result = sqlx::query(
+            "UPDATE signing_sessions SET state = $1, secret_nonces = NULL, updated_at = NOW()
+             WHERE expires_at <= NOW() AND state IN ($2, $3)",
+        )
+        .bind(SessionState::Expired.as_str())
+        .bind(SessionState::NoncesCommitted.as_str())
+        .bind(SessionState::PartiallySigned.as_str())
+        .execute(pool)
+        .await
+        .map_err(|e| SessionError::DatabaseError(e.to_string()))?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionState;
+
+    const ALL: [SessionState; 4] = [
+        SessionState::NoncesCommitted,
+        SessionState::PartiallySigned,
+        SessionState::Aggregated,
+        SessionState::Expired,
+    ];
+
+    #[test]
+    fn test_state_roundtrip() {
+        for state in ALL {
+            assert_eq!(SessionState::parse(state.as_str()), Some(state));
+        }
+        assert_eq!(SessionState::parse("signed"), None);
+    }
+
+    #[test]
+    fn test_nonces_are_used_once() {
+        assert!(SessionState::NoncesCommitted.can_transition_to(SessionState::PartiallySigned));
+        assert!(!SessionState::PartiallySigned.can_transition_to(SessionState::NoncesCommitted));
+        for state in ALL {
+            assert!(!state.can_transition_to(state));
+            assert!(!SessionState::Aggregated.can_transition_to(state));
+            assert!(!SessionState::Expired.can_transition_to(state));
+        }
+    }
+}
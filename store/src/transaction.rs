@@ -0,0 +1,288 @@
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+use crate::Store;
+
+/// What was signed. Link sweeps are signed by the link's wallet, they are recorded for the
+/// user the funds go to or come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionKind {
+    Swap,
+    TokenTransfer,
+    /// Creator's wallet to a claim link's
+    LinkFunding,
+    /// Creator's wallet to a batch of a campaign's links
+    CampaignFunding,
+    /// Link's wallet to whoever claimed it
+    LinkClaim,
+    /// Expired link's wallet back to its creator
+    LinkRefund,
+    /// The wallet's durable nonce account
+    NonceCreation,
+}
+
+impl TransactionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionKind::Swap => "swap",
+            TransactionKind::TokenTransfer => "token_transfer",
+            TransactionKind::LinkFunding => "link_funding",
+            TransactionKind::CampaignFunding => "campaign_funding",
+            TransactionKind::LinkClaim => "link_claim",
+            TransactionKind::LinkRefund => "link_refund",
+            TransactionKind::NonceCreation => "nonce_creation",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "swap" => Some(TransactionKind::Swap),
+            "token_transfer" => Some(TransactionKind::TokenTransfer),
+            "link_funding" => Some(TransactionKind::LinkFunding),
+            "campaign_funding" => Some(TransactionKind::CampaignFunding),
+            "link_claim" => Some(TransactionKind::LinkClaim),
+            "link_refund" => Some(TransactionKind::LinkRefund),
+            "nonce_creation" => Some(TransactionKind::NonceCreation),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionState {
+    /// The message is being put together, nothing was signed yet
+    Building,
+    /// The share servers are running the signing rounds
+    Signing,
+    /// Sent to the cluster, not confirmed yet
+    Submitted,
+    Confirmed,
+    Finalized,
+    Failed,
+    /// Never landed before its blockhash expired or its durable nonce advanced
+    Expired,
+}
+
+impl TransactionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionState::Building => "building",
+            TransactionState::Signing => "signing",
+            TransactionState::Submitted => "submitted",
+            TransactionState::Confirmed => "confirmed",
+            TransactionState::Finalized => "finalized",
+            TransactionState::Failed => "failed",
+            TransactionState::Expired => "expired",
+        }
+    }
+
+    pub fn parse(state: &str) -> Option<Self> {
+        match state {
+            "building" => Some(TransactionState::Building),
+            "signing" => Some(TransactionState::Signing),
+            "submitted" => Some(TransactionState::Submitted),
+            "confirmed" => Some(TransactionState::Confirmed),
+            "finalized" => Some(TransactionState::Finalized),
+            "failed" => Some(TransactionState::Failed),
+            "expired" => Some(TransactionState::Expired),
+            _ => None,
+        }
+    }
+
+    /// Nothing changes a transaction in a final state anymore.
+    pub fn is_final(&self) -> bool {
+        matches!(self, TransactionState::Finalized | TransactionState::Failed | TransactionState::Expired)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutgoingTransaction {
+    pub id: String,
+    pub user_id: String,
+    pub kind: TransactionKind,
+    pub state: TransactionState,
+    pub signature: Option<String>,
+    pub fee_lamports: Option<u64>,
+    /// Set if the transaction was signed over the wallet's durable nonce instead of a blockhash
+    pub durable_nonce: Option<String>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// When the state last changed
+    pub updated_at: DateTime<Utc>,
+}
+
+/// What the coordinator returned for a transaction it landed.
+pub struct SubmittedTransaction<'a> {
+    pub signature: &'a str,
+    /// `Submitted`, `Confirmed` or `Finalized`
+    pub state: TransactionState,
+    pub fee_lamports: u64,
+    pub durable_nonce: Option<&'a str>,
+}
+
+#[derive(Debug)]
+pub enum TransactionError {
+    NotFound,
+    DatabaseError(String),
+}
+
+impl std::fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionError::NotFound => write!(f, "Transaction not found"),
+            TransactionError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+fn transaction_from_row(row: &sqlx::postgres::PgRow) -> Result<OutgoingTransaction, TransactionError> {
+    let get_err = |e: sqlx::Error| TransactionError::DatabaseError(e.to_string());
+    let kind: String = row.try_get("kind").map_err(get_err)?;
+    let state: String = row.try_get("state").map_err(get_err)?;
+    let fee_lamports: Option<i64> = row.try_get("fee_lamports").map_err(get_err)?;
+    Ok(OutgoingTransaction {
+        id: row.try_get("id").map_err(get_err)?,
+        user_id: row.try_get("user_id").map_err(get_err)?,
+        kind: TransactionKind::parse(&kind).ok_or_else(|| TransactionError::DatabaseError(format!("Unknown transaction kind {}", kind)))?,
+        state: TransactionState::parse(&state).ok_or_else(|| TransactionError::DatabaseError(format!("Unknown transaction state {}", state)))?,
+        signature: row.try_get("signature").map_err(get_err)?,
+        fee_lamports: fee_lamports.map(|fee| fee as u64),
+        durable_nonce: row.try_get("durable_nonce").map_err(get_err)?,
+        error: row.try_get("error").map_err(get_err)?,
+        created_at: row.try_get("created_at").map_err(get_err)?,
+        updated_at: row.try_get("updated_at").map_err(get_err)?,
+    })
+}
+
+const TRANSACTION_COLUMNS: &str = "id, user_id, kind, state, signature, fee_lamports, durable_nonce, error, created_at, updated_at";
+
+impl Store {
+    pub async fn create_transaction(&self, user_id: &str, kind: TransactionKind) -> Result<OutgoingTransaction, TransactionError> {
+        let now = Utc::now();
+        let row = sqlx::query(&format!(
+            "INSERT INTO transactions (id, user_id, kind, state, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $5) RETURNING {}",
+            TRANSACTION_COLUMNS
+        ))
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(user_id)
+        .bind(kind.as_str())
+        .bind(TransactionState::Building.as_str())
+        .bind(now)
+        .fetch_one(&self.backend)
+        .await
+        .map_err(|e| TransactionError::DatabaseError(e.to_string()))?;
+        transaction_from_row(&row)
+    }
+
+    /// Only the user who signed a transaction can look it up.
+    pub async fn get_transaction_by_signature(&self, user_id: &str, signature: &str) -> Result<OutgoingTransaction, TransactionError> {
+        let row = sqlx::query(&format!("SELECT {} FROM transactions WHERE signature = $1 AND user_id = $2", TRANSACTION_COLUMNS))
+            .bind(signature)
+            .bind(user_id)
+            .fetch_optional(&self.backend)
+            .await
+            .map_err(|e| TransactionError::DatabaseError(e.to_string()))?;
+        match row {
+            Some(row) => transaction_from_row(&row),
+            None => Err(TransactionError::NotFound),
+        }
+    }
+
+    /// The message was handed to the coordinator.
+    pub async fn mark_transaction_signing(&self, transaction_id: &str) -> Result<(), TransactionError> {
+        self.update_transaction_state(transaction_id, TransactionState::Signing, None).await
+    }
+
+    /// Record what the coordinator returned, `Err` holds why signing or landing failed.
+    pub async fn finish_transaction_submission(&self, transaction_id: &str, result: Result<SubmittedTransaction<'_>, &str>) -> Result<(), TransactionError> {
+        let query = match result {
+            Ok(submitted) => sqlx::query(
+                "UPDATE transactions SET state = $2, signature = $3, fee_lamports = $4, durable_nonce = $5, error = NULL, updated_at = NOW()
+                 WHERE id = $1",
+            )
+            .bind(transaction_id)
+            .bind(submitted.state.as_str())
+            .bind(submitted.signature)
+            .bind(submitted.fee_lamports as i64)
+            .bind(submitted.durable_nonce),
+            Err(error) => sqlx::query("UPDATE transactions SET state = $2, error = $3, updated_at = NOW() WHERE id = $1")
+                .bind(transaction_id)
+                .bind(TransactionState::Failed.as_str())
+                .bind(error),
+        };
+        query
+            .execute(&self.backend)
+            .await
+            .map_err(|e| TransactionError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Move a transaction along, a transaction in a final state stays as it is.
+    pub async fn update_transaction_state(&self, transaction_id: &str, state: TransactionState, error: Option<&str>) -> Result<(), TransactionError> {
+        sqlx::query(
+            "UPDATE transactions SET state = $2, error = COALESCE($3, error), updated_at = NOW()
+             WHERE id = $1 AND state NOT IN ($4, $5, $6) AND state <> $2",
+        )
+        .bind(transaction_id)
+        .bind(state.as_str())
+        .bind(error)
+        .bind(TransactionState::Finalized.as_str())
+        .bind(TransactionState::Failed.as_str())
+        .bind(TransactionState::Expired.as_str())
+        .execute(&self.backend)
+        .await
+        .map_err(|e| TransactionError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Up to `limit` transactions that haven't reached a final state, the longest unchanged first.
+    pub async fn list_in_flight_transactions(&self, limit: i64) -> Result<Vec<OutgoingTransaction>, TransactionError> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM transactions WHERE state IN ($1, $2, $3, $4) ORDER BY updated_at LIMIT $5",
+            TRANSACTION_COLUMNS
+        ))
+        .bind(TransactionState::Building.as_str())
+        .bind(TransactionState::Signing.as_str())
+        .bind(TransactionState::Submitted.as_str())
+        .bind(TransactionState::Confirmed.as_str())
+        .bind(limit)
+        .fetch_all(&self.backend)
+        .await
+        .map_err(|e| TransactionError::DatabaseError(e.to_string()))?;
+        rows.iter().map(transaction_from_row).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TransactionKind, TransactionState};
+
+    #[test]
+    fn test_state_roundtrip() {
+        for state in [
+            TransactionState::Building,
+            TransactionState::Signing,
+            TransactionState::Submitted,
+            TransactionState::Confirmed,
+            TransactionState::Finalized,
+            TransactionState::Failed,
+            TransactionState::Expired,
+        ] {
+            assert_eq!(TransactionState::parse(state.as_str()), Some(state));
+        }
+        assert_eq!(TransactionState::parse("pending"), None);
+        for kind in [
+            TransactionKind::Swap,
+            TransactionKind::TokenTransfer,
+            TransactionKind::LinkFunding,
+            TransactionKind::CampaignFunding,
+            TransactionKind::LinkClaim,
+            TransactionKind::LinkRefund,
+            TransactionKind::NonceCreation,
+        ] {
+            assert_eq!(TransactionKind::parse(kind.as_str()), Some(kind));
+        }
+    }
+}
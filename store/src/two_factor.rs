@@ -0,0 +1,147 @@
+use sqlx::Row;
+
+use crate::{user_session::hash_token, Store};
+
+/// A user's TOTP enrollment. The secret is stored as soon as enrollment starts, it is only
+/// enforced once the user confirmed a code from it.
+#[derive(Debug, Clone)]
+pub struct TwoFactor {
+    /// Base32 TOTP secret
+    pub secret: String,
+    pub enabled: bool,
+}
+
+#[derive(Debug)]
+pub enum TwoFactorError {
+    AlreadyEnabled,
+    NotEnrolled,
+    DatabaseError(String),
+}
+
+impl std::fmt::Display for TwoFactorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TwoFactorError::AlreadyEnabled => write!(f, "Two-factor authentication is already enabled"),
+            TwoFactorError::NotEnrolled => write!(f, "Two-factor authentication is not set up"),
+            TwoFactorError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for TwoFactorError {}
+
+impl Store {
+    /// `None` if the user never started enrolling.
+    pub async fn get_two_factor(&self, user_id: &str) -> Result<Option<TwoFactor>, TwoFactorError> {
+        let row = sqlx::query("SELECT totp_secret, totp_enabled_at IS NOT NULL AS enabled FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.backend)
+            .await
+            .map_err(|e| TwoFactorError::DatabaseError(e.to_string()))?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let get_err = |e: sqlx::Error| TwoFactorError::DatabaseError(e.to_string());
+        let secret: Option<String> = row.try_get("totp_secret").map_err(get_err)?;
+        Ok(match secret {
+            Some(secret) => Some(TwoFactor { secret, enabled: row.try_get("enabled").map_err(get_err)? }),
+            None => None,
+        })
+    }
+
+    /// Store a new secret for a user who doesn't have two-factor authentication enabled yet,
+    /// replacing any unconfirmed one.
+    pub async fn start_totp_enrollment(&self, user_id: &str, secret: &str) -> Result<(), TwoFactorError> {
+        let result = sqlx::query(
+            "UPDATE users SET totp_secret = $2, totp_last_step = NULL, updated_at = NOW() WHERE id = $1 AND totp_enabled_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(secret)
+        .execute(&self.backend)
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(e.to_string()))?;
+        if result.rows_affected() == 0 {
+            return Err(TwoFactorError::AlreadyEnabled);
+        }
+        Ok(())
+    }
+
+    /// Start enforcing the enrolled secret. `step` is the time step of the code the user confirmed
+    /// with, `recovery_codes` replace any earlier ones.
+    pub async fn enable_totp(&self, user_id: &str, step: i64, recovery_codes: &[String]) -> Result<(), TwoFactorError> {
+        let mut tx = self.backend.begin().await.map_err(|e| TwoFactorError::DatabaseError(e.to_string()))?;
+        let result = sqlx::query(
+            "UPDATE users SET totp_enabled_at = NOW(), totp_last_step = $2, updated_at = NOW()
+             WHERE id = $1 AND totp_secret IS NOT NULL AND totp_enabled_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(step)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(e.to_string()))?;
+        if result.rows_affected() == 0 {
+            return Err(TwoFactorError::NotEnrolled);
+        }
+
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| TwoFactorError::DatabaseError(e.to_string()))?;
+        for code in recovery_codes {
+            sqlx::query("INSERT INTO recovery_codes (id, user_id, code_hash, created_at) VALUES ($1, $2, $3, NOW())")
+                .bind(uuid::Uuid::new_v4().to_string())
+                .bind(user_id)
+                .bind(hash_token(code))
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| TwoFactorError::DatabaseError(e.to_string()))?;
+        }
+
+        tx.commit().await.map_err(|e| TwoFactorError::DatabaseError(e.to_string()))
+    }
+
+    /// Record that a code of `step` was used. Returns false if this or a later step was already
+    /// used, the code must then be rejected.
+    pub async fn accept_totp_step(&self, user_id: &str, step: i64) -> Result<bool, TwoFactorError> {
+        let result = sqlx::query(
+            "UPDATE users SET totp_last_step = $2 WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)",
+        )
+        .bind(user_id)
+        .bind(step)
+        .execute(&self.backend)
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(e.to_string()))?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Burn a recovery code. Returns false if it doesn't exist or was already used.
+    pub async fn use_recovery_code(&self, user_id: &str, code: &str) -> Result<bool, TwoFactorError> {
+        let result = sqlx::query(
+            "UPDATE recovery_codes SET used_at = NOW() WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(hash_token(code))
+        .execute(&self.backend)
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(e.to_string()))?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn disable_totp(&self, user_id: &str) -> Result<(), TwoFactorError> {
+        let mut tx = self.backend.begin().await.map_err(|e| TwoFactorError::DatabaseError(e.to_string()))?;
+        sqlx::query(
+            "UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL, updated_at = NOW() WHERE id = $1",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(e.to_string()))?;
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| TwoFactorError::DatabaseError(e.to_string()))?;
+        tx.commit().await.map_err(|e| TwoFactorError::DatabaseError(e.to_string()))
+    }
+}
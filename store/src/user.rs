@@ -1,5 +1,9 @@
 use crate::Store;
 use chrono::{Utc};
+use sqlx::Row;
+
+/// The mint SOL balances are indexed under.
+pub const NATIVE_MINT: &str = "So11111111111111111111111111111111111111112";
 
 #[derive(Debug, Clone)]
 pub struct User {
@@ -36,17 +40,34 @@ impl std::fmt::Display for UserError {
 
 impl std::error::Error for UserError {}
 
+/// A syntax check only, whether the address exists is settled by the verification mail.
+pub fn is_valid_email(email: &str) -> bool {
+    if email.len() > 254 || email.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return false;
+    }
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && local.len() <= 64
+        && !local.contains('@')
+        && domain.contains('.')
+        && domain.split('.').all(|label| !label.is_empty() && !label.starts_with('-') && !label.ends_with('-'))
+}
+
+pub fn validate_password(password: &str) -> Result<(), UserError> {
+    if password.len() < 6 {
+        return Err(UserError::InvalidInput("Password must be at least 6 characters".to_string()));
+    }
+    Ok(())
+}
+
 impl Store {
     pub async fn create_user(&self, request: CreateUserRequest) -> Result<User, UserError> {
-        // Validate email format
-        if !request.email.contains('@') {
+        if !is_valid_email(&request.email) {
             return Err(UserError::InvalidInput("Invalid email format".to_string()));
         }
-
-        // Validate password length
-        if request.password.len() < 6 {
-            return Err(UserError::InvalidInput("Password must be at least 6 characters".to_string()));
-        }
+        validate_password(&request.password)?;
 
         // Check if user already exists
         let existing_user = sqlx::query!(
@@ -69,7 +90,7 @@ impl Store {
         let created_at = Utc::now();
         let pub_key = request.pub_key.clone();
 
-        // Insert user into database
+        // Insert user into database, a trigger notifies the indexer of the new wallet
         sqlx::query!(
             "INSERT INTO users (id, email, password, created_at, updated_at, public_key) VALUES ($1, $2, $3, $4, $5, $6)",
             user_id,
@@ -128,6 +149,75 @@ impl Store {
         Ok(user)
     }
 
+    /// Re-authenticate a signed in user before a sensitive operation.
+    pub async fn verify_password(&self, user_id: String, password: String) -> Result<(), UserError> {
+        let record = sqlx::query!(
+            "SELECT password FROM users WHERE id = $1",
+            user_id
+        )
+        .fetch_optional(&self.backend)
+        .await
+        .map_err(|e| UserError::DatabaseError(e.to_string()))?;
+
+        let record = match record {
+            Some(rec) => rec,
+            None => return Err(UserError::InvalidInput("User not found".to_string())),
+        };
+
+        let is_valid = bcrypt::verify(&password, &record.password)
+            .map_err(|e| UserError::DatabaseError(format!("Password verification failed: {}", e)))?;
+
+        if !is_valid {
+            return Err(UserError::InvalidInput("Invalid password".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Set a new password. Every session of the user ends, including the one that made the change.
+    pub async fn change_password(&self, user_id: &str, password: &str) -> Result<(), UserError> {
+        validate_password(password)?;
+        let password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST)
+            .map_err(|e| UserError::DatabaseError(format!("Password hashing failed: {}", e)))?;
+
+        let mut tx = self.backend.begin().await.map_err(|e| UserError::DatabaseError(e.to_string()))?;
+        let result = sqlx::query("UPDATE users SET password = $2, updated_at = NOW() WHERE id = $1")
+            .bind(user_id)
+            .bind(password_hash)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| UserError::DatabaseError(e.to_string()))?;
+        if result.rows_affected() == 0 {
+            return Err(UserError::InvalidInput("User not found".to_string()));
+        }
+        sqlx::query(
+            "WITH revoked AS (
+                 UPDATE user_sessions SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL
+                 RETURNING access_jti, access_expires_at
+             )
+             INSERT INTO revoked_tokens (jti, expires_at) SELECT access_jti, access_expires_at FROM revoked
+             ON CONFLICT (jti) DO NOTHING",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| UserError::DatabaseError(e.to_string()))?;
+        tx.commit().await.map_err(|e| UserError::DatabaseError(e.to_string()))
+    }
+
+    /// `None` if nobody signed up with `email`.
+    pub async fn find_user_id_by_email(&self, email: &str) -> Result<Option<String>, UserError> {
+        let row = sqlx::query("SELECT id FROM users WHERE email = $1")
+            .bind(email)
+            .fetch_optional(&self.backend)
+            .await
+            .map_err(|e| UserError::DatabaseError(e.to_string()))?;
+        match row {
+            Some(row) => row.try_get("id").map(Some).map_err(|e| UserError::DatabaseError(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
     pub async fn get_user_by_id(&self, user_id: String) -> Result<User, UserError> {
         let record = sqlx::query!(
             "SELECT id, email, created_at, public_key FROM users WHERE id = $1",
@@ -152,6 +242,7 @@ impl Store {
         Ok(user)
     }
 
+    /// As last seen by the indexer, 0 for a wallet it hasn't seen yet.
     pub async fn get_sol_balance(&self, pub_key: String) -> Result<u64, UserError> {
         let user = sqlx::query!(
             "SELECT id FROM users WHERE public_key = $1",
@@ -166,8 +257,8 @@ impl Store {
         }
 
         let asset = sqlx::query!(
-            "SELECT mint_address, id FROM assets WHERE mint_address = $1 AND name = 'SOL'",
-            "123"
+            "SELECT mint_address, id FROM assets WHERE mint_address = $1",
+            NATIVE_MINT
         )
         .fetch_optional(&self.backend)
         .await
@@ -178,7 +269,7 @@ impl Store {
         }
 
         let balance = sqlx::query!(
-            "SELECT amount::BIGINT as amount FROM balances WHERE user_id = $1 AND asset_id = $2",
+            "SELECT SUM(amount)::BIGINT as amount FROM balances WHERE user_id = $1 AND asset_id = $2",
             user.unwrap().id,
             asset.unwrap().id
         )
@@ -195,6 +286,7 @@ impl Store {
         }
     }
 
+    /// Summed over all of the wallet's token accounts for `mint_address`.
     pub async fn get_token_balance(&self, pub_key: String, mint_address: String) -> Result<u64, UserError> {
         let user = sqlx::query!(
             "SELECT id FROM users WHERE public_key = $1",
@@ -221,7 +313,7 @@ impl Store {
         }
 
         let balance = sqlx::query!(
-            "SELECT amount::BIGINT as amount FROM balances WHERE user_id = $1 AND asset_id = $2",
+            "SELECT SUM(amount)::BIGINT as amount FROM balances WHERE user_id = $1 AND asset_id = $2",
             user.unwrap().id,
             asset.unwrap().id
         )
@@ -238,3 +330,18 @@ impl Store {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::is_valid_email;
+
+    #[test]
+    fn test_is_valid_email() {
+        for email in ["alice@example.com", "a.b+wallet@mail.example.org"] {
+            assert!(is_valid_email(email), "{}", email);
+        }
+        for email in ["", "alice", "@example.com", "alice@", "alice@localhost", "alice@@example.com", "al ice@example.com", "alice@example..com", "alice@-example.com"] {
+            assert!(!is_valid_email(email), "{}", email);
+        }
+    }
+}
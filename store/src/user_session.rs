@@ -0,0 +1,229 @@
+use base64::engine::{general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::Row;
+
+use crate::Store;
+
+/// A signed in device. Access tokens are short lived, the refresh token keeps the session going.
+#[derive(Debug, Clone)]
+pub struct UserSession {
+    pub id: String,
+    pub user_id: String,
+    pub access_jti: String,
+    pub access_expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// The access token handed out together with a refresh token.
+pub struct AccessGrant<'a> {
+    pub jti: &'a str,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum UserSessionError {
+    NotFound,
+    InvalidToken,
+    /// A refresh token that was already rotated out came back, the session has been revoked
+    TokenReused,
+    DatabaseError(String),
+}
+
+impl std::fmt::Display for UserSessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserSessionError::NotFound => write!(f, "Session not found"),
+            UserSessionError::InvalidToken => write!(f, "Invalid or expired refresh token"),
+            UserSessionError::TokenReused => write!(f, "Refresh token was already used, the session has been revoked"),
+            UserSessionError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for UserSessionError {}
+
+/// Refresh tokens and recovery codes are random, a plain hash is enough to keep them out of the database.
+pub(crate) fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+fn session_from_row(row: &sqlx::postgres::PgRow) -> Result<UserSession, UserSessionError> {
+    let get_err = |e: sqlx::Error| UserSessionError::DatabaseError(e.to_string());
+    Ok(UserSession {
+        id: row.try_get("id").map_err(get_err)?,
+        user_id: row.try_get("user_id").map_err(get_err)?,
+        access_jti: row.try_get("access_jti").map_err(get_err)?,
+        access_expires_at: row.try_get("access_expires_at").map_err(get_err)?,
+        created_at: row.try_get("created_at").map_err(get_err)?,
+        last_used_at: row.try_get("last_used_at").map_err(get_err)?,
+        expires_at: row.try_get("expires_at").map_err(get_err)?,
+        revoked_at: row.try_get("revoked_at").map_err(get_err)?,
+    })
+}
+
+const SESSION_COLUMNS: &str = "id, user_id, access_jti, access_expires_at, created_at, last_used_at, expires_at, revoked_at";
+
+impl Store {
+    /// Start a session for a user who just signed in. It ends at `expires_at` at the latest.
+    pub async fn create_user_session(
+        &self,
+        session_id: &str,
+        user_id: &str,
+        refresh_token: &str,
+        access: AccessGrant<'_>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<UserSession, UserSessionError> {
+        let row = sqlx::query(&format!(
+            "INSERT INTO user_sessions (id, user_id, refresh_token_hash, access_jti, access_expires_at, created_at, last_used_at, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $6, $7)
+             RETURNING {}",
+            SESSION_COLUMNS
+        ))
+        .bind(session_id)
+        .bind(user_id)
+        .bind(hash_token(refresh_token))
+        .bind(access.jti)
+        .bind(access.expires_at)
+        .bind(Utc::now())
+        .bind(expires_at)
+        .fetch_one(&self.backend)
+        .await
+        .map_err(|e| UserSessionError::DatabaseError(e.to_string()))?;
+        session_from_row(&row)
+    }
+
+    /// Swap `refresh_token` for `new_refresh_token`. Each refresh token works exactly once, using
+    /// any that was already swapped revokes the whole session.
+    pub async fn rotate_refresh_token(
+        &self,
+        refresh_token: &str,
+        new_refresh_token: &str,
+        access: AccessGrant<'_>,
+    ) -> Result<UserSession, UserSessionError> {
+        let hash = hash_token(refresh_token);
+        let mut tx = self.backend.begin().await.map_err(|e| UserSessionError::DatabaseError(e.to_string()))?;
+        let row = sqlx::query(&format!(
+            "UPDATE user_sessions
+             SET refresh_token_hash = $2, access_jti = $3, access_expires_at = $4, last_used_at = NOW()
+             WHERE refresh_token_hash = $1 AND revoked_at IS NULL AND expires_at > NOW()
+             RETURNING {}",
+            SESSION_COLUMNS
+        ))
+        .bind(&hash)
+        .bind(hash_token(new_refresh_token))
+        .bind(access.jti)
+        .bind(access.expires_at)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| UserSessionError::DatabaseError(e.to_string()))?;
+        if let Some(row) = row {
+            let session = session_from_row(&row)?;
+            sqlx::query("INSERT INTO retired_refresh_tokens (token_hash, session_id) VALUES ($1, $2)")
+                .bind(&hash)
+                .bind(&session.id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| UserSessionError::DatabaseError(e.to_string()))?;
+            tx.commit().await.map_err(|e| UserSessionError::DatabaseError(e.to_string()))?;
+            return Ok(session);
+        }
+        tx.rollback().await.map_err(|e| UserSessionError::DatabaseError(e.to_string()))?;
+
+        let reused = sqlx::query(
+            "SELECT s.id, s.user_id FROM retired_refresh_tokens r JOIN user_sessions s ON s.id = r.session_id
+             WHERE r.token_hash = $1 AND s.revoked_at IS NULL",
+        )
+        .bind(&hash)
+        .fetch_optional(&self.backend)
+        .await
+        .map_err(|e| UserSessionError::DatabaseError(e.to_string()))?;
+        match reused {
+            Some(row) => {
+                let get_err = |e: sqlx::Error| UserSessionError::DatabaseError(e.to_string());
+                let session_id: String = row.try_get("id").map_err(get_err)?;
+                let user_id: String = row.try_get("user_id").map_err(get_err)?;
+                self.revoke_user_session(&session_id, &user_id).await?;
+                Err(UserSessionError::TokenReused)
+            }
+            None => Err(UserSessionError::InvalidToken),
+        }
+    }
+
+    /// The user's sessions that are still usable, most recently used first.
+    pub async fn list_user_sessions(&self, user_id: &str) -> Result<Vec<UserSession>, UserSessionError> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM user_sessions WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+             ORDER BY last_used_at DESC",
+            SESSION_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_all(&self.backend)
+        .await
+        .map_err(|e| UserSessionError::DatabaseError(e.to_string()))?;
+        rows.iter().map(session_from_row).collect()
+    }
+
+    /// End a session. Its refresh token stops working and its current access token goes on the
+    /// revocation list until it expires.
+    pub async fn revoke_user_session(&self, session_id: &str, user_id: &str) -> Result<UserSession, UserSessionError> {
+        let mut tx = self.backend.begin().await.map_err(|e| UserSessionError::DatabaseError(e.to_string()))?;
+        let row = sqlx::query(&format!(
+            "UPDATE user_sessions SET revoked_at = NOW() WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL RETURNING {}",
+            SESSION_COLUMNS
+        ))
+        .bind(session_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| UserSessionError::DatabaseError(e.to_string()))?;
+        let session = match row {
+            Some(row) => session_from_row(&row)?,
+            None => return Err(UserSessionError::NotFound),
+        };
+
+        sqlx::query("INSERT INTO revoked_tokens (jti, expires_at) VALUES ($1, $2) ON CONFLICT (jti) DO NOTHING")
+            .bind(&session.access_jti)
+            .bind(session.access_expires_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| UserSessionError::DatabaseError(e.to_string()))?;
+        // Expired tokens are rejected anyway, no need to remember them
+        sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < NOW()")
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| UserSessionError::DatabaseError(e.to_string()))?;
+
+        tx.commit().await.map_err(|e| UserSessionError::DatabaseError(e.to_string()))?;
+        Ok(session)
+    }
+
+    /// Whether an access token no longer works: it was revoked itself, or the session it was
+    /// issued for ended. Checking the session covers every access token a refresh replaced.
+    pub async fn is_token_revoked(&self, jti: &str, session_id: &str) -> Result<bool, UserSessionError> {
+        let revoked: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $1)
+                 OR NOT EXISTS (SELECT 1 FROM user_sessions WHERE id = $2 AND revoked_at IS NULL)",
+        )
+        .bind(jti)
+        .bind(session_id)
+        .fetch_one(&self.backend)
+        .await
+        .map_err(|e| UserSessionError::DatabaseError(e.to_string()))?;
+        Ok(revoked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hash_token;
+
+    #[test]
+    fn test_hash_token() {
+        // SHA-256 of "abc" from FIPS 180-2
+        assert_eq!(hash_token("abc"), "ungWv48Bz-pBQUDeXa4iI7ADYaOWF3qctBD_YfIAFa0");
+    }
+}
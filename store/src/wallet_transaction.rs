@@ -0,0 +1,170 @@
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+use crate::Store;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferDirection {
+    In,
+    Out,
+}
+
+impl TransferDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransferDirection::In => "in",
+            TransferDirection::Out => "out",
+        }
+    }
+
+    pub fn parse(direction: &str) -> Option<Self> {
+        match direction {
+            "in" => Some(TransferDirection::In),
+            "out" => Some(TransferDirection::Out),
+            _ => None,
+        }
+    }
+}
+
+/// One SOL or token transfer into or out of a user's wallet, as decoded by the indexer.
+#[derive(Debug, Clone)]
+pub struct WalletTransaction {
+    pub id: String,
+    pub user_id: String,
+    pub signature: String,
+    pub slot: u64,
+    pub instruction_index: u32,
+    pub direction: TransferDirection,
+    /// The other wallet of the transfer
+    pub counterparty: String,
+    /// `None` for SOL
+    pub mint: Option<String>,
+    /// Lamports or base units of the mint
+    pub amount: u64,
+    pub block_time: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetFilter {
+    Sol,
+    Token(String),
+}
+
+/// Which of a user's transactions to list. Every field left at `None` matches everything.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionFilter {
+    pub asset: Option<AssetFilter>,
+    pub direction: Option<TransferDirection>,
+    /// Inclusive
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// A page of transactions, newest first.
+#[derive(Debug, Clone)]
+pub struct TransactionPage {
+    pub transactions: Vec<WalletTransaction>,
+    /// Pass back as `cursor` for the next page, `None` on the last one
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum WalletTransactionError {
+    DatabaseError(String),
+}
+
+impl std::fmt::Display for WalletTransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WalletTransactionError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for WalletTransactionError {}
+
+fn transaction_from_row(row: &sqlx::postgres::PgRow) -> Result<WalletTransaction, WalletTransactionError> {
+    let get_err = |e: sqlx::Error| WalletTransactionError::DatabaseError(e.to_string());
+    let direction: String = row.try_get("direction").map_err(get_err)?;
+    let slot: i64 = row.try_get("slot").map_err(get_err)?;
+    let instruction_index: i32 = row.try_get("instruction_index").map_err(get_err)?;
+    let amount: i64 = row.try_get("amount").map_err(get_err)?;
+    Ok(WalletTransaction {
+        id: row.try_get("id").map_err(get_err)?,
+        user_id: row.try_get("user_id").map_err(get_err)?,
+        signature: row.try_get("signature").map_err(get_err)?,
+        slot: slot as u64,
+        instruction_index: instruction_index as u32,
+        direction: TransferDirection::parse(&direction)
+            .ok_or_else(|| WalletTransactionError::DatabaseError(format!("Unknown transfer direction {}", direction)))?,
+        counterparty: row.try_get("counterparty").map_err(get_err)?,
+        mint: row.try_get("mint").map_err(get_err)?,
+        amount: amount as u64,
+        block_time: row.try_get("block_time").map_err(get_err)?,
+    })
+}
+
+impl Store {
+    /// The user's transfers matching `filter`, newest first. `cursor` is the `next_cursor` of the
+    /// previous page, an unknown cursor gives an empty page.
+    pub async fn list_wallet_transactions(
+        &self,
+        user_id: &str,
+        filter: &TransactionFilter,
+        cursor: Option<&str>,
+        limit: u32,
+    ) -> Result<TransactionPage, WalletTransactionError> {
+        let (sol_only, mint) = match &filter.asset {
+            Some(AssetFilter::Sol) => (true, None),
+            Some(AssetFilter::Token(mint)) => (false, Some(mint.as_str())),
+            None => (false, None),
+        };
+        // One row more than asked for tells whether there is another page
+        let rows = sqlx::query(
+            "SELECT id, user_id, signature, slot, instruction_index, direction, counterparty, mint, amount, block_time
+             FROM wallet_transactions
+             WHERE user_id = $1
+               AND (NOT $2 OR mint IS NULL)
+               AND ($3::TEXT IS NULL OR mint = $3)
+               AND ($4::TEXT IS NULL OR direction = $4)
+               AND ($5::TIMESTAMPTZ IS NULL OR block_time >= $5)
+               AND ($6::TIMESTAMPTZ IS NULL OR block_time < $6)
+               AND ($7::TEXT IS NULL OR (block_time, id) < (SELECT block_time, id FROM wallet_transactions WHERE id = $7 AND user_id = $1))
+             ORDER BY block_time DESC, id DESC
+             LIMIT $8",
+        )
+        .bind(user_id)
+        .bind(sol_only)
+        .bind(mint)
+        .bind(filter.direction.map(|direction| direction.as_str()))
+        .bind(filter.from)
+        .bind(filter.until)
+        .bind(cursor)
+        .bind(i64::from(limit) + 1)
+        .fetch_all(&self.backend)
+        .await
+        .map_err(|e| WalletTransactionError::DatabaseError(e.to_string()))?;
+        let mut transactions = rows.iter().map(transaction_from_row).collect::<Result<Vec<_>, _>>()?;
+        let next_cursor = if transactions.len() > limit as usize {
+            transactions.truncate(limit as usize);
+            transactions.last().map(|transaction| transaction.id.clone())
+        } else {
+            None
+        };
+        Ok(TransactionPage { transactions, next_cursor })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransferDirection;
+
+    #[test]
+    fn test_direction_roundtrip() {
+        for direction in [TransferDirection::In, TransferDirection::Out] {
+            assert_eq!(TransferDirection::parse(direction.as_str()), Some(direction));
+        }
+        assert_eq!(TransferDirection::parse("both"), None);
+    }
+}
@@ -0,0 +1,292 @@
+//! How the parties authenticate to each other. Every internal request carries a short lived EdDSA
+//! JWT signed with the caller's identity key and addressed to the callee: `kid` and `iss` name the
+//! caller, `aud` the callee and `sub` the user the request is made for. Tokens are checked against
+//! the `identity_key`s in the topology, so end-user tokens (HS256 with `JWT_SECRET`) never pass.
+//! A token works for a single request, the verifier remembers every `jti` it accepted until the
+//! token expires.
+//!
+//! A party's secret key is read from `MPC_IDENTITY_SECRET_KEY` or the file in
+//! `MPC_IDENTITY_SECRET_KEY_FILE`, as a base58 ed25519 seed or a base58 Solana keypair.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::{general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::{Role, Topology, TopologyError};
+
+/// Internal tokens are minted per request, they only have to survive the round trip.
+pub const TOKEN_TTL_SECONDS: u64 = 120;
+
+const SECRET_KEY_VAR: &str = "MPC_IDENTITY_SECRET_KEY";
+/// PKCS#8 v1 wrapping of a raw ed25519 seed, the format `jsonwebtoken` takes private keys in.
+const PKCS8_ED25519_PREFIX: [u8; 16] = [0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceClaims {
+    /// Party id of the caller
+    pub iss: String,
+    /// Party id of the callee
+    pub aud: String,
+    /// The user the request is made for
+    pub sub: String,
+    pub iat: u64,
+    pub exp: u64,
+    pub jti: String,
+}
+
+#[derive(Debug)]
+pub enum IdentityError {
+    MissingKey(String),
+    InvalidKey(String),
+    KeyMismatch(String),
+    Topology(TopologyError),
+    InvalidToken(String),
+    UnknownIssuer(String),
+    /// The token was already used for another request
+    ReplayedToken,
+}
+
+impl std::fmt::Display for IdentityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdentityError::MissingKey(msg) => write!(f, "Missing identity key: {}", msg),
+            IdentityError::InvalidKey(msg) => write!(f, "Invalid identity key: {}", msg),
+            IdentityError::KeyMismatch(id) => write!(f, "Identity key does not match the topology entry of party {}", id),
+            IdentityError::Topology(e) => write!(f, "{}", e),
+            IdentityError::InvalidToken(msg) => write!(f, "Invalid service token: {}", msg),
+            IdentityError::UnknownIssuer(id) => write!(f, "Service token issued by unknown party {}", id),
+            IdentityError::ReplayedToken => write!(f, "Service token was already used"),
+        }
+    }
+}
+
+impl std::error::Error for IdentityError {}
+
+impl From<TopologyError> for IdentityError {
+    fn from(e: TopologyError) -> Self {
+        IdentityError::Topology(e)
+    }
+}
+
+/// The key this party signs its outgoing requests with.
+pub struct ServiceIdentity {
+    party_id: String,
+    key: EncodingKey,
+}
+
+impl ServiceIdentity {
+    /// Load the secret key of `party_id` from the environment and check it against the topology.
+    pub fn load(topology: &Topology, party_id: &str) -> Result<Self, IdentityError> {
+        let secret = match dotenvy::var(SECRET_KEY_VAR) {
+            Ok(secret) => secret,
+            Err(_) => {
+                let path = dotenvy::var(format!("{}_FILE", SECRET_KEY_VAR))
+                    .map_err(|_| IdentityError::MissingKey(format!("{} is not set", SECRET_KEY_VAR)))?;
+                std::fs::read_to_string(&path).map_err(|e| IdentityError::MissingKey(format!("{}: {}", path, e)))?
+            }
+        };
+        let secret = bs58::decode(secret.trim()).into_vec().map_err(|e| IdentityError::InvalidKey(e.to_string()))?;
+        Self::from_secret(topology, party_id, &secret)
+    }
+
+    /// `secret` is a 32 byte seed or a 64 byte Solana keypair.
+    pub fn from_secret(topology: &Topology, party_id: &str, secret: &[u8]) -> Result<Self, IdentityError> {
+        let seed = match secret.len() {
+            32 | 64 => &secret[..32],
+            len => return Err(IdentityError::InvalidKey(format!("expected 32 or 64 bytes, got {}", len))),
+        };
+        let mut der = PKCS8_ED25519_PREFIX.to_vec();
+        der.extend_from_slice(seed);
+        let identity = Self { party_id: party_id.to_string(), key: EncodingKey::from_ed_der(&der) };
+
+        // Sign a token to ourselves to make sure the other parties will accept it
+        let verifier = ServiceVerifier::for_issuers(party_id, topology, |party| party.id == party_id)?;
+        let token = identity.token(party_id, "")?;
+        match verifier.verify(&token) {
+            Ok(_) => Ok(identity),
+            Err(IdentityError::UnknownIssuer(_)) => {
+                Err(IdentityError::MissingKey(format!("party {} has no identity_key in the topology", party_id)))
+            }
+            Err(_) => Err(IdentityError::KeyMismatch(party_id.to_string())),
+        }
+    }
+
+    pub fn party_id(&self) -> &str {
+        &self.party_id
+    }
+
+    /// A token for a single request to the party `audience`, made for `user_id`.
+    pub fn token(&self, audience: &str, user_id: &str) -> Result<String, IdentityError> {
+        let now = unix_now();
+        let claims = ServiceClaims {
+            iss: self.party_id.clone(),
+            aud: audience.to_string(),
+            sub: user_id.to_string(),
+            iat: now,
+            exp: now + TOKEN_TTL_SECONDS,
+            jti: uuid::Uuid::new_v4().to_string(),
+        };
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.party_id.clone());
+        encode(&header, &claims, &self.key).map_err(|e| IdentityError::InvalidKey(e.to_string()))
+    }
+
+    /// A raw ed25519 signature over `message`, for data that reaches the other parties through a
+    /// relay instead of in a request of our own. Checked against the `identity_key` in the topology.
+    pub fn sign(&self, message: &[u8]) -> Result<[u8; 64], IdentityError> {
+        let signature = jsonwebtoken::crypto::sign(message, &self.key, Algorithm::EdDSA)
+            .map_err(|e| IdentityError::InvalidKey(e.to_string()))?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|e| IdentityError::InvalidKey(e.to_string()))?;
+        signature.try_into().map_err(|_| IdentityError::InvalidKey("signature is not 64 bytes".to_string()))
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// Checks the tokens of incoming requests.
+pub struct ServiceVerifier {
+    party_id: String,
+    issuers: HashMap<String, DecodingKey>,
+    /// Issuer and `jti` of every accepted token, with the time it stops passing validation
+    seen: Mutex<HashMap<(String, String), u64>>,
+}
+
+impl ServiceVerifier {
+    /// Accept tokens addressed to `party_id` from the parties with one of `roles`.
+    pub fn new(topology: &Topology, party_id: &str, roles: &[Role]) -> Result<Self, IdentityError> {
+        topology.party(party_id)?;
+        let verifier = Self::for_issuers(party_id, topology, |party| roles.contains(&party.role))?;
+        if verifier.issuers.is_empty() {
+            return Err(IdentityError::MissingKey(format!("no party allowed to call {} has an identity_key", party_id)));
+        }
+        Ok(verifier)
+    }
+
+    fn for_issuers(party_id: &str, topology: &Topology, allowed: impl Fn(&crate::Party) -> bool) -> Result<Self, IdentityError> {
+        let mut issuers = HashMap::new();
+        for party in topology.parties.iter().filter(|party| allowed(party)) {
+            if let Some(identity_key) = &party.identity_key {
+                let key = bs58::decode(identity_key).into_vec().map_err(|e| IdentityError::InvalidKey(e.to_string()))?;
+                let key = DecodingKey::from_ed_components(&URL_SAFE_NO_PAD.encode(key))
+                    .map_err(|e| IdentityError::InvalidKey(e.to_string()))?;
+                issuers.insert(party.id.clone(), key);
+            }
+        }
+        Ok(Self { party_id: party_id.to_string(), issuers, seen: Mutex::new(HashMap::new()) })
+    }
+
+    pub fn verify(&self, token: &str) -> Result<ServiceClaims, IdentityError> {
+        let header = decode_header(token).map_err(|e| IdentityError::InvalidToken(e.to_string()))?;
+        if header.alg != Algorithm::EdDSA {
+            return Err(IdentityError::InvalidToken("not a service token".to_string()));
+        }
+        let issuer = header.kid.ok_or_else(|| IdentityError::InvalidToken("missing kid".to_string()))?;
+        let key = self.issuers.get(&issuer).ok_or(IdentityError::UnknownIssuer(issuer.clone()))?;
+
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_audience(&[&self.party_id]);
+        validation.set_issuer(&[&issuer]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = decode::<ServiceClaims>(token, key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| IdentityError::InvalidToken(e.to_string()))?;
+
+        let now = unix_now();
+        let mut seen = self.seen.lock().map_err(|_| IdentityError::InvalidToken("replay cache poisoned".to_string()))?;
+        // Expired tokens fail validation anyway, no need to remember them
+        seen.retain(|_, expires_at| *expires_at >= now);
+        if seen.insert((claims.iss.clone(), claims.jti.clone()), claims.exp + validation.leeway).is_some() {
+            return Err(IdentityError::ReplayedToken);
+        }
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    use super::{IdentityError, ServiceIdentity, ServiceVerifier};
+    use crate::{Role, Topology};
+
+    // RFC 8032 test vectors 1 and 2
+    const SEED_1: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+    const PUBLIC_1: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+    const SEED_2: &str = "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb";
+    const PUBLIC_2: &str = "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c";
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn topology() -> Topology {
+        let mut topology = Topology::default();
+        for party in &mut topology.parties {
+            party.identity_key = match party.id.as_str() {
+                "coordinator" => Some(bs58::encode(hex(PUBLIC_1)).into_string()),
+                "backend" => Some(bs58::encode(hex(PUBLIC_2)).into_string()),
+                _ => None,
+            };
+        }
+        topology
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let topology = topology();
+        let coordinator = ServiceIdentity::from_secret(&topology, "coordinator", &hex(SEED_1)).unwrap();
+        let verifier = ServiceVerifier::new(&topology, "share-1", &[Role::Coordinator]).unwrap();
+
+        let token = coordinator.token("share-1", "user").unwrap();
+        let claims = verifier.verify(&token).unwrap();
+        assert_eq!((claims.iss.as_str(), claims.aud.as_str(), claims.sub.as_str()), ("coordinator", "share-1", "user"));
+        // A token works for a single request
+        assert!(matches!(verifier.verify(&token), Err(IdentityError::ReplayedToken)));
+        // Addressed to a different party
+        assert!(matches!(verifier.verify(&coordinator.token("share-2", "user").unwrap()), Err(IdentityError::InvalidToken(_))));
+    }
+
+    #[test]
+    fn test_sign() {
+        let coordinator = ServiceIdentity::from_secret(&topology(), "coordinator", &hex(SEED_1)).unwrap();
+        // RFC 8032 test vector 1 signs the empty message
+        let expected = "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b";
+        assert_eq!(coordinator.sign(b"").unwrap().to_vec(), hex(expected));
+    }
+
+    #[test]
+    fn test_rejects_other_roles() {
+        let topology = topology();
+        let backend = ServiceIdentity::from_secret(&topology, "backend", &hex(SEED_2)).unwrap();
+        let verifier = ServiceVerifier::new(&topology, "share-1", &[Role::Coordinator]).unwrap();
+        assert!(matches!(verifier.verify(&backend.token("share-1", "user").unwrap()), Err(IdentityError::UnknownIssuer(_))));
+    }
+
+    #[test]
+    fn test_rejects_user_tokens() {
+        let verifier = ServiceVerifier::new(&topology(), "coordinator", &[Role::Backend]).unwrap();
+        let user_token = encode(
+            &Header::default(),
+            &serde_json::json!({ "user_id": "user", "id": "user", "exp": u64::MAX / 2 }),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert!(matches!(verifier.verify(&user_token), Err(IdentityError::InvalidToken(_))));
+    }
+
+    #[test]
+    fn test_key_mismatch() {
+        let topology = topology();
+        assert!(matches!(
+            ServiceIdentity::from_secret(&topology, "coordinator", &hex(SEED_2)),
+            Err(IdentityError::KeyMismatch(_))
+        ));
+        assert!(matches!(ServiceIdentity::from_secret(&topology, "share-1", &hex(SEED_1)), Err(IdentityError::MissingKey(_))));
+    }
+}
@@ -0,0 +1,279 @@
+//! Where the backend, the coordinator and the share servers live and how to reach them.
+//!
+//! The topology is read from the TOML file in `MPC_TOPOLOGY_FILE` (or `topology.toml` in the
+//! working directory), see `topology.example.toml`. Without a file every party runs on localhost.
+//! Single fields can be overridden per party with `MPC_PARTY_<ID>_URL`, `MPC_PARTY_<ID>_BIND` and
+//! `MPC_PARTY_<ID>_IDENTITY_KEY`, where `<ID>` is the party id in upper case with `-` replaced by `_`.
+
+pub mod identity;
+
+use std::path::Path;
+
+use serde::Deserialize;
+
+const DEFAULT_FILE: &str = "topology.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Backend,
+    Coordinator,
+    ShareServer,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Party {
+    pub id: String,
+    pub role: Role,
+    /// Where the other parties reach this one
+    pub url: String,
+    /// Address to listen on, defaults to the host and port of `url`
+    pub bind: Option<String>,
+    /// Base58 ed25519 public key the party authenticates itself with
+    pub identity_key: Option<String>,
+}
+
+impl Party {
+    fn new(id: &str, role: Role, url: &str) -> Self {
+        Self { id: id.to_string(), role, url: url.to_string(), bind: None, identity_key: None }
+    }
+
+    pub fn bind_address(&self) -> Result<String, TopologyError> {
+        if let Some(bind) = &self.bind {
+            return Ok(bind.clone());
+        }
+        let host_port = self
+            .url
+            .split_once("://")
+            .map(|(_, rest)| rest)
+            .unwrap_or(&self.url)
+            .split('/')
+            .next()
+            .unwrap_or_default();
+        if !host_port.contains(':') {
+            return Err(TopologyError::Invalid(format!("party {} has no port in its url and no bind address", self.id)));
+        }
+        Ok(host_port.to_string())
+    }
+}
+
+#[derive(Debug)]
+pub enum TopologyError {
+    Io(String),
+    Parse(String),
+    Invalid(String),
+    UnknownParty(String),
+}
+
+impl std::fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TopologyError::Io(msg) => write!(f, "Failed to read topology: {}", msg),
+            TopologyError::Parse(msg) => write!(f, "Failed to parse topology: {}", msg),
+            TopologyError::Invalid(msg) => write!(f, "Invalid topology: {}", msg),
+            TopologyError::UnknownParty(id) => write!(f, "Party {} is not part of the topology", id),
+        }
+    }
+}
+
+impl std::error::Error for TopologyError {}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Topology {
+    #[serde(rename = "party")]
+    pub parties: Vec<Party>,
+}
+
+impl Default for Topology {
+    fn default() -> Self {
+        Self {
+            parties: vec![
+                Party::new("backend", Role::Backend, "http://127.0.0.1:3000"),
+                Party::new("coordinator", Role::Coordinator, "http://127.0.0.1:8080"),
+                Party::new("share-1", Role::ShareServer, "http://127.0.0.1:9000"),
+                Party::new("share-2", Role::ShareServer, "http://127.0.0.1:9001"),
+            ],
+        }
+    }
+}
+
+impl Topology {
+    /// Load the topology file, apply the environment overrides and validate the result.
+    pub fn load() -> Result<Self, TopologyError> {
+        let mut topology = match dotenvy::var("MPC_TOPOLOGY_FILE") {
+            Ok(path) => Self::from_file(&path)?,
+            Err(_) if Path::new(DEFAULT_FILE).exists() => Self::from_file(DEFAULT_FILE)?,
+            Err(_) => Self::default(),
+        };
+        topology.apply_overrides(|var| dotenvy::var(var).ok());
+        topology.validate()?;
+        Ok(topology)
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, TopologyError> {
+        let contents = std::fs::read_to_string(path).map_err(|e| TopologyError::Io(format!("{}: {}", path, e)))?;
+        Self::from_toml(&contents)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, TopologyError> {
+        toml::from_str(contents).map_err(|e| TopologyError::Parse(e.to_string()))
+    }
+
+    fn apply_overrides(&mut self, var: impl Fn(&str) -> Option<String>) {
+        for party in &mut self.parties {
+            let prefix = format!("MPC_PARTY_{}", party.id.to_uppercase().replace('-', "_"));
+            if let Some(url) = var(&format!("{}_URL", prefix)) {
+                party.url = url;
+            }
+            if let Some(bind) = var(&format!("{}_BIND", prefix)) {
+                party.bind = Some(bind);
+            }
+            if let Some(identity_key) = var(&format!("{}_IDENTITY_KEY", prefix)) {
+                party.identity_key = Some(identity_key);
+            }
+        }
+    }
+
+    /// Exactly one backend and one coordinator, at least one share server, unique ids and well formed keys.
+    pub fn validate(&self) -> Result<(), TopologyError> {
+        for (index, party) in self.parties.iter().enumerate() {
+            if self.parties[..index].iter().any(|other| other.id == party.id) {
+                return Err(TopologyError::Invalid(format!("party id {} is used twice", party.id)));
+            }
+            if let Some(identity_key) = &party.identity_key {
+                let valid = bs58::decode(identity_key).into_vec().map(|key| key.len() == 32).unwrap_or(false);
+                if !valid {
+                    return Err(TopologyError::Invalid(format!("identity key of party {} is not a base58 ed25519 key", party.id)));
+                }
+            }
+            party.bind_address()?;
+        }
+        for role in [Role::Backend, Role::Coordinator] {
+            let count = self.parties.iter().filter(|party| party.role == role).count();
+            if count != 1 {
+                return Err(TopologyError::Invalid(format!("expected exactly one {:?}, found {}", role, count)));
+            }
+        }
+        if self.share_servers().is_empty() {
+            return Err(TopologyError::Invalid("no share servers configured".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn party(&self, id: &str) -> Result<&Party, TopologyError> {
+        self.parties.iter().find(|party| party.id == id).ok_or_else(|| TopologyError::UnknownParty(id.to_string()))
+    }
+
+    pub fn backend(&self) -> Result<&Party, TopologyError> {
+        self.by_role(Role::Backend)
+    }
+
+    pub fn coordinator(&self) -> Result<&Party, TopologyError> {
+        self.by_role(Role::Coordinator)
+    }
+
+    /// Share servers in the order of the file. New FROST wallets number their parties in this order
+    /// and store the numbering, existing wallets don't depend on it.
+    pub fn share_servers(&self) -> Vec<&Party> {
+        self.parties.iter().filter(|party| party.role == Role::ShareServer).collect()
+    }
+
+    /// The share server this process runs as, picked with `MPC_PARTY_ID` or `default_id`.
+    pub fn local_share_server(&self, default_id: &str) -> Result<&Party, TopologyError> {
+        let id = dotenvy::var("MPC_PARTY_ID").unwrap_or_else(|_| default_id.to_string());
+        let party = self.party(&id)?;
+        if party.role != Role::ShareServer {
+            return Err(TopologyError::Invalid(format!("party {} is not a share server", id)));
+        }
+        Ok(party)
+    }
+
+    fn by_role(&self, role: Role) -> Result<&Party, TopologyError> {
+        self.parties
+            .iter()
+            .find(|party| party.role == role)
+            .ok_or_else(|| TopologyError::Invalid(format!("no {:?} configured", role)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Role, Topology, TopologyError};
+
+    const TOPOLOGY: &str = r#"
+        [[party]]
+        id = "backend"
+        role = "backend"
+        url = "https://api.example.com"
+        bind = "0.0.0.0:3000"
+
+        [[party]]
+        id = "coordinator"
+        role = "coordinator"
+        url = "http://coordinator.internal:8080"
+
+        [[party]]
+        id = "share-1"
+        role = "share_server"
+        url = "http://share-1.internal:9000"
+        identity_key = "4zvwRjXUKGfvwnParsHAS3HuSVzV5cA4McphgmoCtajS"
+
+        [[party]]
+        id = "share-2"
+        role = "share_server"
+        url = "http://share-2.internal:9000"
+
+        [[party]]
+        id = "share-3"
+        role = "share_server"
+        url = "http://share-3.internal:9000"
+    "#;
+
+    #[test]
+    fn test_parse() {
+        let topology = Topology::from_toml(TOPOLOGY).unwrap();
+        topology.validate().unwrap();
+        assert_eq!(topology.coordinator().unwrap().url, "http://coordinator.internal:8080");
+        assert_eq!(topology.backend().unwrap().bind_address().unwrap(), "0.0.0.0:3000");
+        assert_eq!(topology.coordinator().unwrap().bind_address().unwrap(), "coordinator.internal:8080");
+        let ids: Vec<_> = topology.share_servers().iter().map(|party| party.id.as_str()).collect();
+        assert_eq!(ids, ["share-1", "share-2", "share-3"]);
+        assert_eq!(topology.party("share-2").unwrap().role, Role::ShareServer);
+        assert!(matches!(topology.party("share-4"), Err(TopologyError::UnknownParty(_))));
+    }
+
+    #[test]
+    fn test_default_is_valid() {
+        let topology = Topology::default();
+        topology.validate().unwrap();
+        assert_eq!(topology.share_servers().len(), 2);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut topology = Topology::from_toml(TOPOLOGY).unwrap();
+        topology.apply_overrides(|var| match var {
+            "MPC_PARTY_SHARE_2_URL" => Some("http://10.0.0.2:9000".to_string()),
+            "MPC_PARTY_COORDINATOR_BIND" => Some("0.0.0.0:8080".to_string()),
+            _ => None,
+        });
+        assert_eq!(topology.party("share-2").unwrap().url, "http://10.0.0.2:9000");
+        assert_eq!(topology.coordinator().unwrap().bind_address().unwrap(), "0.0.0.0:8080");
+        assert_eq!(topology.party("share-1").unwrap().url, "http://share-1.internal:9000");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut topology = Topology::default();
+        topology.parties[3].id = "share-1".to_string();
+        assert!(matches!(topology.validate(), Err(TopologyError::Invalid(_))));
+
+        let mut topology = Topology::default();
+        topology.parties.remove(1);
+        assert!(matches!(topology.validate(), Err(TopologyError::Invalid(_))));
+
+        let mut topology = Topology::default();
+        topology.parties[2].identity_key = Some("not a key".to_string());
+        assert!(matches!(topology.validate(), Err(TopologyError::Invalid(_))));
+    }
+}